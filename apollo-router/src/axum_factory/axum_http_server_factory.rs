@@ -42,6 +42,8 @@ use super::listeners::ensure_endpoints_consistency;
 use super::listeners::ensure_listenaddrs_consistency;
 use super::listeners::extra_endpoints;
 use super::listeners::ListenersAndRouters;
+use super::tls::ClientCertificate;
+use super::tls::CLIENT_CERTIFICATE_CONTEXT_KEY;
 use super::utils::decompress_request_body;
 use super::utils::PropagatingMakeSpan;
 use super::ListenAddrAndRouter;
@@ -432,8 +434,17 @@ async fn handle_graphql(
 ) -> impl IntoResponse {
     tracing::info!(counter.apollo_router_session_count_active = 1,);
 
+    let client_certificate = http_request
+        .extensions()
+        .get::<ClientCertificate>()
+        .cloned();
     let request: router::Request = http_request.into();
     let context = request.context.clone();
+    if let Some(client_certificate) = client_certificate {
+        if let Err(e) = context.insert(CLIENT_CERTIFICATE_CONTEXT_KEY, client_certificate) {
+            tracing::error!("could not insert the client certificate in the context: {e}");
+        }
+    }
 
     let res = service.oneshot(request).await;
     let dur = context.busy_time().await;
@@ -16,7 +16,9 @@ use multimap::MultiMap;
 use tokio::net::UnixListener;
 use tokio::sync::mpsc;
 use tokio::sync::Notify;
+use tower::ServiceBuilder;
 
+use super::tls::ClientCertificate;
 use crate::configuration::Configuration;
 use crate::http_server_factory::Listener;
 use crate::http_server_factory::NetworkStream;
@@ -287,6 +289,22 @@ pub(super) fn serve_router_on_listen_addr(
                                             let protocol = stream.get_ref().1.alpn_protocol();
                                             let http2 = protocol == Some(&b"h2"[..]);
 
+                                            // the client certificate was verified during the handshake
+                                            let client_certificate = stream
+                                                .get_ref()
+                                                .1
+                                                .peer_certificates()
+                                                .and_then(|certificates| certificates.first())
+                                                .and_then(|certificate| ClientCertificate::from_der(&certificate.0));
+                                            let app = ServiceBuilder::new()
+                                                .map_request(move |mut request: http::Request<hyper::Body>| {
+                                                    if let Some(client_certificate) = &client_certificate {
+                                                        request.extensions_mut().insert(client_certificate.clone());
+                                                    }
+                                                    request
+                                                })
+                                                .service(app);
+
                                             let connection = Http::new()
                                             .http1_keep_alive(true)
                                             .http1_header_read_timeout(Duration::from_secs(10))
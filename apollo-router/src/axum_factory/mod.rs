@@ -3,6 +3,7 @@ mod axum_http_server_factory;
 mod listeners;
 #[cfg(test)]
 pub(crate) mod tests;
+pub(crate) mod tls;
 pub(crate) mod utils;
 
 pub(crate) use axum_http_server_factory::make_axum_router;
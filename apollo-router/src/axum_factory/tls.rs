@@ -0,0 +1,263 @@
+//! TLS connections to the router
+
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+use futures::stream::select;
+use futures::StreamExt;
+use rustls::server::ClientHello;
+use rustls::server::ResolvesServerCert;
+use rustls::sign::CertifiedKey;
+use rustls::Certificate;
+use rustls::PrivateKey;
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+use tokio::task::JoinHandle;
+use x509_parser::extensions::GeneralName;
+
+use crate::configuration::check_key_pair;
+use crate::configuration::TlsSupergraph;
+use crate::router::ApolloRouterError;
+
+/// Context key holding the [`ClientCertificate`] of requests authenticated with a TLS client
+/// certificate
+pub(crate) const CLIENT_CERTIFICATE_CONTEXT_KEY: &str = "apollo_tls::client_certificate";
+
+/// Identity of a client authenticated with a TLS certificate
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ClientCertificate {
+    /// distinguished name of the certificate's subject
+    pub(crate) subject: String,
+    /// subject alternative names, prefixed by their type (`DNS:`, `IP:`, `email:` or `URI:`)
+    pub(crate) subject_alternative_names: Vec<String>,
+    /// hex encoded SHA-256 fingerprint of the certificate
+    pub(crate) fingerprint: String,
+}
+
+impl ClientCertificate {
+    /// Reads the identity from a DER encoded certificate, already verified by rustls
+    pub(crate) fn from_der(der: &[u8]) -> Option<Self> {
+        let (_, certificate) = x509_parser::parse_x509_certificate(der)
+            .map_err(|e| tracing::debug!("could not parse the client certificate: {e}"))
+            .ok()?;
+
+        let subject_alternative_names = match certificate.subject_alternative_name() {
+            Ok(Some(extension)) => extension
+                .value
+                .general_names
+                .iter()
+                .filter_map(general_name)
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        Some(Self {
+            subject: certificate.subject().to_string(),
+            subject_alternative_names,
+            fingerprint: hex::encode(Sha256::digest(der)),
+        })
+    }
+}
+
+fn general_name(name: &GeneralName) -> Option<String> {
+    match name {
+        GeneralName::DNSName(name) => Some(format!("DNS:{name}")),
+        GeneralName::RFC822Name(email) => Some(format!("email:{email}")),
+        GeneralName::URI(uri) => Some(format!("URI:{uri}")),
+        GeneralName::IPAddress(ip) => {
+            let ip = match ip.len() {
+                4 => IpAddr::from(<[u8; 4]>::try_from(*ip).ok()?),
+                16 => IpAddr::from(<[u8; 16]>::try_from(*ip).ok()?),
+                _ => return None,
+            };
+            Some(format!("IP:{ip}"))
+        }
+        _ => None,
+    }
+}
+
+/// Server certificate of the supergraph listener, reloaded when its files change
+///
+/// Only new TLS handshakes use the reloaded certificate, established connections are kept.
+pub(crate) struct ServerCertificateResolver {
+    certified_key: Arc<RwLock<Arc<CertifiedKey>>>,
+    watcher: Option<JoinHandle<()>>,
+}
+
+impl ServerCertificateResolver {
+    pub(crate) fn new(
+        config: &TlsSupergraph,
+        certificates: Vec<Certificate>,
+        key: &PrivateKey,
+    ) -> Result<Self, ApolloRouterError> {
+        let certified_key = Arc::new(RwLock::new(Arc::new(
+            load_certified_key(certificates, key).map_err(ApolloRouterError::Rustls)?,
+        )));
+
+        let files = config.files();
+        let watcher = (!files.is_empty()).then(|| {
+            tokio::spawn(watch_certificate(
+                files,
+                config.clone(),
+                certified_key.clone(),
+            ))
+        });
+
+        Ok(Self {
+            certified_key,
+            watcher,
+        })
+    }
+}
+
+impl Drop for ServerCertificateResolver {
+    fn drop(&mut self) {
+        if let Some(watcher) = self.watcher.take() {
+            watcher.abort();
+        }
+    }
+}
+
+impl ResolvesServerCert for ServerCertificateResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.certified_key.read().expect("lock poisoned").clone())
+    }
+}
+
+fn load_certified_key(
+    certificates: Vec<Certificate>,
+    key: &PrivateKey,
+) -> Result<CertifiedKey, rustls::Error> {
+    let signing_key = rustls::sign::any_supported_type(key)
+        .map_err(|_| rustls::Error::General("invalid private key".to_string()))?;
+    if let Some(certificate) = certificates.first() {
+        check_key_pair(certificate, &*signing_key).map_err(rustls::Error::General)?;
+        record_expiration(certificate);
+    }
+
+    Ok(CertifiedKey::new(certificates, signing_key))
+}
+
+fn record_expiration(certificate: &Certificate) {
+    match x509_parser::parse_x509_certificate(&certificate.0) {
+        Ok((_, parsed)) => {
+            let not_after = parsed.validity().not_after.timestamp();
+            tracing::info!(
+                value.apollo_router_tls_certificate_expiration_timestamp =
+                    not_after.max(0) as u64,
+                subject = %parsed.subject(),
+            );
+        }
+        Err(e) => tracing::debug!("could not read the server certificate expiration: {e}"),
+    }
+}
+
+async fn watch_certificate(
+    files: Vec<PathBuf>,
+    config: TlsSupergraph,
+    certified_key: Arc<RwLock<Arc<CertifiedKey>>>,
+) {
+    let mut changes = files
+        .iter()
+        .map(|file| crate::files::watch(file).boxed())
+        .reduce(|changes, file_changes| select(changes, file_changes).boxed())
+        .expect("there is at least one file to watch");
+
+    while changes.next().await.is_some() {
+        let reloaded =
+            config
+                .reload()
+                .map_err(|e| e.to_string())
+                .and_then(|(certificates, key)| {
+                    load_certified_key(certificates, &key).map_err(|e| e.to_string())
+                });
+        match reloaded {
+            Ok(new_key) => {
+                tracing::info!("reloaded the supergraph TLS certificate");
+                *certified_key.write().expect("lock poisoned") = Arc::new(new_key);
+            }
+            // the certificate and key files might not be updated at the same time, the
+            // previous certificate is kept until the new key matches the new certificate
+            Err(e) => tracing::error!("could not reload the supergraph TLS certificate: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::load_certs;
+
+    #[test]
+    fn it_reads_the_certificate_identity() {
+        let certificates =
+            load_certs(include_str!("../configuration/testdata/server.crt")).unwrap();
+        let identity = ClientCertificate::from_der(&certificates[0].0).unwrap();
+
+        assert!(!identity.subject.is_empty());
+        assert_eq!(identity.fingerprint.len(), 64);
+        assert!(ClientCertificate::from_der(b"not a certificate").is_none());
+    }
+
+    #[tokio::test]
+    async fn it_reloads_the_server_certificate() {
+        let dir = tempfile::tempdir().unwrap();
+        let certificate_path = dir.path().join("server.crt");
+        let key_path = dir.path().join("server.key");
+        std::fs::write(
+            &certificate_path,
+            include_str!("../configuration/testdata/server.crt"),
+        )
+        .unwrap();
+        std::fs::write(
+            &key_path,
+            include_str!("../configuration/testdata/server.key"),
+        )
+        .unwrap();
+
+        let config: TlsSupergraph = serde_json::from_value(serde_json::json!({
+            "certificate": { "file": certificate_path },
+            "certificate_chain": { "file": certificate_path },
+            "key": { "file": key_path },
+        }))
+        .unwrap();
+        let mut certificates = vec![config.certificate.value.clone()];
+        certificates.extend(config.certificate_chain.value.iter().cloned());
+        let resolver =
+            ServerCertificateResolver::new(&config, certificates, &config.key.value).unwrap();
+        let served = || resolver.certified_key.read().unwrap().cert[0].clone();
+        assert_eq!(served(), config.certificate.value);
+
+        // the new key does not match the current certificate, so the previous pair is kept
+        let served_key = || resolver.certified_key.read().unwrap().key.clone();
+        let previous_key = served_key();
+        std::fs::write(
+            &key_path,
+            include_str!("../configuration/testdata/server_rotated.key"),
+        )
+        .unwrap();
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        assert_eq!(served(), config.certificate.value);
+        assert!(Arc::ptr_eq(&served_key(), &previous_key));
+
+        let rotated =
+            load_certs(include_str!("../configuration/testdata/server_rotated.crt")).unwrap();
+        std::fs::write(
+            &certificate_path,
+            include_str!("../configuration/testdata/server_rotated.crt"),
+        )
+        .unwrap();
+
+        for _ in 0..50 {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            if served() == rotated[0] {
+                return;
+            }
+        }
+        panic!("the server certificate was not reloaded");
+    }
+}
@@ -0,0 +1,191 @@
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use lru::LruCache;
+use sha2::Digest;
+use sha2::Sha256;
+
+use super::KeyType;
+use super::ValueType;
+
+/// Stores cache entries as JSON files in a local directory, one file per key.
+///
+/// File names are the SHA-256 hash of the key, so that any key can be stored. The number of
+/// files is bounded: when it is reached, the least recently used entries are deleted.
+#[derive(Clone)]
+pub(crate) struct FileCacheStorage {
+    directory: Arc<PathBuf>,
+    /// File names of the entries, by order of use
+    entries: Arc<Mutex<LruCache<String, ()>>>,
+}
+
+impl FileCacheStorage {
+    pub(crate) async fn new(
+        directory: PathBuf,
+        max_entries: NonZeroUsize,
+    ) -> std::io::Result<Self> {
+        tokio::fs::create_dir_all(&directory).await?;
+
+        // entries written before a restart are ordered by modification time
+        let mut files = Vec::new();
+        let mut read_dir = tokio::fs::read_dir(&directory).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.ends_with(".tmp") {
+                // left by a write that did not complete
+                let _ = tokio::fs::remove_file(entry.path()).await;
+            } else if name.ends_with(".json") {
+                let modified = entry.metadata().await?.modified()?;
+                files.push((modified, name));
+            }
+        }
+        files.sort();
+
+        let storage = Self {
+            directory: Arc::new(directory),
+            entries: Arc::new(Mutex::new(LruCache::new(max_entries))),
+        };
+        for (_, name) in files {
+            storage.record_use(name).await;
+        }
+        Ok(storage)
+    }
+
+    fn file_name<K: KeyType>(key: &K) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(key.to_string().as_bytes());
+        format!("{}.json", hex::encode(hasher.finalize()))
+    }
+
+    /// Marks an entry as the most recently used one, and deletes the least recently used entry
+    /// if there are too many entries
+    async fn record_use(&self, name: String) {
+        let evicted = self
+            .entries
+            .lock()
+            .expect("lock poisoned")
+            .push(name.clone(), ());
+        if let Some((evicted, ())) = evicted {
+            if evicted != name {
+                let path = self.directory.join(evicted);
+                if let Err(e) = tokio::fs::remove_file(&path).await {
+                    tracing::warn!("could not delete cache entry {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+
+    pub(crate) async fn get<K: KeyType, V: ValueType>(&self, key: &K) -> Option<V> {
+        let name = Self::file_name(key);
+        let path = self.directory.join(&name);
+        let data = tokio::fs::read(&path).await.ok()?;
+        match serde_json::from_slice(&data) {
+            Ok(value) => {
+                self.record_use(name).await;
+                Some(value)
+            }
+            Err(e) => {
+                // the entry was written by an incompatible version of the router
+                tracing::debug!(
+                    "could not deserialize cache entry {}: {}",
+                    path.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    pub(crate) async fn insert<K: KeyType, V: ValueType>(&self, key: &K, value: &V) {
+        let name = Self::file_name(key);
+        let path = self.directory.join(&name);
+        let data = match serde_json::to_vec(value) {
+            Ok(data) => data,
+            Err(e) => {
+                tracing::error!("could not serialize cache entry {}: {}", path.display(), e);
+                return;
+            }
+        };
+
+        // write to a temporary file first, so that readers never see a partially written entry
+        let temporary = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+        let result = match tokio::fs::write(&temporary, data).await {
+            Ok(()) => tokio::fs::rename(&temporary, &path).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => self.record_use(name).await,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&temporary).await;
+                tracing::warn!("could not write cache entry {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_stores_entries_in_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let max_entries = NonZeroUsize::new(10).unwrap();
+        let storage = FileCacheStorage::new(directory.path().join("cache"), max_entries)
+            .await
+            .unwrap();
+
+        let key = "key".to_string();
+        assert_eq!(storage.get::<String, usize>(&key).await, None);
+
+        storage.insert(&key, &42usize).await;
+        assert_eq!(storage.get::<String, usize>(&key).await, Some(42));
+
+        // a new storage on the same directory sees the same entries
+        let storage = FileCacheStorage::new(directory.path().join("cache"), max_entries)
+            .await
+            .unwrap();
+        assert_eq!(storage.get::<String, usize>(&key).await, Some(42));
+
+        // entries that cannot be deserialized are ignored
+        assert_eq!(storage.get::<String, Vec<String>>(&key).await, None);
+    }
+
+    #[tokio::test]
+    async fn it_evicts_the_least_recently_used_entries() {
+        let directory = tempfile::tempdir().unwrap();
+        let max_entries = NonZeroUsize::new(2).unwrap();
+        let storage = FileCacheStorage::new(directory.path().to_path_buf(), max_entries)
+            .await
+            .unwrap();
+        let files = || std::fs::read_dir(directory.path()).unwrap().count();
+
+        storage.insert(&"a".to_string(), &1usize).await;
+        storage.insert(&"b".to_string(), &2usize).await;
+        assert_eq!(
+            storage.get::<String, usize>(&"a".to_string()).await,
+            Some(1)
+        );
+
+        storage.insert(&"c".to_string(), &3usize).await;
+        assert_eq!(files(), 2);
+        assert_eq!(storage.get::<String, usize>(&"b".to_string()).await, None);
+        assert_eq!(
+            storage.get::<String, usize>(&"a".to_string()).await,
+            Some(1)
+        );
+        assert_eq!(
+            storage.get::<String, usize>(&"c".to_string()).await,
+            Some(3)
+        );
+
+        // the bound applies to the entries written before a restart
+        let max_entries = NonZeroUsize::new(1).unwrap();
+        FileCacheStorage::new(directory.path().to_path_buf(), max_entries)
+            .await
+            .unwrap();
+        assert_eq!(files(), 1);
+    }
+}
@@ -10,6 +10,7 @@ use self::storage::CacheStorage;
 use self::storage::KeyType;
 use self::storage::ValueType;
 
+pub(crate) mod file;
 pub(crate) mod redis;
 pub(crate) mod storage;
 
@@ -54,6 +55,18 @@ where
         .await
     }
 
+    /// Adds a tier storing entries in a local directory, checked before Redis
+    pub(crate) async fn with_file_storage(
+        mut self,
+        config: &crate::configuration::FileCache,
+    ) -> Self {
+        self.storage = self
+            .storage
+            .with_file_storage(config.path.clone(), config.max_entries)
+            .await;
+        self
+    }
+
     pub(crate) async fn get(&self, key: &K) -> Entry<K, V> {
         // waiting on a value from the cache is a potentially long(millisecond scale) task that
         // can involve a network call to an external database. To reduce the waiting time, we
@@ -185,7 +198,6 @@ where
     }
 
     /// sends the value without storing it into the cache
-    #[allow(unused)]
     pub(crate) async fn send(self, value: V) {
         if let EntryInner::First {
             sender, cache, key, ..
@@ -13,6 +13,9 @@ use fred::types::Expiration;
 use fred::types::FromRedis;
 use fred::types::ReconnectPolicy;
 use fred::types::RedisConfig;
+use fred::types::Scanner;
+use futures::stream::BoxStream;
+use futures::StreamExt;
 use url::Url;
 
 use super::KeyType;
@@ -224,10 +227,6 @@ impl RedisCacheStorage {
         }
     }
 
-    pub(crate) fn set_ttl(&mut self, ttl: Option<Duration>) {
-        self.ttl = ttl;
-    }
-
     pub(crate) async fn get<K: KeyType, V: ValueType>(
         &self,
         key: RedisKey<K>,
@@ -300,33 +299,87 @@ impl RedisCacheStorage {
         tracing::trace!("insert result {:?}", r);
     }
 
-    pub(crate) async fn insert_multiple<K: KeyType, V: ValueType>(
+    /// Inserts multiple entries, each one with its own expiration
+    pub(crate) async fn insert_multiple_with_ttl<K: KeyType, V: ValueType>(
         &self,
-        data: &[(RedisKey<K>, RedisValue<V>)],
+        data: &[(RedisKey<K>, RedisValue<V>, Duration)],
     ) {
         tracing::trace!("inserting into redis: {:#?}", data);
 
-        let r = match self.ttl.as_ref() {
-            None => self.inner.mset(data.to_owned()).await,
-            Some(ttl) => {
-                let expiration = Some(Expiration::EX(ttl.as_secs() as i64));
-                let pipeline = self.inner.pipeline();
-
-                for (key, value) in data {
-                    let _ = pipeline
-                        .set::<(), _, _>(
-                            key.clone(),
-                            value.clone(),
-                            expiration.clone(),
-                            None,
-                            false,
-                        )
-                        .await;
-                }
-
-                pipeline.last().await
+        let pipeline = self.inner.pipeline();
+        for (key, value, ttl) in data {
+            if let Err(e) = pipeline
+                .set::<(), _, _>(
+                    key.clone(),
+                    value.clone(),
+                    // sub second TTLs would round down to 0, which Redis rejects
+                    Some(Expiration::PX(ttl.as_millis().max(1) as i64)),
+                    None,
+                    false,
+                )
+                .await
+            {
+                tracing::error!("could not queue redis insert: {}", e);
             }
+        }
+
+        if let Err(e) = pipeline.last::<()>().await {
+            tracing::error!("redis insert error: {}", e);
+        }
+    }
+
+    /// Increments the request counter of the current rate limiting window, and returns it with
+    /// the counter of the previous window
+    pub(crate) async fn increment_window(
+        &self,
+        key: String,
+        previous_key: String,
+        ttl: Duration,
+    ) -> Result<(u64, u64), RedisError> {
+        tracing::trace!("incrementing rate limiting window in redis: {}", key);
+
+        let pipeline = self.inner.pipeline();
+        pipeline.incr::<(), _>(key.clone()).await?;
+        pipeline
+            .expire::<(), _>(key, ttl.as_secs().max(1) as i64)
+            .await?;
+        pipeline.get::<(), _>(previous_key).await?;
+
+        let (current, _, previous) = pipeline.all::<(u64, i64, Option<u64>)>().await?;
+        Ok((current, previous.unwrap_or_default()))
+    }
+
+    /// Takes back a request counted by `increment_window`
+    pub(crate) async fn decrement(&self, key: String) -> Result<(), RedisError> {
+        tracing::trace!("decrementing rate limiting window in redis: {}", key);
+
+        self.inner.decr::<(), _>(key).await
+    }
+
+    /// Deletes all the keys matching a glob style pattern, and returns how many were deleted
+    pub(crate) async fn delete_matching(&self, pattern: &str) -> Result<u64, RedisError> {
+        tracing::trace!("deleting from redis keys matching: {}", pattern);
+
+        let mut scan: BoxStream<'_, Result<_, RedisError>> = if self.inner.is_clustered() {
+            self.inner
+                .scan_cluster(pattern.to_string(), Some(100), None)
+                .boxed()
+        } else {
+            self.inner
+                .scan(pattern.to_string(), Some(100), None)
+                .boxed()
         };
-        tracing::trace!("insert result {:?}", r);
+
+        let mut deleted = 0;
+        while let Some(page) = scan.next().await {
+            let mut page = page?;
+            // keys are deleted one by one because they can be on different cluster slots
+            for key in page.take_results().unwrap_or_default() {
+                deleted += self.inner.del::<u64, _>(key).await?;
+            }
+            page.next()?;
+        }
+
+        Ok(deleted)
     }
 }
@@ -4,6 +4,7 @@ use std::fmt::Display;
 use std::fmt::{self};
 use std::hash::Hash;
 use std::num::NonZeroUsize;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use lru::LruCache;
@@ -12,6 +13,7 @@ use serde::Serialize;
 use tokio::sync::Mutex;
 use tokio::time::Instant;
 
+use super::file::FileCacheStorage;
 use super::redis::*;
 
 pub(crate) trait KeyType:
@@ -50,6 +52,7 @@ pub(crate) struct CacheStorage<K: KeyType, V: ValueType> {
     caller: String,
     inner: Arc<Mutex<LruCache<K, V>>>,
     redis: Option<RedisCacheStorage>,
+    file: Option<FileCacheStorage>,
 }
 
 impl<K, V> CacheStorage<K, V>
@@ -80,9 +83,29 @@ where
             } else {
                 None
             },
+            file: None,
         }
     }
 
+    /// Adds a tier storing at most `max_entries` entries as files in `directory`, which is
+    /// checked before Redis
+    pub(crate) async fn with_file_storage(
+        mut self,
+        directory: PathBuf,
+        max_entries: NonZeroUsize,
+    ) -> Self {
+        match FileCacheStorage::new(directory.clone(), max_entries).await {
+            Ok(storage) => self.file = Some(storage),
+            Err(e) => tracing::error!(
+                "could not open directory {} for {} caching: {}",
+                directory.display(),
+                self.caller,
+                e
+            ),
+        }
+        self
+    }
+
     pub(crate) async fn get(&self, key: &K) -> Option<V> {
         let mut guard = self.inner.lock().await;
         let instant_memory = Instant::now();
@@ -114,6 +137,40 @@ where
                     storage = &tracing::field::display(CacheStorageName::Memory),
                 );
 
+                if let Some(file) = self.file.as_ref() {
+                    let instant_file = Instant::now();
+                    match file.get::<K, V>(key).await {
+                        Some(v) => {
+                            guard.put(key.clone(), v.clone());
+                            tracing::info!(
+                                monotonic_counter.apollo_router_cache_hit_count = 1u64,
+                                kind = %self.caller,
+                                storage = &tracing::field::display(CacheStorageName::File),
+                            );
+                            let duration = instant_file.elapsed().as_secs_f64();
+                            tracing::info!(
+                                histogram.apollo_router_cache_hit_time = duration,
+                                kind = %self.caller,
+                                storage = &tracing::field::display(CacheStorageName::File),
+                            );
+                            return Some(v);
+                        }
+                        None => {
+                            tracing::info!(
+                                monotonic_counter.apollo_router_cache_miss_count = 1u64,
+                                kind = %self.caller,
+                                storage = &tracing::field::display(CacheStorageName::File),
+                            );
+                            let duration = instant_file.elapsed().as_secs_f64();
+                            tracing::info!(
+                                histogram.apollo_router_cache_miss_time = duration,
+                                kind = %self.caller,
+                                storage = &tracing::field::display(CacheStorageName::File),
+                            );
+                        }
+                    }
+                }
+
                 let instant_redis = Instant::now();
                 if let Some(redis) = self.redis.as_ref() {
                     let inner_key = RedisKey(key.clone());
@@ -156,6 +213,10 @@ where
     }
 
     pub(crate) async fn insert(&self, key: K, value: V) {
+        if let Some(file) = self.file.as_ref() {
+            file.insert(&key, &value).await;
+        }
+
         if let Some(redis) = self.redis.as_ref() {
             redis
                 .insert(RedisKey(key.clone()), RedisValue(value.clone()))
@@ -190,6 +251,7 @@ where
 enum CacheStorageName {
     Redis,
     Memory,
+    File,
 }
 
 impl Display for CacheStorageName {
@@ -197,6 +259,7 @@ impl Display for CacheStorageName {
         match self {
             CacheStorageName::Redis => write!(f, "redis"),
             CacheStorageName::Memory => write!(f, "memory"),
+            CacheStorageName::File => write!(f, "file"),
         }
     }
 }
@@ -18,17 +18,25 @@ use std::iter;
 use std::net::IpAddr;
 use std::net::SocketAddr;
 use std::num::NonZeroUsize;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use derivative::Derivative;
 use displaydoc::Display;
 use itertools::Itertools;
 use once_cell::sync::Lazy;
 use regex::Regex;
+use rustls::server::AllowAnyAnonymousOrAuthenticatedClient;
+use rustls::server::AllowAnyAuthenticatedClient;
+use rustls::server::ClientCertVerifier;
+use rustls::sign::SigningKey;
 use rustls::Certificate;
 use rustls::PrivateKey;
+use rustls::RootCertStore;
 use rustls::ServerConfig;
+use rustls::SignatureScheme;
 use rustls_pemfile::certs;
 use rustls_pemfile::read_one;
 use rustls_pemfile::Item;
@@ -50,6 +58,7 @@ pub(crate) use self::experimental::print_all_experimental_conf;
 pub(crate) use self::schema::generate_config_schema;
 pub(crate) use self::schema::generate_upgrade;
 use self::subgraph::SubgraphConfiguration;
+use crate::axum_factory::tls::ServerCertificateResolver;
 use crate::cache::DEFAULT_CACHE_CAPACITY;
 use crate::configuration::schema::Mode;
 use crate::plugin::plugins;
@@ -91,6 +100,9 @@ pub enum ConfigurationError {
 
     /// could not load certificate authorities: {error}
     CertificateAuthorities { error: String },
+
+    /// could not load client certificate: {error}
+    ClientCertificate { error: String },
 }
 
 /// The configuration for the router.
@@ -136,6 +148,14 @@ pub struct Configuration {
     #[serde(default)]
     pub(crate) apq: Apq,
 
+    /// Limits on the shape of incoming operations
+    #[serde(default)]
+    pub(crate) preview_operation_limits: OperationLimits,
+
+    /// Configures persisted queries loaded from an operation manifest
+    #[serde(default)]
+    pub(crate) preview_persisted_queries: PersistedQueries,
+
     /// Plugin configuration
     #[serde(default)]
     plugins: UserPlugins,
@@ -167,6 +187,8 @@ impl<'de> serde::Deserialize<'de> for Configuration {
             apollo_plugins: ApolloPlugins,
             tls: Tls,
             apq: Apq,
+            preview_operation_limits: OperationLimits,
+            preview_persisted_queries: PersistedQueries,
         }
         let ad_hoc: AdHocConfiguration = serde::Deserialize::deserialize(deserializer)?;
 
@@ -181,6 +203,8 @@ impl<'de> serde::Deserialize<'de> for Configuration {
             .apollo_plugins(ad_hoc.apollo_plugins.plugins)
             .tls(ad_hoc.tls)
             .apq(ad_hoc.apq)
+            .operation_limits(ad_hoc.preview_operation_limits)
+            .persisted_queries(ad_hoc.preview_persisted_queries)
             .build()
             .map_err(|e| serde::de::Error::custom(e.to_string()))
     }
@@ -212,6 +236,8 @@ impl Configuration {
         apollo_plugins: Map<String, Value>,
         tls: Option<Tls>,
         apq: Option<Apq>,
+        operation_limits: Option<OperationLimits>,
+        persisted_queries: Option<PersistedQueries>,
     ) -> Result<Self, ConfigurationError> {
         let conf = Self {
             validated_yaml: Default::default(),
@@ -222,6 +248,8 @@ impl Configuration {
             homepage: homepage.unwrap_or_default(),
             cors: cors.unwrap_or_default(),
             apq: apq.unwrap_or_default(),
+            preview_operation_limits: operation_limits.unwrap_or_default(),
+            preview_persisted_queries: persisted_queries.unwrap_or_default(),
             plugins: UserPlugins {
                 plugins: Some(plugins),
             },
@@ -280,6 +308,8 @@ impl Configuration {
         apollo_plugins: Map<String, Value>,
         tls: Option<Tls>,
         apq: Option<Apq>,
+        operation_limits: Option<OperationLimits>,
+        persisted_queries: Option<PersistedQueries>,
     ) -> Result<Self, ConfigurationError> {
         let configuration = Self {
             validated_yaml: Default::default(),
@@ -297,6 +327,8 @@ impl Configuration {
             },
             tls: tls.unwrap_or_default(),
             apq: apq.unwrap_or_default(),
+            preview_operation_limits: operation_limits.unwrap_or_default(),
+            preview_persisted_queries: persisted_queries.unwrap_or_default(),
         };
 
         configuration.validate()
@@ -319,6 +351,15 @@ impl Configuration {
                 error: "sandbox needs introspection to be enabled".to_string(),
             });
         }
+        if self.preview_persisted_queries.enabled
+            && self.preview_persisted_queries.manifest.is_none()
+        {
+            return Err(ConfigurationError::InvalidConfiguration {
+                message: "persisted queries require an operation manifest",
+                error: "set 'preview_persisted_queries.manifest' to the path of the manifest"
+                    .to_string(),
+            });
+        }
         if !self.supergraph.path.starts_with('/') {
             return Err(ConfigurationError::InvalidConfiguration {
             message: "invalid 'server.graphql_path' configuration",
@@ -556,6 +597,58 @@ pub(crate) struct Apq {
     pub(crate) subgraph: SubgraphConfiguration<SubgraphApq>,
 }
 
+/// Limits on the shape of incoming operations, measured with fragments expanded
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+pub(crate) struct OperationLimits {
+    /// Maximum nesting of fields, root fields having a depth of 1
+    pub(crate) max_depth: Option<u32>,
+    /// Maximum number of unique fields. A field selected several times
+    /// in the same selection set, with different aliases, is counted once
+    pub(crate) max_height: Option<u32>,
+    /// Maximum number of fields selected on the root operation type, aliases included
+    pub(crate) max_root_fields: Option<u32>,
+    /// Maximum number of aliased fields
+    pub(crate) max_aliases: Option<u32>,
+    /// If true, operations exceeding the limits are not rejected: violations are
+    /// reported as metrics and span attributes
+    pub(crate) warn_only: bool,
+}
+
+impl OperationLimits {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.max_depth.is_some()
+            || self.max_height.is_some()
+            || self.max_root_fields.is_some()
+            || self.max_aliases.is_some()
+    }
+}
+
+/// Persisted queries configuration
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+pub(crate) struct PersistedQueries {
+    /// Activates persisted queries (disabled by default)
+    pub(crate) enabled: bool,
+    /// Path to the operation manifest. The file is reloaded when it changes
+    pub(crate) manifest: Option<PathBuf>,
+    /// How operations sent as a `query` string instead of an id are handled
+    pub(crate) mode: PersistedQueriesMode,
+}
+
+/// How operations sent as a `query` string instead of an id are handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum PersistedQueriesMode {
+    /// Any operation is executed
+    #[default]
+    AllowAll,
+    /// Only operations from the manifest are executed
+    Safelist,
+    /// Any operation is executed, but operations missing from the manifest are logged
+    LogUnknown,
+}
+
 /// Subgraph level Automatic Persisted Queries (APQ) configuration
 #[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
@@ -588,12 +681,46 @@ impl Default for Apq {
 #[serde(deny_unknown_fields, default)]
 pub(crate) struct QueryPlanning {
     /// Cache configuration
-    pub(crate) experimental_cache: Cache,
+    pub(crate) experimental_cache: QueryPlanCache,
     /// Warm up the cache on reloads by running the query plan over
     /// a list of the most used queries
     /// Defaults to 0 (do not warm up the cache)
     #[serde(default)]
     pub(crate) warmed_up_queries: usize,
+    /// Path to a JSON file containing a list of GraphQL requests (`query` and `operationName`)
+    /// that are planned on startup and on reloads, before the router starts serving traffic
+    #[serde(default)]
+    pub(crate) experimental_warm_up_operations: Option<PathBuf>,
+    /// Query planner pool configuration
+    #[serde(default)]
+    pub(crate) experimental_planner_pool: PlannerPool,
+}
+
+/// Query planner pool configuration
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+pub(crate) struct PlannerPool {
+    /// Number of query planners planning queries in parallel. Each planner runs its own
+    /// JavaScript runtime with a copy of the schema. Defaults to 1
+    pub(crate) size: NonZeroUsize,
+    /// Maximum duration of the planning of a query, after which the request fails.
+    /// Unlimited by default
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    pub(crate) timeout: Option<Duration>,
+    /// Maximum number of queries waiting for a planner, after which new queries are rejected.
+    /// Unlimited by default
+    pub(crate) queue_size: Option<usize>,
+}
+
+impl Default for PlannerPool {
+    fn default() -> Self {
+        Self {
+            size: NonZeroUsize::new(1).expect("1 is not 0"),
+            timeout: None,
+            queue_size: None,
+        }
+    }
 }
 
 /// Cache configuration
@@ -606,6 +733,44 @@ pub(crate) struct Cache {
     pub(crate) redis: Option<RedisCache>,
 }
 
+/// Query plan cache configuration
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+pub(crate) struct QueryPlanCache {
+    /// Configures the in memory cache (always active)
+    pub(crate) in_memory: InMemoryCache,
+    /// Configures and activates the Redis cache
+    pub(crate) redis: Option<RedisCache>,
+    /// Configures and activates a cache stored in a local directory, which persists across restarts
+    pub(crate) experimental_file: Option<FileCache>,
+}
+
+impl QueryPlanCache {
+    /// The memory and Redis tiers of the cache
+    pub(crate) fn shared(&self) -> Cache {
+        Cache {
+            in_memory: self.in_memory.clone(),
+            redis: self.redis.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+/// Local file cache configuration
+pub(crate) struct FileCache {
+    /// Directory where cache entries are stored, created if it does not exist
+    pub(crate) path: PathBuf,
+    /// Maximum number of entries stored in the directory. When it is reached, the least recently
+    /// used entries are deleted
+    #[serde(default = "default_file_cache_max_entries")]
+    pub(crate) max_entries: NonZeroUsize,
+}
+
+fn default_file_cache_max_entries() -> NonZeroUsize {
+    NonZeroUsize::new(10_000).expect("10000 is not 0")
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 /// In memory cache configuration
@@ -648,53 +813,140 @@ pub(crate) struct Tls {
 pub(crate) struct TlsSupergraph {
     /// server certificate in PEM format
     #[serde(deserialize_with = "deserialize_certificate", skip_serializing)]
-    #[schemars(with = "String")]
-    pub(crate) certificate: Certificate,
+    #[schemars(with = "PemSource")]
+    pub(crate) certificate: LoadedPem<Certificate>,
     /// server key in PEM format
     #[serde(deserialize_with = "deserialize_key", skip_serializing)]
-    #[schemars(with = "String")]
-    pub(crate) key: PrivateKey,
+    #[schemars(with = "PemSource")]
+    pub(crate) key: LoadedPem<PrivateKey>,
     /// list of certificate authorities in PEM format
+    #[serde(
+        deserialize_with = "deserialize_loaded_certificate_chain",
+        skip_serializing
+    )]
+    #[schemars(with = "PemSource")]
+    pub(crate) certificate_chain: LoadedPem<Vec<Certificate>>,
+    /// client certificate verification
+    #[serde(default)]
+    pub(crate) client_authentication: Option<TlsClientVerification>,
+}
+
+/// Client certificate verification on the supergraph listener
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TlsClientVerification {
+    /// list of certificate authorities used to verify client certificates, in PEM format
     #[serde(deserialize_with = "deserialize_certificate_chain", skip_serializing)]
     #[schemars(with = "String")]
-    pub(crate) certificate_chain: Vec<Certificate>,
+    pub(crate) certificate_authorities: Vec<Certificate>,
+    /// whether clients must present a certificate
+    #[serde(default)]
+    pub(crate) mode: TlsClientVerificationMode,
+}
+
+/// Client certificate verification mode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum TlsClientVerificationMode {
+    /// connections without a valid client certificate are rejected
+    #[default]
+    Required,
+    /// connections without a client certificate are accepted, connections with an invalid
+    /// client certificate are rejected
+    Optional,
+}
+
+impl TlsClientVerification {
+    fn verifier(&self) -> Result<Arc<dyn ClientCertVerifier>, ApolloRouterError> {
+        let mut store = RootCertStore::empty();
+        for certificate in &self.certificate_authorities {
+            store.add(certificate).map_err(|e| {
+                ApolloRouterError::Rustls(rustls::Error::General(format!(
+                    "could not add client certificate authority: {e}"
+                )))
+            })?;
+        }
+
+        Ok(match self.mode {
+            TlsClientVerificationMode::Required => AllowAnyAuthenticatedClient::new(store),
+            TlsClientVerificationMode::Optional => {
+                AllowAnyAnonymousOrAuthenticatedClient::new(store)
+            }
+        })
+    }
+}
+
+/// PEM data parsed when loading the configuration, with its source to reload it
+#[derive(Debug, Clone)]
+pub(crate) struct LoadedPem<T> {
+    pub(crate) value: T,
+    pub(crate) source: PemSource,
 }
 
 impl TlsSupergraph {
+    /// Files the certificate and key were loaded from
+    pub(crate) fn files(&self) -> Vec<PathBuf> {
+        [
+            &self.certificate.source,
+            &self.certificate_chain.source,
+            &self.key.source,
+        ]
+        .into_iter()
+        .filter_map(PemSource::file)
+        .unique()
+        .cloned()
+        .collect()
+    }
+
+    /// Loads the certificate chain and key again from their sources
+    pub(crate) fn reload(&self) -> io::Result<(Vec<Certificate>, PrivateKey)> {
+        let mut certificates = vec![parse_certificate(&self.certificate.source.load()?)?];
+        certificates.extend(load_certs(&self.certificate_chain.source.load()?)?);
+        let key = load_keys(&self.key.source.load()?)?;
+        Ok((certificates, key))
+    }
+
     pub(crate) fn tls_config(&self) -> Result<Arc<rustls::ServerConfig>, ApolloRouterError> {
-        let mut certificates = vec![self.certificate.clone()];
-        certificates.extend(self.certificate_chain.iter().cloned());
-
-        let mut config = ServerConfig::builder()
-            .with_safe_defaults()
-            .with_no_client_auth()
-            .with_single_cert(certificates, self.key.clone())
-            .map_err(ApolloRouterError::Rustls)?;
+        let mut certificates = vec![self.certificate.value.clone()];
+        certificates.extend(self.certificate_chain.value.iter().cloned());
+        let resolver = ServerCertificateResolver::new(self, certificates, &self.key.value)?;
+
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match &self.client_authentication {
+            Some(client_authentication) => {
+                builder.with_client_cert_verifier(client_authentication.verifier()?)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let mut config = builder.with_cert_resolver(Arc::new(resolver));
         config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
 
         Ok(Arc::new(config))
     }
 }
 
-fn deserialize_certificate<'de, D>(deserializer: D) -> Result<Certificate, D::Error>
+fn deserialize_certificate<'de, D>(deserializer: D) -> Result<LoadedPem<Certificate>, D::Error>
 where
     D: Deserializer<'de>,
 {
-    let data = String::deserialize(deserializer)?;
+    let source = PemSource::deserialize(deserializer)?;
+    let data = source.load().map_err(serde::de::Error::custom)?;
 
-    load_certs(&data)
+    parse_certificate(&data)
+        .map(|value| LoadedPem { value, source })
         .map_err(serde::de::Error::custom)
-        .and_then(|mut certs| {
-            if certs.len() > 1 {
-                Err(serde::de::Error::custom(
-                    "expected exactly one server certificate",
-                ))
-            } else {
-                certs.pop().ok_or(serde::de::Error::custom(
-                    "expected exactly one server certificate",
-                ))
-            }
-        })
+}
+
+fn parse_certificate(data: &str) -> io::Result<Certificate> {
+    let mut certs = load_certs(data)?;
+    if certs.len() == 1 {
+        Ok(certs.remove(0))
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "expected exactly one server certificate",
+        ))
+    }
 }
 
 fn deserialize_certificate_chain<'de, D>(deserializer: D) -> Result<Vec<Certificate>, D::Error>
@@ -706,22 +958,39 @@ where
     load_certs(&data).map_err(serde::de::Error::custom)
 }
 
-fn deserialize_key<'de, D>(deserializer: D) -> Result<PrivateKey, D::Error>
+fn deserialize_loaded_certificate_chain<'de, D>(
+    deserializer: D,
+) -> Result<LoadedPem<Vec<Certificate>>, D::Error>
 where
     D: Deserializer<'de>,
 {
-    let data = String::deserialize(deserializer)?;
+    let source = PemSource::deserialize(deserializer)?;
+    let data = source.load().map_err(serde::de::Error::custom)?;
+
+    load_certs(&data)
+        .map(|value| LoadedPem { value, source })
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_key<'de, D>(deserializer: D) -> Result<LoadedPem<PrivateKey>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let source = PemSource::deserialize(deserializer)?;
+    let data = source.load().map_err(serde::de::Error::custom)?;
 
-    load_keys(&data).map_err(serde::de::Error::custom)
+    load_keys(&data)
+        .map(|value| LoadedPem { value, source })
+        .map_err(serde::de::Error::custom)
 }
 
-fn load_certs(data: &str) -> io::Result<Vec<Certificate>> {
+pub(crate) fn load_certs(data: &str) -> io::Result<Vec<Certificate>> {
     certs(&mut BufReader::new(data.as_bytes()))
         .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid cert"))
         .map(|mut certs| certs.drain(..).map(Certificate).collect())
 }
 
-fn load_keys(data: &str) -> io::Result<PrivateKey> {
+pub(crate) fn load_keys(data: &str) -> io::Result<PrivateKey> {
     let mut reader = BufReader::new(data.as_bytes());
     let mut key_iterator = iter::from_fn(|| read_one(&mut reader).transpose());
 
@@ -758,6 +1027,36 @@ fn load_keys(data: &str) -> io::Result<PrivateKey> {
     Ok(private_key)
 }
 
+/// Checks that a private key belongs to a certificate, by verifying a signature made with the
+/// key against the public key of the certificate
+pub(crate) fn check_key_pair(
+    certificate: &Certificate,
+    key: &dyn SigningKey,
+) -> Result<(), String> {
+    const MESSAGE: &[u8] = b"apollo router key pair check";
+
+    let signer = key
+        .choose_scheme(&[
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+            SignatureScheme::ECDSA_NISTP384_SHA384,
+            SignatureScheme::ED25519,
+            SignatureScheme::RSA_PSS_SHA256,
+        ])
+        .ok_or_else(|| "unsupported private key type".to_string())?;
+    let algorithm = match signer.scheme() {
+        SignatureScheme::ECDSA_NISTP256_SHA256 => &webpki::ECDSA_P256_SHA256,
+        SignatureScheme::ECDSA_NISTP384_SHA384 => &webpki::ECDSA_P384_SHA384,
+        SignatureScheme::ED25519 => &webpki::ED25519,
+        SignatureScheme::RSA_PSS_SHA256 => &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+        scheme => return Err(format!("unsupported signature scheme {scheme:?}")),
+    };
+    let signature = signer.sign(MESSAGE).map_err(|e| e.to_string())?;
+
+    webpki::EndEntityCert::try_from(certificate.0.as_slice())
+        .and_then(|certificate| certificate.verify_signature(algorithm, MESSAGE, &signature))
+        .map_err(|_| "the private key does not match the certificate".to_string())
+}
+
 /// Configuration options pertaining to the subgraph server component.
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
@@ -765,14 +1064,59 @@ fn load_keys(data: &str) -> io::Result<PrivateKey> {
 pub(crate) struct TlsSubgraph {
     /// list of certificate authorities in PEM format
     pub(crate) certificate_authorities: Option<String>,
+    /// client certificate authentication
+    pub(crate) client_authentication: Option<TlsClientAuth>,
 }
 
 #[buildstructor::buildstructor]
 impl TlsSubgraph {
     #[builder]
-    pub(crate) fn new(certificate_authorities: Option<String>) -> Self {
+    pub(crate) fn new(
+        certificate_authorities: Option<String>,
+        client_authentication: Option<TlsClientAuth>,
+    ) -> Self {
         Self {
             certificate_authorities,
+            client_authentication,
+        }
+    }
+}
+
+/// TLS client authentication
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TlsClientAuth {
+    /// list of certificates in PEM format, starting with the client certificate
+    pub(crate) certificate_chain: PemSource,
+    /// key in PEM format
+    pub(crate) key: PemSource,
+}
+
+/// PEM data, set inline or loaded from a file. Files are watched, and reloaded when they change
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(untagged)]
+pub(crate) enum PemSource {
+    /// PEM file
+    File {
+        /// path to the file
+        file: PathBuf,
+    },
+    /// PEM data
+    Inline(String),
+}
+
+impl PemSource {
+    pub(crate) fn load(&self) -> io::Result<String> {
+        match self {
+            PemSource::File { file } => std::fs::read_to_string(file),
+            PemSource::Inline(data) => Ok(data.clone()),
+        }
+    }
+
+    pub(crate) fn file(&self) -> Option<&PathBuf> {
+        match self {
+            PemSource::File { file } => Some(file),
+            PemSource::Inline(_) => None,
         }
     }
 }
@@ -767,6 +767,40 @@ tls:
     cfg.tls.supergraph.unwrap().tls_config().unwrap();
 }
 
+#[tokio::test]
+async fn load_tls_from_files() {
+    let mut testdata = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    testdata.push("src");
+    testdata.push("configuration");
+    testdata.push("testdata");
+    let cert_path = testdata.join("server.crt");
+    let key_path = testdata.join("server.key");
+
+    let cfg = validate_yaml_configuration(
+        &format!(
+            r#"
+tls:
+  supergraph:
+    certificate:
+      file: {}
+    certificate_chain:
+      file: {}
+    key:
+      file: {}
+"#,
+            cert_path.display(),
+            cert_path.display(),
+            key_path.display()
+        ),
+        Expansion::default().unwrap(),
+        Mode::NoUpgrade,
+    )
+    .expect("should not have resulted in an error");
+    let tls = cfg.tls.supergraph.unwrap();
+    assert_eq!(tls.files(), vec![cert_path, key_path]);
+    tls.tls_config().unwrap();
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
 struct TestSubgraphOverride {
     value: Option<u8>,
@@ -1,5 +1,6 @@
 //! Router errors.
 use std::sync::Arc;
+use std::time::Duration;
 
 use displaydoc::Display;
 use lazy_static::__Deref;
@@ -99,6 +100,21 @@ pub(crate) enum FetchError {
 
     /// could not find path: {reason}
     ExecutionPathNotFound { reason: String },
+    /// websocket connection to '{service}' failed: {reason}
+    SubrequestWsError {
+        /// The service that the websocket connection was opened to.
+        service: String,
+
+        /// The reason the connection failed.
+        reason: String,
+    },
+
+    /// circuit breaker for service '{service}' is open
+    SubrequestCircuitOpen {
+        /// The service that is considered unavailable.
+        service: String,
+    },
+
     /// could not compress request: {reason}
     CompressionError {
         /// The service that failed.
@@ -121,6 +137,8 @@ impl FetchError {
                 FetchError::SubrequestMalformedResponse { service, .. }
                 | FetchError::SubrequestUnexpectedPatchResponse { service }
                 | FetchError::SubrequestHttpError { service, .. }
+                | FetchError::SubrequestWsError { service, .. }
+                | FetchError::SubrequestCircuitOpen { service }
                 | FetchError::CompressionError { service, .. } => {
                     extensions
                         .entry("service")
@@ -167,6 +185,8 @@ impl ErrorExtension for FetchError {
                 "SUBREQUEST_UNEXPECTED_PATCH_RESPONSE"
             }
             FetchError::SubrequestHttpError { .. } => "SUBREQUEST_HTTP_ERROR",
+            FetchError::SubrequestWsError { .. } => "SUBREQUEST_WEBSOCKET_ERROR",
+            FetchError::SubrequestCircuitOpen { .. } => "SUBREQUEST_CIRCUIT_OPEN",
             FetchError::ExecutionFieldNotFound { .. } => "EXECUTION_FIELD_NOT_FOUND",
             FetchError::ExecutionPathNotFound { .. } => "EXECUTION_PATH_NOT_FOUND",
             FetchError::CompressionError { .. } => "COMPRESSION_ERROR",
@@ -268,6 +288,22 @@ pub(crate) enum QueryPlannerError {
 
     /// introspection error: {0}
     Introspection(IntrospectionError),
+
+    /// query planning took longer than {0:?}
+    Timeout(Duration),
+
+    /// too many queries are waiting to be planned
+    Overloaded,
+}
+
+impl QueryPlannerError {
+    /// Transient errors depend on the load of the router, rather than on the query
+    pub(crate) fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            QueryPlannerError::Timeout(_) | QueryPlannerError::Overloaded
+        )
+    }
 }
 
 impl IntoGraphQLErrors for QueryPlannerError {
@@ -296,6 +332,12 @@ impl IntoGraphQLErrors for QueryPlannerError {
                 .iter()
                 .map(|p_err| Error::from(p_err.clone()))
                 .collect()),
+            err @ (QueryPlannerError::Timeout(_) | QueryPlannerError::Overloaded) => {
+                Ok(vec![Error::builder()
+                    .message(err.to_string())
+                    .extension_code(err.extension_code())
+                    .build()])
+            }
             err => Err(err),
         }
     }
@@ -313,6 +355,8 @@ impl ErrorExtension for QueryPlannerError {
             QueryPlannerError::RouterBridgeError(_) => "ROUTER_BRIDGE_ERROR",
             QueryPlannerError::SpecError(_) => "SPEC_ERROR",
             QueryPlannerError::Introspection(_) => "INTROSPECTION",
+            QueryPlannerError::Timeout(_) => "QUERY_PLANNING_TIMEOUT",
+            QueryPlannerError::Overloaded => "QUERY_PLANNER_OVERLOADED",
         }
         .to_string()
     }
@@ -436,7 +480,7 @@ impl std::fmt::Display for PlanErrors {
 #[non_exhaustive]
 pub(crate) enum SchemaError {
     /// URL parse error for subgraph {0}: {1}
-    UrlParse(String, http::uri::InvalidUri),
+    UrlParse(String, crate::services::unix_socket::SubgraphUrlError),
     /// Could not find an URL for subgraph {0}
     MissingSubgraphUrl(String),
     /// Parsing error(s).
@@ -27,6 +27,8 @@ use crate::configuration::generate_config_schema;
 use crate::configuration::generate_upgrade;
 use crate::configuration::ConfigurationError;
 use crate::plugins::telemetry::reload::init_telemetry;
+use crate::query_planner::BridgeQueryPlanner;
+use crate::query_planner::QueryPlan;
 use crate::router::ConfigurationSource;
 use crate::router::RouterHttpServer;
 use crate::router::SchemaSource;
@@ -101,6 +103,27 @@ extern "C" fn drop_ad_hoc_profiler() {
 enum Commands {
     /// Configuration subcommands.
     Config(ConfigSubcommandArgs),
+
+    /// Rhai script subcommands.
+    Rhai(RhaiSubcommandArgs),
+
+    /// Print the query plan of an operation, without starting the router.
+    Plan(PlanArgs),
+}
+
+#[derive(Args, Debug)]
+struct PlanArgs {
+    /// The location of the supergraph schema.
+    #[clap(short, long, value_parser)]
+    supergraph: PathBuf,
+
+    /// The location of the GraphQL operation to plan.
+    #[clap(short, long, value_parser)]
+    query: PathBuf,
+
+    /// The name of the operation to plan, if the query contains several operations.
+    #[clap(long)]
+    operation_name: Option<String>,
 }
 
 #[derive(Args, Debug)]
@@ -129,6 +152,60 @@ enum ConfigSubcommand {
     Experimental,
 }
 
+#[derive(Args, Debug)]
+struct RhaiSubcommandArgs {
+    /// Subcommands
+    #[clap(subcommand)]
+    command: RhaiSubcommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum RhaiSubcommand {
+    /// Run the test cases of fixture files against a Rhai script.
+    Test {
+        /// The directory where Rhai scripts can be found.
+        #[clap(long, default_value = "./rhai")]
+        scripts: PathBuf,
+
+        /// The main entry point for Rhai script evaluation.
+        #[clap(long, default_value = "main.rhai")]
+        main: String,
+
+        /// YAML or JSON fixture files, or directories containing them.
+        #[clap(value_parser, required = true)]
+        fixtures: Vec<PathBuf>,
+    },
+}
+
+fn print_query_plan(plan: &QueryPlan) -> Result<()> {
+    println!("Query plan:\n");
+    println!(
+        "{}\n",
+        plan.formatted_query_plan
+            .as_deref()
+            .unwrap_or("(no text representation)")
+    );
+
+    println!("Subgraph operations:\n");
+    let mut operations = Vec::new();
+    plan.root
+        .subgraph_operations(&Default::default(), &mut operations);
+    for (index, operation) in operations.iter().enumerate() {
+        let path = operation.path.to_string();
+        println!(
+            "{}. {} at {}",
+            index + 1,
+            operation.service_name,
+            if path.is_empty() { "/" } else { &path }
+        );
+        println!("{}\n", operation.operation);
+    }
+
+    println!("JSON:\n");
+    println!("{}", serde_json::to_string_pretty(&plan.root)?);
+    Ok(())
+}
+
 /// Options for the router
 #[derive(Parser, Debug)]
 #[clap(name = "router", about = "Apollo federation router")]
@@ -387,6 +464,28 @@ impl Executable {
                 configuration::print_all_experimental_conf();
                 Ok(())
             }
+            Some(Commands::Rhai(RhaiSubcommandArgs {
+                command:
+                    RhaiSubcommand::Test {
+                        scripts,
+                        main,
+                        fixtures,
+                    },
+            })) => crate::plugins::rhai::test_runner::run(scripts, main, fixtures)
+                .await
+                .map_err(|e| anyhow!(e)),
+            Some(Commands::Plan(PlanArgs {
+                supergraph,
+                query,
+                operation_name,
+            })) => {
+                let schema = std::fs::read_to_string(supergraph)?;
+                let query = std::fs::read_to_string(query)?;
+                let plan = BridgeQueryPlanner::plan_offline(schema, query, operation_name.clone())
+                    .await
+                    .map_err(|e| anyhow!(e))?;
+                print_query_plan(&plan)
+            }
             None => Self::inner_start(shutdown, schema, config, entitlement, opt).await,
         };
 
@@ -1,6 +1,4 @@
 //! Main entry point for CLI command to start server.
-//! // add custom plguns here
-mod graph_allow_list;
 
 fn main() {
     match apollo_router::main() {
@@ -7,6 +7,8 @@ use std::sync::Arc;
 use std::task::Poll;
 
 use futures::future;
+use futures::stream;
+use futures::StreamExt;
 use http::StatusCode;
 use tower::BoxError;
 use tower::Service;
@@ -18,11 +20,13 @@ use crate::services::SubgraphRequest;
 use crate::services::SubgraphResponse;
 
 type MockResponses = HashMap<Request, Response>;
+type MockSubscriptions = HashMap<Request, Vec<Response>>;
 
 #[derive(Clone, Default)]
 pub struct MockSubgraph {
     // using an arc to improve efficiency when service is cloned
     mocks: Arc<MockResponses>,
+    subscriptions: Arc<MockSubscriptions>,
     extensions: Option<Object>,
 }
 
@@ -30,6 +34,7 @@ impl MockSubgraph {
     pub fn new(mocks: MockResponses) -> Self {
         Self {
             mocks: Arc::new(mocks),
+            subscriptions: Default::default(),
             extensions: None,
         }
     }
@@ -48,6 +53,7 @@ impl MockSubgraph {
 #[derive(Clone, Default)]
 pub struct MockSubgraphBuilder {
     mocks: MockResponses,
+    subscriptions: MockSubscriptions,
     extensions: Option<Object>,
 }
 impl MockSubgraphBuilder {
@@ -68,9 +74,26 @@ impl MockSubgraphBuilder {
         self
     }
 
+    /// adds mocked events for a subscription request
+    ///
+    /// the arguments must deserialize to `crate::graphql::Request` and a list of `crate::graphql::Response`
+    pub fn with_subscription_json(
+        mut self,
+        request: serde_json::Value,
+        events: serde_json::Value,
+    ) -> Self {
+        self.subscriptions.insert(
+            serde_json::from_value(request).unwrap(),
+            serde_json::from_value(events).unwrap(),
+        );
+
+        self
+    }
+
     pub fn build(self) -> MockSubgraph {
         MockSubgraph {
             mocks: Arc::new(self.mocks),
+            subscriptions: Arc::new(self.subscriptions),
             extensions: self.extensions,
         }
     }
@@ -88,6 +111,29 @@ impl Service<SubgraphRequest> for MockSubgraph {
     }
 
     fn call(&mut self, req: SubgraphRequest) -> Self::Future {
+        if let (Some(events), Some(subscription_stream)) = (
+            self.subscriptions.get(req.subgraph_request.body()),
+            req.subscription_stream(),
+        ) {
+            let mut subscription_stream = subscription_stream;
+            let response = match subscription_stream.try_send(stream::iter(events.clone()).boxed())
+            {
+                Ok(()) => SubgraphResponse::fake_builder()
+                    .context(req.context)
+                    .build(),
+                Err(e) => SubgraphResponse::fake_builder()
+                    .error(
+                        crate::error::Error::builder()
+                            .message(format!("cannot send the subscription stream: {e}"))
+                            .extension_code("FETCH_ERROR".to_string())
+                            .build(),
+                    )
+                    .context(req.context)
+                    .build(),
+            };
+            return future::ok(response);
+        }
+
         let response = if let Some(response) = self.mocks.get(req.subgraph_request.body()) {
             // Build an http Response
             let http_response = http::Response::builder()
@@ -3,7 +3,10 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::sync::RwLock;
+use std::time::Duration;
+use std::time::Instant;
 
 use futures::future::join_all;
 use futures::future::select;
@@ -11,38 +14,50 @@ use futures::future::Either;
 use futures::pin_mut;
 use futures::stream::repeat;
 use futures::stream::select_all;
+use futures::stream::BoxStream;
+use futures::StreamExt;
 use http::header::ACCEPT;
 use http::header::CONTENT_TYPE;
 use jsonwebtoken::jwk::JwkSet;
 use jsonwebtoken::Algorithm;
 use mime::APPLICATION_JSON;
+use rand::Rng;
 use tokio::fs::read_to_string;
 use tokio::sync::oneshot;
 use tower::BoxError;
 use url::Url;
 
+use super::ClaimRules;
 use super::CLIENT;
 use super::DEFAULT_AUTHENTICATION_NETWORK_TIMEOUT;
-use crate::plugins::authentication::DEFAULT_AUTHENTICATION_DOWNLOAD_INTERVAL;
+
+/// Minimum delay between two downloads triggered by tokens with an unknown `kid`, so that
+/// invalid tokens cannot be used to flood the JWKS servers
+const MIN_REFETCH_INTERVAL: Duration = Duration::from_secs(10);
 
 #[derive(Clone)]
 pub(super) struct JwksManager {
     list: Vec<JwksConfig>,
     jwks_map: Arc<RwLock<HashMap<Url, JwkSet>>>,
+    last_refetch: Arc<Mutex<Option<Instant>>>,
     _drop_signal: Arc<oneshot::Sender<()>>,
 }
 
 #[derive(Clone)]
 pub(super) struct JwksConfig {
     pub(super) url: Url,
-    pub(super) issuer: Option<String>,
+    pub(super) rules: ClaimRules,
     pub(super) algorithms: Option<HashSet<Algorithm>>,
+    /// Delay between two downloads of a remote JWKS. Local files are reloaded when they change
+    pub(super) poll_interval: Duration,
+    /// Maximum random delay added to `poll_interval`
+    pub(super) poll_jitter: Duration,
 }
 
 #[derive(Clone)]
 pub(super) struct JwkSetInfo {
     pub(super) jwks: JwkSet,
-    pub(super) issuer: Option<String>,
+    pub(super) rules: ClaimRules,
     pub(super) algorithms: Option<HashSet<Algorithm>>,
 }
 
@@ -68,6 +83,7 @@ impl JwksManager {
         Ok(JwksManager {
             list,
             jwks_map,
+            last_refetch: Default::default(),
             _drop_signal: Arc::new(_drop_signal),
         })
     }
@@ -79,10 +95,39 @@ impl JwksManager {
         JwksManager {
             list,
             jwks_map: Arc::new(RwLock::new(jwks)),
+            last_refetch: Default::default(),
             _drop_signal: Arc::new(_drop_signal),
         }
     }
 
+    /// Downloads the remote JWKS in the background, because a token was signed by an unknown
+    /// key that might have been added since the last download.
+    ///
+    /// Returns false if a download was already triggered recently.
+    pub(super) fn refetch(&self) -> bool {
+        {
+            let mut last_refetch = self.last_refetch.lock().expect("lock poisoned");
+            if matches!(*last_refetch, Some(last) if last.elapsed() < MIN_REFETCH_INTERVAL) {
+                return false;
+            }
+            *last_refetch = Some(Instant::now());
+        }
+
+        let remote: Vec<Url> = self
+            .list
+            .iter()
+            .filter(|config| config.url.scheme() != "file")
+            .map(|config| config.url.clone())
+            .collect();
+        let jwks_map = self.jwks_map.clone();
+        tokio::task::spawn(async move {
+            for url in remote {
+                update(&jwks_map, url).await;
+            }
+        });
+        true
+    }
+
     pub(super) fn iter_jwks(&self) -> Iter {
         Iter {
             list: self.list.clone(),
@@ -96,22 +141,10 @@ async fn poll(
     jwks_map: Arc<RwLock<HashMap<Url, JwkSet>>>,
     drop_receiver: oneshot::Receiver<()>,
 ) {
-    use futures::stream::StreamExt;
-
-    let mut streams = select_all(list.into_iter().map(move |config| {
-        let jwks_map = jwks_map.clone();
-        Box::pin(
-            repeat((config, jwks_map)).then(|(config, jwks_map)| async move {
-                tokio::time::sleep(DEFAULT_AUTHENTICATION_DOWNLOAD_INTERVAL).await;
-
-                if let Some(jwks) = get_jwks(config.url.clone()).await {
-                    if let Ok(mut map) = jwks_map.write() {
-                        map.insert(config.url, jwks);
-                    }
-                }
-            }),
-        )
-    }));
+    let mut streams = select_all(
+        list.into_iter()
+            .map(move |config| updates(config, jwks_map.clone())),
+    );
 
     pin_mut!(drop_receiver);
 
@@ -131,6 +164,59 @@ async fn poll(
     }
 }
 
+/// Reloads local files when they change, and downloads remote JWKS periodically
+fn updates(
+    config: JwksConfig,
+    jwks_map: Arc<RwLock<HashMap<Url, JwkSet>>>,
+) -> BoxStream<'static, ()> {
+    if config.url.scheme() == "file" {
+        return match config.url.to_file_path() {
+            Ok(path) => crate::files::watch(&path)
+                .then(move |_| {
+                    let jwks_map = jwks_map.clone();
+                    let url = config.url.clone();
+                    async move { update(&jwks_map, url).await }
+                })
+                .boxed(),
+            Err(_) => futures::stream::empty().boxed(),
+        };
+    }
+
+    repeat((config, jwks_map))
+        .then(|(config, jwks_map)| async move {
+            let jitter = rand::thread_rng().gen_range(Duration::ZERO..=config.poll_jitter);
+            tokio::time::sleep(config.poll_interval + jitter).await;
+
+            update(&jwks_map, config.url).await;
+        })
+        .boxed()
+}
+
+/// Replaces the JWKS if it could be loaded. Otherwise the previous version keeps being used,
+/// so a JWKS server outage does not reject every request
+async fn update(jwks_map: &RwLock<HashMap<Url, JwkSet>>, url: Url) {
+    match get_jwks(url.clone()).await {
+        Some(jwks) => {
+            if let Ok(mut map) = jwks_map.write() {
+                map.insert(url, jwks);
+            }
+        }
+        None => {
+            tracing::info!(
+                monotonic_counter.apollo_authentication_jwks_update_failure_count = 1u64,
+                url = %url,
+            );
+            if jwks_map
+                .read()
+                .map(|map| map.contains_key(&url))
+                .unwrap_or(false)
+            {
+                tracing::warn!(%url, "could not update the JWKS, using the previous version");
+            }
+        }
+    }
+}
+
 // This function is expected to return an Optional value, but we'd like to let
 // users know the various failure conditions. Hence the various clumsy map_err()
 // scattered through the processing.
@@ -206,7 +292,7 @@ impl<'a> Iterator for Iter<'a> {
                         if let Some(jwks) = map.get(&config.url) {
                             return Some(JwkSetInfo {
                                 jwks: jwks.clone(),
-                                issuer: config.issuer.clone(),
+                                rules: config.rules.clone(),
                                 algorithms: config.algorithms.clone(),
                             });
                         }
@@ -1,15 +1,20 @@
 //! Authentication plugin
 // With regards to ELv2 licensing, this entire file is license key functionality
 
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::ops::ControlFlow;
 use std::str::FromStr;
 use std::time::Duration;
 
 use displaydoc::Display;
+use http::header::COOKIE;
 use http::StatusCode;
 use jsonwebtoken::decode;
 use jsonwebtoken::decode_header;
 use jsonwebtoken::errors::Error as JWTError;
+use jsonwebtoken::errors::ErrorKind as JWTErrorKind;
 use jsonwebtoken::jwk::AlgorithmParameters;
 use jsonwebtoken::jwk::EllipticCurve;
 use jsonwebtoken::jwk::Jwk;
@@ -26,10 +31,12 @@ use thiserror::Error;
 use tower::BoxError;
 use tower::ServiceBuilder;
 use tower::ServiceExt;
+use url::form_urlencoded;
 use url::Url;
 
 use self::jwks::JwksManager;
 use crate::graphql;
+use crate::graphql::ErrorExtension;
 use crate::layers::ServiceBuilderExt;
 use crate::plugin::Plugin;
 use crate::plugin::PluginInit;
@@ -84,10 +91,51 @@ enum AuthenticationError<'a> {
 
     /// Invalid issuer: the token's `iss` was '{token}', but signed with a key from '{expected}'
     InvalidIssuer { expected: String, token: String },
+
+    /// Invalid audience: the token's `aud` was '{0}'
+    InvalidAudience(serde_json::Value),
+
+    /// Missing required claim: '{0}'
+    MissingClaim(String),
+
+    /// Invalid value for claim '{claim}': expected '{expected}'
+    InvalidClaimValue {
+        claim: String,
+        expected: serde_json::Value,
+    },
+}
+
+impl ErrorExtension for AuthenticationError<'_> {
+    fn extension_code(&self) -> String {
+        match self {
+            AuthenticationError::CannotConvertToString => "AUTH_INVALID_CREDENTIALS",
+            AuthenticationError::InvalidPrefix(..) => "AUTH_INVALID_PREFIX",
+            AuthenticationError::MissingJWT(_) => "AUTH_MISSING_JWT",
+            AuthenticationError::InvalidHeader(..) => "AUTH_INVALID_JWT_HEADER",
+            AuthenticationError::CannotRetrieveJWKS(_) => "AUTH_JWKS_UNAVAILABLE",
+            AuthenticationError::CannotCreateDecodingKey(_)
+            | AuthenticationError::JWKHasNoAlgorithm => "AUTH_INVALID_JWK",
+            AuthenticationError::CannotDecodeJWT(e) => match e.kind() {
+                JWTErrorKind::ExpiredSignature => "AUTH_TOKEN_EXPIRED",
+                JWTErrorKind::ImmatureSignature => "AUTH_TOKEN_NOT_YET_VALID",
+                JWTErrorKind::InvalidSignature => "AUTH_INVALID_SIGNATURE",
+                _ => "AUTH_INVALID_TOKEN",
+            },
+            AuthenticationError::CannotInsertClaimsIntoContext(_) => "AUTH_INTERNAL_ERROR",
+            AuthenticationError::CannotFindKID(_) => "AUTH_UNKNOWN_KID",
+            AuthenticationError::CannotFindSuitableKey(..) => "AUTH_NO_SUITABLE_KEY",
+            AuthenticationError::InvalidIssuer { .. } => "AUTH_INVALID_ISSUER",
+            AuthenticationError::InvalidAudience(_) => "AUTH_INVALID_AUDIENCE",
+            AuthenticationError::MissingClaim(_) => "AUTH_MISSING_CLAIM",
+            AuthenticationError::InvalidClaimValue { .. } => "AUTH_INVALID_CLAIM_VALUE",
+        }
+        .to_string()
+    }
 }
 
 const DEFAULT_AUTHENTICATION_NETWORK_TIMEOUT: Duration = Duration::from_secs(15);
 const DEFAULT_AUTHENTICATION_DOWNLOAD_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_AUTHENTICATION_LEEWAY: Duration = Duration::from_secs(60);
 
 static CLIENT: Lazy<Result<Client, BoxError>> = Lazy::new(|| Ok(Client::new()));
 
@@ -112,6 +160,30 @@ struct JWTConf {
     /// Header value prefix
     #[serde(default = "default_header_value_prefix")]
     header_value_prefix: String,
+    /// Other locations of the JWT, searched in order when the header is absent
+    #[serde(default)]
+    sources: Vec<Source>,
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    /// Accepted clock skew when checking the `exp` and `nbf` claims. The default value is 60
+    /// seconds
+    leeway: Option<Duration>,
+}
+
+/// Location of the JWT in the request
+#[derive(Clone, Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "snake_case", tag = "type")]
+enum Source {
+    /// Read the JWT from a cookie
+    Cookie {
+        /// Name of the cookie
+        name: String,
+    },
+    /// Read the JWT from a query parameter of the URL
+    QueryParameter {
+        /// Name of the query parameter
+        name: String,
+    },
 }
 
 #[derive(Clone, Debug, Deserialize, JsonSchema)]
@@ -120,10 +192,30 @@ struct JwksConf {
     url: String,
     /// Expected issuer for tokens verified by that JWKS
     issuer: Option<String>,
+    /// Accepted audiences for tokens verified by that JWKS: the token's `aud` claim must
+    /// contain one of them
+    audience: Option<Vec<String>>,
+    /// Claims that must be present in tokens verified by that JWKS
+    #[serde(default)]
+    required_claims: Vec<String>,
+    /// Expected claim values for tokens verified by that JWKS. If the claim is a list, it must
+    /// contain the value
+    #[serde(default)]
+    claim_values: HashMap<String, serde_json::Value>,
     /// List of accepted algorithms. Possible values are `HS256`, `HS384`, `HS512`, `ES256`, `ES384`, `RS256`, `RS384`, `RS512`, `PS256`, `PS384`, `PS512`, `EdDSA`
     #[schemars(with = "Option<Vec<String>>", default)]
     #[serde(default)]
     algorithms: Option<Vec<Algorithm>>,
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    /// Delay between two downloads of a remote JWKS. The default value is 60 seconds. JWKS
+    /// read from a `file://` URL are reloaded when the file changes instead
+    poll_interval: Option<Duration>,
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    /// Maximum random delay added to `poll_interval`, to spread the downloads of several
+    /// routers. There is no jitter by default
+    poll_jitter: Option<Duration>,
 }
 
 impl Default for JWTConf {
@@ -132,10 +224,67 @@ impl Default for JWTConf {
             jwks: Default::default(),
             header_name: default_header_name(),
             header_value_prefix: default_header_value_prefix(),
+            sources: Default::default(),
+            leeway: None,
         }
     }
 }
 
+/// Checks of the claims of tokens verified by a JWKS
+#[derive(Clone, Debug, Default)]
+struct ClaimRules {
+    issuer: Option<String>,
+    audience: Option<HashSet<String>>,
+    required_claims: Vec<String>,
+    claim_values: HashMap<String, serde_json::Value>,
+}
+
+impl ClaimRules {
+    /// Checks the audience, required claims and claim values. The issuer is checked separately
+    fn check(&self, claims: &serde_json::Value) -> Result<(), AuthenticationError<'static>> {
+        if let Some(audience) = &self.audience {
+            let accepted = match claims.get("aud") {
+                Some(serde_json::Value::String(aud)) => audience.contains(aud),
+                Some(serde_json::Value::Array(auds)) => auds
+                    .iter()
+                    .filter_map(|aud| aud.as_str())
+                    .any(|aud| audience.contains(aud)),
+                Some(_) => false,
+                None => return Err(AuthenticationError::MissingClaim("aud".to_string())),
+            };
+            if !accepted {
+                return Err(AuthenticationError::InvalidAudience(
+                    claims.get("aud").cloned().unwrap_or_default(),
+                ));
+            }
+        }
+
+        for claim in &self.required_claims {
+            if claims.get(claim).is_none() {
+                return Err(AuthenticationError::MissingClaim(claim.clone()));
+            }
+        }
+
+        for (claim, expected) in &self.claim_values {
+            let matches = match claims.get(claim) {
+                Some(serde_json::Value::Array(values)) if !expected.is_array() => {
+                    values.contains(expected)
+                }
+                Some(value) => value == expected,
+                None => return Err(AuthenticationError::MissingClaim(claim.clone())),
+            };
+            if !matches {
+                return Err(AuthenticationError::InvalidClaimValue {
+                    claim: claim.clone(),
+                    expected: expected.clone(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
 // We may support additional authentication mechanisms in future, so all
 // configuration (which is currently JWT specific) is isolated to the
 // JWTConf structure.
@@ -168,12 +317,12 @@ struct JWTCriteria {
 fn search_jwks(
     jwks_manager: &JwksManager,
     criteria: &JWTCriteria,
-) -> Result<Option<(Option<String>, Jwk)>, BoxError> {
+) -> Result<Option<(ClaimRules, Jwk)>, BoxError> {
     const HIGHEST_SCORE: usize = 2;
     let mut candidates = vec![];
     for JwkSetInfo {
         jwks,
-        issuer,
+        rules,
         algorithms,
     } in jwks_manager.iter_jwks()
     {
@@ -272,10 +421,10 @@ fn search_jwks(
 
             // If we find a key with a HIGHEST_SCORE, let's stop looking.
             if key_score == HIGHEST_SCORE {
-                return Ok(Some((issuer, key)));
+                return Ok(Some((rules, key)));
             }
 
-            candidates.push((key_score, (issuer.clone(), key)));
+            candidates.push((key_score, (rules.clone(), key)));
         }
     }
 
@@ -322,11 +471,23 @@ impl Plugin for AuthenticationPlugin {
             let url: Url = Url::from_str(jwks_conf.url.as_str())?;
             list.push(JwksConfig {
                 url,
-                issuer: jwks_conf.issuer.clone(),
+                rules: ClaimRules {
+                    issuer: jwks_conf.issuer.clone(),
+                    audience: jwks_conf
+                        .audience
+                        .as_ref()
+                        .map(|audience| audience.iter().cloned().collect()),
+                    required_claims: jwks_conf.required_claims.clone(),
+                    claim_values: jwks_conf.claim_values.clone(),
+                },
                 algorithms: jwks_conf
                     .algorithms
                     .as_ref()
                     .map(|algs| algs.iter().cloned().collect()),
+                poll_interval: jwks_conf
+                    .poll_interval
+                    .unwrap_or(DEFAULT_AUTHENTICATION_DOWNLOAD_INTERVAL),
+                poll_jitter: jwks_conf.poll_jitter.unwrap_or_default(),
             });
         }
 
@@ -395,6 +556,73 @@ impl Plugin for AuthenticationPlugin {
     }
 }
 
+/// Reads the JWT from the configured header or, if the header is absent, from the other sources
+fn extract_jwt<'a, B>(
+    config: &'a JWTConf,
+    request: &'a http::Request<B>,
+) -> Option<Result<Cow<'a, str>, AuthenticationError<'a>>> {
+    if let Some(value) = request.headers().get(&config.header_name) {
+        return Some(jwt_from_header(config, value).map(Cow::Borrowed));
+    }
+
+    config.sources.iter().find_map(|source| match source {
+        Source::Cookie { name } => request.headers().get_all(COOKIE).iter().find_map(|value| {
+            let cookies = match value.to_str() {
+                Ok(cookies) => cookies,
+                Err(_not_a_string_error) => {
+                    return Some(Err(AuthenticationError::CannotConvertToString));
+                }
+            };
+            cookies
+                .split(';')
+                .filter_map(|cookie| cookie.trim().split_once('='))
+                .find(|(cookie_name, _)| cookie_name == name)
+                .map(|(_, jwt)| Ok(Cow::Borrowed(jwt.trim_matches('"'))))
+        }),
+        Source::QueryParameter { name } => request.uri().query().and_then(|query| {
+            form_urlencoded::parse(query.as_bytes())
+                .find(|(parameter, _)| parameter == name)
+                .map(|(_, jwt)| Ok(jwt))
+        }),
+    })
+}
+
+fn jwt_from_header<'a>(
+    config: &'a JWTConf,
+    value: &'a http::HeaderValue,
+) -> Result<&'a str, AuthenticationError<'a>> {
+    // If we find the header, but can't convert it to a string, let the client know
+    let jwt_value_untrimmed = value
+        .to_str()
+        .map_err(|_not_a_string_error| AuthenticationError::CannotConvertToString)?;
+
+    // Let's trim out leading and trailing whitespace to be accommodating
+    let jwt_value = jwt_value_untrimmed.trim();
+
+    // Make sure the format of our message matches our expectations
+    // Technically, the spec is case sensitive, but let's accept
+    // case variations
+    //
+    let prefix_len = config.header_value_prefix.len();
+    if jwt_value.len() < prefix_len
+        || !&jwt_value[..prefix_len].eq_ignore_ascii_case(&config.header_value_prefix)
+    {
+        return Err(AuthenticationError::InvalidPrefix(
+            jwt_value_untrimmed,
+            &config.header_value_prefix,
+        ));
+    }
+
+    // Split our string in (at most 2) sections.
+    let jwt_parts: Vec<&str> = jwt_value.splitn(2, ' ').collect();
+    if jwt_parts.len() != 2 {
+        return Err(AuthenticationError::MissingJWT(jwt_value));
+    }
+
+    // We have our jwt
+    Ok(jwt_parts[1])
+}
+
 fn authenticate(
     config: &JWTConf,
     jwks_manager: &JwksManager,
@@ -419,7 +647,7 @@ fn authenticate(
             .error(
                 graphql::Error::builder()
                     .message(error.to_string())
-                    .extension_code("AUTH_ERROR")
+                    .extension_code(error.extension_code())
                     .build(),
             )
             .status_code(status)
@@ -428,57 +656,16 @@ fn authenticate(
         Ok(ControlFlow::Break(response))
     }
 
-    // The http_request is stored in a `Router::Request` context.
-    // We are going to check the headers for the presence of the configured header
-    let jwt_value_result = match request.router_request.headers().get(&config.header_name) {
-        Some(value) => value.to_str(),
+    let jwt = match extract_jwt(config, &request.router_request) {
+        Some(Ok(jwt)) => jwt.into_owned(),
+        Some(Err(error)) => {
+            return failure_message(request.context, error, StatusCode::BAD_REQUEST);
+        }
         None => {
             return Ok(ControlFlow::Continue(request));
         }
     };
-
-    // If we find the header, but can't convert it to a string, let the client know
-    let jwt_value_untrimmed = match jwt_value_result {
-        Ok(value) => value,
-        Err(_not_a_string_error) => {
-            return failure_message(
-                request.context,
-                AuthenticationError::CannotConvertToString,
-                StatusCode::BAD_REQUEST,
-            );
-        }
-    };
-
-    // Let's trim out leading and trailing whitespace to be accommodating
-    let jwt_value = jwt_value_untrimmed.trim();
-
-    // Make sure the format of our message matches our expectations
-    // Technically, the spec is case sensitive, but let's accept
-    // case variations
-    //
-    let prefix_len = config.header_value_prefix.len();
-    if jwt_value.len() < prefix_len
-        || !&jwt_value[..prefix_len].eq_ignore_ascii_case(&config.header_value_prefix)
-    {
-        return failure_message(
-            request.context,
-            AuthenticationError::InvalidPrefix(jwt_value_untrimmed, &config.header_value_prefix),
-            StatusCode::BAD_REQUEST,
-        );
-    }
-
-    // Split our string in (at most 2) sections.
-    let jwt_parts: Vec<&str> = jwt_value.splitn(2, ' ').collect();
-    if jwt_parts.len() != 2 {
-        return failure_message(
-            request.context,
-            AuthenticationError::MissingJWT(jwt_value),
-            StatusCode::BAD_REQUEST,
-        );
-    }
-
-    // We have our jwt
-    let jwt = jwt_parts[1];
+    let jwt = jwt.as_str();
 
     // Try to create a valid header to work with
     let jwt_header = match decode_header(jwt) {
@@ -515,7 +702,7 @@ fn authenticate(
         }
     };
 
-    if let Some((issuer, jwk)) = jwk_opt {
+    if let Some((rules, jwk)) = jwk_opt {
         let decoding_key = match DecodingKey::from_jwk(&jwk) {
             Ok(k) => k,
             Err(e) => {
@@ -540,6 +727,10 @@ fn authenticate(
 
         let mut validation = Validation::new(algorithm);
         validation.validate_nbf = true;
+        validation.leeway = config
+            .leeway
+            .unwrap_or(DEFAULT_AUTHENTICATION_LEEWAY)
+            .as_secs();
 
         let token_data = match decode::<serde_json::Value>(jwt, &decoding_key, &validation) {
             Ok(v) => v,
@@ -552,7 +743,7 @@ fn authenticate(
             }
         };
 
-        if let Some(configured_issuer) = issuer {
+        if let Some(configured_issuer) = rules.issuer.clone() {
             if let Some(token_issuer) = token_data
                 .claims
                 .as_object()
@@ -572,6 +763,10 @@ fn authenticate(
             }
         }
 
+        if let Err(e) = rules.check(&token_data.claims) {
+            return failure_message(request.context, e, StatusCode::UNAUTHORIZED);
+        }
+
         if let Err(e) = request
             .context
             .insert(APOLLO_AUTHENTICATION_JWT_CLAIMS, token_data.claims)
@@ -592,6 +787,10 @@ fn authenticate(
 
     // We can't find a key to process this JWT.
     if criteria.kid.is_some() {
+        // the key might have been added to the JWKS since the last download
+        if jwks_manager.refetch() {
+            tracing::debug!(kid = ?criteria.kid, "unknown kid, downloading the JWKS again");
+        }
         failure_message(
             request.context,
             AuthenticationError::CannotFindKID(criteria.kid),
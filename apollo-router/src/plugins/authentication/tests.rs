@@ -208,7 +208,7 @@ async fn it_rejects_when_auth_prefix_is_missing() {
 
     let expected_error = graphql::Error::builder()
         .message("Header Value: 'invalid' is not correctly formatted. prefix should be 'Bearer'")
-        .extension_code("AUTH_ERROR")
+        .extension_code("AUTH_INVALID_PREFIX")
         .build();
 
     assert_eq!(response.errors, vec![expected_error]);
@@ -245,7 +245,7 @@ async fn it_rejects_when_auth_prefix_has_no_jwt() {
 
     let expected_error = graphql::Error::builder()
         .message("Header Value: 'Bearer' is not correctly formatted. Missing JWT")
-        .extension_code("AUTH_ERROR")
+        .extension_code("AUTH_MISSING_JWT")
         .build();
 
     assert_eq!(response.errors, vec![expected_error]);
@@ -284,7 +284,7 @@ async fn it_rejects_when_auth_prefix_has_invalid_format_jwt() {
         .message(format!(
             "'{HEADER_TOKEN_TRUNCATED}' is not a valid JWT header: InvalidToken"
         ))
-        .extension_code("AUTH_ERROR")
+        .extension_code("AUTH_INVALID_JWT_HEADER")
         .build();
 
     assert_eq!(response.errors, vec![expected_error]);
@@ -324,7 +324,7 @@ async fn it_rejects_when_auth_prefix_has_correct_format_but_invalid_jwt() {
 
     let expected_error = graphql::Error::builder()
             .message(format!("'{HEADER_TOKEN_TRUNCATED}' is not a valid JWT header: Base64 error: Invalid last symbol 114, offset 5."))
-            .extension_code("AUTH_ERROR")
+            .extension_code("AUTH_INVALID_JWT_HEADER")
             .build();
 
     assert_eq!(response.errors, vec![expected_error]);
@@ -364,7 +364,7 @@ async fn it_rejects_when_auth_prefix_has_correct_format_and_invalid_jwt() {
 
     let expected_error = graphql::Error::builder()
         .message("Cannot decode JWT: InvalidSignature")
-        .extension_code("AUTH_ERROR")
+        .extension_code("AUTH_INVALID_SIGNATURE")
         .build();
 
     assert_eq!(response.errors, vec![expected_error]);
@@ -552,8 +552,10 @@ async fn build_jwks_search_components() -> JwksManager {
         let url: Url = Url::from_str(s_url).expect("created a valid url");
         urls.push(JwksConfig {
             url,
-            issuer: None,
+            rules: Default::default(),
             algorithms: None,
+            poll_interval: DEFAULT_AUTHENTICATION_DOWNLOAD_INTERVAL,
+            poll_jitter: Duration::ZERO,
         });
     }
 
@@ -569,7 +571,7 @@ async fn it_finds_key_with_criteria_kid_and_algorithm() {
         alg: Algorithm::HS256,
     };
 
-    let (_issuer, key) = search_jwks(&jwks_manager, &criteria)
+    let (_rules, key) = search_jwks(&jwks_manager, &criteria)
         .expect("search worked")
         .expect("found a key");
     assert_eq!(Algorithm::HS256, key.common.algorithm.unwrap());
@@ -585,7 +587,7 @@ async fn it_finds_best_matching_key_with_criteria_algorithm() {
         alg: Algorithm::HS256,
     };
 
-    let (_issuer, key) = search_jwks(&jwks_manager, &criteria)
+    let (_rules, key) = search_jwks(&jwks_manager, &criteria)
         .expect("search worked")
         .expect("found a key");
     assert_eq!(Algorithm::HS256, key.common.algorithm.unwrap());
@@ -615,7 +617,7 @@ async fn it_finds_key_with_criteria_algorithm_ec() {
         alg: Algorithm::ES256,
     };
 
-    let (_issuer, key) = search_jwks(&jwks_manager, &criteria)
+    let (_rules, key) = search_jwks(&jwks_manager, &criteria)
         .expect("search worked")
         .expect("found a key");
     assert_eq!(Algorithm::ES256, key.common.algorithm.unwrap());
@@ -634,7 +636,7 @@ async fn it_finds_key_with_criteria_algorithm_rsa() {
         alg: Algorithm::RS256,
     };
 
-    let (_issuer, key) = search_jwks(&jwks_manager, &criteria)
+    let (_rules, key) = search_jwks(&jwks_manager, &criteria)
         .expect("search worked")
         .expect("found a key");
     assert_eq!(Algorithm::RS256, key.common.algorithm.unwrap());
@@ -652,6 +654,16 @@ struct Claims {
 }
 
 fn make_manager(jwk: &Jwk, issuer: Option<String>) -> JwksManager {
+    make_manager_with_rules(
+        jwk,
+        ClaimRules {
+            issuer,
+            ..Default::default()
+        },
+    )
+}
+
+fn make_manager_with_rules(jwk: &Jwk, rules: ClaimRules) -> JwksManager {
     let jwks = JwkSet {
         keys: vec![jwk.clone()],
     };
@@ -659,16 +671,17 @@ fn make_manager(jwk: &Jwk, issuer: Option<String>) -> JwksManager {
     let url = Url::from_str("file:///jwks.json").unwrap();
     let list = vec![JwksConfig {
         url: url.clone(),
-        issuer,
+        rules,
         algorithms: None,
+        poll_interval: DEFAULT_AUTHENTICATION_DOWNLOAD_INTERVAL,
+        poll_jitter: Duration::ZERO,
     }];
     let map = HashMap::from([(url, jwks); 1]);
 
     JwksManager::new_test(list, map)
 }
 
-#[tokio::test]
-async fn issuer_check() {
+fn make_es256_key() -> (Jwk, EncodingKey) {
     let signing_key = SigningKey::random(&mut OsRng);
     let verifying_key = signing_key.verifying_key();
     let point = verifying_key.to_encoded_point(false);
@@ -695,6 +708,12 @@ async fn issuer_check() {
         }),
     };
 
+    (jwk, encoding_key)
+}
+
+#[tokio::test]
+async fn issuer_check() {
+    let (jwk, encoding_key) = make_es256_key();
     let manager = make_manager(&jwk, Some("hello".to_string()));
 
     // No issuer
@@ -757,7 +776,7 @@ async fn issuer_check() {
             )
             .unwrap();
             assert_eq!(response, graphql::Response::builder()
-        .errors(vec![graphql::Error::builder().extension_code("AUTH_ERROR").message("Invalid issuer: the token's `iss` was 'hallo', but signed with a key from 'hello'").build()]).build());
+        .errors(vec![graphql::Error::builder().extension_code("AUTH_INVALID_ISSUER").message("Invalid issuer: the token's `iss` was 'hallo', but signed with a key from 'hello'").build()]).build());
         }
         ControlFlow::Continue(req) => {
             println!("got req with issuer check");
@@ -797,7 +816,7 @@ async fn issuer_check() {
             )
             .unwrap();
             assert_eq!(response, graphql::Response::builder()
-            .errors(vec![graphql::Error::builder().extension_code("AUTH_ERROR").message("Invalid issuer: the token's `iss` was 'AAAA', but signed with a key from 'hello'").build()]).build());
+            .errors(vec![graphql::Error::builder().extension_code("AUTH_INVALID_ISSUER").message("Invalid issuer: the token's `iss` was 'AAAA', but signed with a key from 'hello'").build()]).build());
         }
         ControlFlow::Continue(_) => {
             panic!("issuer check should have failed")
@@ -832,7 +851,7 @@ async fn issuer_check() {
             )
             .unwrap();
             assert_eq!(response, graphql::Response::builder()
-        .errors(vec![graphql::Error::builder().extension_code("AUTH_ERROR").message("Invalid issuer: the token's `iss` was 'AAAA', but signed with a key from 'hello'").build()]).build());
+        .errors(vec![graphql::Error::builder().extension_code("AUTH_INVALID_ISSUER").message("Invalid issuer: the token's `iss` was 'AAAA', but signed with a key from 'hello'").build()]).build());
         }
         ControlFlow::Continue(req) => {
             println!("got req with issuer check");
@@ -859,8 +878,10 @@ async fn it_rejects_key_with_restricted_algorithm() {
         let url: Url = Url::from_str(s_url).expect("created a valid url");
         urls.push(JwksConfig {
             url,
-            issuer: None,
+            rules: Default::default(),
             algorithms: Some(HashSet::from([Algorithm::RS256])),
+            poll_interval: DEFAULT_AUTHENTICATION_DOWNLOAD_INTERVAL,
+            poll_jitter: Duration::ZERO,
         });
     }
 
@@ -876,3 +897,231 @@ async fn it_rejects_key_with_restricted_algorithm() {
         .expect("search worked")
         .is_none());
 }
+
+async fn error_code(response: ControlFlow<router::Response, router::Request>) -> Option<String> {
+    match response {
+        ControlFlow::Break(res) => {
+            let response: graphql::Response = serde_json::from_slice(
+                &hyper::body::to_bytes(res.response.into_body())
+                    .await
+                    .unwrap(),
+            )
+            .unwrap();
+            response.errors[0]
+                .extensions
+                .get("code")
+                .and_then(|code| code.as_str())
+                .map(str::to_string)
+        }
+        ControlFlow::Continue(_) => None,
+    }
+}
+
+fn bearer_request(token: &str) -> router::Request {
+    supergraph::Request::canned_builder()
+        .operation_name("me".to_string())
+        .header(http::header::AUTHORIZATION, format!("Bearer {token}"))
+        .build()
+        .unwrap()
+        .try_into()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn it_checks_claims() {
+    let (jwk, encoding_key) = make_es256_key();
+    let manager = make_manager_with_rules(
+        &jwk,
+        ClaimRules {
+            audience: Some(HashSet::from(["router".to_string()])),
+            required_claims: vec!["sub".to_string()],
+            claim_values: HashMap::from([("roles".to_string(), serde_json::json!("admin"))]),
+            ..Default::default()
+        },
+    );
+    let token = |claims: Value| {
+        encode(
+            &jsonwebtoken::Header::new(Algorithm::ES256),
+            &claims,
+            &encoding_key,
+        )
+        .unwrap()
+    };
+    let exp = get_current_timestamp() + 60;
+
+    let valid = token(serde_json::json!({
+        "sub": "test", "exp": exp, "aud": ["router", "other"], "roles": ["user", "admin"]
+    }));
+    let response = authenticate(&JWTConf::default(), &manager, bearer_request(&valid)).unwrap();
+    assert_eq!(error_code(response).await, None);
+
+    let wrong_audience = token(serde_json::json!({
+        "sub": "test", "exp": exp, "aud": "other", "roles": "admin"
+    }));
+    let response = authenticate(
+        &JWTConf::default(),
+        &manager,
+        bearer_request(&wrong_audience),
+    )
+    .unwrap();
+    assert_eq!(
+        error_code(response).await.as_deref(),
+        Some("AUTH_INVALID_AUDIENCE")
+    );
+
+    let missing_claim = token(serde_json::json!({
+        "exp": exp, "aud": "router", "roles": "admin"
+    }));
+    let response = authenticate(
+        &JWTConf::default(),
+        &manager,
+        bearer_request(&missing_claim),
+    )
+    .unwrap();
+    assert_eq!(
+        error_code(response).await.as_deref(),
+        Some("AUTH_MISSING_CLAIM")
+    );
+
+    let wrong_value = token(serde_json::json!({
+        "sub": "test", "exp": exp, "aud": "router", "roles": ["user"]
+    }));
+    let response =
+        authenticate(&JWTConf::default(), &manager, bearer_request(&wrong_value)).unwrap();
+    assert_eq!(
+        error_code(response).await.as_deref(),
+        Some("AUTH_INVALID_CLAIM_VALUE")
+    );
+}
+
+#[tokio::test]
+async fn it_accepts_clock_skew_within_leeway() {
+    let (jwk, encoding_key) = make_es256_key();
+    let manager = make_manager(&jwk, None);
+    let expired = encode(
+        &jsonwebtoken::Header::new(Algorithm::ES256),
+        &Claims {
+            sub: "test".to_string(),
+            exp: get_current_timestamp() - 30,
+            iss: None,
+        },
+        &encoding_key,
+    )
+    .unwrap();
+
+    let response = authenticate(&JWTConf::default(), &manager, bearer_request(&expired)).unwrap();
+    assert_eq!(error_code(response).await, None);
+
+    let config = JWTConf {
+        leeway: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let response = authenticate(&config, &manager, bearer_request(&expired)).unwrap();
+    assert_eq!(
+        error_code(response).await.as_deref(),
+        Some("AUTH_TOKEN_EXPIRED")
+    );
+}
+
+#[test]
+fn it_reads_the_jwt_from_other_sources() {
+    let config = JWTConf {
+        sources: vec![
+            Source::Cookie {
+                name: "authz".to_string(),
+            },
+            Source::QueryParameter {
+                name: "token".to_string(),
+            },
+        ],
+        ..Default::default()
+    };
+
+    let request = http::Request::builder()
+        .uri("http://localhost/graphql")
+        .header(http::header::COOKIE, "theme=dark; authz=cookie.jwt")
+        .body(())
+        .unwrap();
+    assert_eq!(
+        extract_jwt(&config, &request).unwrap().unwrap(),
+        "cookie.jwt"
+    );
+
+    let request = http::Request::builder()
+        .uri("http://localhost/graphql?token=query%2Ejwt")
+        .body(())
+        .unwrap();
+    assert_eq!(
+        extract_jwt(&config, &request).unwrap().unwrap(),
+        "query.jwt"
+    );
+
+    // the header takes precedence
+    let request = http::Request::builder()
+        .uri("http://localhost/graphql?token=query.jwt")
+        .header(http::header::AUTHORIZATION, "Bearer header.jwt")
+        .body(())
+        .unwrap();
+    assert_eq!(
+        extract_jwt(&config, &request).unwrap().unwrap(),
+        "header.jwt"
+    );
+
+    let request = http::Request::builder()
+        .uri("http://localhost/graphql")
+        .body(())
+        .unwrap();
+    assert!(extract_jwt(&config, &request).is_none());
+}
+
+#[tokio::test]
+async fn it_reloads_jwks_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("jwks.json");
+    let fixture = std::fs::read_to_string("tests/fixtures/jwks.json").unwrap();
+    std::fs::write(&path, &fixture).unwrap();
+
+    let jwks_manager = JwksManager::new(vec![JwksConfig {
+        url: Url::from_file_path(&path).unwrap(),
+        rules: Default::default(),
+        algorithms: None,
+        poll_interval: DEFAULT_AUTHENTICATION_DOWNLOAD_INTERVAL,
+        poll_jitter: Duration::ZERO,
+    }])
+    .await
+    .unwrap();
+    let criteria = JWTCriteria {
+        kid: Some("rotated".to_string()),
+        alg: Algorithm::HS256,
+    };
+    let (_rules, key) = search_jwks(&jwks_manager, &criteria)
+        .expect("search worked")
+        .expect("found a key");
+    assert_ne!(key.common.key_id.as_deref(), Some("rotated"));
+
+    let mut rotated: Value = serde_json::from_str(&fixture).unwrap();
+    for key in rotated["keys"].as_array_mut().unwrap() {
+        key["kid"] = Value::String("rotated".to_string());
+    }
+    std::fs::write(&path, serde_json::to_string(&rotated).unwrap()).unwrap();
+
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let (_rules, key) = search_jwks(&jwks_manager, &criteria)
+            .expect("search worked")
+            .expect("found a key");
+        if key.common.key_id.as_deref() == Some("rotated") {
+            return;
+        }
+    }
+    panic!("the JWKS file was not reloaded");
+}
+
+#[tokio::test]
+async fn it_rate_limits_refetches() {
+    let (jwk, _) = make_es256_key();
+    let manager = make_manager(&jwk, None);
+
+    assert!(manager.refetch());
+    assert!(!manager.refetch());
+}
@@ -0,0 +1,358 @@
+//! Authorization with the `@authenticated` and `@requiresScopes` schema directives.
+//!
+//! Before query planning, the fields the request is not allowed to query are removed from the
+//! operation, using the JWT claims set by the authentication plugin. The removed fields are
+//! returned as `null` with an error at their path, or the whole operation can be rejected.
+
+// This entire file is license key functionality
+
+use std::ops::ControlFlow;
+use std::sync::Arc;
+
+use http::StatusCode;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json_bytes::Value;
+use tower::BoxError;
+use tower::ServiceBuilder;
+use tower::ServiceExt;
+
+use crate::error::Error;
+use crate::json_ext::Path;
+use crate::json_ext::PathElement;
+use crate::json_ext::ValueExt;
+use crate::layers::ServiceBuilderExt;
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::plugins::authentication::APOLLO_AUTHENTICATION_JWT_CLAIMS;
+use crate::register_plugin;
+use crate::services::supergraph;
+use crate::spec::authorization::FilteredOperation;
+use crate::spec::authorization::RequestAccess;
+use crate::spec::Schema;
+use crate::Configuration;
+use crate::Context;
+
+const UNAUTHORIZED_PATHS_CONTEXT_KEY: &str = "apollo_authorization::unauthorized_paths";
+const UNAUTHORIZED_FIELD_OR_TYPE: &str = "UNAUTHORIZED_FIELD_OR_TYPE";
+
+/// Authorization directives configuration
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct AuthorizationConfig {
+    /// Reject operations querying fields the request is not allowed to access,
+    /// instead of removing those fields
+    #[serde(default)]
+    reject_unauthorized: bool,
+    /// JWT claim holding the scopes checked by `@requiresScopes`, as a space separated
+    /// string or a list of strings
+    #[serde(default = "default_scope_claim")]
+    scope_claim: String,
+}
+
+fn default_scope_claim() -> String {
+    "scope".to_string()
+}
+
+#[derive(Debug)]
+struct Authorization {
+    reject_unauthorized: bool,
+    scope_claim: Arc<String>,
+    schema: Arc<Schema>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for Authorization {
+    type Config = AuthorizationConfig;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        let schema = Schema::parse(&init.supergraph_sdl, &Configuration::default(), None)?;
+
+        Ok(Authorization {
+            reject_unauthorized: init.config.reject_unauthorized,
+            scope_claim: Arc::new(init.config.scope_claim),
+            schema: Arc::new(schema),
+        })
+    }
+
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        if self.schema.authorization.is_empty() {
+            return service;
+        }
+
+        let reject_unauthorized = self.reject_unauthorized;
+        let scope_claim = self.scope_claim.clone();
+        let schema = self.schema.clone();
+        let response_schema = self.schema.clone();
+
+        ServiceBuilder::new()
+            .checkpoint(move |mut req: supergraph::Request| {
+                let access = request_access(&req.context, &scope_claim);
+                let body = req.supergraph_request.body();
+                let filtered = match body.query.as_deref() {
+                    Some(query) => FilteredOperation::filter(
+                        &schema,
+                        query,
+                        body.operation_name.as_deref(),
+                        &access,
+                    ),
+                    None => None,
+                };
+                let FilteredOperation {
+                    query,
+                    unauthorized_paths,
+                } = match filtered {
+                    Some(filtered) => filtered,
+                    None => return Ok(ControlFlow::Continue(req)),
+                };
+
+                tracing::info!(
+                    monotonic_counter.apollo_router_authorization_unauthorized_fields_count =
+                        unauthorized_paths.len() as u64,
+                    rejected = reject_unauthorized,
+                );
+
+                if reject_unauthorized {
+                    let errors = unauthorized_paths
+                        .into_iter()
+                        .map(|path| {
+                            // type conditions are not part of response paths
+                            unauthorized_error(
+                                path.iter()
+                                    .filter(|element| !matches!(element, PathElement::Fragment(_)))
+                                    .cloned()
+                                    .collect(),
+                            )
+                        })
+                        .collect::<Vec<_>>();
+                    let res = supergraph::Response::builder()
+                        .errors(errors)
+                        .status_code(StatusCode::FORBIDDEN)
+                        .context(req.context)
+                        .build()?;
+                    return Ok(ControlFlow::Break(res));
+                }
+
+                match query {
+                    Some(query) => {
+                        req.supergraph_request.body_mut().query = Some(query);
+                        req.context
+                            .insert(UNAUTHORIZED_PATHS_CONTEXT_KEY, unauthorized_paths)?;
+                        Ok(ControlFlow::Continue(req))
+                    }
+                    // there is nothing left to execute
+                    None => {
+                        let mut data = Value::Object(Default::default());
+                        let errors = insert_nulls(&schema, &mut data, &unauthorized_paths);
+                        let res = supergraph::Response::builder()
+                            .data(data)
+                            .errors(errors)
+                            .context(req.context)
+                            .build()?;
+                        Ok(ControlFlow::Break(res))
+                    }
+                }
+            })
+            .map_response(move |res: supergraph::Response| {
+                let unauthorized_paths: Option<Vec<Path>> = res
+                    .context
+                    .get(UNAUTHORIZED_PATHS_CONTEXT_KEY)
+                    .ok()
+                    .flatten();
+                let unauthorized_paths = match unauthorized_paths {
+                    Some(paths) => Arc::new(paths),
+                    None => return res,
+                };
+                let schema = response_schema.clone();
+
+                res.map_stream(move |mut response| {
+                    // deferred responses are merged in the primary response, which
+                    // holds the removed fields
+                    if response.path.is_none() {
+                        if let Some(data) = response.data.as_mut() {
+                            let errors = insert_nulls(&schema, data, &unauthorized_paths);
+                            response.errors.extend(errors);
+                        }
+                    }
+                    response
+                })
+            })
+            .service(service)
+            .boxed()
+    }
+}
+
+fn request_access(context: &Context, scope_claim: &str) -> RequestAccess {
+    let claims: Option<serde_json::Value> =
+        context.get(APOLLO_AUTHENTICATION_JWT_CLAIMS).ok().flatten();
+    let scopes = match claims.as_ref().and_then(|claims| claims.get(scope_claim)) {
+        Some(serde_json::Value::String(scopes)) => {
+            scopes.split_whitespace().map(str::to_string).collect()
+        }
+        Some(serde_json::Value::Array(scopes)) => scopes
+            .iter()
+            .filter_map(|scope| scope.as_str())
+            .map(str::to_string)
+            .collect(),
+        _ => Default::default(),
+    };
+
+    RequestAccess {
+        authenticated: claims.is_some(),
+        scopes,
+    }
+}
+
+fn unauthorized_error(path: Path) -> Error {
+    Error::builder()
+        .message("Unauthorized field or type")
+        .path(path)
+        .extension_code(UNAUTHORIZED_FIELD_OR_TYPE)
+        .build()
+}
+
+/// Sets removed fields to `null` in the response data, and returns an error for each of them
+fn insert_nulls(schema: &Schema, data: &mut Value, unauthorized_paths: &[Path]) -> Vec<Error> {
+    let mut errors = Vec::new();
+    for path in unauthorized_paths {
+        let (key, parent) = match path.0.split_last() {
+            Some((PathElement::Key(key), parent)) => (key, Path(parent.to_vec())),
+            _ => continue,
+        };
+
+        data.select_values_and_paths_mut(schema, &parent, |parent_path, value| {
+            if let Value::Object(object) = value {
+                // the key can be set by another selection with the same response key
+                if !object.contains_key(key.as_str()) {
+                    object.insert(key.clone(), Value::Null);
+                    errors.push(unauthorized_error(
+                        parent_path.join(Path(vec![PathElement::Key(key.clone())])),
+                    ));
+                }
+            }
+        });
+    }
+    errors
+}
+
+register_plugin!("apollo", "authorization", Authorization);
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::plugin::test::MockSubgraph;
+    use crate::test_harness::MockedSubgraphs;
+    use crate::TestHarness;
+
+    const SCHEMA: &str = include_str!("../testdata/authorization_supergraph.graphql");
+
+    async fn execute(
+        config: serde_json::Value,
+        claims: Option<serde_json::Value>,
+        query: &str,
+    ) -> crate::graphql::Response {
+        let subgraphs = MockedSubgraphs(
+            [(
+                "accounts",
+                MockSubgraph::builder()
+                    .with_json(
+                        json! {{"query":"{me{name}}"}},
+                        json! {{"data": {"me": {"name": "Ada"}}}},
+                    )
+                    .with_json(
+                        json! {{"query":"{publicInfo}"}},
+                        json! {{"data": {"publicInfo": "hello"}}},
+                    )
+                    .build(),
+            )]
+            .into_iter()
+            .collect(),
+        );
+
+        let service = TestHarness::builder()
+            .configuration_json(json!({ "authorization": config }))
+            .unwrap()
+            .schema(SCHEMA)
+            .extra_plugin(subgraphs)
+            .build_supergraph()
+            .await
+            .unwrap();
+
+        let context = Context::new();
+        if let Some(claims) = claims {
+            context
+                .insert(APOLLO_AUTHENTICATION_JWT_CLAIMS, claims)
+                .unwrap();
+        }
+        let request = supergraph::Request::fake_builder()
+            .context(context)
+            .query(query)
+            .build()
+            .unwrap();
+
+        service
+            .oneshot(request)
+            .await
+            .unwrap()
+            .next_response()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn authenticated_requests_query_every_field() {
+        let response = execute(json!({}), Some(json!({"sub": "1"})), "{ me { name } }").await;
+        assert!(response.errors.is_empty());
+        assert_eq!(
+            response.data,
+            Some(serde_json_bytes::json!({"me": {"name": "Ada"}}))
+        );
+    }
+
+    #[tokio::test]
+    async fn unauthorized_fields_are_removed() {
+        let response = execute(json!({}), None, "{ publicInfo me { name } }").await;
+        assert_eq!(
+            response.data,
+            Some(serde_json_bytes::json!({"publicInfo": "hello", "me": null}))
+        );
+        assert_eq!(response.errors.len(), 1);
+        assert_eq!(response.errors[0].path, Some(Path::from("me")));
+        assert_eq!(
+            response.errors[0].extensions.get("code").unwrap(),
+            UNAUTHORIZED_FIELD_OR_TYPE
+        );
+    }
+
+    #[tokio::test]
+    async fn unauthorized_operations_can_be_rejected() {
+        let response = execute(
+            json!({"reject_unauthorized": true}),
+            None,
+            "{ publicInfo me { name } }",
+        )
+        .await;
+        assert_eq!(response.data, None);
+        assert_eq!(response.errors[0].path, Some(Path::from("me")));
+    }
+
+    #[test]
+    fn it_reads_scopes() {
+        let context = Context::new();
+        context
+            .insert(
+                APOLLO_AUTHENTICATION_JWT_CLAIMS,
+                json!({"scope": "read:user write:user"}),
+            )
+            .unwrap();
+        let access = request_access(&context, "scope");
+        assert!(access.authenticated);
+        assert!(access.scopes.contains("write:user"));
+
+        assert!(!request_access(&Context::new(), "scope").authenticated);
+    }
+}
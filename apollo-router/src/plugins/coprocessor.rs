@@ -8,6 +8,9 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use bytes::Bytes;
+use futures::future::ready;
+use futures::stream::once;
+use futures::StreamExt;
 use http::header::HeaderName;
 use http::HeaderMap;
 use http::HeaderValue;
@@ -30,7 +33,10 @@ use crate::layers::async_checkpoint::AsyncCheckpointLayer;
 use crate::layers::ServiceBuilderExt;
 use crate::plugin::Plugin;
 use crate::plugin::PluginInit;
+use crate::query_planner::PlanNode;
+use crate::query_planner::QueryPlan;
 use crate::register_plugin;
+use crate::services::execution;
 use crate::services::external::Control;
 use crate::services::external::Externalizable;
 use crate::services::external::PipelineStep;
@@ -38,6 +44,7 @@ use crate::services::external::DEFAULT_EXTERNALIZATION_TIMEOUT;
 use crate::services::external::EXTERNALIZABLE_VERSION;
 use crate::services::router;
 use crate::services::subgraph;
+use crate::services::supergraph;
 use crate::tracer::TraceId;
 
 pub(crate) const EXTERNAL_SPAN_NAME: &str = "external_plugin";
@@ -77,6 +84,14 @@ impl Plugin for CoprocessorPlugin<HTTPClientService> {
         self.router_service(service)
     }
 
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        self.supergraph_service(service)
+    }
+
+    fn execution_service(&self, service: execution::BoxService) -> execution::BoxService {
+        self.execution_service(service)
+    }
+
     fn subgraph_service(&self, name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
         self.subgraph_service(name, service)
     }
@@ -140,6 +155,24 @@ where
         )
     }
 
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        self.configuration.supergraph.as_service(
+            self.http_client.clone(),
+            service,
+            self.configuration.url.clone(),
+            self.sdl.clone(),
+        )
+    }
+
+    fn execution_service(&self, service: execution::BoxService) -> execution::BoxService {
+        self.configuration.execution.as_service(
+            self.http_client.clone(),
+            service,
+            self.configuration.url.clone(),
+            self.sdl.clone(),
+        )
+    }
+
     fn subgraph_service(&self, name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
         self.configuration.subgraph.all.as_service(
             self.http_client.clone(),
@@ -182,6 +215,56 @@ pub(super) struct RouterResponseConf {
     /// Send the HTTP status
     pub(super) status_code: bool,
 }
+/// What information is passed to a supergraph request stage
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct SupergraphRequestConf {
+    /// Send the headers
+    pub(super) headers: bool,
+    /// Send the context
+    pub(super) context: bool,
+    /// Send the body
+    pub(super) body: bool,
+    /// Send the SDL
+    pub(super) sdl: bool,
+    /// Send the method
+    pub(super) method: bool,
+}
+
+/// What information is passed to a supergraph or execution response stage
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct SupergraphResponseConf {
+    /// Send the headers
+    pub(super) headers: bool,
+    /// Send the context
+    pub(super) context: bool,
+    /// Send the body
+    pub(super) body: bool,
+    /// Send the SDL
+    pub(super) sdl: bool,
+    /// Send the HTTP status
+    pub(super) status_code: bool,
+}
+
+/// What information is passed to an execution request stage
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub(super) struct ExecutionRequestConf {
+    /// Send the headers
+    pub(super) headers: bool,
+    /// Send the context
+    pub(super) context: bool,
+    /// Send the body
+    pub(super) body: bool,
+    /// Send the SDL
+    pub(super) sdl: bool,
+    /// Send the method
+    pub(super) method: bool,
+    /// Send the query plan
+    pub(super) query_plan: bool,
+}
+
 /// What information is passed to a subgraph request/response stage
 #[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
 #[serde(default, deny_unknown_fields)]
@@ -230,6 +313,12 @@ struct Conf {
     /// The router stage request/response configuration
     #[serde(default)]
     router: RouterStage,
+    /// The supergraph stage request/response configuration
+    #[serde(default)]
+    supergraph: SupergraphStage,
+    /// The execution stage request/response configuration
+    #[serde(default)]
+    execution: ExecutionStage,
     /// The subgraph stage request/response configuration
     #[serde(default)]
     subgraph: SubgraphStages,
@@ -346,6 +435,222 @@ impl RouterStage {
 
 // -----------------------------------------------------------------------------------------
 
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[serde(default)]
+pub(super) struct SupergraphStage {
+    /// The request configuration
+    pub(super) request: SupergraphRequestConf,
+    /// The response configuration
+    pub(super) response: SupergraphResponseConf,
+}
+
+impl SupergraphStage {
+    pub(crate) fn as_service<C>(
+        &self,
+        http_client: C,
+        service: supergraph::BoxService,
+        coprocessor_url: String,
+        sdl: Arc<String>,
+    ) -> supergraph::BoxService
+    where
+        C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        <C as tower::Service<http::Request<hyper::Body>>>::Future: Send + 'static,
+    {
+        let request_layer = (self.request != Default::default()).then_some({
+            let request_config = self.request.clone();
+            let coprocessor_url = coprocessor_url.clone();
+            let http_client = http_client.clone();
+            let sdl = sdl.clone();
+
+            AsyncCheckpointLayer::new(move |request: supergraph::Request| {
+                let request_config = request_config.clone();
+                let coprocessor_url = coprocessor_url.clone();
+                let http_client = http_client.clone();
+                let sdl = sdl.clone();
+
+                async move {
+                    process_supergraph_request_stage(
+                        http_client,
+                        coprocessor_url,
+                        sdl,
+                        request,
+                        request_config,
+                    )
+                    .await
+                    .map_err(|error| {
+                        tracing::error!(
+                            "external extensibility: supergraph request stage error: {error}"
+                        );
+                        error
+                    })
+                }
+            })
+        });
+
+        let response_layer = (self.response != Default::default()).then_some({
+            let response_config = self.response.clone();
+            MapFutureLayer::new(move |fut| {
+                let sdl = sdl.clone();
+                let coprocessor_url = coprocessor_url.clone();
+                let http_client = http_client.clone();
+                let response_config = response_config.clone();
+
+                async move {
+                    let response: supergraph::Response = fut.await?;
+
+                    process_response_stream_stage(
+                        http_client,
+                        coprocessor_url,
+                        sdl,
+                        response,
+                        response_config,
+                        PipelineStep::SupergraphResponse,
+                    )
+                    .await
+                    .map_err(|error| {
+                        tracing::error!(
+                            "external extensibility: supergraph response stage error: {error}"
+                        );
+                        error
+                    })
+                }
+            })
+        });
+
+        fn external_service_span() -> impl Fn(&supergraph::Request) -> tracing::Span + Clone {
+            move |_request: &supergraph::Request| {
+                tracing::info_span!(
+                    EXTERNAL_SPAN_NAME,
+                    "external service" = stringify!(supergraph::Request),
+                    "otel.kind" = "INTERNAL"
+                )
+            }
+        }
+
+        ServiceBuilder::new()
+            .instrument(external_service_span())
+            .option_layer(request_layer)
+            .option_layer(response_layer)
+            .buffered()
+            .service(service)
+            .boxed()
+    }
+}
+
+// -----------------------------------------------------------------------------------------
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
+#[serde(default)]
+pub(super) struct ExecutionStage {
+    /// The request configuration
+    pub(super) request: ExecutionRequestConf,
+    /// The response configuration
+    pub(super) response: SupergraphResponseConf,
+}
+
+impl ExecutionStage {
+    pub(crate) fn as_service<C>(
+        &self,
+        http_client: C,
+        service: execution::BoxService,
+        coprocessor_url: String,
+        sdl: Arc<String>,
+    ) -> execution::BoxService
+    where
+        C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        <C as tower::Service<http::Request<hyper::Body>>>::Future: Send + 'static,
+    {
+        let request_layer = (self.request != Default::default()).then_some({
+            let request_config = self.request.clone();
+            let coprocessor_url = coprocessor_url.clone();
+            let http_client = http_client.clone();
+            let sdl = sdl.clone();
+
+            AsyncCheckpointLayer::new(move |request: execution::Request| {
+                let request_config = request_config.clone();
+                let coprocessor_url = coprocessor_url.clone();
+                let http_client = http_client.clone();
+                let sdl = sdl.clone();
+
+                async move {
+                    process_execution_request_stage(
+                        http_client,
+                        coprocessor_url,
+                        sdl,
+                        request,
+                        request_config,
+                    )
+                    .await
+                    .map_err(|error| {
+                        tracing::error!(
+                            "external extensibility: execution request stage error: {error}"
+                        );
+                        error
+                    })
+                }
+            })
+        });
+
+        let response_layer = (self.response != Default::default()).then_some({
+            let response_config = self.response.clone();
+            MapFutureLayer::new(move |fut| {
+                let sdl = sdl.clone();
+                let coprocessor_url = coprocessor_url.clone();
+                let http_client = http_client.clone();
+                let response_config = response_config.clone();
+
+                async move {
+                    let response: execution::Response = fut.await?;
+
+                    process_response_stream_stage(
+                        http_client,
+                        coprocessor_url,
+                        sdl,
+                        response,
+                        response_config,
+                        PipelineStep::ExecutionResponse,
+                    )
+                    .await
+                    .map_err(|error| {
+                        tracing::error!(
+                            "external extensibility: execution response stage error: {error}"
+                        );
+                        error
+                    })
+                }
+            })
+        });
+
+        fn external_service_span() -> impl Fn(&execution::Request) -> tracing::Span + Clone {
+            move |_request: &execution::Request| {
+                tracing::info_span!(
+                    EXTERNAL_SPAN_NAME,
+                    "external service" = stringify!(execution::Request),
+                    "otel.kind" = "INTERNAL"
+                )
+            }
+        }
+
+        ServiceBuilder::new()
+            .instrument(external_service_span())
+            .option_layer(request_layer)
+            .option_layer(response_layer)
+            .buffered()
+            .service(service)
+            .boxed()
+    }
+}
+
+// -----------------------------------------------------------------------------------------
+
 /// What information is passed to a subgraph request/response stage
 #[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize, JsonSchema)]
 #[serde(default, deny_unknown_fields)]
@@ -513,6 +818,7 @@ where
         method: Some(parts.method.to_string()),
         service_name: None,
         status_code: None,
+        query_plan: None,
     };
 
     tracing::debug!(?payload, "externalized output");
@@ -638,6 +944,7 @@ where
         path: None,
         method: None,
         service_name: None,
+        query_plan: None,
     };
 
     // Second, call our co-processor and get a reply.
@@ -677,6 +984,426 @@ where
 }
 // -----------------------------------------------------------------------------------------------------
 
+async fn process_supergraph_request_stage<C>(
+    http_client: C,
+    coprocessor_url: String,
+    sdl: Arc<String>,
+    mut request: supergraph::Request,
+    request_config: SupergraphRequestConf,
+) -> Result<ControlFlow<supergraph::Response, supergraph::Request>, BoxError>
+where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    <C as tower::Service<http::Request<hyper::Body>>>::Future: Send + 'static,
+{
+    // Call into our out of process processor with a body of our body
+    // First, extract the data we need from our request and prepare our
+    // external call. Use our configuration to figure out which data to send.
+    let (parts, body) = request.supergraph_request.into_parts();
+
+    let headers_to_send = request_config
+        .headers
+        .then(|| externalize_header_map(&parts.headers))
+        .transpose()?;
+    let body_to_send = request_config
+        .body
+        .then(|| serde_json::to_value(&body))
+        .transpose()?;
+    let context_to_send = request_config.context.then(|| request.context.clone());
+    let sdl = request_config.sdl.then(|| sdl.clone().to_string());
+    let method = request_config.method.then(|| parts.method.to_string());
+
+    let payload = Externalizable {
+        version: EXTERNALIZABLE_VERSION,
+        stage: PipelineStep::SupergraphRequest.to_string(),
+        control: Some(Control::default()),
+        id: TraceId::maybe_new().map(|id| id.to_string()),
+        headers: headers_to_send,
+        body: body_to_send,
+        context: context_to_send,
+        sdl,
+        uri: None,
+        path: None,
+        method,
+        service_name: None,
+        status_code: None,
+        query_plan: None,
+    };
+
+    tracing::debug!(?payload, "externalized output");
+    request.context.enter_active_request().await;
+    let co_processor_result = payload.call(http_client, &coprocessor_url).await;
+    request.context.leave_active_request().await;
+    tracing::debug!(?co_processor_result, "co-processor returned");
+    let co_processor_output = co_processor_result?;
+
+    validate_coprocessor_output(&co_processor_output, PipelineStep::SupergraphRequest)?;
+    // unwrap is safe here because validate_coprocessor_output made sure control is available
+    let control = co_processor_output
+        .control
+        .clone()
+        .expect("validated above; qed");
+
+    // Thirdly, we need to interpret the control flow which may have been
+    // updated by our co-processor and decide if we should proceed or stop.
+
+    if matches!(control, Control::Break(_)) {
+        return Ok(ControlFlow::Break(break_response(
+            control,
+            co_processor_output,
+            request.context,
+        )?));
+    }
+
+    // Finally, process our reply and act on the contents. Our processing logic is
+    // that we replace "bits" of our incoming request with the updated bits if they
+    // are present in our co_processor_output.
+
+    let new_body: crate::graphql::Request = match co_processor_output.body {
+        Some(value) => serde_json::from_value(value)?,
+        None => body,
+    };
+
+    request.supergraph_request = http::Request::from_parts(parts, new_body);
+
+    if let Some(context) = co_processor_output.context {
+        request.context = context;
+    }
+
+    if let Some(headers) = co_processor_output.headers {
+        *request.supergraph_request.headers_mut() = internalize_header_map(headers)?;
+    }
+
+    Ok(ControlFlow::Continue(request))
+}
+
+async fn process_execution_request_stage<C>(
+    http_client: C,
+    coprocessor_url: String,
+    sdl: Arc<String>,
+    mut request: execution::Request,
+    request_config: ExecutionRequestConf,
+) -> Result<ControlFlow<execution::Response, execution::Request>, BoxError>
+where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    <C as tower::Service<http::Request<hyper::Body>>>::Future: Send + 'static,
+{
+    // Call into our out of process processor with a body of our body
+    // First, extract the data we need from our request and prepare our
+    // external call. Use our configuration to figure out which data to send.
+    let supergraph_request = &request.supergraph_request;
+
+    let headers_to_send = request_config
+        .headers
+        .then(|| externalize_header_map(supergraph_request.headers()))
+        .transpose()?;
+    let body_to_send = request_config
+        .body
+        .then(|| serde_json::to_value(supergraph_request.body()))
+        .transpose()?;
+    let query_plan_to_send = request_config
+        .query_plan
+        .then(|| serde_json::to_value(&request.query_plan.root))
+        .transpose()?;
+    let context_to_send = request_config.context.then(|| request.context.clone());
+    let sdl = request_config.sdl.then(|| sdl.clone().to_string());
+    let method = request_config
+        .method
+        .then(|| supergraph_request.method().to_string());
+
+    let payload = Externalizable {
+        version: EXTERNALIZABLE_VERSION,
+        stage: PipelineStep::ExecutionRequest.to_string(),
+        control: Some(Control::default()),
+        id: TraceId::maybe_new().map(|id| id.to_string()),
+        headers: headers_to_send,
+        body: body_to_send,
+        context: context_to_send,
+        sdl,
+        uri: None,
+        path: None,
+        method,
+        service_name: None,
+        status_code: None,
+        query_plan: query_plan_to_send,
+    };
+
+    tracing::debug!(?payload, "externalized output");
+    request.context.enter_active_request().await;
+    let co_processor_result = payload.call(http_client, &coprocessor_url).await;
+    request.context.leave_active_request().await;
+    tracing::debug!(?co_processor_result, "co-processor returned");
+    let co_processor_output = co_processor_result?;
+
+    validate_coprocessor_output(&co_processor_output, PipelineStep::ExecutionRequest)?;
+    // unwrap is safe here because validate_coprocessor_output made sure control is available
+    let control = co_processor_output
+        .control
+        .clone()
+        .expect("validated above; qed");
+
+    // Thirdly, we need to interpret the control flow which may have been
+    // updated by our co-processor and decide if we should proceed or stop.
+
+    if matches!(control, Control::Break(_)) {
+        return Ok(ControlFlow::Break(break_response(
+            control,
+            co_processor_output,
+            request.context,
+        )?));
+    }
+
+    // Finally, process our reply and act on the contents. Our processing logic is
+    // that we replace "bits" of our incoming request with the updated bits if they
+    // are present in our co_processor_output.
+
+    if let Some(value) = co_processor_output.body {
+        *request.supergraph_request.body_mut() = serde_json::from_value(value)?;
+    }
+
+    // The query plan is only taken back if we sent it
+    if let (true, Some(value)) = (request_config.query_plan, co_processor_output.query_plan) {
+        let root: PlanNode = serde_json::from_value(value)?;
+        request.query_plan = Arc::new(QueryPlan {
+            usage_reporting: request.query_plan.usage_reporting.clone(),
+            root,
+            formatted_query_plan: request.query_plan.formatted_query_plan.clone(),
+            query: request.query_plan.query.clone(),
+        });
+    }
+
+    if let Some(context) = co_processor_output.context {
+        request.context = context;
+    }
+
+    if let Some(headers) = co_processor_output.headers {
+        *request.supergraph_request.headers_mut() = internalize_header_map(headers)?;
+    }
+
+    Ok(ControlFlow::Continue(request))
+}
+
+/// Processes the response stream of the supergraph and execution stages.
+///
+/// The first response is sent with the headers and status code, deferred responses
+/// only carry their body and the context.
+async fn process_response_stream_stage<C>(
+    http_client: C,
+    coprocessor_url: String,
+    sdl: Arc<String>,
+    response: supergraph::Response,
+    response_config: SupergraphResponseConf,
+    stage: PipelineStep,
+) -> Result<supergraph::Response, BoxError>
+where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    <C as tower::Service<http::Request<hyper::Body>>>::Future: Send + 'static,
+{
+    // Call into our out of process processor with a body of our body
+    // First, extract the data we need from our response and prepare our
+    // external call. Use our configuration to figure out which data to send.
+    let supergraph::Response { response, context } = response;
+    let (mut parts, mut stream) = response.into_parts();
+    let first = stream.next().await.unwrap_or_default();
+
+    let headers_to_send = response_config
+        .headers
+        .then(|| externalize_header_map(&parts.headers))
+        .transpose()?;
+    let body_to_send = response_config
+        .body
+        .then(|| serde_json::to_value(&first))
+        .transpose()?;
+    let status_to_send = response_config.status_code.then(|| parts.status.as_u16());
+    let context_to_send = response_config.context.then(|| context.clone());
+    let sdl_to_send = response_config.sdl.then(|| sdl.clone().to_string());
+
+    let payload = Externalizable {
+        version: EXTERNALIZABLE_VERSION,
+        stage: stage.to_string(),
+        control: None,
+        id: TraceId::maybe_new().map(|id| id.to_string()),
+        headers: headers_to_send,
+        body: body_to_send,
+        context: context_to_send,
+        status_code: status_to_send,
+        sdl: sdl_to_send,
+        uri: None,
+        path: None,
+        method: None,
+        service_name: None,
+        query_plan: None,
+    };
+
+    // Second, call our co-processor and get a reply.
+    tracing::debug!(?payload, "externalized output");
+    context.enter_active_request().await;
+    let co_processor_result = payload.call(http_client.clone(), &coprocessor_url).await;
+    context.leave_active_request().await;
+    tracing::debug!(?co_processor_result, "co-processor returned");
+    let co_processor_output = co_processor_result?;
+
+    validate_coprocessor_output(&co_processor_output, stage.clone())?;
+
+    // Third, process our reply and act on the contents. Our processing logic is
+    // that we replace "bits" of our incoming response with the updated bits if they
+    // are present in our co_processor_output. If they aren't present, just use the
+    // bits that we sent to the co_processor.
+
+    let first: crate::graphql::Response = match co_processor_output.body {
+        Some(value) => serde_json::from_value(value)?,
+        None => first,
+    };
+
+    if let Some(control) = co_processor_output.control {
+        parts.status = control.get_http_status()?
+    }
+
+    let context = match co_processor_output.context {
+        Some(context) => context,
+        None => context,
+    };
+
+    if let Some(headers) = co_processor_output.headers {
+        parts.headers = internalize_header_map(headers)?;
+    }
+
+    // Deferred responses only need to go through the coprocessor if it can see them
+    let deferred = if response_config.body {
+        let context = context.clone();
+        stream
+            .then(move |deferred| {
+                let http_client = http_client.clone();
+                let coprocessor_url = coprocessor_url.clone();
+                let context = context.clone();
+                let stage = stage.clone();
+                let context_to_send = response_config.context.then(|| context.clone());
+
+                async move {
+                    let has_next = deferred.has_next;
+                    process_deferred_response(
+                        http_client,
+                        &coprocessor_url,
+                        deferred,
+                        context_to_send,
+                        stage,
+                    )
+                    .await
+                    .unwrap_or_else(|error| {
+                        tracing::error!(
+                            "external extensibility: deferred response stage error: {error}"
+                        );
+                        crate::graphql::Response::builder()
+                            .errors(vec![Error::builder()
+                                .message(format!("coprocessor error: {error}"))
+                                .extension_code("EXTERNAL_DEFERRED_RESPONSE_ERROR")
+                                .build()])
+                            .and_has_next(has_next)
+                            .build()
+                    })
+                }
+            })
+            .boxed()
+    } else {
+        stream
+    };
+
+    Ok(supergraph::Response {
+        response: http::Response::from_parts(parts, once(ready(first)).chain(deferred).boxed()),
+        context,
+    })
+}
+
+async fn process_deferred_response<C>(
+    http_client: C,
+    coprocessor_url: &str,
+    deferred: crate::graphql::Response,
+    context: Option<crate::Context>,
+    stage: PipelineStep,
+) -> Result<crate::graphql::Response, BoxError>
+where
+    C: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = BoxError>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    <C as tower::Service<http::Request<hyper::Body>>>::Future: Send + 'static,
+{
+    let payload = Externalizable {
+        version: EXTERNALIZABLE_VERSION,
+        stage: stage.to_string(),
+        control: None,
+        id: TraceId::maybe_new().map(|id| id.to_string()),
+        headers: None,
+        body: Some(serde_json::to_value(&deferred)?),
+        context,
+        status_code: None,
+        sdl: None,
+        uri: None,
+        path: None,
+        method: None,
+        service_name: None,
+        query_plan: None,
+    };
+
+    tracing::debug!(?payload, "externalized output");
+    let co_processor_output = payload.call(http_client, coprocessor_url).await?;
+    tracing::debug!(?co_processor_output, "co-processor returned");
+    validate_coprocessor_output(&co_processor_output, stage)?;
+
+    match co_processor_output.body {
+        Some(value) => Ok(serde_json::from_value(value)?),
+        None => Ok(deferred),
+    }
+}
+
+/// Builds the response returned when the coprocessor breaks out of a supergraph or execution request stage
+fn break_response(
+    control: Control,
+    co_processor_output: Externalizable<serde_json::Value>,
+    context: crate::Context,
+) -> Result<supergraph::Response, BoxError> {
+    // Ensure the code is a valid http status code
+    let code = control.get_http_status()?;
+
+    let graphql_response: crate::graphql::Response =
+        serde_json::from_value(co_processor_output.body.unwrap_or(serde_json::Value::Null))
+            .unwrap_or_else(|error| {
+                crate::graphql::Response::builder()
+                    .errors(vec![Error::builder()
+                        .message(format!(
+                            "couldn't deserialize coprocessor output body: {error}"
+                        ))
+                        .extension_code("EXERNAL_DESERIALIZATION_ERROR")
+                        .build()])
+                    .build()
+            });
+
+    let mut res = supergraph::Response::new_from_graphql_response(
+        graphql_response,
+        co_processor_output.context.unwrap_or(context),
+    );
+    *res.response.status_mut() = code;
+    if let Some(headers) = co_processor_output.headers {
+        *res.response.headers_mut() = internalize_header_map(headers)?;
+    }
+
+    Ok(res)
+}
+
+// -----------------------------------------------------------------------------------------------------
+
 async fn process_subgraph_request_stage<C>(
     http_client: C,
     coprocessor_url: String,
@@ -725,6 +1452,7 @@ where
         uri,
         method: Some(parts.method.to_string()),
         status_code: None,
+        query_plan: None,
     };
 
     tracing::debug!(?payload, "externalized output");
@@ -855,6 +1583,7 @@ where
         path: None,
         method: None,
         service_name,
+        query_plan: None,
     };
 
     tracing::debug!(?payload, "externalized output");
@@ -18,9 +18,13 @@ mod tests {
     use tower::ServiceExt;
 
     use super::super::coprocessor::*;
+    use crate::plugin::test::MockExecutionService;
     use crate::plugin::test::MockHttpClientService;
     use crate::plugin::test::MockRouterService;
     use crate::plugin::test::MockSubgraphService;
+    use crate::plugin::test::MockSupergraphService;
+    use crate::query_planner::PlanNode;
+    use crate::services::execution;
     use crate::services::external::Externalizable;
     use crate::services::external::PipelineStep;
     use crate::services::external::EXTERNALIZABLE_VERSION;
@@ -1099,6 +1103,338 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn external_plugin_supergraph_request() {
+        let supergraph_stage = SupergraphStage {
+            request: SupergraphRequestConf {
+                headers: false,
+                context: false,
+                body: true,
+                sdl: false,
+                method: false,
+            },
+            response: Default::default(),
+        };
+
+        let mut mock_supergraph_service = MockSupergraphService::new();
+
+        mock_supergraph_service
+            .expect_call()
+            .returning(|req: supergraph::Request| {
+                // The operation name and the variables should have changed
+                let body = req.supergraph_request.body();
+                assert_eq!(body.operation_name.as_deref(), Some("Changed"));
+                assert_eq!(
+                    body.variables.get("id").unwrap(),
+                    &serde_json_bytes::json!("2")
+                );
+
+                Ok(supergraph::Response::builder()
+                    .data(json!({ "test": 1234_u32 }))
+                    .context(req.context)
+                    .build()
+                    .unwrap())
+            });
+
+        let mock_http_client = mock_with_callback(move |req: hyper::Request<Body>| {
+            Box::pin(async {
+                let deserialized_request: Externalizable<serde_json::Value> =
+                    serde_json::from_slice(&hyper::body::to_bytes(req.into_body()).await.unwrap())
+                        .unwrap();
+
+                assert_eq!(
+                    PipelineStep::SupergraphRequest.to_string(),
+                    deserialized_request.stage
+                );
+                assert_eq!(
+                    deserialized_request.body.unwrap()["query"],
+                    "query Long($id: ID) { me { name } }"
+                );
+
+                Ok(hyper::Response::builder()
+                    .body(Body::from(
+                        r##"{
+                            "version": 1,
+                            "stage": "SupergraphRequest",
+                            "control": "continue",
+                            "body": {
+                                "query": "query Changed($id: ID) { me { name } }",
+                                "operationName": "Changed",
+                                "variables": { "id": "2" }
+                            }
+                        }"##,
+                    ))
+                    .unwrap())
+            })
+        });
+
+        let service = supergraph_stage.as_service(
+            mock_http_client,
+            mock_supergraph_service.boxed(),
+            "http://test".to_string(),
+            Arc::new("".to_string()),
+        );
+
+        let request = supergraph::Request::fake_builder()
+            .query("query Long($id: ID) { me { name } }")
+            .operation_name("Long")
+            .variable("id", "1")
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            serde_json_bytes::json!({ "test": 1234_u32 }),
+            service
+                .oneshot(request)
+                .await
+                .unwrap()
+                .next_response()
+                .await
+                .unwrap()
+                .data
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn external_plugin_supergraph_request_controlflow_break() {
+        let supergraph_stage = SupergraphStage {
+            request: SupergraphRequestConf {
+                headers: true,
+                context: true,
+                body: true,
+                sdl: false,
+                method: true,
+            },
+            response: Default::default(),
+        };
+
+        // This will never be called because the coprocessor breaks the control flow.
+        let mock_supergraph_service = MockSupergraphService::new();
+
+        let mock_http_client = mock_with_callback(move |_: hyper::Request<Body>| {
+            Box::pin(async {
+                Ok(hyper::Response::builder()
+                    .body(Body::from(
+                        r##"{
+                            "version": 1,
+                            "stage": "SupergraphRequest",
+                            "control": {
+                                "break": 403
+                            },
+                            "body": {
+                                "errors": [{ "message": "my error message" }]
+                            },
+                            "context": {
+                                "entries": {
+                                    "testKey": true
+                                }
+                            },
+                            "headers": {
+                                "aheader": ["a value"]
+                            }
+                        }"##,
+                    ))
+                    .unwrap())
+            })
+        });
+
+        let service = supergraph_stage.as_service(
+            mock_http_client,
+            mock_supergraph_service.boxed(),
+            "http://test".to_string(),
+            Arc::new("".to_string()),
+        );
+
+        let request = supergraph::Request::canned_builder().build().unwrap();
+
+        let mut response = service.oneshot(request).await.unwrap();
+
+        assert!(response.context.get::<_, bool>("testKey").unwrap().unwrap());
+        assert_eq!(response.response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            response.response.headers().get("aheader").unwrap(),
+            "a value"
+        );
+        assert_eq!(
+            "my error message",
+            response.next_response().await.unwrap().errors[0].message
+        );
+    }
+
+    #[tokio::test]
+    async fn external_plugin_execution_request_query_plan() {
+        let execution_stage = ExecutionStage {
+            request: ExecutionRequestConf {
+                headers: false,
+                context: false,
+                body: false,
+                sdl: false,
+                method: false,
+                query_plan: true,
+            },
+            response: Default::default(),
+        };
+
+        let mut mock_execution_service = MockExecutionService::new();
+
+        mock_execution_service
+            .expect_call()
+            .returning(|req: execution::Request| {
+                // The query plan should have changed
+                assert!(matches!(
+                    req.query_plan.root,
+                    PlanNode::Parallel { ref nodes } if nodes.is_empty()
+                ));
+
+                Ok(execution::Response::builder()
+                    .data(json!({ "test": 1234_u32 }))
+                    .context(req.context)
+                    .build()
+                    .unwrap())
+            });
+
+        let mock_http_client = mock_with_callback(move |req: hyper::Request<Body>| {
+            Box::pin(async {
+                let deserialized_request: Externalizable<serde_json::Value> =
+                    serde_json::from_slice(&hyper::body::to_bytes(req.into_body()).await.unwrap())
+                        .unwrap();
+
+                assert_eq!(
+                    PipelineStep::ExecutionRequest.to_string(),
+                    deserialized_request.stage
+                );
+                assert!(deserialized_request.body.is_none());
+                assert_eq!(
+                    json!({ "kind": "Sequence", "nodes": [] }),
+                    deserialized_request.query_plan.unwrap()
+                );
+
+                Ok(hyper::Response::builder()
+                    .body(Body::from(
+                        r##"{
+                            "version": 1,
+                            "stage": "ExecutionRequest",
+                            "control": "continue",
+                            "queryPlan": { "kind": "Parallel", "nodes": [] }
+                        }"##,
+                    ))
+                    .unwrap())
+            })
+        });
+
+        let service = execution_stage.as_service(
+            mock_http_client,
+            mock_execution_service.boxed(),
+            "http://test".to_string(),
+            Arc::new("".to_string()),
+        );
+
+        let request = execution::Request::fake_builder().build();
+
+        assert_eq!(
+            serde_json_bytes::json!({ "test": 1234_u32 }),
+            service
+                .oneshot(request)
+                .await
+                .unwrap()
+                .next_response()
+                .await
+                .unwrap()
+                .data
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn external_plugin_supergraph_response() {
+        let supergraph_stage = SupergraphStage {
+            response: SupergraphResponseConf {
+                headers: false,
+                context: true,
+                body: true,
+                sdl: false,
+                status_code: true,
+            },
+            request: Default::default(),
+        };
+
+        let mut mock_supergraph_service = MockSupergraphService::new();
+
+        mock_supergraph_service
+            .expect_call()
+            .returning(|req: supergraph::Request| {
+                Ok(supergraph::Response::builder()
+                    .data(json!({ "test": 1234_u32 }))
+                    .context(req.context)
+                    .build()
+                    .unwrap())
+            });
+
+        let mock_http_client = mock_with_callback(move |res: hyper::Request<Body>| {
+            Box::pin(async {
+                let deserialized_response: Externalizable<serde_json::Value> =
+                    serde_json::from_slice(&hyper::body::to_bytes(res.into_body()).await.unwrap())
+                        .unwrap();
+
+                assert_eq!(
+                    PipelineStep::SupergraphResponse.to_string(),
+                    deserialized_response.stage
+                );
+                assert_eq!(Some(200), deserialized_response.status_code);
+                assert_eq!(
+                    json!({ "data": { "test": 1234_u32 } }),
+                    deserialized_response.body.unwrap()
+                );
+
+                Ok(hyper::Response::builder()
+                    .body(Body::from(
+                        r##"{
+                            "version": 1,
+                            "stage": "SupergraphResponse",
+                            "control": {
+                                "break": 400
+                            },
+                            "body": {
+                                "data": { "test": 42 }
+                            },
+                            "context": {
+                                "entries": {
+                                    "this-is-a-test-context": 42
+                                }
+                            }
+                        }"##,
+                    ))
+                    .unwrap())
+            })
+        });
+
+        let service = supergraph_stage.as_service(
+            mock_http_client,
+            mock_supergraph_service.boxed(),
+            "http://test".to_string(),
+            Arc::new("".to_string()),
+        );
+
+        let request = supergraph::Request::canned_builder().build().unwrap();
+
+        let mut res = service.oneshot(request).await.unwrap();
+
+        assert_eq!(res.response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            res.context
+                .get::<&str, u8>("this-is-a-test-context")
+                .unwrap()
+                .unwrap(),
+            42
+        );
+        assert_eq!(
+            serde_json_bytes::json!({ "test": 42_u32 }),
+            res.next_response().await.unwrap().data.unwrap()
+        );
+        assert!(res.next_response().await.is_none());
+    }
+
     #[test]
     fn it_externalizes_headers() {
         // Build our expected HashMap
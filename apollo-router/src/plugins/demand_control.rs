@@ -0,0 +1,754 @@
+//! Static cost analysis of operations.
+//!
+//! The estimated cost of an operation is computed from its selections and its query plan before
+//! execution, and operations above the configured maximum can be rejected. The actual cost is
+//! computed from the primary response, to compare it with the estimation.
+//!
+//! Cost model:
+//! * composite fields (objects, interfaces, unions) have a default weight of 1, leaf fields 0.
+//!   Weights can be set with the `@cost(weight:)` directive in the schema, or in the configuration
+//! * list fields multiply the cost of their selections by the value of a slicing argument
+//!   (`first`, `last`, `limit`...), or by the configured default list size
+//! * each subgraph request adds a configurable cost
+
+// This entire file is license key functionality
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::future::ready;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+
+use apollo_compiler::hir;
+use apollo_compiler::ApolloCompiler;
+use apollo_compiler::HirDatabase;
+use futures::stream::once;
+use futures::StreamExt;
+use http::StatusCode;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json_bytes::Value;
+use tower::BoxError;
+use tower::ServiceBuilder;
+use tower::ServiceExt;
+
+use crate::error::Error;
+use crate::json_ext::Object;
+use crate::layers::ServiceBuilderExt;
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::query_planner::QueryPlan;
+use crate::register_plugin;
+use crate::services::execution;
+use crate::services::subgraph;
+use crate::services::ExecutionRequest;
+use crate::services::ExecutionResponse;
+use crate::spec::FieldType;
+use crate::spec::Fragments;
+use crate::spec::IntArgument;
+use crate::spec::Schema;
+use crate::spec::Selection;
+use crate::spec::TYPENAME;
+use crate::Configuration;
+
+pub(crate) const ESTIMATED_COST_CONTEXT_KEY: &str = "apollo_demand_control::estimated_cost";
+pub(crate) const ACTUAL_COST_CONTEXT_KEY: &str = "apollo_demand_control::actual_cost";
+const SUBGRAPH_REQUESTS_CONTEXT_KEY: &str = "apollo_demand_control::subgraph_requests";
+const COST_ESTIMATED_TOO_EXPENSIVE: &str = "COST_ESTIMATED_TOO_EXPENSIVE";
+
+/// Demand control configuration
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct DemandControlConfig {
+    /// Maximum estimated cost of an operation
+    max: f64,
+    /// `enforce` rejects operations above the maximum cost, `measure` only reports costs
+    #[serde(default)]
+    mode: Mode,
+    /// Size assumed for list fields without a slicing argument
+    #[serde(default = "default_list_size")]
+    list_size: u32,
+    /// Arguments setting the size of the list returned by a field
+    #[serde(default = "default_slicing_arguments")]
+    slicing_arguments: Vec<String>,
+    /// Cost of each subgraph request
+    #[serde(default = "default_subgraph_request_cost")]
+    subgraph_request_cost: f64,
+    /// Field weights by schema coordinate (example: `Query.topProducts`).
+    /// They override the weights set by `@cost` directives
+    #[serde(default)]
+    field_weights: HashMap<String, f64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum Mode {
+    #[default]
+    Enforce,
+    Measure,
+}
+
+fn default_list_size() -> u32 {
+    10
+}
+
+fn default_slicing_arguments() -> Vec<String> {
+    vec!["first".to_string(), "last".to_string(), "limit".to_string()]
+}
+
+fn default_subgraph_request_cost() -> f64 {
+    1.0
+}
+
+#[derive(Debug)]
+struct CostCalculator {
+    schema: Schema,
+    field_weights: HashMap<String, f64>,
+    list_size: u32,
+    slicing_arguments: Vec<String>,
+    subgraph_request_cost: f64,
+}
+
+#[derive(Debug)]
+struct DemandControl {
+    calculator: Arc<CostCalculator>,
+    max: f64,
+    mode: Mode,
+}
+
+#[async_trait::async_trait]
+impl Plugin for DemandControl {
+    type Config = DemandControlConfig;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        let schema = Schema::parse(&init.supergraph_sdl, &Configuration::default(), None)?;
+        let mut field_weights = cost_directives(&init.supergraph_sdl);
+        field_weights.extend(init.config.field_weights);
+
+        Ok(DemandControl {
+            calculator: Arc::new(CostCalculator {
+                schema,
+                field_weights,
+                list_size: init.config.list_size,
+                slicing_arguments: init.config.slicing_arguments,
+                subgraph_request_cost: init.config.subgraph_request_cost,
+            }),
+            max: init.config.max,
+            mode: init.config.mode,
+        })
+    }
+
+    fn execution_service(&self, service: execution::BoxService) -> execution::BoxService {
+        let calculator = self.calculator.clone();
+        let actual_calculator = self.calculator.clone();
+        let max = self.max;
+        let mode = self.mode;
+
+        ServiceBuilder::new()
+            .checkpoint(move |req: ExecutionRequest| {
+                let body = req.supergraph_request.body();
+                // in measure mode, the full cost is estimated to be reported
+                let limit = match mode {
+                    Mode::Enforce => max,
+                    Mode::Measure => f64::INFINITY,
+                };
+                let estimated = calculator.estimated(
+                    &req.query_plan,
+                    body.operation_name.as_deref(),
+                    &body.variables,
+                    limit,
+                );
+
+                req.context.insert(ESTIMATED_COST_CONTEXT_KEY, estimated)?;
+                tracing::info!(histogram.apollo_router_operation_estimated_cost = estimated);
+
+                if mode == Mode::Enforce && estimated > max {
+                    tracing::info!(
+                        monotonic_counter.apollo_router_operation_cost_rejected_count = 1u64
+                    );
+                    let error = Error::builder()
+                        .message(format!(
+                            "the estimated cost of the operation ({estimated}) exceeds the maximum cost ({max})"
+                        ))
+                        .extension_code(COST_ESTIMATED_TOO_EXPENSIVE)
+                        .extension("cost.estimated", estimated)
+                        .extension("cost.max", max)
+                        .build();
+                    let res = ExecutionResponse::builder()
+                        .error(error)
+                        .status_code(StatusCode::BAD_REQUEST)
+                        .context(req.context)
+                        .build()?;
+                    Ok(ControlFlow::Break(res))
+                } else {
+                    Ok(ControlFlow::Continue(req))
+                }
+            })
+            .map_future_with_request_data(
+                |req: &ExecutionRequest| {
+                    let body = req.supergraph_request.body();
+                    (
+                        req.query_plan.clone(),
+                        body.operation_name.clone(),
+                        body.variables.clone(),
+                    )
+                },
+                move |(query_plan, operation_name, variables): (
+                    Arc<QueryPlan>,
+                    Option<String>,
+                    Object,
+                ),
+                      f| {
+                    let calculator = actual_calculator.clone();
+                    async move {
+                        let mut res: ExecutionResponse = f.await?;
+                        let (parts, stream) = res.response.into_parts();
+                        let (first, rest) = stream.into_future().await;
+
+                        if let Some(first) = first.as_ref() {
+                            let subgraph_requests: u64 = res
+                                .context
+                                .get(SUBGRAPH_REQUESTS_CONTEXT_KEY)
+                                .ok()
+                                .flatten()
+                                .unwrap_or_default();
+                            let actual = calculator.actual(
+                                &query_plan,
+                                operation_name.as_deref(),
+                                &variables,
+                                first.data.as_ref(),
+                                subgraph_requests,
+                            );
+                            res.context.insert(ACTUAL_COST_CONTEXT_KEY, actual)?;
+                            tracing::info!(histogram.apollo_router_operation_actual_cost = actual);
+
+                            let estimated: Option<f64> =
+                                res.context.get(ESTIMATED_COST_CONTEXT_KEY).ok().flatten();
+                            if let Some(estimated) = estimated {
+                                tracing::info!(
+                                    histogram.apollo_router_operation_cost_delta =
+                                        actual - estimated
+                                );
+                                tracing::debug!(
+                                    "operation cost: estimated {estimated}, actual {actual}"
+                                );
+                            }
+                        }
+
+                        res.response = http::Response::from_parts(
+                            parts,
+                            once(ready(first.unwrap_or_default())).chain(rest).boxed(),
+                        );
+                        Ok(res)
+                    }
+                },
+            )
+            .service(service)
+            .boxed()
+    }
+
+    fn subgraph_service(&self, _name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
+        ServiceBuilder::new()
+            .map_request(|req: subgraph::Request| {
+                let _ = req
+                    .context
+                    .upsert(SUBGRAPH_REQUESTS_CONTEXT_KEY, |count: u64| count + 1);
+                req
+            })
+            .service(service)
+            .boxed()
+    }
+}
+
+/// Collects the weights set by `@cost(weight:)` directives on object and interface fields
+fn cost_directives(sdl: &str) -> HashMap<String, f64> {
+    let mut compiler = ApolloCompiler::new();
+    compiler.add_type_system(sdl, "schema.graphql");
+
+    fn weight(field: &hir::FieldDefinition) -> Option<f64> {
+        match field
+            .directive_by_name("cost")?
+            .argument_by_name("weight")?
+        {
+            hir::Value::Int(weight) | hir::Value::Float(weight) => Some(weight.get()),
+            _ => None,
+        }
+    }
+
+    let mut weights = HashMap::new();
+    for (type_name, ty) in compiler.db.object_types().iter() {
+        for field in ty.self_fields() {
+            if let Some(weight) = weight(field) {
+                weights.insert(format!("{type_name}.{}", field.name()), weight);
+            }
+        }
+    }
+    for (type_name, ty) in compiler.db.interfaces().iter() {
+        for field in ty.self_fields() {
+            if let Some(weight) = weight(field) {
+                weights.insert(format!("{type_name}.{}", field.name()), weight);
+            }
+        }
+    }
+    weights
+}
+
+fn is_list(field_type: &FieldType) -> bool {
+    match field_type {
+        FieldType::List(_) => true,
+        FieldType::NonNull(inner) => is_list(inner),
+        _ => false,
+    }
+}
+
+impl CostCalculator {
+    /// Estimates the cost of an operation. The estimation stops as soon as the cost is above
+    /// `max`, and then returns a cost above `max`, but lower than the full estimation
+    fn estimated(
+        &self,
+        query_plan: &QueryPlan,
+        operation_name: Option<&str>,
+        variables: &Object,
+        max: f64,
+    ) -> f64 {
+        let query = &query_plan.query;
+        let selections_cost = query
+            .operation(operation_name)
+            .map(|operation| {
+                let root_type = self.schema.root_operation_name(*operation.kind());
+                let mut visitor = EstimatedCostVisitor {
+                    calculator: self,
+                    fragments: &query.fragments,
+                    variables,
+                    max,
+                    fragment_costs: HashMap::new(),
+                    active_fragments: Vec::new(),
+                };
+                visitor.selection_set(root_type, operation.selection_set())
+            })
+            .unwrap_or_default();
+
+        selections_cost + query_plan.root.subgraph_fetches() as f64 * self.subgraph_request_cost
+    }
+
+    fn field_weight(&self, parent_type: &str, name: &str, is_composite: bool) -> f64 {
+        self.field_weights
+            .get(&format!("{parent_type}.{name}"))
+            .copied()
+            .unwrap_or(if is_composite { 1.0 } else { 0.0 })
+    }
+
+    fn list_size(&self, int_arguments: &[(String, IntArgument)], variables: &Object) -> f64 {
+        int_arguments
+            .iter()
+            .filter(|(name, _)| self.slicing_arguments.contains(name))
+            .find_map(|(_, value)| value.eval(variables))
+            .map(|size| size.max(0) as f64)
+            .unwrap_or(self.list_size as f64)
+    }
+
+    fn actual(
+        &self,
+        query_plan: &QueryPlan,
+        operation_name: Option<&str>,
+        variables: &Object,
+        data: Option<&Value>,
+        subgraph_requests: u64,
+    ) -> f64 {
+        let query = &query_plan.query;
+        let selections_cost = match (query.operation(operation_name), data) {
+            (Some(operation), Some(Value::Object(data))) => {
+                let root_type = self.schema.root_operation_name(*operation.kind());
+                let mut visitor = ActualCostVisitor {
+                    calculator: self,
+                    fragments: &query.fragments,
+                    variables,
+                };
+                visitor.object(root_type, operation.selection_set(), data)
+            }
+            _ => 0.0,
+        };
+
+        selections_cost + subgraph_requests as f64 * self.subgraph_request_cost
+    }
+}
+
+struct EstimatedCostVisitor<'a> {
+    calculator: &'a CostCalculator,
+    fragments: &'a Fragments,
+    variables: &'a Object,
+    max: f64,
+    /// fragments are estimated once, however many times they are spread
+    fragment_costs: HashMap<&'a str, f64>,
+    /// fragments being estimated, to stop on (invalid) fragment cycles
+    active_fragments: Vec<&'a str>,
+}
+
+impl<'a> EstimatedCostVisitor<'a> {
+    fn selection_set(&mut self, parent_type: &str, selection_set: &'a [Selection]) -> f64 {
+        let mut cost = 0.0;
+        for selection in selection_set {
+            if cost > self.max {
+                break;
+            }
+            match selection {
+                Selection::Field {
+                    name,
+                    selection_set,
+                    field_type,
+                    include_skip,
+                    int_arguments,
+                    ..
+                } => {
+                    if include_skip.should_skip(self.variables) || name.as_str() == TYPENAME {
+                        continue;
+                    }
+                    let weight = self.calculator.field_weight(
+                        parent_type,
+                        name.as_str(),
+                        selection_set.is_some(),
+                    );
+
+                    let children = match (selection_set, field_type.inner_type_name()) {
+                        (Some(selection_set), Some(field_type)) => {
+                            self.selection_set(field_type, selection_set)
+                        }
+                        _ => 0.0,
+                    };
+
+                    let size = if is_list(field_type) {
+                        self.calculator.list_size(int_arguments, self.variables)
+                    } else {
+                        1.0
+                    };
+                    cost += size * (weight + children);
+                }
+                Selection::InlineFragment {
+                    type_condition,
+                    include_skip,
+                    selection_set,
+                    ..
+                } => {
+                    if !include_skip.should_skip(self.variables) {
+                        cost += self.selection_set(type_condition, selection_set);
+                    }
+                }
+                Selection::FragmentSpread {
+                    name, include_skip, ..
+                } => {
+                    if !include_skip.should_skip(self.variables) {
+                        cost += self.fragment(name);
+                    }
+                }
+            }
+        }
+        cost
+    }
+
+    fn fragment(&mut self, name: &str) -> f64 {
+        let (name, fragment) = match self.fragments.map.get_key_value(name) {
+            Some(fragment) => fragment,
+            None => return 0.0,
+        };
+        if let Some(cost) = self.fragment_costs.get(name.as_str()) {
+            return *cost;
+        }
+        if self.active_fragments.contains(&name.as_str()) {
+            return 0.0;
+        }
+
+        self.active_fragments.push(name.as_str());
+        let cost = self.selection_set(&fragment.type_condition, &fragment.selection_set);
+        self.active_fragments.pop();
+        self.fragment_costs.insert(name.as_str(), cost);
+        cost
+    }
+}
+
+struct ActualCostVisitor<'a> {
+    calculator: &'a CostCalculator,
+    fragments: &'a Fragments,
+    variables: &'a Object,
+}
+
+/// Response keys and fragments already visited in an object
+#[derive(Default)]
+struct Seen<'a> {
+    keys: HashSet<&'a str>,
+    fragments: HashSet<&'a str>,
+}
+
+impl<'a> ActualCostVisitor<'a> {
+    fn object(&mut self, parent_type: &str, selection_set: &'a [Selection], data: &Object) -> f64 {
+        let typename = data
+            .get(TYPENAME)
+            .and_then(|t| t.as_str())
+            .unwrap_or(parent_type)
+            .to_string();
+        let mut seen = Seen::default();
+        self.selection_set(&typename, parent_type, selection_set, data, &mut seen)
+    }
+
+    fn selection_set(
+        &mut self,
+        typename: &str,
+        parent_type: &str,
+        selection_set: &'a [Selection],
+        data: &Object,
+        seen: &mut Seen<'a>,
+    ) -> f64 {
+        let mut cost = 0.0;
+        for selection in selection_set {
+            match selection {
+                Selection::Field {
+                    name,
+                    alias,
+                    selection_set,
+                    field_type,
+                    include_skip,
+                    ..
+                } => {
+                    let key = alias.as_ref().unwrap_or(name).as_str();
+                    if include_skip.should_skip(self.variables)
+                        || name.as_str() == TYPENAME
+                        || !seen.keys.insert(key)
+                    {
+                        continue;
+                    }
+
+                    let weight = self.calculator.field_weight(
+                        parent_type,
+                        name.as_str(),
+                        selection_set.is_some(),
+                    );
+                    let value = match data.get(key) {
+                        Some(value) if !value.is_null() => value,
+                        _ => continue,
+                    };
+
+                    cost += match (selection_set, field_type.inner_type_name()) {
+                        (Some(selection_set), Some(field_type)) => {
+                            self.value(weight, field_type, selection_set, value)
+                        }
+                        _ => weight,
+                    };
+                }
+                Selection::InlineFragment {
+                    type_condition,
+                    include_skip,
+                    selection_set,
+                    ..
+                } => {
+                    if !include_skip.should_skip(self.variables)
+                        && self.applies(type_condition, typename)
+                    {
+                        cost +=
+                            self.selection_set(typename, type_condition, selection_set, data, seen);
+                    }
+                }
+                Selection::FragmentSpread {
+                    name, include_skip, ..
+                } => {
+                    if include_skip.should_skip(self.variables) {
+                        continue;
+                    }
+                    if let Some((name, fragment)) = self.fragments.map.get_key_value(name) {
+                        // the fields of a fragment already spread in this object are all seen
+                        if self.applies(&fragment.type_condition, typename)
+                            && seen.fragments.insert(name.as_str())
+                        {
+                            cost += self.selection_set(
+                                typename,
+                                &fragment.type_condition,
+                                &fragment.selection_set,
+                                data,
+                                seen,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        cost
+    }
+
+    /// Cost of a composite field's value, which can be a (nested) list of objects
+    fn value(
+        &mut self,
+        weight: f64,
+        field_type: &str,
+        selection_set: &'a [Selection],
+        value: &Value,
+    ) -> f64 {
+        match value {
+            Value::Object(object) => weight + self.object(field_type, selection_set, object),
+            Value::Array(items) => items
+                .iter()
+                .map(|item| self.value(weight, field_type, selection_set, item))
+                .sum(),
+            _ => 0.0,
+        }
+    }
+
+    fn applies(&self, type_condition: &str, typename: &str) -> bool {
+        type_condition == typename || self.calculator.schema.is_subtype(type_condition, typename)
+    }
+}
+
+register_plugin!("apollo", "preview_demand_control", DemandControl);
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::plugin::test::MockSubgraph;
+    use crate::services::supergraph;
+    use crate::spec::Query;
+    use crate::test_harness::MockedSubgraphs;
+    use crate::TestHarness;
+
+    const SCHEMA: &str = include_str!("../../testing_schema.graphql");
+
+    async fn calculator(field_weights: serde_json::Value) -> Arc<CostCalculator> {
+        let config = serde_json::from_value(json!({
+            "max": 100,
+            "field_weights": field_weights,
+        }))
+        .unwrap();
+        DemandControl::new(PluginInit::new(config, Arc::new(SCHEMA.to_string())))
+            .await
+            .unwrap()
+            .calculator
+    }
+
+    fn query_plan(query: &str) -> QueryPlan {
+        let schema = Schema::parse_test(SCHEMA, &Default::default()).unwrap();
+        let mut plan = QueryPlan::fake_builder().build();
+        plan.query = Arc::new(Query::parse(query, &schema, &Default::default()).unwrap());
+        plan
+    }
+
+    #[tokio::test]
+    async fn it_estimates_costs() {
+        let calculator = calculator(json!({})).await;
+
+        // me: 1, name: 0
+        let plan = query_plan("{ me { name } }");
+        assert_eq!(
+            calculator.estimated(&plan, None, &Object::new(), f64::INFINITY),
+            1.0
+        );
+
+        // topProducts: 3 * (1 + reviews: 10 * 1)
+        let plan = query_plan("{ topProducts(first: 3) { upc reviews { id } } }");
+        assert_eq!(
+            calculator.estimated(&plan, None, &Object::new(), f64::INFINITY),
+            33.0
+        );
+
+        let plan = query_plan("query($n: Int) { topProducts(first: $n) { upc reviews { id } } }");
+        let variables = serde_json_bytes::json!({"n": 1});
+        assert_eq!(
+            calculator.estimated(&plan, None, variables.as_object().unwrap(), f64::INFINITY),
+            11.0
+        );
+    }
+
+    #[tokio::test]
+    async fn it_uses_configured_weights() {
+        let calculator = calculator(json!({"Query.me": 5, "User.name": 2})).await;
+        let plan = query_plan("{ me { name } }");
+        assert_eq!(
+            calculator.estimated(&plan, None, &Object::new(), f64::INFINITY),
+            7.0
+        );
+    }
+
+    #[tokio::test]
+    async fn it_estimates_each_fragment_once() {
+        let calculator = calculator(json!({})).await;
+
+        // each fragment spreads the previous one twice: expanding them would visit 2^40 fields
+        let mut query = String::from("{ me { ...F40 } }\nfragment F0 on User { reviews { id } }\n");
+        for i in 1..=40 {
+            query.push_str(&format!(
+                "fragment F{i} on User {{ ...F{previous} ...F{previous} }}\n",
+                previous = i - 1
+            ));
+        }
+        let plan = query_plan(&query);
+
+        // me: 1 + 2^40 * reviews: 10 * 1
+        assert_eq!(
+            calculator.estimated(&plan, None, &Object::new(), f64::INFINITY),
+            1.0 + 2f64.powi(40) * 10.0
+        );
+        // the estimation stops above the maximum
+        let estimated = calculator.estimated(&plan, None, &Object::new(), 100.0);
+        assert!(estimated > 100.0);
+
+        let data = serde_json_bytes::json!({"me": {"reviews": [{"id": "1"}]}});
+        assert_eq!(
+            calculator.actual(&plan, None, &Object::new(), Some(&data), 1),
+            3.0
+        );
+    }
+
+    #[tokio::test]
+    async fn it_computes_actual_costs() {
+        let calculator = calculator(json!({})).await;
+        let plan = query_plan("{ topProducts { upc reviews { id } } }");
+        let data = serde_json_bytes::json!({
+            "topProducts": [
+                {"upc": "1", "reviews": [{"id": "1"}, {"id": "2"}]},
+                {"upc": "2", "reviews": []},
+                null
+            ]
+        });
+
+        assert_eq!(
+            calculator.actual(&plan, None, &Object::new(), Some(&data), 2),
+            6.0
+        );
+    }
+
+    #[tokio::test]
+    async fn it_rejects_expensive_operations() {
+        let service = TestHarness::builder()
+            .configuration_json(json!({
+                "preview_demand_control": {
+                    "max": 10
+                }
+            }))
+            .unwrap()
+            .schema(SCHEMA)
+            .extra_plugin(MockedSubgraphs(
+                [("products", MockSubgraph::builder().build())]
+                    .into_iter()
+                    .collect(),
+            ))
+            .build_supergraph()
+            .await
+            .unwrap();
+
+        let request = supergraph::Request::fake_builder()
+            .query("{ topProducts { upc reviews { id } } }")
+            .build()
+            .unwrap();
+
+        let response = service
+            .oneshot(request)
+            .await
+            .unwrap()
+            .next_response()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.errors[0].extensions.get("code").unwrap(),
+            COST_ESTIMATED_TOO_EXPENSIVE
+        );
+    }
+}
@@ -0,0 +1,454 @@
+//! Restricts the fields an operation can select depending on the authenticated client.
+//!
+//! A JWT claim (set by the authentication plugin) is mapped to a list of schema coordinates
+//! (`Type.field`). Operations selecting a field outside of that list are rejected before
+//! execution.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+
+use http::StatusCode;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json_bytes::Value;
+use tower::BoxError;
+use tower::ServiceBuilder;
+use tower::ServiceExt;
+
+use crate::configuration::ConfigurationError;
+use crate::error::Error;
+use crate::json_ext::Object;
+use crate::layers::ServiceBuilderExt;
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::plugins::authentication::APOLLO_AUTHENTICATION_JWT_CLAIMS;
+use crate::register_plugin;
+use crate::services::execution;
+use crate::services::ExecutionRequest;
+use crate::services::ExecutionResponse;
+use crate::spec::Fragments;
+use crate::spec::Query;
+use crate::spec::Schema;
+use crate::spec::Selection;
+use crate::spec::TYPENAME;
+use crate::Configuration;
+
+const FIELD_NOT_ALLOWED: &str = "FIELD_NOT_ALLOWED";
+
+/// Graph allow list configuration
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct GraphAllowListConfig {
+    /// Name of the JWT claim used to select the allow list (example: `role`).
+    /// The claim can be a string or a list of strings
+    claim: String,
+    /// Maps claim values to the schema coordinates they are allowed to query
+    /// (example: `Query.me`, `User.name`)
+    allow_list: HashMap<String, Vec<String>>,
+    /// Schema coordinates allowed when the request has no claim, or a claim value
+    /// missing from `allow_list`. If not set, those requests are rejected
+    #[serde(default)]
+    default: Option<Vec<String>>,
+}
+
+#[derive(Debug)]
+struct GraphAllowList {
+    claim: String,
+    allow_list: Arc<HashMap<String, HashSet<String>>>,
+    default: Option<Arc<HashSet<String>>>,
+    schema: Arc<Schema>,
+}
+
+#[async_trait::async_trait]
+impl Plugin for GraphAllowList {
+    type Config = GraphAllowListConfig;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        let schema = Schema::parse(&init.supergraph_sdl, &Configuration::default(), None)?;
+
+        let mut unknown: Vec<&str> = init
+            .config
+            .allow_list
+            .values()
+            .chain(init.config.default.iter())
+            .flatten()
+            .filter(|coordinate| !is_schema_coordinate(&schema, coordinate))
+            .map(String::as_str)
+            .collect();
+        if !unknown.is_empty() {
+            unknown.sort_unstable();
+            unknown.dedup();
+            return Err(ConfigurationError::InvalidConfiguration {
+                message: "bad configuration for graph_allow_list plugin",
+                error: format!("unknown schema coordinates: {}", unknown.join(", ")),
+            }
+            .into());
+        }
+
+        let allow_list = init
+            .config
+            .allow_list
+            .into_iter()
+            .map(|(claim_value, coordinates)| (claim_value, coordinates.into_iter().collect()))
+            .collect();
+
+        Ok(GraphAllowList {
+            claim: init.config.claim,
+            allow_list: Arc::new(allow_list),
+            default: init
+                .config
+                .default
+                .map(|coordinates| Arc::new(coordinates.into_iter().collect())),
+            schema: Arc::new(schema),
+        })
+    }
+
+    fn execution_service(&self, service: execution::BoxService) -> execution::BoxService {
+        let claim = self.claim.clone();
+        let allow_list = self.allow_list.clone();
+        let default = self.default.clone();
+        let schema = self.schema.clone();
+
+        ServiceBuilder::new()
+            .checkpoint(move |req: ExecutionRequest| {
+                let claims: Option<serde_json::Value> =
+                    req.context.get(APOLLO_AUTHENTICATION_JWT_CLAIMS)?;
+                let claim_values = claims
+                    .as_ref()
+                    .and_then(|claims| claims.get(&claim))
+                    .map(claim_values)
+                    .unwrap_or_default();
+
+                let mut allowed: Vec<&HashSet<String>> = claim_values
+                    .iter()
+                    .filter_map(|value| allow_list.get(*value))
+                    .collect();
+                if allowed.is_empty() {
+                    if let Some(default) = default.as_deref() {
+                        allowed.push(default);
+                    }
+                }
+
+                let body = req.supergraph_request.body();
+                let rejected = rejected_fields(
+                    &req.query_plan.query,
+                    body.operation_name.as_deref(),
+                    &body.variables,
+                    &schema,
+                    &allowed,
+                );
+
+                if rejected.is_empty() {
+                    Ok(ControlFlow::Continue(req))
+                } else {
+                    tracing::debug!("operation rejected by the graph allow list: {rejected:?}");
+                    let error = Error::builder()
+                        .message(format!(
+                            "the operation selects fields that are not allowed: {}",
+                            rejected.join(", ")
+                        ))
+                        .extension_code(FIELD_NOT_ALLOWED)
+                        .extension(
+                            "rejectedFields",
+                            rejected.into_iter().map(Value::from).collect::<Vec<_>>(),
+                        )
+                        .build();
+                    let res = ExecutionResponse::builder()
+                        .error(error)
+                        .status_code(StatusCode::FORBIDDEN)
+                        .context(req.context)
+                        .build()?;
+                    Ok(ControlFlow::Break(res))
+                }
+            })
+            .service(service)
+            .boxed()
+    }
+}
+
+/// Returns true if the coordinate (`Type.field`) is a field of an object or interface type
+fn is_schema_coordinate(schema: &Schema, coordinate: &str) -> bool {
+    let (type_name, field_name) = match coordinate.split_once('.') {
+        Some(parts) => parts,
+        None => return false,
+    };
+    schema
+        .object_types
+        .get(type_name)
+        .map(|object| &object.fields)
+        .or_else(|| {
+            schema
+                .interfaces
+                .get(type_name)
+                .map(|interface| &interface.fields)
+        })
+        .map(|fields| fields.contains_key(field_name))
+        .unwrap_or(false)
+}
+
+/// A claim can hold a single value or a list of values
+fn claim_values(claim: &serde_json::Value) -> Vec<&str> {
+    match claim {
+        serde_json::Value::String(value) => vec![value.as_str()],
+        serde_json::Value::Array(values) => values.iter().filter_map(|v| v.as_str()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Returns the coordinates of the selected fields that none of the allow lists contain,
+/// sorted and deduplicated
+fn rejected_fields(
+    query: &Query,
+    operation_name: Option<&str>,
+    variables: &Object,
+    schema: &Schema,
+    allowed: &[&HashSet<String>],
+) -> Vec<String> {
+    let mut visitor = AllowListVisitor {
+        fragments: &query.fragments,
+        variables,
+        allowed,
+        rejected: Vec::new(),
+        visited_fragments: HashSet::new(),
+    };
+
+    if let Some(operation) = query.operation(operation_name) {
+        let root_type = schema.root_operation_name(*operation.kind());
+        visitor.selection_set(root_type, operation.selection_set());
+    }
+
+    let mut rejected = visitor.rejected;
+    rejected.sort();
+    rejected.dedup();
+    rejected
+}
+
+struct AllowListVisitor<'a> {
+    fragments: &'a Fragments,
+    variables: &'a Object,
+    allowed: &'a [&'a HashSet<String>],
+    rejected: Vec<String>,
+    visited_fragments: HashSet<&'a str>,
+}
+
+impl<'a> AllowListVisitor<'a> {
+    fn selection_set(&mut self, parent_type: &str, selection_set: &'a [Selection]) {
+        for selection in selection_set {
+            match selection {
+                Selection::Field {
+                    name,
+                    selection_set,
+                    field_type,
+                    include_skip,
+                    ..
+                } => {
+                    if include_skip.should_skip(self.variables) || name.as_str() == TYPENAME {
+                        continue;
+                    }
+
+                    let coordinate = format!("{parent_type}.{}", name.as_str());
+                    if !self.allowed.iter().any(|list| list.contains(&coordinate)) {
+                        self.rejected.push(coordinate);
+                    }
+
+                    if let (Some(selection_set), Some(field_type)) =
+                        (selection_set, field_type.inner_type_name())
+                    {
+                        self.selection_set(field_type, selection_set);
+                    }
+                }
+                Selection::InlineFragment {
+                    type_condition,
+                    include_skip,
+                    selection_set,
+                    ..
+                } => {
+                    if !include_skip.should_skip(self.variables) {
+                        self.selection_set(type_condition, selection_set);
+                    }
+                }
+                Selection::FragmentSpread {
+                    name, include_skip, ..
+                } => {
+                    if include_skip.should_skip(self.variables) {
+                        continue;
+                    }
+                    // a fragment's fields only need to be checked once
+                    if let Some((name, fragment)) = self.fragments.map.get_key_value(name) {
+                        if self.visited_fragments.insert(name.as_str()) {
+                            self.selection_set(&fragment.type_condition, &fragment.selection_set);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+register_plugin!("apollo", "graph_allow_list", GraphAllowList);
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::plugin::test::MockSubgraph;
+    use crate::services::supergraph;
+    use crate::test_harness::MockedSubgraphs;
+    use crate::Context;
+    use crate::TestHarness;
+
+    async fn execute(claims: Option<serde_json::Value>, query: &str) -> crate::graphql::Response {
+        let subgraphs = MockedSubgraphs(
+            [
+                (
+                    "accounts",
+                    MockSubgraph::builder()
+                        .with_json(
+                            json! {{"query":"{me{name}}"}},
+                            json! {{"data": {"me": {"name": "Ada"}}}},
+                        )
+                        .with_json(
+                            json! {{"query":"{me{id name}}"}},
+                            json! {{"data": {"me": {"id": "1", "name": "Ada"}}}},
+                        )
+                        .build(),
+                ),
+                ("products", MockSubgraph::builder().build()),
+                ("reviews", MockSubgraph::builder().build()),
+                ("inventory", MockSubgraph::builder().build()),
+            ]
+            .into_iter()
+            .collect(),
+        );
+
+        let service = TestHarness::builder()
+            .configuration_json(json!({
+                "graph_allow_list": {
+                    "claim": "role",
+                    "allow_list": {
+                        "user": ["Query.me", "User.name"],
+                        "admin": ["Query.me", "Query.topProducts", "User.id", "User.name"]
+                    }
+                }
+            }))
+            .unwrap()
+            .schema(include_str!("../../testing_schema.graphql"))
+            .extra_plugin(subgraphs)
+            .build_supergraph()
+            .await
+            .unwrap();
+
+        let context = Context::new();
+        if let Some(claims) = claims {
+            context
+                .insert(APOLLO_AUTHENTICATION_JWT_CLAIMS, claims)
+                .unwrap();
+        }
+        let request = supergraph::Request::fake_builder()
+            .context(context)
+            .query(query)
+            .build()
+            .unwrap();
+
+        service
+            .oneshot(request)
+            .await
+            .unwrap()
+            .next_response()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_rejects_unknown_coordinates() {
+        let result = TestHarness::builder()
+            .configuration_json(json!({
+                "graph_allow_list": {
+                    "claim": "role",
+                    "allow_list": {
+                        "user": ["Query.me", "User.nickname", "Unknown.id"]
+                    },
+                    "default": ["Query"]
+                }
+            }))
+            .unwrap()
+            .schema(include_str!("../../testing_schema.graphql"))
+            .build_supergraph()
+            .await;
+
+        match result {
+            Ok(_) => panic!("unknown coordinates must be rejected"),
+            Err(err) => assert!(
+                err.to_string()
+                    .contains("unknown schema coordinates: Query, Unknown.id, User.nickname"),
+                "{err}"
+            ),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_allows_listed_fields() {
+        let response = execute(Some(json!({"role": "user"})), "{ me { name } }").await;
+        assert!(response.errors.is_empty());
+        assert_eq!(
+            response.data,
+            Some(serde_json_bytes::json!({"me": {"name": "Ada"}}))
+        );
+    }
+
+    #[tokio::test]
+    async fn it_rejects_unlisted_fields() {
+        let response = execute(
+            Some(json!({"role": "user"})),
+            "query { me { id name ... on User { __typename } } }",
+        )
+        .await;
+        assert!(response.data.is_none());
+        assert_eq!(response.errors.len(), 1);
+        assert_eq!(
+            response.errors[0].extensions.get("code").unwrap(),
+            FIELD_NOT_ALLOWED
+        );
+        assert_eq!(
+            response.errors[0].extensions.get("rejectedFields").unwrap(),
+            &serde_json_bytes::json!(["User.id"])
+        );
+    }
+
+    #[tokio::test]
+    async fn it_checks_fragments() {
+        let response = execute(
+            Some(json!({"role": "user"})),
+            "query { me { ...UserFields } } fragment UserFields on User { id name }",
+        )
+        .await;
+        assert_eq!(
+            response.errors[0].extensions.get("rejectedFields").unwrap(),
+            &serde_json_bytes::json!(["User.id"])
+        );
+    }
+
+    #[tokio::test]
+    async fn it_merges_multiple_claim_values() {
+        let response = execute(
+            Some(json!({"role": ["user", "admin"]})),
+            "{ me { id name } }",
+        )
+        .await;
+        assert!(response.errors.is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_rejects_requests_without_claims() {
+        let response = execute(None, "{ me { name } }").await;
+        assert_eq!(
+            response.errors[0].extensions.get("rejectedFields").unwrap(),
+            &serde_json_bytes::json!(["Query.me", "User.name"])
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::task::Context;
 use std::task::Poll;
 
@@ -34,19 +35,28 @@ use crate::plugin::serde::deserialize_option_header_value;
 use crate::plugin::serde::deserialize_regex;
 use crate::plugin::Plugin;
 use crate::plugin::PluginInit;
+use crate::plugins::authentication::APOLLO_AUTHENTICATION_JWT_CLAIMS;
+use crate::query_planner::fetch::OperationKind;
 use crate::register_plugin;
 use crate::services::subgraph;
+use crate::services::supergraph;
 use crate::services::SubgraphRequest;
+use crate::services::SubgraphResponse;
 
 register_plugin!("apollo", "headers", Headers);
 
+/// Headers collected from subgraph responses, to be set on the client response
+const RESPONSE_HEADERS_CONTEXT_KEY: &str = "apollo_headers::response_headers";
+
 #[derive(Clone, JsonSchema, Deserialize)]
 #[serde(rename_all = "snake_case", deny_unknown_fields)]
 struct HeadersLocation {
     /// Propagate/Insert/Remove headers from request
+    #[serde(default)]
     request: Vec<Operation>,
-    // Propagate/Insert/Remove headers from response
-    // response: Option<Operation>
+    /// Propagate/Insert headers from subgraph responses to the client response
+    #[serde(default)]
+    response: Vec<ResponseOperation>,
 }
 
 #[derive(Clone, JsonSchema, Deserialize)]
@@ -90,6 +100,8 @@ enum Insert {
     FromContext(InsertFromContext),
     /// Insert header with a value coming from body
     FromBody(InsertFromBody),
+    /// Insert header with a value built from a template
+    Template(InsertTemplate),
 }
 
 #[derive(Clone, JsonSchema, Deserialize)]
@@ -105,6 +117,10 @@ struct InsertStatic {
     #[schemars(with = "String")]
     #[serde(deserialize_with = "deserialize_header_value")]
     value: HeaderValue,
+
+    /// Only insert the header when this condition holds
+    #[serde(default)]
+    condition: Option<Condition>,
 }
 
 #[derive(Clone, JsonSchema, Deserialize)]
@@ -117,6 +133,9 @@ struct InsertFromContext {
     name: HeaderName,
     /// Specify context key to fetch value
     from_context: String,
+    /// Only insert the header when this condition holds
+    #[serde(default)]
+    condition: Option<Condition>,
 }
 
 #[derive(Clone, JsonSchema, Deserialize)]
@@ -137,6 +156,188 @@ struct InsertFromBody {
     #[schemars(with = "Option<String>", default)]
     #[serde(deserialize_with = "deserialize_option_header_value")]
     default: Option<HeaderValue>,
+
+    /// Only insert the header when this condition holds
+    #[serde(default)]
+    condition: Option<Condition>,
+}
+
+#[derive(Clone, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+/// Insert header with a value built from a template
+struct InsertTemplate {
+    /// The target header name
+    #[schemars(with = "String")]
+    #[serde(deserialize_with = "deserialize_header_name")]
+    name: HeaderName,
+
+    /// The template, for example `${jwt.sub}/${operation_name}`. Placeholders can be
+    /// `${jwt.<claim>}`, `${context.<key>}` or `${operation_name}`
+    #[schemars(with = "String")]
+    template: Template,
+
+    /// The default if a placeholder of the template did not resolve to a value
+    #[schemars(with = "Option<String>", default)]
+    #[serde(deserialize_with = "deserialize_option_header_value", default)]
+    default: Option<HeaderValue>,
+
+    /// Only insert the header when this condition holds
+    #[serde(default)]
+    condition: Option<Condition>,
+}
+
+/// A header value template, made of literal text and placeholders
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+struct Template(Vec<TemplatePart>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum TemplatePart {
+    Literal(String),
+    /// Path in the JWT claims
+    JwtClaim(Vec<String>),
+    /// Context key
+    Context(String),
+    OperationName,
+}
+
+impl TryFrom<String> for Template {
+    type Error = String;
+
+    fn try_from(template: String) -> Result<Self, Self::Error> {
+        let mut parts = Vec::new();
+        let mut rest = template.as_str();
+        while let Some(start) = rest.find("${") {
+            if start > 0 {
+                parts.push(TemplatePart::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed placeholder in template '{template}'"))?;
+            let placeholder = rest[start + 2..start + end].trim();
+            parts.push(match placeholder.split_once('.') {
+                Some(("jwt", path)) if !path.is_empty() => {
+                    TemplatePart::JwtClaim(path.split('.').map(str::to_string).collect())
+                }
+                Some(("context", key)) if !key.is_empty() => TemplatePart::Context(key.to_string()),
+                None if placeholder == "operation_name" => TemplatePart::OperationName,
+                _ => {
+                    return Err(format!(
+                        "unknown placeholder '{placeholder}' in template '{template}', expected \
+                        'jwt.<claim>', 'context.<key>' or 'operation_name'"
+                    ))
+                }
+            });
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Literal(rest.to_string()));
+        }
+        Ok(Template(parts))
+    }
+}
+
+impl Template {
+    /// Renders the template, or returns `None` if a placeholder did not resolve to a value
+    fn render(&self, req: &SubgraphRequest) -> Option<String> {
+        let mut rendered = String::new();
+        for part in &self.0 {
+            let value = match part {
+                TemplatePart::Literal(literal) => {
+                    rendered.push_str(literal);
+                    continue;
+                }
+                TemplatePart::JwtClaim(path) => {
+                    let claims = req
+                        .context
+                        .get_json_value(APOLLO_AUTHENTICATION_JWT_CLAIMS)?;
+                    path.iter()
+                        .try_fold(&claims, |value, segment| value.get(segment.as_str()))?
+                        .clone()
+                }
+                TemplatePart::Context(key) => req.context.get_json_value(key.as_str())?,
+                TemplatePart::OperationName => {
+                    req.supergraph_request.body().operation_name.clone()?.into()
+                }
+            };
+            match value {
+                serde_json_bytes::Value::Null => return None,
+                serde_json_bytes::Value::String(value) => rendered.push_str(value.as_str()),
+                value => rendered.push_str(&value.to_string()),
+            }
+        }
+        Some(rendered)
+    }
+}
+
+/// A condition on the subgraph request
+#[derive(Clone, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum Condition {
+    /// The subgraph operation is of this kind (`query`, `mutation` or `subscription`)
+    OperationKind(#[schemars(with = "String")] OperationKind),
+    /// A header of the client request matches a regex
+    HeaderMatches {
+        /// The header name
+        #[schemars(with = "String")]
+        #[serde(deserialize_with = "deserialize_header_name")]
+        name: HeaderName,
+        /// The regex on the header value
+        #[schemars(with = "String")]
+        #[serde(deserialize_with = "deserialize_regex")]
+        matching: Regex,
+    },
+    /// A context entry is present, and equal to a value if one is given
+    Context {
+        /// The context key
+        key: String,
+        /// The expected value
+        #[schemars(with = "Option<Value>", default)]
+        #[serde(default)]
+        equals: Option<serde_json_bytes::Value>,
+    },
+    /// All the conditions hold
+    All(Vec<Condition>),
+    /// At least one of the conditions holds
+    Any(Vec<Condition>),
+    /// The condition does not hold
+    Not(Box<Condition>),
+}
+
+impl Condition {
+    fn evaluate(&self, req: &SubgraphRequest) -> bool {
+        match self {
+            Condition::OperationKind(kind) => req.operation_kind == *kind,
+            Condition::HeaderMatches { name, matching } => req
+                .supergraph_request
+                .headers()
+                .get_all(name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .any(|value| matching.is_match(value)),
+            Condition::Context { key, equals } => match req.context.get_json_value(key.as_str()) {
+                Some(value) => equals.as_ref().map_or(true, |equals| *equals == value),
+                None => false,
+            },
+            Condition::All(conditions) => conditions.iter().all(|c| c.evaluate(req)),
+            Condition::Any(conditions) => conditions.iter().any(|c| c.evaluate(req)),
+            Condition::Not(condition) => !condition.evaluate(req),
+        }
+    }
+}
+
+impl Operation {
+    fn condition(&self) -> Option<&Condition> {
+        match self {
+            Operation::Insert(Insert::Static(insert)) => insert.condition.as_ref(),
+            Operation::Insert(Insert::FromContext(insert)) => insert.condition.as_ref(),
+            Operation::Insert(Insert::FromBody(insert)) => insert.condition.as_ref(),
+            Operation::Insert(Insert::Template(insert)) => insert.condition.as_ref(),
+            Operation::Remove(_) => None,
+            Operation::Propagate(Propagate::Named { condition, .. })
+            | Operation::Propagate(Propagate::Matching { condition, .. }) => condition.as_ref(),
+        }
+    }
 }
 
 schemar_fn!(
@@ -166,6 +367,75 @@ enum Propagate {
         #[schemars(with = "Option<String>", default)]
         #[serde(deserialize_with = "deserialize_option_header_value", default)]
         default: Option<HeaderValue>,
+
+        /// Only propagate the header when this condition holds
+        #[serde(default)]
+        condition: Option<Condition>,
+    },
+    /// Propagate header given a regex to match header name
+    Matching {
+        /// The regex on header name
+        #[schemars(schema_with = "propagate_matching")]
+        #[serde(deserialize_with = "deserialize_regex")]
+        matching: Regex,
+
+        /// Only propagate the headers when this condition holds
+        #[serde(default)]
+        condition: Option<Condition>,
+    },
+}
+
+#[derive(Clone, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum ResponseOperation {
+    Insert(ResponseInsert),
+    Propagate(ResponsePropagate),
+}
+
+#[derive(Clone, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+/// Insert header in the client response when the subgraph is called
+struct ResponseInsert {
+    /// The name of the header
+    #[schemars(with = "String")]
+    #[serde(deserialize_with = "deserialize_header_name")]
+    name: HeaderName,
+
+    /// The value for the header
+    #[schemars(with = "String")]
+    #[serde(deserialize_with = "deserialize_header_value")]
+    value: HeaderValue,
+
+    /// How to merge the header with the same header from other subgraphs
+    #[serde(default)]
+    merge: MergeStrategy,
+}
+
+#[derive(Clone, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+#[serde(untagged)]
+/// Propagate header from the subgraph response to the client response
+enum ResponsePropagate {
+    /// Propagate header given a header name
+    Named {
+        /// The source header name
+        #[schemars(with = "String")]
+        #[serde(deserialize_with = "deserialize_header_name")]
+        named: HeaderName,
+
+        /// An optional target header name
+        #[schemars(with = "Option<String>", default)]
+        #[serde(deserialize_with = "deserialize_option_header_name", default)]
+        rename: Option<HeaderName>,
+
+        /// Default value for the header.
+        #[schemars(with = "Option<String>", default)]
+        #[serde(deserialize_with = "deserialize_option_header_value", default)]
+        default: Option<HeaderValue>,
+
+        /// How to merge the header with the same header from other subgraphs
+        #[serde(default)]
+        merge: MergeStrategy,
     },
     /// Propagate header given a regex to match header name
     Matching {
@@ -173,9 +443,137 @@ enum Propagate {
         #[schemars(schema_with = "propagate_matching")]
         #[serde(deserialize_with = "deserialize_regex")]
         matching: Regex,
+
+        /// How to merge the headers with the same headers from other subgraphs
+        #[serde(default)]
+        merge: MergeStrategy,
     },
 }
 
+/// How to merge a header returned by several subgraphs
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum MergeStrategy {
+    /// Keep the value of the first subgraph response
+    First,
+    /// Keep the value of the last subgraph response
+    #[default]
+    Last,
+    /// Keep the values of all subgraph responses
+    Append,
+    /// Combine `Cache-Control` values, keeping the most restrictive directives
+    MostRestrictive,
+}
+
+impl MergeStrategy {
+    fn merge(self, current: &mut Vec<String>, values: Vec<String>) {
+        match self {
+            MergeStrategy::First => {
+                if current.is_empty() {
+                    *current = values;
+                }
+            }
+            MergeStrategy::Last => *current = values,
+            MergeStrategy::Append => current.extend(values),
+            MergeStrategy::MostRestrictive => {
+                *current = current
+                    .iter()
+                    .chain(values.iter())
+                    .map(|value| CacheControl::parse(value))
+                    .reduce(CacheControl::most_restrictive)
+                    .map(|cache_control| vec![cache_control.to_string()])
+                    .unwrap_or_default();
+            }
+        }
+    }
+}
+
+/// Directives of a `Cache-Control` header relevant to merging
+#[derive(Debug, Default, PartialEq, Eq)]
+struct CacheControl {
+    no_store: bool,
+    no_cache: bool,
+    private: bool,
+    public: bool,
+    must_revalidate: bool,
+    max_age: Option<u64>,
+    s_maxage: Option<u64>,
+}
+
+impl CacheControl {
+    fn parse(value: &str) -> Self {
+        let mut cache_control = CacheControl::default();
+        for directive in value.split(',') {
+            let (name, argument) = match directive.split_once('=') {
+                Some((name, argument)) => (name.trim(), Some(argument.trim().trim_matches('"'))),
+                None => (directive.trim(), None),
+            };
+            let seconds = argument.and_then(|argument| argument.parse().ok());
+            match name.to_ascii_lowercase().as_str() {
+                "no-store" => cache_control.no_store = true,
+                "no-cache" => cache_control.no_cache = true,
+                "private" => cache_control.private = true,
+                "public" => cache_control.public = true,
+                "must-revalidate" => cache_control.must_revalidate = true,
+                "max-age" => cache_control.max_age = seconds,
+                "s-maxage" => cache_control.s_maxage = seconds,
+                _ => {}
+            }
+        }
+        cache_control
+    }
+
+    fn most_restrictive(self, other: Self) -> Self {
+        fn min(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            }
+        }
+
+        let private = self.private || other.private;
+        CacheControl {
+            no_store: self.no_store || other.no_store,
+            no_cache: self.no_cache || other.no_cache,
+            private,
+            public: !private && self.public && other.public,
+            must_revalidate: self.must_revalidate || other.must_revalidate,
+            max_age: min(self.max_age, other.max_age),
+            s_maxage: min(self.s_maxage, other.s_maxage),
+        }
+    }
+}
+
+impl fmt::Display for CacheControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.no_store {
+            return write!(f, "no-store");
+        }
+
+        let mut directives = Vec::new();
+        if self.private {
+            directives.push("private".to_string());
+        }
+        if self.public {
+            directives.push("public".to_string());
+        }
+        if self.no_cache {
+            directives.push("no-cache".to_string());
+        }
+        if self.must_revalidate {
+            directives.push("must-revalidate".to_string());
+        }
+        if let Some(max_age) = self.max_age {
+            directives.push(format!("max-age={max_age}"));
+        }
+        // shared caches do not store private responses
+        if let Some(s_maxage) = self.s_maxage.filter(|_| !self.private) {
+            directives.push(format!("s-maxage={s_maxage}"));
+        }
+        write!(f, "{}", directives.join(", "))
+    }
+}
+
 /// Configuration for header propagation
 #[derive(Clone, JsonSchema, Default, Deserialize)]
 #[serde(rename_all = "snake_case", deny_unknown_fields, default)]
@@ -199,6 +597,44 @@ impl Plugin for Headers {
             config: init.config,
         })
     }
+
+    fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
+        let has_response_operations = self
+            .config
+            .all
+            .iter()
+            .chain(self.config.subgraphs.values())
+            .any(|location| !location.response.is_empty());
+        if !has_response_operations {
+            return service;
+        }
+
+        ServiceBuilder::new()
+            .map_response(|mut response: supergraph::Response| {
+                let collected: Option<HashMap<String, Vec<String>>> = response
+                    .context
+                    .get(RESPONSE_HEADERS_CONTEXT_KEY)
+                    .ok()
+                    .flatten();
+                let headers = response.response.headers_mut();
+                for (name, values) in collected.into_iter().flatten() {
+                    let name = match HeaderName::try_from(name) {
+                        Ok(name) => name,
+                        Err(_) => continue,
+                    };
+                    headers.remove(&name);
+                    for value in values {
+                        if let Ok(value) = HeaderValue::try_from(value) {
+                            headers.append(&name, value);
+                        }
+                    }
+                }
+                response
+            })
+            .service(service)
+            .boxed()
+    }
+
     fn subgraph_service(&self, name: &str, service: subgraph::BoxService) -> subgraph::BoxService {
         let mut operations: Vec<Operation> = self
             .config
@@ -211,14 +647,98 @@ impl Plugin for Headers {
         {
             operations.append(&mut subgraph_operations);
         }
+        let mut response_operations: Vec<ResponseOperation> = self
+            .config
+            .all
+            .as_ref()
+            .map(|a| a.response.clone())
+            .unwrap_or_default();
+        if let Some(mut subgraph_operations) =
+            self.config.subgraphs.get(name).map(|s| s.response.clone())
+        {
+            response_operations.append(&mut subgraph_operations);
+        }
+
+        if response_operations.is_empty() {
+            return ServiceBuilder::new()
+                .layer(HeadersLayer::new(operations))
+                .service(service)
+                .boxed();
+        }
 
         ServiceBuilder::new()
+            .map_response(move |response: SubgraphResponse| {
+                collect_response_headers(&response_operations, &response);
+                response
+            })
             .layer(HeadersLayer::new(operations))
             .service(service)
             .boxed()
     }
 }
 
+/// Stores the headers of a subgraph response selected by the rules in the context, merged with
+/// the headers of previous subgraph responses
+fn collect_response_headers(operations: &[ResponseOperation], response: &SubgraphResponse) {
+    let headers = response.response.headers();
+    let mut collected: Vec<(&HeaderName, Vec<&HeaderValue>, MergeStrategy)> = Vec::new();
+    for operation in operations {
+        match operation {
+            ResponseOperation::Insert(insert) => {
+                collected.push((&insert.name, vec![&insert.value], insert.merge));
+            }
+            ResponseOperation::Propagate(ResponsePropagate::Named {
+                named,
+                rename,
+                default,
+                merge,
+            }) => {
+                let mut values: Vec<&HeaderValue> = headers.get_all(named).iter().collect();
+                if values.is_empty() {
+                    values.extend(default.as_ref());
+                }
+                if !values.is_empty() {
+                    collected.push((rename.as_ref().unwrap_or(named), values, *merge));
+                }
+            }
+            ResponseOperation::Propagate(ResponsePropagate::Matching { matching, merge }) => {
+                headers
+                    .keys()
+                    .filter(|name| {
+                        !RESERVED_HEADERS.contains(name) && matching.is_match(name.as_str())
+                    })
+                    .for_each(|name| {
+                        collected.push((name, headers.get_all(name).iter().collect(), *merge));
+                    });
+            }
+        }
+    }
+    if collected.is_empty() {
+        return;
+    }
+
+    let result = response.context.upsert(
+        RESPONSE_HEADERS_CONTEXT_KEY,
+        |mut response_headers: HashMap<String, Vec<String>>| {
+            for (name, values, merge) in &collected {
+                let values = values
+                    .iter()
+                    .filter_map(|value| value.to_str().ok())
+                    .map(str::to_string)
+                    .collect();
+                merge.merge(
+                    response_headers.entry(name.to_string()).or_default(),
+                    values,
+                );
+            }
+            response_headers
+        },
+    );
+    if let Err(err) = result {
+        tracing::error!("cannot store the subgraph response headers in the context: {err}");
+    }
+}
+
 struct HeadersLayer {
     operations: Vec<Operation>,
 }
@@ -280,6 +800,11 @@ where
 
     fn call(&mut self, mut req: SubgraphRequest) -> Self::Future {
         for operation in &self.operations {
+            if let Some(condition) = operation.condition() {
+                if !condition.evaluate(&req) {
+                    continue;
+                }
+            }
             match operation {
                 Operation::Insert(insert_config) => match insert_config {
                     Insert::Static(static_insert) => {
@@ -334,6 +859,27 @@ where
                                 .insert(&from_body.name, default_val.clone());
                         }
                     }
+                    Insert::Template(insert_template) => {
+                        match insert_template.template.render(&req) {
+                            Some(value) => match HeaderValue::from_str(&value) {
+                                Ok(header_value) => {
+                                    req.subgraph_request
+                                        .headers_mut()
+                                        .insert(&insert_template.name, header_value);
+                                }
+                                Err(err) => {
+                                    tracing::error!("cannot convert the template into a header value for header name '{}': {:?}", insert_template.name, err);
+                                }
+                            },
+                            None => {
+                                if let Some(default_val) = &insert_template.default {
+                                    req.subgraph_request
+                                        .headers_mut()
+                                        .insert(&insert_template.name, default_val.clone());
+                                }
+                            }
+                        }
+                    }
                 },
                 Operation::Remove(Remove::Named(name)) => {
                     req.subgraph_request.headers_mut().remove(name);
@@ -357,6 +903,7 @@ where
                     named,
                     rename,
                     default,
+                    ..
                 }) => {
                     let headers = req.subgraph_request.headers_mut();
                     let value = req.supergraph_request.headers().get(named);
@@ -364,7 +911,7 @@ where
                         headers.insert(rename.as_ref().unwrap_or(named), value.clone());
                     }
                 }
-                Operation::Propagate(Propagate::Matching { matching }) => {
+                Operation::Propagate(Propagate::Matching { matching, .. }) => {
                     let headers = req.subgraph_request.headers_mut();
                     req.supergraph_request
                         .headers()
@@ -527,6 +1074,7 @@ mod test {
             HeadersLayer::new(vec![Operation::Insert(Insert::Static(InsertStatic {
                 name: "c".try_into()?,
                 value: "d".try_into()?,
+                condition: None,
             }))])
             .layer(mock);
 
@@ -553,6 +1101,7 @@ mod test {
             InsertFromContext {
                 name: "header_from_context".try_into()?,
                 from_context: "my_key".to_string(),
+                condition: None,
             },
         ))])
         .layer(mock);
@@ -581,6 +1130,7 @@ mod test {
                 name: "header_from_request".try_into()?,
                 path: JSONQuery::parse(".operationName")?,
                 default: None,
+                condition: None,
             }))])
             .layer(mock);
 
@@ -638,6 +1188,7 @@ mod test {
 
         let mut service = HeadersLayer::new(vec![Operation::Propagate(Propagate::Matching {
             matching: Regex::from_str("d[ab]")?,
+            condition: None,
         })])
         .layer(mock);
 
@@ -664,6 +1215,7 @@ mod test {
             named: "da".try_into()?,
             rename: None,
             default: None,
+            condition: None,
         })])
         .layer(mock);
 
@@ -690,6 +1242,7 @@ mod test {
             named: "da".try_into()?,
             rename: Some("ea".try_into()?),
             default: None,
+            condition: None,
         })])
         .layer(mock);
 
@@ -716,6 +1269,7 @@ mod test {
             named: "ea".try_into()?,
             rename: None,
             default: Some("defaulted".try_into()?),
+            condition: None,
         })])
         .layer(mock);
 
@@ -723,6 +1277,306 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_condition_config() {
+        serde_yaml::from_str::<Config>(
+            r#"
+        all:
+            request:
+                - insert:
+                    name: "test"
+                    value: "test"
+                    condition:
+                        operation_kind: mutation
+                - propagate:
+                    named: "test"
+                    condition:
+                        all:
+                            - header_matches:
+                                name: "x-client"
+                                matching: "^web-.*"
+                            - not:
+                                context:
+                                    key: "my_key"
+                                    equals: "my_value"
+                - insert:
+                    name: "x-user"
+                    template: "${jwt.sub}/${context.my_key}/${operation_name}"
+                    default: "anonymous"
+        "#,
+        )
+        .unwrap();
+
+        assert!(serde_yaml::from_str::<Config>(
+            r#"
+        all:
+            request:
+                - insert:
+                    name: "x-user"
+                    template: "${jwt}"
+        "#,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_template() {
+        assert_eq!(
+            Template::try_from(
+                "user ${jwt.org.id}:${ context.my_key }${operation_name}".to_string()
+            ),
+            Ok(Template(vec![
+                TemplatePart::Literal("user ".to_string()),
+                TemplatePart::JwtClaim(vec!["org".to_string(), "id".to_string()]),
+                TemplatePart::Literal(":".to_string()),
+                TemplatePart::Context("my_key".to_string()),
+                TemplatePart::OperationName,
+            ]))
+        );
+        assert!(Template::try_from("${jwt.sub".to_string()).is_err());
+        assert!(Template::try_from("${unknown}".to_string()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_insert_conditionally() -> Result<(), BoxError> {
+        let mut mock = MockSubgraphService::new();
+        mock.expect_call()
+            .times(1)
+            .withf(|request| {
+                request.assert_headers(vec![
+                    ("aa", "vaa"),
+                    ("ab", "vab"),
+                    ("ac", "vac"),
+                    ("query", "true"),
+                    ("da", "vda"),
+                ])
+            })
+            .returning(example_response);
+
+        let insert = |name: &'static str, condition| {
+            Operation::Insert(Insert::Static(InsertStatic {
+                name: HeaderName::from_static(name),
+                value: HeaderValue::from_static("true"),
+                condition: Some(condition),
+            }))
+        };
+        let mut service = HeadersLayer::new(vec![
+            insert("query", Condition::OperationKind(OperationKind::Query)),
+            insert(
+                "mutation",
+                Condition::OperationKind(OperationKind::Mutation),
+            ),
+            insert(
+                "missing_context",
+                Condition::Context {
+                    key: "missing_key".to_string(),
+                    equals: None,
+                },
+            ),
+            insert(
+                "other_context_value",
+                Condition::Context {
+                    key: "my_key".to_string(),
+                    equals: Some("other_value".into()),
+                },
+            ),
+            Operation::Propagate(Propagate::Named {
+                named: "da".try_into()?,
+                rename: None,
+                default: None,
+                condition: Some(Condition::All(vec![
+                    Condition::HeaderMatches {
+                        name: "db".try_into()?,
+                        matching: Regex::from_str("^vd")?,
+                    },
+                    Condition::Context {
+                        key: "my_key".to_string(),
+                        equals: Some("my_value_from_context".into()),
+                    },
+                ])),
+            }),
+            Operation::Propagate(Propagate::Named {
+                named: "db".try_into()?,
+                rename: None,
+                default: None,
+                condition: Some(Condition::Not(Box::new(Condition::HeaderMatches {
+                    name: "da".try_into()?,
+                    matching: Regex::from_str("vda")?,
+                }))),
+            }),
+        ])
+        .layer(mock);
+
+        service.ready().await?.call(example_request()).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_insert_template() -> Result<(), BoxError> {
+        let mut mock = MockSubgraphService::new();
+        mock.expect_call()
+            .times(1)
+            .withf(|request| {
+                request.assert_headers(vec![
+                    ("aa", "vaa"),
+                    ("ab", "vab"),
+                    ("ac", "vac"),
+                    (
+                        "x-user",
+                        "user-1/42/my_value_from_context/my_operation_name",
+                    ),
+                    ("x-missing", "defaulted"),
+                ])
+            })
+            .returning(example_response);
+
+        let insert = |name: &'static str, template: &str| -> Result<Operation, BoxError> {
+            Ok(Operation::Insert(Insert::Template(InsertTemplate {
+                name: HeaderName::from_static(name),
+                template: Template::try_from(template.to_string())?,
+                default: Some(HeaderValue::from_static("defaulted")),
+                condition: None,
+            })))
+        };
+        let mut service = HeadersLayer::new(vec![
+            insert(
+                "x-user",
+                "${jwt.sub}/${jwt.org.id}/${context.my_key}/${operation_name}",
+            )?,
+            insert("x-missing", "${jwt.missing}")?,
+        ])
+        .layer(mock);
+
+        let request = example_request();
+        request.context.insert(
+            APOLLO_AUTHENTICATION_JWT_CLAIMS,
+            serde_json::json!({ "sub": "user-1", "org": { "id": 42 } }),
+        )?;
+        service.ready().await?.call(request).await?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_response_config() {
+        serde_yaml::from_str::<Config>(
+            r#"
+        all:
+            response:
+                - insert:
+                    name: "test"
+                    value: "test"
+                - propagate:
+                    named: "cache-control"
+                    merge: most_restrictive
+                - propagate:
+                    named: "test"
+                    rename: "bif"
+                    default: "bof"
+                - propagate:
+                    matching: "x-.*"
+                    merge: append
+        "#,
+        )
+        .unwrap();
+
+        assert!(serde_yaml::from_str::<Config>(
+            r#"
+        all:
+            response:
+                - propagate:
+                    named: "test"
+                    merge: "unknown"
+        "#,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_collect_response_headers() {
+        let config = serde_yaml::from_str::<Config>(
+            r#"
+        all:
+            response:
+                - insert:
+                    name: "inserted"
+                    value: "value"
+                - propagate:
+                    named: "first"
+                    merge: first
+                - propagate:
+                    named: "last"
+                    rename: "renamed"
+                - propagate:
+                    named: "missing"
+                    default: "default"
+                - propagate:
+                    matching: "x-.*"
+                    merge: append
+        "#,
+        )
+        .unwrap();
+        let operations = config.all.unwrap().response;
+
+        let context = Context::new();
+        for subgraph in ["a", "b"] {
+            let response = SubgraphResponse::new_from_response(
+                http::Response::builder()
+                    .header("first", subgraph)
+                    .header("last", subgraph)
+                    .header("x-subgraph", subgraph)
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Default::default())
+                    .unwrap(),
+                context.clone(),
+            );
+            collect_response_headers(&operations, &response);
+        }
+
+        let collected: HashMap<String, Vec<String>> =
+            context.get(RESPONSE_HEADERS_CONTEXT_KEY).unwrap().unwrap();
+        let expected: HashMap<String, Vec<String>> = [
+            ("inserted", vec!["value"]),
+            ("first", vec!["a"]),
+            ("renamed", vec!["b"]),
+            ("missing", vec!["default"]),
+            ("x-subgraph", vec!["a", "b"]),
+        ]
+        .into_iter()
+        .map(|(name, values)| {
+            (
+                name.to_string(),
+                values.into_iter().map(str::to_string).collect(),
+            )
+        })
+        .collect();
+        assert_eq!(collected, expected);
+    }
+
+    #[test]
+    fn test_merge_cache_control() {
+        let merge = |values: &[&str]| {
+            let mut current = Vec::new();
+            for value in values {
+                MergeStrategy::MostRestrictive.merge(&mut current, vec![value.to_string()]);
+            }
+            current
+        };
+
+        assert_eq!(
+            merge(&["public, max-age=60", "public, max-age=30, s-maxage=10"]),
+            vec!["public, max-age=30, s-maxage=10"]
+        );
+        assert_eq!(
+            merge(&["public, max-age=60, s-maxage=60", "private, max-age=120"]),
+            vec!["private, max-age=60"]
+        );
+        assert_eq!(
+            merge(&["max-age=60", "no-cache", "must-revalidate"]),
+            vec!["no-cache, must-revalidate, max-age=60"]
+        );
+        assert_eq!(merge(&["public, max-age=60", "no-store"]), vec!["no-store"]);
+    }
+
     fn example_response(_: SubgraphRequest) -> Result<SubgraphResponse, BoxError> {
         Ok(SubgraphResponse::new_from_response(
             http::Response::default(),
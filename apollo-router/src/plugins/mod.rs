@@ -21,15 +21,19 @@ macro_rules! schemar_fn {
 }
 
 mod authentication;
+mod authorization;
 mod coprocessor;
 #[cfg(test)]
 mod coprocessor_test;
 pub(crate) mod csrf;
+mod demand_control;
 mod expose_query_plan;
 mod forbid_mutations;
+mod graph_allow_list;
 mod headers;
 mod include_subgraph_errors;
 pub(crate) mod override_url;
 pub(crate) mod rhai;
+pub(crate) mod subscription;
 pub(crate) mod telemetry;
 pub(crate) mod traffic_shaping;
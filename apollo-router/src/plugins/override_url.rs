@@ -1,7 +1,6 @@
 //! Allows subgraph URLs to be overridden.
 
 use std::collections::HashMap;
-use std::str::FromStr;
 
 use http::Uri;
 use schemars::JsonSchema;
@@ -14,6 +13,7 @@ use crate::plugin::Plugin;
 use crate::plugin::PluginInit;
 use crate::register_plugin;
 use crate::services::subgraph;
+use crate::services::unix_socket::parse_subgraph_url;
 use crate::services::SubgraphRequest;
 
 #[derive(Debug, Clone)]
@@ -39,8 +39,8 @@ impl Plugin for OverrideSubgraphUrl {
         Ok(OverrideSubgraphUrl {
             urls: urls
                 .into_iter()
-                .map(|(k, v)| (k, Uri::from_str(v.as_str()).unwrap()))
-                .collect(),
+                .map(|(k, v)| Ok((k, parse_subgraph_url(v.as_str())?)))
+                .collect::<Result<_, BoxError>>()?,
         })
     }
 
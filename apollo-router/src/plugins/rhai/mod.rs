@@ -1,5 +1,6 @@
 //! Customization via Rhai.
 
+use std::cell::Cell;
 use std::fmt;
 use std::ops::ControlFlow;
 use std::path::PathBuf;
@@ -10,11 +11,15 @@ use std::sync::Arc;
 use std::sync::Mutex;
 use std::time::Duration;
 use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 use arc_swap::ArcSwap;
+use bytes::Bytes;
 use futures::future::ready;
 use futures::stream::once;
 use futures::StreamExt;
+use hmac::Hmac;
+use hmac::Mac;
 use http::header::HeaderName;
 use http::header::HeaderValue;
 use http::header::InvalidHeaderName;
@@ -22,6 +27,7 @@ use http::uri::Authority;
 use http::uri::Parts;
 use http::uri::PathAndQuery;
 use http::HeaderMap;
+use http::Method;
 use http::StatusCode;
 use http::Uri;
 use notify::event::DataChange;
@@ -37,6 +43,7 @@ use rhai::plugin::*;
 use rhai::serde::from_dynamic;
 use rhai::serde::to_dynamic;
 use rhai::Array;
+use rhai::Blob;
 use rhai::Dynamic;
 use rhai::Engine;
 use rhai::EvalAltResult;
@@ -49,6 +56,8 @@ use rhai::Shared;
 use rhai::AST;
 use schemars::JsonSchema;
 use serde::Deserialize;
+use sha2::Digest;
+use sha2::Sha256;
 use tower::util::BoxService;
 use tower::BoxError;
 use tower::ServiceBuilder;
@@ -85,6 +94,15 @@ type SharedMut<T> = rhai::Shared<Mutex<Option<T>>>;
 
 pub(crate) const RHAI_SPAN_NAME: &str = "rhai_plugin";
 
+/// Number of operations between two checks of the callback deadline
+const DEADLINE_CHECK_INTERVAL: u64 = 1000;
+
+thread_local! {
+    // Callbacks run synchronously on the thread that calls them, so the deadline of the callback
+    // being executed is kept per thread for the `on_progress` hook of the engine
+    static CALLBACK_DEADLINE: Cell<Option<std::time::Instant>> = Cell::new(None);
+}
+
 impl<T> OptionDance<T> for SharedMut<T> {
     fn with_mut<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
         let mut guard = self.lock().expect("poisoned mutex");
@@ -109,8 +127,10 @@ impl<T> OptionDance<T> for SharedMut<T> {
 }
 
 mod execution;
+mod router;
 mod subgraph;
 mod supergraph;
+pub(crate) mod test_runner;
 
 // We have to keep the modules that we export using `export_module` inline because
 // error[E0658]: non-inline modules in proc macro input are unstable
@@ -128,6 +148,68 @@ mod router_base64 {
     }
 }
 
+#[export_module]
+mod router_crypto {
+    /// Hex encoded SHA-256 digest of the input
+    #[rhai_fn(pure)]
+    pub(crate) fn sha256(input: &mut ImmutableString) -> String {
+        hex::encode(Sha256::digest(input.as_bytes()))
+    }
+
+    /// Hex encoded HMAC-SHA256 of the message
+    #[rhai_fn(pure)]
+    pub(crate) fn hmac_sha256(key: &mut ImmutableString, message: ImmutableString) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any size");
+        mac.update(message.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+}
+
+#[export_module]
+mod router_json {
+    /// Serializes a value to a JSON string
+    #[rhai_fn(return_raw)]
+    pub(crate) fn encode(value: Dynamic) -> Result<String, Box<EvalAltResult>> {
+        serde_json::to_string(&value).map_err(|e| e.to_string().into())
+    }
+
+    /// Parses a JSON string into Rhai values (object maps, arrays, strings, numbers...)
+    #[rhai_fn(pure, return_raw)]
+    pub(crate) fn decode(input: &mut ImmutableString) -> Result<Dynamic, Box<EvalAltResult>> {
+        let value: serde_json::Value =
+            serde_json::from_str(input.as_str()).map_err(|e| e.to_string())?;
+        to_dynamic(value)
+    }
+}
+
+#[export_module]
+mod router_time {
+    /// The current time, formatted as ISO-8601 in UTC with millisecond precision
+    pub(crate) fn now() -> String {
+        humantime::format_rfc3339_millis(SystemTime::now()).to_string()
+    }
+
+    /// Formats a unix timestamp in seconds as ISO-8601 in UTC
+    #[rhai_fn(return_raw)]
+    pub(crate) fn format(timestamp: i64) -> Result<String, Box<EvalAltResult>> {
+        let seconds = u64::try_from(timestamp).map_err(|e| e.to_string())?;
+        Ok(
+            humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(seconds))
+                .to_string(),
+        )
+    }
+
+    /// Parses an ISO-8601 date and time in UTC into a unix timestamp in seconds
+    #[rhai_fn(pure, return_raw)]
+    pub(crate) fn parse(input: &mut ImmutableString) -> Result<i64, Box<EvalAltResult>> {
+        let time = humantime::parse_rfc3339_weak(input.as_str()).map_err(|e| e.to_string())?;
+        time.duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs() as i64)
+            .map_err(|e| e.to_string().into())
+    }
+}
+
 // We have to keep the modules that we export using `export_module` inline because
 // error[E0658]: non-inline modules in proc macro input are unstable
 #[export_module]
@@ -389,8 +471,11 @@ impl EngineBlock {
         scripts: Option<PathBuf>,
         main: PathBuf,
         sdl: Arc<String>,
+        limits: &Limits,
     ) -> Result<Self, BoxError> {
-        let engine = Arc::new(Rhai::new_rhai_engine(scripts, sdl.to_string()));
+        let mut engine = Rhai::new_rhai_engine(scripts, sdl.to_string());
+        limits.apply(&mut engine);
+        let engine = Arc::new(engine);
         let ast = engine.compile_file(main)?;
         let mut scope = Scope::new();
         // Keep these two lower cases ones as mistakes until 2.0
@@ -419,6 +504,8 @@ impl EngineBlock {
 /// we'll use ArcSwap to accomplish our goal.
 struct Rhai {
     block: Arc<ArcSwap<EngineBlock>>,
+    budget: CallbackBudget,
+    max_router_request_size: Option<usize>,
     park_flag: Arc<AtomicBool>,
     watcher_handle: Option<std::thread::JoinHandle<()>>,
 }
@@ -431,6 +518,74 @@ pub(crate) struct Conf {
     scripts: Option<PathBuf>,
     /// The main entry point for Rhai script evaluation
     main: Option<String>,
+    /// Limits on the execution of script callbacks
+    #[serde(default)]
+    limits: Limits,
+    /// The maximum size of a request body read by `router_service` callbacks, in bytes. Larger
+    /// requests are rejected with a 413 status code. Not limited by default
+    max_router_request_size: Option<usize>,
+}
+
+/// Limits on the execution of Rhai script callbacks. Limits are disabled by default.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+pub(crate) struct Limits {
+    /// The maximum number of operations a callback can run
+    max_operations: Option<u64>,
+    /// The maximum depth of nested function calls
+    max_call_levels: Option<usize>,
+    /// The maximum length of a string, in bytes
+    max_string_size: Option<usize>,
+    /// The maximum number of elements of an array
+    max_array_size: Option<usize>,
+    /// The maximum number of properties of an object map
+    max_map_size: Option<usize>,
+    /// The maximum duration of a callback
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    timeout: Option<Duration>,
+    /// The HTTP status code of the response when a callback exceeds a limit (default: 500)
+    status_code: Option<u16>,
+}
+
+impl Limits {
+    fn apply(&self, engine: &mut Engine) {
+        if let Some(max) = self.max_operations {
+            engine.set_max_operations(max);
+        }
+        if let Some(max) = self.max_call_levels {
+            engine.set_max_call_levels(max);
+        }
+        if let Some(max) = self.max_string_size {
+            engine.set_max_string_size(max);
+        }
+        if let Some(max) = self.max_array_size {
+            engine.set_max_array_size(max);
+        }
+        if let Some(max) = self.max_map_size {
+            engine.set_max_map_size(max);
+        }
+        if self.timeout.is_some() {
+            engine.on_progress(|operations| {
+                if operations % DEADLINE_CHECK_INTERVAL != 0 {
+                    return None;
+                }
+                CALLBACK_DEADLINE.with(|deadline| {
+                    deadline
+                        .get()
+                        .filter(|deadline| std::time::Instant::now() >= *deadline)
+                        .map(|_| Dynamic::UNIT)
+                })
+            });
+        }
+    }
+}
+
+/// What [`execute`] needs to enforce the limits of a callback
+#[derive(Clone, Copy, Debug)]
+struct CallbackBudget {
+    timeout: Option<Duration>,
+    status: StatusCode,
 }
 
 #[async_trait::async_trait]
@@ -451,6 +606,16 @@ impl Plugin for Rhai {
 
         let main = scripts_path.join(main_file);
 
+        let limits = init.config.limits;
+        let budget = CallbackBudget {
+            timeout: limits.timeout,
+            status: match limits.status_code {
+                Some(status_code) => StatusCode::from_u16(status_code)?,
+                None => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+        };
+        let watched_limits = limits.clone();
+
         let watched_path = scripts_path.clone();
         let watched_main = main.clone();
         let watched_sdl = sdl.clone();
@@ -459,6 +624,7 @@ impl Plugin for Rhai {
             Some(scripts_path),
             main,
             sdl,
+            &limits,
         )?));
         let watched_block = block.clone();
 
@@ -498,6 +664,7 @@ impl Plugin for Rhai {
                                         Some(watching_path.clone()),
                                         watched_main.clone(),
                                         watched_sdl.clone(),
+                                        &watched_limits,
                                     ) {
                                         Ok(eb) => {
                                             tracing::info!("updating rhai execution engine");
@@ -533,11 +700,31 @@ impl Plugin for Rhai {
 
         Ok(Self {
             block,
+            budget,
+            max_router_request_size: init.config.max_router_request_size,
             park_flag,
             watcher_handle: Some(watcher_handle),
         })
     }
 
+    fn router_service(&self, service: router::BoxService) -> router::BoxService {
+        const FUNCTION_NAME_SERVICE: &str = "router_service";
+        if !self.ast_has_function(FUNCTION_NAME_SERVICE) {
+            return service;
+        }
+        tracing::debug!("router_service function found");
+        let shared_service = Arc::new(Mutex::new(Some(service)));
+        if let Err(error) = self.run_rhai_service(
+            FUNCTION_NAME_SERVICE,
+            None,
+            ServiceStep::Router(shared_service.clone()),
+            self.block.load().scope.clone(),
+        ) {
+            tracing::error!("service callback failed: {error}");
+        }
+        shared_service.take_unwrap()
+    }
+
     fn supergraph_service(&self, service: supergraph::BoxService) -> supergraph::BoxService {
         const FUNCTION_NAME_SERVICE: &str = "supergraph_service";
         if !self.ast_has_function(FUNCTION_NAME_SERVICE) {
@@ -605,6 +792,7 @@ impl Drop for Rhai {
 
 #[derive(Clone, Debug)]
 pub(crate) enum ServiceStep {
+    Router(SharedMut<router::BoxService>),
     Supergraph(SharedMut<supergraph::BoxService>),
     Execution(SharedMut<execution::BoxService>),
     Subgraph(SharedMut<subgraph::BoxService>),
@@ -656,21 +844,7 @@ macro_rules! gen_map_request {
                         Ok(ControlFlow::Break(res))
                     }
                     let shared_request = Shared::new(Mutex::new(Some(request)));
-                    let result: Result<Dynamic, Box<EvalAltResult>> = if $callback.is_curried() {
-                        $callback.call(
-                            &$rhai_service.engine,
-                            &$rhai_service.ast,
-                            (shared_request.clone(),),
-                        )
-                    } else {
-                        let mut guard = $rhai_service.scope.lock().unwrap();
-                        $rhai_service.engine.call_fn(
-                            &mut guard,
-                            &$rhai_service.ast,
-                            $callback.fn_name(),
-                            (shared_request.clone(),),
-                        )
-                    };
+                    let result = execute(&$rhai_service, &$callback, (shared_request.clone(),));
                     if let Err(error) = result {
                         let error_details = process_error(error);
                         tracing::error!("map_request callback failed: {error_details:#?}");
@@ -779,21 +953,7 @@ macro_rules! gen_map_response {
                         res
                     }
                     let shared_response = Shared::new(Mutex::new(Some(response)));
-                    let result: Result<Dynamic, Box<EvalAltResult>> = if $callback.is_curried() {
-                        $callback.call(
-                            &$rhai_service.engine,
-                            &$rhai_service.ast,
-                            (shared_response.clone(),),
-                        )
-                    } else {
-                        let mut guard = $rhai_service.scope.lock().unwrap();
-                        $rhai_service.engine.call_fn(
-                            &mut guard,
-                            &$rhai_service.ast,
-                            $callback.fn_name(),
-                            (shared_response.clone(),),
-                        )
-                    };
+                    let result = execute(&$rhai_service, &$callback, (shared_response.clone(),));
                     if let Err(error) = result {
                         tracing::error!("map_response callback failed: {error}");
                         let error_details = process_error(error);
@@ -933,6 +1093,18 @@ macro_rules! gen_map_deferred_response {
     };
 }
 
+#[derive(Default)]
+pub(crate) struct RhaiRouterRequest {
+    context: Context,
+    request: http::Request<Bytes>,
+}
+
+#[derive(Default)]
+pub(crate) struct RhaiRouterResponse {
+    context: Context,
+    response: http::Response<Bytes>,
+}
+
 #[derive(Default)]
 pub(crate) struct RhaiExecutionResponse {
     context: Context,
@@ -1068,9 +1240,59 @@ macro_rules! register_rhai_interface {
     };
 }
 
+// Log events are emitted within the current span, and carry the trace id so that they can be
+// correlated with the request being processed. A map of fields can be passed as a second
+// argument, it is logged as JSON.
+macro_rules! register_rhai_logging {
+    ($engine: ident, $($name: ident => $level: ident, $message: literal), *) => {
+        $(
+            $engine.register_fn(stringify!($name), |out: Dynamic| {
+                tracing::$level!(
+                    %out,
+                    trace_id = %current_trace_id(),
+                    $message
+                );
+            })
+            .register_fn(stringify!($name), |out: Dynamic, fields: Map| {
+                tracing::$level!(
+                    %out,
+                    trace_id = %current_trace_id(),
+                    fields = %serde_json::to_string(&fields).unwrap_or_default(),
+                    $message
+                );
+            });
+        )*
+    };
+}
+
+fn current_trace_id() -> String {
+    TraceId::maybe_new()
+        .map(|id| id.to_string())
+        .unwrap_or_default()
+}
+
 impl ServiceStep {
     fn map_request(&mut self, rhai_service: RhaiService, callback: FnPtr) {
         match self {
+            ServiceStep::Router(service) => service.replace(|service| {
+                fn rhai_service_span() -> impl Fn(&router::Request) -> tracing::Span + Clone {
+                    move |_request: &router::Request| {
+                        tracing::info_span!(
+                            RHAI_SPAN_NAME,
+                            "rhai service" = stringify!(router::Request),
+                            "otel.kind" = "INTERNAL"
+                        )
+                    }
+                }
+                ServiceBuilder::new()
+                    .instrument(rhai_service_span())
+                    .checkpoint_async(move |request: router::Request| {
+                        map_router_request(rhai_service.clone(), callback.clone(), request)
+                    })
+                    .buffered()
+                    .service(service)
+                    .boxed()
+            }),
             ServiceStep::Supergraph(service) => {
                 gen_map_deferred_request!(
                     SupergraphRequest,
@@ -1097,6 +1319,11 @@ impl ServiceStep {
 
     fn map_response(&mut self, rhai_service: RhaiService, callback: FnPtr) {
         match self {
+            ServiceStep::Router(service) => service.replace(|service| {
+                BoxService::new(service.and_then(move |response: router::Response| {
+                    map_router_response(rhai_service, callback, response)
+                }))
+            }),
             ServiceStep::Supergraph(service) => {
                 gen_map_deferred_response!(
                     SupergraphResponse,
@@ -1124,6 +1351,121 @@ impl ServiceStep {
     }
 }
 
+// The router stage works on raw HTTP payloads, so the body is collected before running the
+// callback, and the callback sees it as a blob of bytes
+async fn map_router_request(
+    rhai_service: RhaiService,
+    callback: FnPtr,
+    request: router::Request,
+) -> Result<ControlFlow<router::Response, router::Request>, BoxError> {
+    let router::Request {
+        router_request,
+        context,
+    } = request;
+    let (parts, body) = router_request.into_parts();
+    let max_size = rhai_service.max_router_request_size.unwrap_or(usize::MAX);
+    let body = match hyper::body::to_bytes(http_body::Limited::new(body, max_size)).await {
+        Ok(body) => body,
+        Err(e) if e.is::<http_body::LengthLimitError>() => {
+            return router::Response::error_builder()
+                .error(
+                    Error::builder()
+                        .message(format!(
+                            "the request body is larger than the maximum size of {max_size} bytes"
+                        ))
+                        .extension_code("REQUEST_TOO_LARGE")
+                        .build(),
+                )
+                .status_code(StatusCode::PAYLOAD_TOO_LARGE)
+                .context(context)
+                .build()
+                .map(ControlFlow::Break);
+        }
+        Err(e) => return Err(e),
+    };
+    let shared_request = Shared::new(Mutex::new(Some(router::RhaiRequest {
+        context,
+        request: http::Request::from_parts(parts, body),
+    })));
+
+    let result = execute(&rhai_service, &callback, (shared_request.clone(),));
+    let router::RhaiRequest { context, request } = shared_request.take_unwrap();
+    if let Err(error) = result {
+        let error_details = process_error(error);
+        tracing::error!("map_request callback failed: {error_details:#?}");
+        return router_failure_message(context, error_details).map(ControlFlow::Break);
+    }
+    Ok(ControlFlow::Continue(router::Request {
+        router_request: request.map(router::Body::from),
+        context,
+    }))
+}
+
+// Multipart responses (@defer and subscriptions) are streamed: their body is not collected, and
+// the callback only sees their status and headers, with an empty body
+async fn map_router_response(
+    rhai_service: RhaiService,
+    callback: FnPtr,
+    response: router::Response,
+) -> Result<router::Response, BoxError> {
+    let router::Response { response, context } = response;
+    let (parts, body) = response.into_parts();
+    let is_multipart = parts
+        .headers
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|content_type| content_type.starts_with("multipart/mixed"))
+        .unwrap_or_default();
+    let (buffered, streamed) = if is_multipart {
+        (Bytes::new(), Some(body))
+    } else {
+        (hyper::body::to_bytes(body).await?, None)
+    };
+    let shared_response = Shared::new(Mutex::new(Some(router::RhaiResponse {
+        context,
+        response: http::Response::from_parts(parts, buffered),
+    })));
+
+    let result = execute(&rhai_service, &callback, (shared_response.clone(),));
+    let router::RhaiResponse { context, response } = shared_response.take_unwrap();
+    if let Err(error) = result {
+        tracing::error!("map_response callback failed: {error}");
+        let error_details = process_error(error);
+        return router_failure_message(context, error_details);
+    }
+    let response = match streamed {
+        Some(body) => http::Response::from_parts(response.into_parts().0, body),
+        None => response.map(router::Body::from),
+    };
+    Ok(router::Response { response, context })
+}
+
+fn router_failure_message(
+    context: Context,
+    error_details: ErrorDetails,
+) -> Result<router::Response, BoxError> {
+    if let Some(body) = error_details.body {
+        router::Response::builder()
+            .extensions(body.extensions)
+            .errors(body.errors)
+            .status_code(error_details.status)
+            .context(context)
+            .and_data(body.data)
+            .and_label(body.label)
+            .and_path(body.path)
+            .build()
+    } else {
+        router::Response::error_builder()
+            .errors(vec![Error {
+                message: error_details.message.unwrap_or_default(),
+                ..Default::default()
+            }])
+            .context(context)
+            .status_code(error_details.status)
+            .build()
+    }
+}
+
 #[derive(Deserialize, Debug)]
 struct Position {
     line: Option<usize>,
@@ -1201,18 +1543,61 @@ fn execute(
     callback: &FnPtr,
     args: impl FuncArgs,
 ) -> Result<Dynamic, Box<EvalAltResult>> {
-    if callback.is_curried() {
+    let deadline = rhai_service
+        .budget
+        .timeout
+        .map(|timeout| std::time::Instant::now() + timeout);
+    let previous_deadline = CALLBACK_DEADLINE.with(|current| current.replace(deadline));
+    let result = if callback.is_curried() {
         callback.call(&rhai_service.engine, &rhai_service.ast, args)
     } else {
         let mut guard = rhai_service.scope.lock().unwrap();
         rhai_service
             .engine
             .call_fn(&mut guard, &rhai_service.ast, callback.fn_name(), args)
-    }
+    };
+    CALLBACK_DEADLINE.with(|current| current.set(previous_deadline));
+
+    result.map_err(|error| {
+        let limit = match error.unwrap_inner() {
+            EvalAltResult::ErrorTooManyOperations(_) => "operations",
+            EvalAltResult::ErrorStackOverflow(_) => "call_levels",
+            EvalAltResult::ErrorDataTooLarge(..) => "data_size",
+            EvalAltResult::ErrorTerminated(..) => "timeout",
+            _ => return error,
+        };
+        let function = callback.fn_name().to_string();
+        tracing::info!(
+            monotonic_counter.apollo_router_rhai_limit_exceeded_count = 1u64,
+            function = %function,
+            limit,
+        );
+
+        // Turn the error into one thrown by the script, so that it is processed like any other
+        // script error, with the configured status code
+        let position = error.position();
+        let mut details = Map::new();
+        details.insert(
+            "status".into(),
+            (rhai_service.budget.status.as_u16() as rhai::INT).into(),
+        );
+        details.insert(
+            "message".into(),
+            format!("rhai execution error: '{function}' exceeded its {limit} limit").into(),
+        );
+        Box::new(EvalAltResult::ErrorInFunctionCall(
+            function,
+            String::new(),
+            Box::new(EvalAltResult::ErrorRuntime(details.into(), position)),
+            position,
+        ))
+    })
 }
 
 #[derive(Clone, Debug)]
 pub(crate) struct RhaiService {
+    budget: CallbackBudget,
+    max_router_request_size: Option<usize>,
     scope: Arc<Mutex<Scope<'static>>>,
     service: ServiceStep,
     engine: Arc<Engine>,
@@ -1229,6 +1614,8 @@ impl Rhai {
     ) -> Result<(), String> {
         let block = self.block.load();
         let rhai_service = RhaiService {
+            budget: self.budget,
+            max_router_request_size: self.max_router_request_size,
             scope: scope.clone(),
             service,
             engine: block.engine.clone(),
@@ -1278,6 +1665,12 @@ impl Rhai {
 
         let base64_module = exported_module!(router_base64);
 
+        let crypto_module = exported_module!(router_crypto);
+
+        let json_module = exported_module!(router_json);
+
+        let time_module = exported_module!(router_time);
+
         // Configure our engine for execution
         engine
             .set_max_expr_depths(0, 0)
@@ -1288,6 +1681,10 @@ impl Rhai {
             .register_global_module(module.into())
             // Register our base64 module (not global)
             .register_static_module("base64", base64_module.into())
+            // Register our crypto, json and time modules (not global)
+            .register_static_module("crypto", crypto_module.into())
+            .register_static_module("json", json_module.into())
+            .register_static_module("time", time_module.into())
             // Register types accessible in plugin scripts
             .register_type::<Context>()
             .register_type::<HeaderMap>()
@@ -1545,22 +1942,6 @@ impl Rhai {
                 TraceId::maybe_new().ok_or_else(|| "trace unavailable".into())
             })
             .register_fn("to_string", |id: &mut TraceId| -> String { id.to_string() })
-            // Register a series of logging functions
-            .register_fn("log_trace", |out: Dynamic| {
-                tracing::trace!(%out, "rhai_trace");
-            })
-            .register_fn("log_debug", |out: Dynamic| {
-                tracing::debug!(%out, "rhai_debug");
-            })
-            .register_fn("log_info", |out: Dynamic| {
-                tracing::info!(%out, "rhai_info");
-            })
-            .register_fn("log_warn", |out: Dynamic| {
-                tracing::warn!(%out, "rhai_warn");
-            })
-            .register_fn("log_error", |out: Dynamic| {
-                tracing::error!(%out, "rhai_error");
-            })
             // Register a function for printing to stderr
             .register_fn("eprint", |x: &str| {
                 eprintln!("{x}");
@@ -1673,10 +2054,123 @@ impl Rhai {
                     obj.with_mut(|response| response.context = context);
                     Ok(())
                 },
+            )
+            // Router stage: raw HTTP request and response
+            .register_get(
+                "context",
+                |obj: &mut SharedMut<router::RhaiRequest>| -> Result<Context, Box<EvalAltResult>> {
+                    Ok(obj.with_mut(|request| request.context.clone()))
+                },
+            )
+            .register_set(
+                "context",
+                |obj: &mut SharedMut<router::RhaiRequest>, context: Context| {
+                    obj.with_mut(|request| request.context = context);
+                    Ok(())
+                },
+            )
+            .register_get(
+                "context",
+                |obj: &mut SharedMut<router::RhaiResponse>| -> Result<Context, Box<EvalAltResult>> {
+                    Ok(obj.with_mut(|response| response.context.clone()))
+                },
+            )
+            .register_set(
+                "context",
+                |obj: &mut SharedMut<router::RhaiResponse>, context: Context| {
+                    obj.with_mut(|response| response.context = context);
+                    Ok(())
+                },
+            )
+            .register_get("method", |obj: &mut SharedMut<router::RhaiRequest>| -> String {
+                obj.with_mut(|request| request.request.method().to_string())
+            })
+            .register_set(
+                "method",
+                |obj: &mut SharedMut<router::RhaiRequest>,
+                 method: &str|
+                 -> Result<(), Box<EvalAltResult>> {
+                    let method = Method::from_bytes(method.to_uppercase().as_bytes())
+                        .map_err(|e| e.to_string())?;
+                    obj.with_mut(|request| *request.request.method_mut() = method);
+                    Ok(())
+                },
+            )
+            .register_get("uri", |obj: &mut SharedMut<router::RhaiRequest>| -> Uri {
+                obj.with_mut(|request| request.request.uri().clone())
+            })
+            .register_set("uri", |obj: &mut SharedMut<router::RhaiRequest>, uri: Uri| {
+                obj.with_mut(|request| *request.request.uri_mut() = uri);
+            })
+            .register_get("headers", |obj: &mut SharedMut<router::RhaiRequest>| -> HeaderMap {
+                obj.with_mut(|request| request.request.headers().clone())
+            })
+            .register_set(
+                "headers",
+                |obj: &mut SharedMut<router::RhaiRequest>, headers: HeaderMap| {
+                    obj.with_mut(|request| *request.request.headers_mut() = headers);
+                },
+            )
+            .register_get("headers", |obj: &mut SharedMut<router::RhaiResponse>| -> HeaderMap {
+                obj.with_mut(|response| response.response.headers().clone())
+            })
+            .register_set(
+                "headers",
+                |obj: &mut SharedMut<router::RhaiResponse>, headers: HeaderMap| {
+                    obj.with_mut(|response| *response.response.headers_mut() = headers);
+                },
+            )
+            .register_get("body", |obj: &mut SharedMut<router::RhaiRequest>| -> Blob {
+                obj.with_mut(|request| request.request.body().to_vec())
+            })
+            .register_set("body", |obj: &mut SharedMut<router::RhaiRequest>, body: Blob| {
+                obj.with_mut(|request| *request.request.body_mut() = body.into());
+            })
+            .register_set("body", |obj: &mut SharedMut<router::RhaiRequest>, body: &str| {
+                obj.with_mut(|request| {
+                    *request.request.body_mut() = Bytes::copy_from_slice(body.as_bytes())
+                });
+            })
+            .register_get("body", |obj: &mut SharedMut<router::RhaiResponse>| -> Blob {
+                obj.with_mut(|response| response.response.body().to_vec())
+            })
+            .register_set("body", |obj: &mut SharedMut<router::RhaiResponse>, body: Blob| {
+                obj.with_mut(|response| *response.response.body_mut() = body.into());
+            })
+            .register_set("body", |obj: &mut SharedMut<router::RhaiResponse>, body: &str| {
+                obj.with_mut(|response| {
+                    *response.response.body_mut() = Bytes::copy_from_slice(body.as_bytes())
+                });
+            })
+            .register_get("status_code", |obj: &mut SharedMut<router::RhaiResponse>| -> i64 {
+                obj.with_mut(|response| response.response.status().as_u16().into())
+            })
+            .register_set(
+                "status_code",
+                |obj: &mut SharedMut<router::RhaiResponse>,
+                 status: i64|
+                 -> Result<(), Box<EvalAltResult>> {
+                    let status = u16::try_from(status)
+                        .ok()
+                        .and_then(|status| StatusCode::from_u16(status).ok())
+                        .ok_or_else(|| format!("invalid status code: {status}"))?;
+                    obj.with_mut(|response| *response.response.status_mut() = status);
+                    Ok(())
+                },
             );
         // Add common getter/setters for different types
         register_rhai_interface!(engine, supergraph, execution, subgraph);
 
+        // Register a series of logging functions
+        register_rhai_logging!(
+            engine,
+            log_trace => trace, "rhai_trace",
+            log_debug => debug, "rhai_debug",
+            log_info => info, "rhai_info",
+            log_warn => warn, "rhai_warn",
+            log_error => error, "rhai_error"
+        );
+
         // Since constants in Rhai don't give us the behaviour we expect, let's create some global
         // variables which we use in a variable resolver when we create our engine.
         // Note: We keep the constants for now, since they are documented.
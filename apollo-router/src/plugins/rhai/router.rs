@@ -0,0 +1,5 @@
+//! router module
+
+pub(crate) use crate::services::router::*;
+pub(crate) type RhaiRequest = super::RhaiRouterRequest;
+pub(crate) type RhaiResponse = super::RhaiRouterResponse;
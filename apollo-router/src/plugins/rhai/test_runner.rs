@@ -0,0 +1,427 @@
+//! Runs fixture requests through the callbacks of a Rhai script (`router rhai test`)
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use http::header::HeaderName;
+use http::HeaderMap;
+use http::HeaderValue;
+use http::Method;
+use http::StatusCode;
+use serde::Deserialize;
+use tower::service_fn;
+use tower::BoxError;
+use tower::ServiceExt;
+
+use crate::graphql;
+use crate::json_ext::Object;
+use crate::json_ext::Value;
+use crate::json_ext::ValueExt;
+use crate::plugin::DynPlugin;
+use crate::query_planner::fetch::OperationKind;
+use crate::services::execution;
+use crate::services::subgraph;
+use crate::services::supergraph;
+use crate::Context;
+
+/// A test case, as found in a fixture file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TestCase {
+    /// Name of the test case
+    name: String,
+    /// Service whose callbacks are tested
+    #[serde(default)]
+    service: Stage,
+    /// Name of the subgraph passed to `subgraph_service`
+    subgraph: Option<String>,
+    /// Request sent to the script
+    #[serde(default)]
+    request: RequestFixture,
+    /// Response returned by the service wrapped by the script
+    #[serde(default)]
+    response: ResponseFixture,
+    /// What the test case checks
+    #[serde(default)]
+    expect: Expectations,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Stage {
+    #[default]
+    Supergraph,
+    Execution,
+    Subgraph,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RequestFixture {
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    /// GraphQL request
+    #[serde(default)]
+    body: Object,
+    #[serde(default)]
+    context: Object,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ResponseFixture {
+    status: Option<u16>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    /// GraphQL response
+    #[serde(default)]
+    body: Object,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Expectations {
+    /// The request, as received by the wrapped service
+    request: Option<Expected>,
+    /// The response, as returned by the script
+    response: Option<Expected>,
+    /// Part of the message of an error in the response
+    error: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Expected {
+    status: Option<u16>,
+    /// Expected header values, `null` if the header must be absent
+    #[serde(default)]
+    headers: HashMap<String, Option<String>>,
+    /// Subset of the expected body
+    body: Option<Value>,
+    /// Subset of the expected context entries
+    #[serde(default)]
+    context: Object,
+}
+
+/// A request or response as seen by the test runner
+struct Observed {
+    status: Option<StatusCode>,
+    headers: HeaderMap,
+    body: Value,
+    context: Context,
+}
+
+type Captured = Arc<Mutex<Option<Observed>>>;
+
+/// Runs every test case found in `fixtures` against the script `main` in the `scripts` directory.
+///
+/// Fixtures are YAML or JSON files containing a list of test cases, or directories of such files.
+/// Returns an error if any of the test cases fails.
+pub(crate) async fn run(scripts: &Path, main: &str, fixtures: &[PathBuf]) -> Result<(), BoxError> {
+    let plugin = crate::plugin::plugins()
+        .find(|factory| factory.name == "apollo.rhai")
+        .expect("the rhai plugin is always registered")
+        .create_instance_without_schema(&serde_json::json!({
+            "scripts": scripts,
+            "main": main,
+        }))
+        .await?;
+
+    let mut passed = 0;
+    let mut failed = 0;
+    for file in fixture_files(fixtures)? {
+        let content = std::fs::read_to_string(&file)?;
+        let cases: Vec<TestCase> = serde_yaml::from_str(&content)
+            .map_err(|e| format!("could not parse {}: {e}", file.display()))?;
+        for case in cases {
+            let failures = match run_case(plugin.as_ref(), &case).await {
+                Ok(failures) => failures,
+                Err(e) => vec![format!("the test case could not run: {e}")],
+            };
+            if failures.is_empty() {
+                passed += 1;
+                println!("ok   {}: {}", file.display(), case.name);
+            } else {
+                failed += 1;
+                println!("FAIL {}: {}", file.display(), case.name);
+                for failure in failures {
+                    println!("       {failure}");
+                }
+            }
+        }
+    }
+
+    println!("\n{passed} passed; {failed} failed");
+    if failed > 0 {
+        return Err(format!("{failed} rhai test case(s) failed").into());
+    }
+    Ok(())
+}
+
+fn fixture_files(fixtures: &[PathBuf]) -> Result<Vec<PathBuf>, BoxError> {
+    let mut files = Vec::new();
+    for path in fixtures {
+        if path.is_dir() {
+            let mut entries = std::fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            entries.retain(|path| {
+                matches!(
+                    path.extension().and_then(|ext| ext.to_str()),
+                    Some("yaml" | "yml" | "json")
+                )
+            });
+            entries.sort();
+            files.extend(entries);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    Ok(files)
+}
+
+pub(crate) async fn run_case(
+    plugin: &dyn DynPlugin,
+    case: &TestCase,
+) -> Result<Vec<String>, BoxError> {
+    let captured = Captured::default();
+    let response = case.response.clone();
+    let context = case.request.context();
+
+    let observed = match case.service {
+        Stage::Supergraph => {
+            let inner = service_fn({
+                let captured = captured.clone();
+                move |request: supergraph::Request| {
+                    capture(
+                        &captured,
+                        &request.supergraph_request,
+                        request.context.clone(),
+                    );
+                    let response = response.supergraph(request.context);
+                    async move { response }
+                }
+            });
+            let request = supergraph::Request {
+                supergraph_request: case.request.http_request()?,
+                context,
+            };
+            let response = plugin
+                .supergraph_service(inner.boxed())
+                .oneshot(request)
+                .await?;
+            observe_supergraph(response).await?
+        }
+        Stage::Execution => {
+            let inner = service_fn({
+                let captured = captured.clone();
+                move |request: execution::Request| {
+                    capture(
+                        &captured,
+                        &request.supergraph_request,
+                        request.context.clone(),
+                    );
+                    let response = response.supergraph(request.context);
+                    async move { response }
+                }
+            });
+            let request = execution::Request::fake_builder()
+                .supergraph_request(case.request.http_request()?)
+                .context(context)
+                .build();
+            let response = plugin
+                .execution_service(inner.boxed())
+                .oneshot(request)
+                .await?;
+            observe_supergraph(response).await?
+        }
+        Stage::Subgraph => {
+            let inner = service_fn({
+                let captured = captured.clone();
+                move |request: subgraph::Request| {
+                    capture(
+                        &captured,
+                        &request.subgraph_request,
+                        request.context.clone(),
+                    );
+                    let response = response.subgraph(request.context);
+                    async move { response }
+                }
+            });
+            let request = subgraph::Request::builder()
+                .supergraph_request(Arc::new(case.request.http_request()?))
+                .subgraph_request(case.request.http_request()?)
+                .operation_kind(OperationKind::Query)
+                .context(context)
+                .build();
+            let name = case.subgraph.as_deref().unwrap_or_default();
+            let response = plugin
+                .subgraph_service(name, inner.boxed())
+                .oneshot(request)
+                .await?;
+            Observed {
+                status: Some(response.response.status()),
+                headers: response.response.headers().clone(),
+                body: serde_json_bytes::to_value(response.response.body())?,
+                context: response.context,
+            }
+        }
+    };
+
+    let mut failures = Vec::new();
+    if let Some(expected) = &case.expect.request {
+        match captured.lock().expect("lock poisoned").as_ref() {
+            Some(request) => expected.check("request", request, &mut failures),
+            None => failures.push("the request never reached the service".to_string()),
+        }
+    }
+    if let Some(expected) = &case.expect.response {
+        expected.check("response", &observed, &mut failures);
+    }
+    if let Some(message) = &case.expect.error {
+        let messages: Vec<&str> = observed
+            .body
+            .get("errors")
+            .and_then(|errors| errors.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|error| error.get("message").and_then(|message| message.as_str()))
+            .collect();
+        if !messages.iter().any(|m| m.contains(message.as_str())) {
+            failures.push(format!(
+                "error: expected a message containing {message:?}, got {messages:?}"
+            ));
+        }
+    }
+    Ok(failures)
+}
+
+fn capture(captured: &Captured, request: &http::Request<graphql::Request>, context: Context) {
+    let body = serde_json_bytes::to_value(request.body()).unwrap_or_default();
+    *captured.lock().expect("lock poisoned") = Some(Observed {
+        status: None,
+        headers: request.headers().clone(),
+        body,
+        context,
+    });
+}
+
+async fn observe_supergraph(mut response: supergraph::Response) -> Result<Observed, BoxError> {
+    let body = response.next_response().await.unwrap_or_default();
+    Ok(Observed {
+        status: Some(response.response.status()),
+        headers: response.response.headers().clone(),
+        body: serde_json_bytes::to_value(body)?,
+        context: response.context,
+    })
+}
+
+fn header_map(headers: &HashMap<String, String>) -> Result<HeaderMap, BoxError> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        map.insert(
+            HeaderName::try_from(name.as_str())?,
+            HeaderValue::try_from(value.as_str())?,
+        );
+    }
+    Ok(map)
+}
+
+fn json(value: &Value) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+impl RequestFixture {
+    fn http_request(&self) -> Result<http::Request<graphql::Request>, BoxError> {
+        let body: graphql::Request =
+            serde_json_bytes::from_value(Value::Object(self.body.clone()))?;
+        let mut request = http::Request::builder()
+            .method(Method::POST)
+            .uri("http://default")
+            .body(body)?;
+        *request.headers_mut() = header_map(&self.headers)?;
+        Ok(request)
+    }
+
+    fn context(&self) -> Context {
+        let context = Context::new();
+        for (key, value) in &self.context {
+            context.insert_json_value(key.as_str(), value.clone());
+        }
+        context
+    }
+}
+
+impl ResponseFixture {
+    fn http_response(&self) -> Result<http::Response<graphql::Response>, BoxError> {
+        let body: graphql::Response =
+            serde_json_bytes::from_value(Value::Object(self.body.clone()))?;
+        let mut response = http::Response::new(body);
+        *response.status_mut() = StatusCode::from_u16(self.status.unwrap_or(200))?;
+        *response.headers_mut() = header_map(&self.headers)?;
+        Ok(response)
+    }
+
+    fn supergraph(&self, context: Context) -> Result<supergraph::Response, BoxError> {
+        let (parts, body) = self.http_response()?.into_parts();
+        let mut response = supergraph::Response::new_from_graphql_response(body, context);
+        *response.response.status_mut() = parts.status;
+        *response.response.headers_mut() = parts.headers;
+        Ok(response)
+    }
+
+    fn subgraph(&self, context: Context) -> Result<subgraph::Response, BoxError> {
+        Ok(subgraph::Response::new_from_response(
+            self.http_response()?,
+            context,
+        ))
+    }
+}
+
+impl Expected {
+    fn check(&self, what: &str, observed: &Observed, failures: &mut Vec<String>) {
+        if let Some(status) = self.status {
+            let actual = observed.status.map(|status| status.as_u16());
+            if actual != Some(status) {
+                failures.push(format!(
+                    "{what} status: expected {status}, got {}",
+                    actual.map(|s| s.to_string()).unwrap_or_default()
+                ));
+            }
+        }
+        for (name, expected) in &self.headers {
+            let actual = observed
+                .headers
+                .get(name.as_str())
+                .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned());
+            if &actual != expected {
+                failures.push(format!(
+                    "{what} header {name}: expected {expected:?}, got {actual:?}"
+                ));
+            }
+        }
+        if let Some(body) = &self.body {
+            if !body.is_subset(&observed.body) {
+                failures.push(format!(
+                    "{what} body: expected {} to be contained in {}",
+                    json(body),
+                    json(&observed.body)
+                ));
+            }
+        }
+        for (key, expected) in &self.context {
+            match observed.context.get_json_value(key.as_str()) {
+                Some(actual) if expected.is_subset(&actual) => {}
+                actual => failures.push(format!(
+                    "{what} context entry {}: expected {}, got {}",
+                    key.as_str(),
+                    json(expected),
+                    actual.map(|v| json(&v)).unwrap_or_else(|| "nothing".into())
+                )),
+            }
+        }
+    }
+}
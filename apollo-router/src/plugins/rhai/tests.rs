@@ -1,5 +1,7 @@
 //! Rhai module tests.
 
+use std::path::Path;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::Mutex;
@@ -27,8 +29,10 @@ use crate::graphql::Error;
 use crate::graphql::Request;
 use crate::http_ext;
 use crate::plugin::test::MockExecutionService;
+use crate::plugin::test::MockRouterService;
 use crate::plugin::test::MockSupergraphService;
 use crate::plugin::DynPlugin;
+use crate::services::router;
 use crate::services::ExecutionRequest;
 use crate::services::SubgraphRequest;
 use crate::services::SupergraphRequest;
@@ -89,6 +93,241 @@ async fn rhai_plugin_router_service() -> Result<(), BoxError> {
     Ok(())
 }
 
+async fn router_service_from_fixture(
+    config: Value,
+    mock_service: MockRouterService,
+) -> router::BoxService {
+    let dyn_plugin: Box<dyn DynPlugin> = crate::plugin::plugins()
+        .find(|factory| factory.name == "apollo.rhai")
+        .expect("Plugin not found")
+        .create_instance_without_schema(&config)
+        .await
+        .unwrap();
+    dyn_plugin.router_service(BoxService::new(mock_service))
+}
+
+async fn call_router_service(
+    router_service: &mut router::BoxService,
+    path: &str,
+) -> Result<router::Response, BoxError> {
+    let request = http::Request::builder()
+        .method("POST")
+        .uri(format!("http://localhost{path}"))
+        .body(r#"{"query":"{ field }"}"#.into())
+        .unwrap();
+    router_service
+        .ready()
+        .await?
+        .call(router::Request::from(request))
+        .await
+}
+
+#[tokio::test]
+async fn rhai_plugin_router_stage_rewrites_raw_http() -> Result<(), BoxError> {
+    let mut mock_service = MockRouterService::new();
+    mock_service
+        .expect_call()
+        .times(1)
+        .returning(move |req: router::Request| {
+            assert_eq!(
+                req.router_request.headers().get("x-method").unwrap(),
+                "POST"
+            );
+            let body =
+                futures::executor::block_on(hyper::body::to_bytes(req.router_request.into_body()))
+                    .unwrap();
+            assert_eq!(body, r#"{"query":"{ field }"}"#);
+            Ok(router::Response {
+                response: http::Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(r#"{"data":null}"#.into())
+                    .unwrap(),
+                context: req.context,
+            })
+        });
+
+    let mut router_service = router_service_from_fixture(
+        serde_json::json!({"scripts": "tests/fixtures", "main": "router_service_test.rhai"}),
+        mock_service,
+    )
+    .await;
+    let request = http::Request::builder()
+        .method("POST")
+        .uri("http://localhost/graphql")
+        .body(r#"{"query":"{ legacyField }"}"#.into())
+        .unwrap();
+    let response = router_service
+        .ready()
+        .await?
+        .call(router::Request::from(request))
+        .await?;
+
+    assert_eq!(response.response.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(
+        response.response.headers().get("x-request-size").unwrap(),
+        "21"
+    );
+    let body = hyper::body::to_bytes(response.response.into_body()).await?;
+    assert_eq!(body, "{\"data\":null}\n");
+    Ok(())
+}
+
+#[tokio::test]
+async fn rhai_plugin_router_stage_short_circuits() -> Result<(), BoxError> {
+    let mut mock_service = MockRouterService::new();
+    mock_service.expect_call().never();
+
+    let mut router_service = router_service_from_fixture(
+        serde_json::json!({"scripts": "tests/fixtures", "main": "router_service_test.rhai"}),
+        mock_service,
+    )
+    .await;
+    let request = http::Request::builder()
+        .method("POST")
+        .uri("http://localhost/forbidden")
+        .body(r#"{"query":"{ field }"}"#.into())
+        .unwrap();
+    let response = router_service
+        .ready()
+        .await?
+        .call(router::Request::from(request))
+        .await?;
+
+    assert_eq!(response.response.status(), StatusCode::FORBIDDEN);
+    let body = hyper::body::to_bytes(response.response.into_body()).await?;
+    let body: crate::graphql::Response = serde_json::from_slice(&body)?;
+    assert_eq!(body.errors[0].message, "forbidden path");
+    Ok(())
+}
+
+#[tokio::test]
+async fn rhai_plugin_router_stage_limits_request_size() -> Result<(), BoxError> {
+    let mut mock_service = MockRouterService::new();
+    mock_service.expect_call().never();
+
+    let mut router_service = router_service_from_fixture(
+        serde_json::json!({
+            "scripts": "tests/fixtures",
+            "main": "router_service_test.rhai",
+            "max_router_request_size": 10
+        }),
+        mock_service,
+    )
+    .await;
+    let response = call_router_service(&mut router_service, "/graphql").await?;
+
+    assert_eq!(response.response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let body = hyper::body::to_bytes(response.response.into_body()).await?;
+    let body: crate::graphql::Response = serde_json::from_slice(&body)?;
+    assert_eq!(
+        body.errors[0].extensions.get("code").unwrap(),
+        "REQUEST_TOO_LARGE"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn rhai_plugin_router_stage_streams_multipart_responses() -> Result<(), BoxError> {
+    let mut mock_service = MockRouterService::new();
+    mock_service
+        .expect_call()
+        .times(1)
+        .returning(move |req: router::Request| {
+            // the response body never completes, like a subscription
+            let (mut sender, body) = hyper::Body::channel();
+            tokio::spawn(async move {
+                sender.send_data("--graphql".into()).await.unwrap();
+                futures::future::pending::<()>().await;
+            });
+            Ok(router::Response {
+                response: http::Response::builder()
+                    .header("content-type", "multipart/mixed;boundary=\"graphql\"")
+                    .body(body)
+                    .unwrap(),
+                context: req.context,
+            })
+        });
+
+    let mut router_service = router_service_from_fixture(
+        serde_json::json!({"scripts": "tests/fixtures", "main": "router_service_test.rhai"}),
+        mock_service,
+    )
+    .await;
+    let response = tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        call_router_service(&mut router_service, "/graphql"),
+    )
+    .await
+    .expect("the response body must not be buffered")?;
+
+    // the callback sees the headers, and its changes to the body are ignored
+    assert_eq!(
+        response.response.headers().get("x-request-size").unwrap(),
+        "21"
+    );
+    let mut body = response.response.into_body();
+    let chunk = hyper::body::HttpBody::data(&mut body).await.unwrap()?;
+    assert_eq!(chunk, "--graphql");
+    Ok(())
+}
+
+#[tokio::test]
+async fn rhai_plugin_enforces_callback_timeout() -> Result<(), BoxError> {
+    let mut mock_service = MockRouterService::new();
+    mock_service.expect_call().never();
+
+    let mut router_service = router_service_from_fixture(
+        serde_json::json!({
+            "scripts": "tests/fixtures",
+            "main": "limits_test.rhai",
+            "limits": { "timeout": "100ms", "status_code": 503 }
+        }),
+        mock_service,
+    )
+    .await;
+    let response = call_router_service(&mut router_service, "/loop").await?;
+
+    assert_eq!(response.response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let body = hyper::body::to_bytes(response.response.into_body()).await?;
+    let body: crate::graphql::Response = serde_json::from_slice(&body)?;
+    assert_eq!(
+        body.errors[0].message,
+        "rhai execution error: 'process_request' exceeded its timeout limit"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn rhai_plugin_enforces_engine_limits() -> Result<(), BoxError> {
+    let mut mock_service = MockRouterService::new();
+    mock_service.expect_call().never();
+
+    let mut router_service = router_service_from_fixture(
+        serde_json::json!({
+            "scripts": "tests/fixtures",
+            "main": "limits_test.rhai",
+            "limits": { "max_operations": 10000, "max_string_size": 100 }
+        }),
+        mock_service,
+    )
+    .await;
+
+    for (path, limit) in [("/loop", "operations"), ("/large", "data_size")] {
+        let response = call_router_service(&mut router_service, path).await?;
+        assert_eq!(
+            response.response.status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        let body = hyper::body::to_bytes(response.response.into_body()).await?;
+        let body: crate::graphql::Response = serde_json::from_slice(&body)?;
+        assert_eq!(
+            body.errors[0].message,
+            format!("rhai execution error: 'process_request' exceeded its {limit} limit")
+        );
+    }
+    Ok(())
+}
+
 #[tokio::test]
 async fn rhai_plugin_execution_service_error() -> Result<(), BoxError> {
     let mut mock_service = MockExecutionService::new();
@@ -524,6 +763,83 @@ fn it_can_generate_uuid() {
     assert_eq!(uuid_v4_rhai, uuid_parsed.to_string());
 }
 
+#[test]
+fn it_can_hash_strings() {
+    let engine = new_rhai_test_engine();
+    let digest: String = engine
+        .eval(r#"crypto::sha256("abc")"#)
+        .expect("can hash string");
+    assert_eq!(
+        digest,
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    // RFC 4231 test case 2
+    let hmac: String = engine
+        .eval(r#"crypto::hmac_sha256("Jefe", "what do ya want for nothing?")"#)
+        .expect("can compute hmac");
+    assert_eq!(
+        hmac,
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+}
+
+#[test]
+fn it_can_encode_and_decode_json() {
+    let engine = new_rhai_test_engine();
+    let encoded: String = engine
+        .eval(r#"json::encode(#{ name: "router", tags: [1, 2] })"#)
+        .expect("can encode json");
+    assert_eq!(
+        serde_json::from_str::<Value>(&encoded).unwrap(),
+        serde_json::json!({ "name": "router", "tags": [1, 2] })
+    );
+    let name: String = engine
+        .eval(r#"json::decode(`{"user": {"name": "ada"}}`).user.name"#)
+        .expect("can decode json");
+    assert_eq!(name, "ada");
+    assert!(engine
+        .eval::<rhai::Dynamic>(r#"json::decode("{")"#)
+        .is_err());
+}
+
+#[test]
+fn it_can_format_and_parse_time() {
+    let engine = new_rhai_test_engine();
+    let formatted: String = engine
+        .eval(r#"time::format(1234567890)"#)
+        .expect("can format time");
+    assert_eq!(formatted, "2009-02-13T23:31:30Z");
+    let parsed: i64 = engine
+        .eval(r#"time::parse("2009-02-13T23:31:30Z")"#)
+        .expect("can parse time");
+    assert_eq!(parsed, 1234567890);
+    let now: i64 = engine
+        .eval(r#"time::parse(time::now())"#)
+        .expect("can parse current time");
+    let st = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("can get system time")
+        .as_secs() as i64;
+    assert!(st - 1 <= now && now <= st);
+}
+
+#[test]
+fn it_logs_messages_with_fields() {
+    let env_filter = "apollo_router=trace";
+    let mock_writer = tracing_test::internal::MockWriter::new(&tracing_test::internal::GLOBAL_BUF);
+    let subscriber = tracing_test::internal::get_subscriber(mock_writer, env_filter);
+
+    let _guard = tracing::dispatcher::set_default(&subscriber);
+    let engine = new_rhai_test_engine();
+    engine
+        .eval::<()>(r#"log_info("fields log", #{ subgraph: "products_subgraph" })"#)
+        .expect("it logged a message");
+    assert!(tracing_test::internal::logs_with_scope_contain(
+        "apollo_router",
+        "products_subgraph"
+    ));
+}
+
 async fn base_globals_function(fn_name: &str) -> Result<bool, Box<rhai::EvalAltResult>> {
     let dyn_plugin: Box<dyn DynPlugin> = crate::plugin::plugins()
         .find(|factory| factory.name == "apollo.rhai")
@@ -686,3 +1002,28 @@ async fn it_cannot_process_om_subgraph_missing_message_and_body() {
         panic!("error processed incorrectly");
     }
 }
+
+#[tokio::test]
+async fn rhai_test_runner_passes_matching_fixtures() {
+    super::test_runner::run(
+        Path::new("tests/fixtures"),
+        "test_runner.rhai",
+        &[PathBuf::from(
+            "tests/fixtures/rhai_test_runner/passing.yaml",
+        )],
+    )
+    .await
+    .expect("all test cases should pass");
+}
+
+#[tokio::test]
+async fn rhai_test_runner_fails_on_mismatch() {
+    let error = super::test_runner::run(
+        Path::new("tests/fixtures"),
+        "test_runner.rhai",
+        &[PathBuf::from("tests/fixtures/rhai_test_runner")],
+    )
+    .await
+    .expect_err("the failing fixture should fail");
+    assert_eq!(error.to_string(), "1 rhai test case(s) failed");
+}
@@ -0,0 +1,195 @@
+//! GraphQL subscriptions support.
+//!
+//! Subscriptions are forwarded to subgraphs over a WebSocket connection using the
+//! `graphql-transport-ws` protocol, and the events are streamed back to the client
+//! as multipart responses.
+
+use std::collections::HashMap;
+
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use tower::BoxError;
+
+use crate::plugin::Plugin;
+use crate::plugin::PluginInit;
+use crate::register_plugin;
+use crate::Configuration;
+
+pub(crate) const APOLLO_SUBSCRIPTION_PLUGIN: &str = "apollo.subscription";
+const APOLLO_SUBSCRIPTION_PLUGIN_NAME: &str = "subscription";
+
+#[derive(Debug, Clone)]
+pub(crate) struct Subscription {
+    pub(crate) config: SubscriptionConfig,
+}
+
+/// Subscriptions configuration
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+pub(crate) struct SubscriptionConfig {
+    /// Enable subscriptions (default: false)
+    pub(crate) enabled: bool,
+    /// Select a subscription mode
+    pub(crate) mode: SubscriptionModeConfig,
+}
+
+/// Subscription modes
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+pub(crate) struct SubscriptionModeConfig {
+    /// Open a WebSocket connection to the subgraph for every client subscription
+    pub(crate) passthrough: SubgraphPassthroughMode,
+}
+
+/// Passthrough mode configuration
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+pub(crate) struct SubgraphPassthroughMode {
+    /// Configuration for all subgraphs
+    pub(crate) all: Option<WebSocketConfiguration>,
+    /// Configuration for specific subgraphs, overriding `all`
+    pub(crate) subgraphs: HashMap<String, WebSocketConfiguration>,
+}
+
+impl SubgraphPassthroughMode {
+    /// Returns the WebSocket configuration for a subgraph, if subscriptions are enabled for it
+    pub(crate) fn get(&self, subgraph_name: &str) -> Option<&WebSocketConfiguration> {
+        self.subgraphs.get(subgraph_name).or(self.all.as_ref())
+    }
+}
+
+/// WebSocket configuration for a subgraph
+#[derive(Debug, Clone, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+pub(crate) struct WebSocketConfiguration {
+    /// Path on which the subgraph accepts WebSocket connections, if different from its routing url path
+    pub(crate) path: Option<String>,
+    /// Which WebSocket GraphQL protocol to use for this subgraph (default: graphql_ws)
+    pub(crate) protocol: WebSocketProtocol,
+}
+
+/// WebSocket GraphQL protocol
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum WebSocketProtocol {
+    /// The `graphql-transport-ws` protocol, as implemented by the `graphql-ws` library
+    #[default]
+    GraphqlWs,
+}
+
+impl WebSocketProtocol {
+    /// The value sent in the `Sec-WebSocket-Protocol` header
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            WebSocketProtocol::GraphqlWs => "graphql-transport-ws",
+        }
+    }
+}
+
+impl SubscriptionConfig {
+    /// Extracts the subscription configuration from the router configuration
+    pub(crate) fn from_configuration(configuration: &Configuration) -> Option<Self> {
+        configuration
+            .apollo_plugins
+            .plugins
+            .get(APOLLO_SUBSCRIPTION_PLUGIN_NAME)
+            .and_then(|config| serde_json::from_value(config.clone()).ok())
+    }
+
+    /// Returns true if subscriptions are enabled in the router configuration
+    pub(crate) fn is_enabled(configuration: &Configuration) -> bool {
+        Self::from_configuration(configuration)
+            .map(|config| config.enabled)
+            .unwrap_or(false)
+    }
+}
+
+#[async_trait::async_trait]
+impl Plugin for Subscription {
+    type Config = SubscriptionConfig;
+
+    async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        Ok(Subscription {
+            config: init.config,
+        })
+    }
+}
+
+impl Subscription {
+    /// Returns the WebSocket configuration to use for a subgraph, if subscriptions are enabled for it
+    pub(crate) fn subgraph_configuration(
+        &self,
+        subgraph_name: &str,
+    ) -> Option<WebSocketConfiguration> {
+        if self.config.enabled {
+            self.config.mode.passthrough.get(subgraph_name).cloned()
+        } else {
+            None
+        }
+    }
+}
+
+register_plugin!("apollo", "subscription", Subscription);
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn it_resolves_subgraph_websocket_configuration() {
+        let plugin = crate::plugin::plugins()
+            .find(|factory| factory.name == APOLLO_SUBSCRIPTION_PLUGIN)
+            .expect("Plugin not found")
+            .create_instance_without_schema(&json!({
+                "enabled": true,
+                "mode": {
+                    "passthrough": {
+                        "all": {
+                            "path": "/ws"
+                        },
+                        "subgraphs": {
+                            "accounts": {
+                                "path": "/subscriptions"
+                            }
+                        }
+                    }
+                }
+            }))
+            .await
+            .unwrap();
+        let subscription = plugin
+            .as_any()
+            .downcast_ref::<Subscription>()
+            .expect("it is a subscription plugin");
+
+        assert_eq!(
+            subscription
+                .subgraph_configuration("accounts")
+                .unwrap()
+                .path
+                .as_deref(),
+            Some("/subscriptions")
+        );
+        let products = subscription.subgraph_configuration("products").unwrap();
+        assert_eq!(products.path.as_deref(), Some("/ws"));
+        assert_eq!(products.protocol.as_str(), "graphql-transport-ws");
+    }
+
+    #[tokio::test]
+    async fn it_is_disabled_by_default() {
+        let configuration = Configuration::default();
+        assert!(!SubscriptionConfig::is_enabled(&configuration));
+
+        let configuration: Configuration = serde_yaml::from_str(
+            r#"
+subscription:
+  enabled: true
+"#,
+        )
+        .unwrap();
+        assert!(SubscriptionConfig::is_enabled(&configuration));
+    }
+}
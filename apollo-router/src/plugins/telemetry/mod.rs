@@ -658,6 +658,10 @@ impl Telemetry {
                 apollo_private.field_level_instrumentation_ratio =
                     field_level_instrumentation_ratio,
                 apollo_private.operation_signature = field::Empty,
+                apollo_router.operation_limits.max_depth = field::Empty,
+                apollo_router.operation_limits.max_height = field::Empty,
+                apollo_router.operation_limits.max_root_fields = field::Empty,
+                apollo_router.operation_limits.max_aliases = field::Empty,
                 apollo_private.graphql.variables = Self::filter_variables_values(
                     &request.supergraph_request.body().variables,
                     &config.send_variable_values,
@@ -1,11 +1,17 @@
 // With regards to ELv2 licensing, this entire file is license key functionality
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
 use std::task::Context;
 use std::task::Poll;
 use std::time::Duration;
+use std::time::Instant;
 
 use futures::future::BoxFuture;
 use futures::FutureExt;
+use http::header::CACHE_CONTROL;
+use http::HeaderMap;
+use lru::LruCache;
 use serde::Deserialize;
 use serde::Serialize;
 use serde_json_bytes::Value;
@@ -17,6 +23,8 @@ use tower::Service;
 use tower::ServiceExt;
 use tracing::Level;
 
+use super::invalidation::Invalidation;
+use super::InMemoryEntityCache;
 use crate::cache::redis::RedisCacheStorage;
 use crate::cache::redis::RedisKey;
 use crate::cache::redis::RedisValue;
@@ -26,20 +34,239 @@ use crate::json_ext::Object;
 use crate::services::subgraph;
 use crate::spec::TYPENAME;
 
+/// Entity cache storage: Redis, with an optional in memory tier in front of it
+#[derive(Clone)]
+pub(crate) struct EntityCache {
+    redis: RedisCacheStorage,
+    in_memory: Option<InMemoryTier>,
+}
+
+#[derive(Clone)]
+struct InMemoryTier {
+    entries: Arc<Mutex<LruCache<String, InMemoryEntry>>>,
+    ttl: Duration,
+}
+
+/// Entries stay at most this long in the in memory tier, because invalidation requests only
+/// reach the in memory tier of one router instance
+const MAX_IN_MEMORY_TTL: Duration = Duration::from_secs(30);
+
+struct InMemoryEntry {
+    value: Value,
+    expires_at: Instant,
+}
+
+impl EntityCache {
+    pub(crate) fn new(redis: RedisCacheStorage, in_memory: Option<&InMemoryEntityCache>) -> Self {
+        EntityCache {
+            redis,
+            in_memory: in_memory.map(|config| {
+                if config.ttl > MAX_IN_MEMORY_TTL {
+                    tracing::warn!(
+                        "the entity cache in memory TTL is limited to {}s",
+                        MAX_IN_MEMORY_TTL.as_secs()
+                    );
+                }
+                InMemoryTier {
+                    entries: Arc::new(Mutex::new(LruCache::new(config.limit))),
+                    ttl: config.ttl.min(MAX_IN_MEMORY_TTL),
+                }
+            }),
+        }
+    }
+
+    pub(super) async fn get_multiple(&self, keys: &[String]) -> Vec<Option<Value>> {
+        let mut result: Vec<Option<Value>> = vec![None; keys.len()];
+
+        let missing: Vec<usize> = match &self.in_memory {
+            None => (0..keys.len()).collect(),
+            Some(in_memory) => {
+                let now = Instant::now();
+                let mut entries = in_memory.entries.lock().expect("lock poisoned");
+                let mut missing = Vec::new();
+                for (index, key) in keys.iter().enumerate() {
+                    match entries.get(key) {
+                        Some(entry) if entry.expires_at > now => {
+                            result[index] = Some(entry.value.clone())
+                        }
+                        Some(_) => {
+                            entries.pop(key);
+                            missing.push(index);
+                        }
+                        None => missing.push(index),
+                    }
+                }
+                missing
+            }
+        };
+
+        if missing.is_empty() {
+            return result;
+        }
+
+        let redis_result = self
+            .redis
+            .get_multiple(
+                missing
+                    .iter()
+                    .map(|index| RedisKey(keys[*index].clone()))
+                    .collect::<Vec<_>>(),
+            )
+            .await
+            .unwrap_or_default();
+
+        let mut in_memory = self.in_memory.as_ref().map(|in_memory| {
+            (
+                in_memory.ttl,
+                in_memory.entries.lock().expect("lock poisoned"),
+            )
+        });
+        for (index, value) in missing.into_iter().zip(redis_result) {
+            if let Some(RedisValue(value)) = value {
+                // we do not know how long the entry will stay in Redis, so it is only
+                // kept in memory for the in memory tier's TTL
+                if let Some((ttl, entries)) = in_memory.as_mut() {
+                    entries.put(
+                        keys[index].clone(),
+                        InMemoryEntry {
+                            value: value.clone(),
+                            expires_at: Instant::now() + *ttl,
+                        },
+                    );
+                }
+                result[index] = Some(value);
+            }
+        }
+
+        result
+    }
+
+    pub(super) async fn insert_multiple(&self, entries: Vec<(String, Value, Duration)>) {
+        if let Some(in_memory) = &self.in_memory {
+            let now = Instant::now();
+            let mut in_memory_entries = in_memory.entries.lock().expect("lock poisoned");
+            for (key, value, ttl) in &entries {
+                in_memory_entries.put(
+                    key.clone(),
+                    InMemoryEntry {
+                        value: value.clone(),
+                        expires_at: now + (*ttl).min(in_memory.ttl),
+                    },
+                );
+            }
+        }
+
+        let entries = entries
+            .into_iter()
+            .map(|(key, value, ttl)| (RedisKey(key), RedisValue(value), ttl))
+            .collect::<Vec<_>>();
+        self.redis.insert_multiple_with_ttl(&entries).await;
+    }
+
+    /// Removes the matching entries from Redis and from the in memory tier of this router
+    /// instance, and returns how many were deleted from Redis
+    ///
+    /// Other router instances keep their in memory entries until they expire, which is why
+    /// their TTL is capped by [`MAX_IN_MEMORY_TTL`]
+    pub(crate) async fn invalidate(&self, invalidation: &Invalidation) -> Result<u64, BoxError> {
+        if let Some(in_memory) = &self.in_memory {
+            let mut entries = in_memory.entries.lock().expect("lock poisoned");
+            let keys: Vec<String> = entries
+                .iter()
+                .filter(|(key, _)| invalidation.matches(key))
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in keys {
+                entries.pop(&key);
+            }
+        }
+
+        Ok(self
+            .redis
+            .delete_matching(&invalidation.redis_pattern())
+            .await?)
+    }
+}
+
+/// How long entities are cached for a subgraph
+#[derive(Clone, Debug, Default)]
+pub(crate) struct EntityTtl {
+    /// used if neither the type configuration nor the subgraph response set a TTL
+    pub(crate) default: Duration,
+    pub(crate) types: HashMap<String, Duration>,
+}
+
+impl EntityTtl {
+    /// Returns `None` if the entity must not be cached
+    fn for_entity(&self, typename: &str, cache_control: CacheControl) -> Option<Duration> {
+        let ttl = match cache_control {
+            CacheControl::NoStore => return None,
+            CacheControl::MaxAge(max_age) => self.types.get(typename).copied().unwrap_or(max_age),
+            CacheControl::Unspecified => self.types.get(typename).copied().unwrap_or(self.default),
+        };
+
+        (!ttl.is_zero()).then_some(ttl)
+    }
+}
+
+/// What the subgraph's `Cache-Control` header allows
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CacheControl {
+    Unspecified,
+    NoStore,
+    MaxAge(Duration),
+}
+
+impl CacheControl {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let mut max_age = None;
+        let mut shared_max_age = None;
+
+        for value in headers.get_all(CACHE_CONTROL) {
+            let value = match value.to_str() {
+                Ok(value) => value,
+                Err(_) => continue,
+            };
+
+            for directive in value.split(',') {
+                let (name, argument) = match directive.split_once('=') {
+                    Some((name, argument)) => {
+                        (name.trim(), Some(argument.trim().trim_matches('"')))
+                    }
+                    None => (directive.trim(), None),
+                };
+
+                match name.to_ascii_lowercase().as_str() {
+                    "no-store" | "no-cache" | "private" => return CacheControl::NoStore,
+                    "max-age" => max_age = argument.and_then(|a| a.parse::<u64>().ok()),
+                    // the router is a shared cache
+                    "s-maxage" => shared_max_age = argument.and_then(|a| a.parse::<u64>().ok()),
+                    _ => {}
+                }
+            }
+        }
+
+        match shared_max_age.or(max_age) {
+            Some(seconds) => CacheControl::MaxAge(Duration::from_secs(seconds)),
+            None => CacheControl::Unspecified,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct SubgraphCacheLayer {
-    storage: RedisCacheStorage,
+    cache: EntityCache,
     name: String,
+    ttl: Arc<EntityTtl>,
 }
 
 impl SubgraphCacheLayer {
-    pub(crate) fn new_with_storage(
-        name: String,
-        mut storage: RedisCacheStorage,
-        ttl: Duration,
-    ) -> Self {
-        storage.set_ttl(Some(ttl));
-        SubgraphCacheLayer { storage, name }
+    pub(crate) fn new(name: String, cache: EntityCache, ttl: EntityTtl) -> Self {
+        SubgraphCacheLayer {
+            cache,
+            name,
+            ttl: Arc::new(ttl),
+        }
     }
 }
 
@@ -49,7 +276,8 @@ impl<S: Clone> Layer<S> for SubgraphCacheLayer {
     fn layer(&self, service: S) -> Self::Service {
         SubgraphCache {
             name: self.name.clone(),
-            storage: self.storage.clone(),
+            cache: self.cache.clone(),
+            ttl: self.ttl.clone(),
             service,
         }
     }
@@ -57,8 +285,9 @@ impl<S: Clone> Layer<S> for SubgraphCacheLayer {
 
 #[derive(Clone)]
 pub(crate) struct SubgraphCache<S: Clone> {
-    storage: RedisCacheStorage,
+    cache: EntityCache,
     name: String,
+    ttl: Arc<EntityTtl>,
     service: S,
 }
 
@@ -96,16 +325,18 @@ where
             return service.oneshot(request).boxed();
         }
 
-        let cache = self.storage.clone();
+        let cache = self.cache.clone();
         let name = self.name.clone();
-        Box::pin(cache_call(service, name, cache, request))
+        let ttl = self.ttl.clone();
+        Box::pin(cache_call(service, name, cache, ttl, request))
     }
 }
 
 async fn cache_call<S>(
     service: S,
     name: String,
-    cache: RedisCacheStorage,
+    cache: EntityCache,
+    ttl: Arc<EntityTtl>,
     mut request: subgraph::Request,
 ) -> Result<<S as Service<subgraph::Request>>::Response, <S as Service<subgraph::Request>>::Error>
 where
@@ -126,14 +357,10 @@ where
         .expect("we already checked that representations exist");
 
     let keys = extract_cache_keys(representations, &name, &query_hash)?;
-    let cache_result = cache
-        .get_multiple(keys.iter().map(|k| RedisKey(k.clone())).collect::<Vec<_>>())
-        .await
-        .map(|res| res.into_iter().map(|r| r.map(|v| v.0)).collect())
-        .unwrap_or_else(|| std::iter::repeat(None).take(keys.len()).collect());
+    let cache_result = cache.get_multiple(&keys).await;
 
     let (new_representations, mut result) =
-        filter_representations(representations, keys, cache_result)?;
+        filter_representations(&name, representations, keys, cache_result)?;
 
     if !new_representations.is_empty() {
         body.variables
@@ -141,6 +368,7 @@ where
 
         let mut response = service.oneshot(request).await?;
 
+        let cache_control = CacheControl::from_headers(response.response.headers());
         let mut data = response.response.body_mut().data.take();
 
         if let Some(mut entities) = data
@@ -155,6 +383,8 @@ where
                         reason: "expected an array of entities".to_string(),
                     })?,
                 &cache,
+                &ttl,
+                cache_control,
                 &mut result,
             )
             .await?;
@@ -171,7 +401,14 @@ where
             .into())
         }
     } else {
-        let entities = insert_entities_in_result(&mut Vec::new(), &cache, &mut result).await?;
+        let entities = insert_entities_in_result(
+            &mut Vec::new(),
+            &cache,
+            &ttl,
+            CacheControl::Unspecified,
+            &mut result,
+        )
+        .await?;
         let mut data = Object::default();
         data.insert("_entities", entities.into());
 
@@ -183,6 +420,16 @@ where
     }
 }
 
+/// Entity keys are formatted as `subgraph.{subgraph name}|{typename}|{representation}|{query hash}`
+pub(super) fn entity_key(
+    subgraph_name: &str,
+    typename: &str,
+    representation: &str,
+    query_hash: &str,
+) -> String {
+    format!("subgraph.{subgraph_name}|{typename}|{representation}|{query_hash}")
+}
+
 fn hash_request(body: &graphql::Request) -> String {
     let mut digest = Sha256::new();
     digest.update(body.query.as_deref().unwrap_or("-").as_bytes());
@@ -211,12 +458,11 @@ fn extract_cache_keys(
 
         let typename = opt_type.as_str().unwrap_or("-").to_string();
 
-        let key = format!(
-            "subgraph.{}|{}|{}|{}",
+        let key = entity_key(
             subgraph_name,
             &typename,
-            serde_json::to_string(&representation).unwrap(),
-            query_hash
+            &serde_json::to_string(&representation).unwrap(),
+            query_hash,
         );
 
         representation
@@ -235,6 +481,7 @@ struct IntermediateResult {
 
 // build a new list of representations without the ones we got from the cache
 fn filter_representations(
+    subgraph_name: &str,
     representations: &mut Vec<Value>,
     keys: Vec<String>,
     mut cache_result: Vec<Option<Value>>,
@@ -275,12 +522,20 @@ fn filter_representations(
     }
 
     for (ty, (hit, miss)) in cache_hit {
-        tracing::event!(
-            Level::INFO,
-            entity_type = ty.as_str(),
-            cache_hit = hit,
-            cache_miss = miss
-        );
+        if hit > 0 {
+            tracing::info!(
+                monotonic_counter.apollo_router_entity_cache_hit_count = hit as u64,
+                entity_type = ty.as_str(),
+                subgraph = subgraph_name,
+            );
+        }
+        if miss > 0 {
+            tracing::info!(
+                monotonic_counter.apollo_router_entity_cache_miss_count = miss as u64,
+                entity_type = ty.as_str(),
+                subgraph = subgraph_name,
+            );
+        }
     }
 
     Ok((new_representations, result))
@@ -288,7 +543,9 @@ fn filter_representations(
 
 async fn insert_entities_in_result(
     entities: &mut Vec<Value>,
-    cache: &RedisCacheStorage,
+    cache: &EntityCache,
+    ttl: &EntityTtl,
+    cache_control: CacheControl,
     result: &mut Vec<IntermediateResult>,
 ) -> Result<Vec<Value>, BoxError> {
     let mut new_entities = Vec::new();
@@ -313,8 +570,13 @@ async fn insert_entities_in_result(
                     .ok_or_else(|| FetchError::MalformedResponse {
                         reason: "invalid number of entities".to_string(),
                     })?;
-                *inserted_types.entry(typename).or_default() += 1;
-                to_insert.push((RedisKey(key), RedisValue(value.clone())));
+                // null entities come from subgraph errors and are not cached
+                if let (false, Some(ttl)) =
+                    (value.is_null(), ttl.for_entity(&typename, cache_control))
+                {
+                    *inserted_types.entry(typename).or_default() += 1;
+                    to_insert.push((key, value.clone(), ttl));
+                }
 
                 new_entities.push(value);
             }
@@ -322,7 +584,7 @@ async fn insert_entities_in_result(
     }
 
     if !to_insert.is_empty() {
-        cache.insert_multiple(&to_insert).await;
+        cache.insert_multiple(to_insert).await;
     }
 
     for (ty, nb) in inserted_types {
@@ -338,3 +600,128 @@ struct Key {
     opt_type: Option<Value>,
     id: Value,
 }
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+
+    use super::*;
+
+    fn cache_control(values: &[&'static str]) -> CacheControl {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(CACHE_CONTROL, HeaderValue::from_static(value));
+        }
+        CacheControl::from_headers(&headers)
+    }
+
+    #[test]
+    fn it_parses_cache_control() {
+        assert_eq!(cache_control(&[]), CacheControl::Unspecified);
+        assert_eq!(cache_control(&["public"]), CacheControl::Unspecified);
+        assert_eq!(
+            cache_control(&["public, max-age=60"]),
+            CacheControl::MaxAge(Duration::from_secs(60))
+        );
+        assert_eq!(
+            cache_control(&["max-age=60", "s-maxage=30"]),
+            CacheControl::MaxAge(Duration::from_secs(30))
+        );
+        assert_eq!(
+            cache_control(&["max-age=60, no-store"]),
+            CacheControl::NoStore
+        );
+        assert_eq!(cache_control(&["private"]), CacheControl::NoStore);
+    }
+
+    #[test]
+    fn it_resolves_entity_ttl() {
+        let ttl = EntityTtl {
+            default: Duration::from_secs(10),
+            types: [
+                ("User".to_string(), Duration::from_secs(100)),
+                ("Review".to_string(), Duration::ZERO),
+            ]
+            .into_iter()
+            .collect(),
+        };
+
+        assert_eq!(
+            ttl.for_entity("Product", CacheControl::Unspecified),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(
+            ttl.for_entity("Product", CacheControl::MaxAge(Duration::from_secs(5))),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            ttl.for_entity("User", CacheControl::MaxAge(Duration::from_secs(5))),
+            Some(Duration::from_secs(100))
+        );
+        assert_eq!(ttl.for_entity("User", CacheControl::NoStore), None);
+        assert_eq!(ttl.for_entity("Review", CacheControl::Unspecified), None);
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn it_keeps_entities_in_memory() {
+        use std::num::NonZeroUsize;
+
+        use serde_json_bytes::json;
+
+        // Redis is available in CI
+        let redis = RedisCacheStorage::new(vec!["redis://127.0.0.1:6379".parse().unwrap()], None)
+            .await
+            .unwrap();
+        let cache = EntityCache::new(
+            redis,
+            Some(&InMemoryEntityCache {
+                limit: NonZeroUsize::new(10).unwrap(),
+                ttl: Duration::from_millis(500),
+            }),
+        );
+        let subgraph = uuid::Uuid::new_v4().to_string();
+        let inserted = entity_key(&subgraph, "User", r#"{"id":"1"}"#, "hash");
+        let read = entity_key(&subgraph, "User", r#"{"id":"2"}"#, "hash");
+
+        // inserted entries are kept in memory after they are removed from Redis
+        cache
+            .insert_multiple(vec![(
+                inserted.clone(),
+                json!({"name": "Ada"}),
+                Duration::from_secs(60),
+            )])
+            .await;
+        assert_eq!(cache.redis.delete_matching(&inserted).await.unwrap(), 1);
+        assert_eq!(
+            cache.get_multiple(&[inserted.clone()]).await,
+            vec![Some(json!({"name": "Ada"}))]
+        );
+
+        // so are entries read from Redis
+        cache
+            .redis
+            .insert_multiple_with_ttl(&[(
+                RedisKey(read.clone()),
+                RedisValue(json!({"name": "Alan"})),
+                Duration::from_secs(60),
+            )])
+            .await;
+        assert_eq!(
+            cache.get_multiple(&[read.clone()]).await,
+            vec![Some(json!({"name": "Alan"}))]
+        );
+        assert_eq!(cache.redis.delete_matching(&read).await.unwrap(), 1);
+        assert_eq!(
+            cache.get_multiple(&[read.clone()]).await,
+            vec![Some(json!({"name": "Alan"}))]
+        );
+
+        // until the in memory TTL expires
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        assert_eq!(
+            cache.get_multiple(&[inserted, read]).await,
+            vec![None, None]
+        );
+    }
+}
@@ -0,0 +1,407 @@
+//! Stop sending requests to a failing subgraph. Implemented as a tower Layer.
+//!
+//! The circuit opens after too many failures, then requests fail immediately for some time.
+//! After that time, a single probe request is sent to the subgraph (half open state): if it
+//! succeeds the circuit closes, otherwise it opens again.
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Poll;
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::future::BoxFuture;
+use tower::BoxError;
+use tower::Layer;
+use tower::ServiceExt;
+
+use super::rate::RateLimited;
+use crate::error::FetchError;
+use crate::services::SubgraphRequest;
+use crate::services::SubgraphResponse;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct CircuitBreakerSettings {
+    pub(crate) consecutive_failures: Option<u32>,
+    pub(crate) error_rate: Option<f64>,
+    pub(crate) minimum_requests: u32,
+    pub(crate) window: Duration,
+    pub(crate) open_duration: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Closed,
+    Open {
+        until: Instant,
+    },
+    /// A probe request was sent, other requests are rejected until it finishes. If it is
+    /// dropped before finishing, another probe is allowed after the deadline
+    HalfOpen {
+        probe_deadline: Instant,
+    },
+}
+
+impl State {
+    fn metric_value(&self) -> u64 {
+        match self {
+            State::Closed => 0,
+            State::HalfOpen { .. } => 1,
+            State::Open { .. } => 2,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Breaker {
+    state: State,
+    consecutive_failures: u32,
+    window_start: Instant,
+    requests: u32,
+    failures: u32,
+}
+
+/// Circuit breaker state of a subgraph
+#[derive(Debug)]
+struct CircuitBreaker {
+    subgraph: String,
+    settings: CircuitBreakerSettings,
+    breaker: Mutex<Breaker>,
+}
+
+/// How a request was allowed by the circuit breaker
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Permit {
+    Request,
+    Probe,
+}
+
+impl CircuitBreaker {
+    fn new(subgraph: String, settings: CircuitBreakerSettings) -> Self {
+        Self {
+            subgraph,
+            settings,
+            breaker: Mutex::new(Breaker {
+                state: State::Closed,
+                consecutive_failures: 0,
+                window_start: Instant::now(),
+                requests: 0,
+                failures: 0,
+            }),
+        }
+    }
+
+    fn acquire(&self, now: Instant) -> Option<Permit> {
+        let mut breaker = self.breaker.lock().unwrap();
+        match breaker.state {
+            State::Closed => Some(Permit::Request),
+            State::Open { until }
+            | State::HalfOpen {
+                probe_deadline: until,
+            } if now >= until => {
+                self.transition(
+                    &mut breaker,
+                    State::HalfOpen {
+                        probe_deadline: now + self.settings.open_duration,
+                    },
+                );
+                Some(Permit::Probe)
+            }
+            State::Open { .. } | State::HalfOpen { .. } => None,
+        }
+    }
+
+    fn record(&self, permit: Permit, success: bool, now: Instant) {
+        let mut breaker = self.breaker.lock().unwrap();
+        match (permit, breaker.state) {
+            (Permit::Probe, State::HalfOpen { .. }) => {
+                if success {
+                    breaker.consecutive_failures = 0;
+                    breaker.window_start = now;
+                    breaker.requests = 0;
+                    breaker.failures = 0;
+                    self.transition(&mut breaker, State::Closed);
+                } else {
+                    self.open(&mut breaker, now);
+                }
+            }
+            (Permit::Request, State::Closed) => {
+                if now.duration_since(breaker.window_start) > self.settings.window {
+                    breaker.window_start = now;
+                    breaker.requests = 0;
+                    breaker.failures = 0;
+                }
+                breaker.requests += 1;
+                if success {
+                    breaker.consecutive_failures = 0;
+                } else {
+                    breaker.consecutive_failures += 1;
+                    breaker.failures += 1;
+                }
+
+                let too_many_consecutive_failures = self
+                    .settings
+                    .consecutive_failures
+                    .map(|max| breaker.consecutive_failures >= max)
+                    .unwrap_or(false);
+                let error_rate_exceeded = self
+                    .settings
+                    .error_rate
+                    .map(|max| {
+                        breaker.requests >= self.settings.minimum_requests
+                            && breaker.failures as f64 / breaker.requests as f64 >= max
+                    })
+                    .unwrap_or(false);
+                if too_many_consecutive_failures || error_rate_exceeded {
+                    self.open(&mut breaker, now);
+                }
+            }
+            // results of requests sent before the last state change are ignored
+            _ => {}
+        }
+    }
+
+    /// Gives back a permit without recording an outcome, for requests that never reached
+    /// the subgraph. A probe can then be sent by the next request
+    fn release(&self, permit: Permit, now: Instant) {
+        let mut breaker = self.breaker.lock().unwrap();
+        if let (Permit::Probe, State::HalfOpen { .. }) = (permit, breaker.state) {
+            self.transition(
+                &mut breaker,
+                State::HalfOpen {
+                    probe_deadline: now,
+                },
+            );
+        }
+    }
+
+    fn open(&self, breaker: &mut Breaker, now: Instant) {
+        self.transition(
+            breaker,
+            State::Open {
+                until: now + self.settings.open_duration,
+            },
+        );
+    }
+
+    fn transition(&self, breaker: &mut Breaker, state: State) {
+        if breaker.state.metric_value() != state.metric_value() {
+            tracing::debug!(
+                "circuit breaker for subgraph '{}' changed from {:?} to {:?}",
+                self.subgraph,
+                breaker.state,
+                state
+            );
+        }
+        breaker.state = state;
+        tracing::info!(
+            value.apollo_router_circuit_breaker_state = state.metric_value(),
+            subgraph = %self.subgraph,
+        );
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct CircuitBreakerLayer {
+    breaker: Arc<CircuitBreaker>,
+}
+
+impl CircuitBreakerLayer {
+    pub(crate) fn new(subgraph: String, settings: CircuitBreakerSettings) -> Self {
+        Self {
+            breaker: Arc::new(CircuitBreaker::new(subgraph, settings)),
+        }
+    }
+}
+
+impl<S> Layer<S> for CircuitBreakerLayer
+where
+    S: tower::Service<SubgraphRequest, Response = SubgraphResponse, Error = BoxError> + Clone,
+{
+    type Service = CircuitBreakerService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        CircuitBreakerService {
+            service,
+            breaker: self.breaker.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct CircuitBreakerService<S: Clone> {
+    service: S,
+    breaker: Arc<CircuitBreaker>,
+}
+
+impl<S> tower::Service<SubgraphRequest> for CircuitBreakerService<S>
+where
+    S: tower::Service<SubgraphRequest, Response = SubgraphResponse, Error = BoxError>
+        + Clone
+        + Send
+        + 'static,
+    <S as tower::Service<SubgraphRequest>>::Future: Send + 'static,
+{
+    type Response = SubgraphResponse;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: SubgraphRequest) -> Self::Future {
+        let service = self.service.clone();
+        let breaker = self.breaker.clone();
+
+        Box::pin(async move {
+            let permit = match breaker.acquire(Instant::now()) {
+                Some(permit) => permit,
+                None => {
+                    tracing::info!(
+                        monotonic_counter.apollo_router_circuit_breaker_rejected_count = 1u64,
+                        subgraph = %breaker.subgraph,
+                    );
+                    return Err(FetchError::SubrequestCircuitOpen {
+                        service: breaker.subgraph.clone(),
+                    }
+                    .into());
+                }
+            };
+
+            let result = service.oneshot(request).await;
+            match &result {
+                Ok(response) => breaker.record(
+                    permit,
+                    !response.response.status().is_server_error(),
+                    Instant::now(),
+                ),
+                // rate limited requests were not sent to the subgraph, so they tell
+                // nothing about its health
+                Err(error) if error.is::<RateLimited>() => breaker.release(permit, Instant::now()),
+                Err(_) => breaker.record(permit, false, Instant::now()),
+            }
+
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower::Service;
+
+    use super::*;
+
+    fn breaker(consecutive_failures: Option<u32>, error_rate: Option<f64>) -> CircuitBreaker {
+        CircuitBreaker::new(
+            "test".to_string(),
+            CircuitBreakerSettings {
+                consecutive_failures,
+                error_rate,
+                minimum_requests: 4,
+                window: Duration::from_secs(10),
+                open_duration: Duration::from_secs(5),
+            },
+        )
+    }
+
+    #[test]
+    fn it_opens_after_consecutive_failures() {
+        let breaker = breaker(Some(2), None);
+        let now = Instant::now();
+
+        breaker.record(Permit::Request, false, now);
+        breaker.record(Permit::Request, true, now);
+        breaker.record(Permit::Request, false, now);
+        assert_eq!(breaker.acquire(now), Some(Permit::Request));
+
+        breaker.record(Permit::Request, false, now);
+        assert_eq!(breaker.acquire(now), None);
+    }
+
+    #[test]
+    fn it_opens_on_error_rate() {
+        let breaker = breaker(None, Some(0.5));
+        let now = Instant::now();
+
+        breaker.record(Permit::Request, false, now);
+        breaker.record(Permit::Request, false, now);
+        // not enough requests yet
+        assert_eq!(breaker.acquire(now), Some(Permit::Request));
+
+        breaker.record(Permit::Request, true, now);
+        breaker.record(Permit::Request, true, now);
+        assert_eq!(breaker.acquire(now), None);
+    }
+
+    #[test]
+    fn it_probes_when_half_open() {
+        let breaker = breaker(Some(1), None);
+        let now = Instant::now();
+
+        breaker.record(Permit::Request, false, now);
+        assert_eq!(breaker.acquire(now), None);
+
+        let later = now + Duration::from_secs(6);
+        assert_eq!(breaker.acquire(later), Some(Permit::Probe));
+        // only one probe at a time
+        assert_eq!(breaker.acquire(later), None);
+
+        // a failed probe opens the circuit again
+        breaker.record(Permit::Probe, false, later);
+        assert_eq!(breaker.acquire(later), None);
+
+        let even_later = later + Duration::from_secs(6);
+        assert_eq!(breaker.acquire(even_later), Some(Permit::Probe));
+        breaker.record(Permit::Probe, true, even_later);
+        assert_eq!(breaker.acquire(even_later), Some(Permit::Request));
+    }
+
+    #[test]
+    fn it_releases_a_probe_without_closing() {
+        let breaker = breaker(Some(1), None);
+        let now = Instant::now();
+
+        breaker.record(Permit::Request, false, now);
+        let later = now + Duration::from_secs(6);
+        assert_eq!(breaker.acquire(later), Some(Permit::Probe));
+
+        // the probe was not sent, the circuit stays half open and another probe is allowed
+        breaker.release(Permit::Probe, later);
+        assert_eq!(breaker.acquire(later), Some(Permit::Probe));
+        assert_eq!(breaker.acquire(later), None);
+    }
+
+    #[tokio::test]
+    async fn it_does_not_record_rate_limited_requests() {
+        let layer = CircuitBreakerLayer::new(
+            "test".to_string(),
+            CircuitBreakerSettings {
+                consecutive_failures: Some(1),
+                error_rate: None,
+                minimum_requests: 4,
+                window: Duration::from_secs(10),
+                open_duration: Duration::from_millis(10),
+            },
+        );
+        let breaker = layer.breaker.clone();
+        let mut service = layer.layer(tower::service_fn(|_: SubgraphRequest| async {
+            Err::<SubgraphResponse, BoxError>(RateLimited::new().into())
+        }));
+
+        breaker.record(Permit::Request, false, Instant::now());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        // the rate limited probe does not close the circuit
+        let error = service
+            .call(SubgraphRequest::fake_builder().build())
+            .await
+            .unwrap_err();
+        assert!(error.is::<RateLimited>());
+        assert!(matches!(
+            breaker.breaker.lock().unwrap().state,
+            State::HalfOpen { .. }
+        ));
+    }
+}
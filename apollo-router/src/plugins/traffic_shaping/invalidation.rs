@@ -0,0 +1,351 @@
+//! Entity cache invalidation endpoint
+//!
+//! Accepts `POST` requests with a JSON body selecting the entries to remove:
+//! `{"subgraph": "accounts", "type": "User", "key": {"id": "1"}}`.
+//! At least one of `subgraph` or `type` must be set, and `key` requires `type`.
+
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+
+use futures::future::BoxFuture;
+use http::header::AUTHORIZATION;
+use http::header::CONTENT_TYPE;
+use http::Method;
+use http::StatusCode;
+use serde::Deserialize;
+use tower::BoxError;
+use tower::Service;
+
+use super::cache::EntityCache;
+use crate::json_ext::Object;
+use crate::services::router;
+
+/// Selects the entity cache entries to remove
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Invalidation {
+    /// Subgraph name
+    subgraph: Option<String>,
+    /// Entity typename
+    #[serde(rename = "type")]
+    typename: Option<String>,
+    /// Entity key, as sent in the representation without `__typename`
+    key: Option<Object>,
+}
+
+impl Invalidation {
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.subgraph.is_none() && self.typename.is_none() {
+            return Err("at least one of 'subgraph' or 'type' must be set");
+        }
+        if self.key.is_some() && self.typename.is_none() {
+            return Err("'key' requires 'type' to be set");
+        }
+        Ok(())
+    }
+
+    fn representation(&self) -> Option<String> {
+        self.key
+            .as_ref()
+            .map(|key| serde_json::to_string(key).expect("JSON objects can be serialized"))
+    }
+
+    /// Redis `SCAN` pattern matching the entries to remove
+    pub(crate) fn redis_pattern(&self) -> String {
+        let subgraph = self
+            .subgraph
+            .as_deref()
+            .map(escape_pattern)
+            .unwrap_or_else(|| "*".to_string());
+        let typename = self
+            .typename
+            .as_deref()
+            .map(escape_pattern)
+            .unwrap_or_else(|| "*".to_string());
+
+        match self.representation() {
+            Some(representation) => format!(
+                "subgraph.{subgraph}|{typename}|{}|*",
+                escape_pattern(&representation)
+            ),
+            None => format!("subgraph.{subgraph}|{typename}|*"),
+        }
+    }
+
+    /// Returns true if the entity cache key is selected by this invalidation
+    pub(crate) fn matches(&self, key: &str) -> bool {
+        let mut parts = match key.strip_prefix("subgraph.") {
+            Some(key) => key.splitn(3, '|'),
+            None => return false,
+        };
+        let (subgraph, typename, rest) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(subgraph), Some(typename), Some(rest)) => (subgraph, typename, rest),
+            _ => return false,
+        };
+
+        self.subgraph.as_deref().map_or(true, |s| s == subgraph)
+            && self.typename.as_deref().map_or(true, |t| t == typename)
+            && self.representation().map_or(true, |representation| {
+                // the query hash is the last part of the key
+                rest.rsplit_once('|')
+                    .map_or(false, |(key, _)| key == representation)
+            })
+    }
+}
+
+/// Escapes the glob special characters used by Redis patterns
+fn escape_pattern(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Compares secrets without returning early on the first difference
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[derive(Clone)]
+pub(crate) struct InvalidationService {
+    pub(crate) cache: EntityCache,
+    pub(crate) shared_key: Arc<String>,
+}
+
+impl Service<router::Request> for InvalidationService {
+    type Response = router::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, req: router::Request) -> Self::Future {
+        let cache = self.cache.clone();
+        let shared_key = self.shared_key.clone();
+
+        Box::pin(async move {
+            let context = req.context;
+            let (parts, body) = req.router_request.into_parts();
+
+            let (status, message) = if parts.method != Method::POST {
+                (
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    "only POST is allowed".to_string(),
+                )
+            } else if !parts
+                .headers
+                .get(AUTHORIZATION)
+                .map(|value| constant_time_eq(value.as_bytes(), shared_key.as_bytes()))
+                .unwrap_or(false)
+            {
+                (
+                    StatusCode::UNAUTHORIZED,
+                    "invalid authorization".to_string(),
+                )
+            } else {
+                let body = hyper::body::to_bytes(body).await?;
+                match serde_json::from_slice::<Invalidation>(&body)
+                    .map_err(|e| e.to_string())
+                    .and_then(|invalidation| {
+                        invalidation
+                            .validate()
+                            .map(|_| invalidation)
+                            .map_err(str::to_string)
+                    }) {
+                    Err(e) => (StatusCode::BAD_REQUEST, e),
+                    Ok(invalidation) => match cache.invalidate(&invalidation).await {
+                        Ok(count) => {
+                            tracing::info!(
+                                monotonic_counter.apollo_router_entity_cache_invalidation_count =
+                                    1u64,
+                            );
+                            (StatusCode::OK, format!("{{\"count\":{count}}}"))
+                        }
+                        Err(e) => {
+                            tracing::error!("entity cache invalidation failed: {e}");
+                            (
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                                "invalidation failed".to_string(),
+                            )
+                        }
+                    },
+                }
+            };
+
+            let body = if status == StatusCode::OK {
+                message
+            } else {
+                serde_json::json!({ "error": message }).to_string()
+            };
+
+            Ok(router::Response {
+                response: http::Response::builder()
+                    .status(status)
+                    .header(CONTENT_TYPE, "application/json")
+                    .body::<hyper::Body>(body.into())
+                    .map_err(BoxError::from)?,
+                context,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn invalidation(value: serde_json::Value) -> Invalidation {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn it_validates_invalidations() {
+        assert!(invalidation(json!({})).validate().is_err());
+        assert!(
+            invalidation(json!({"subgraph": "accounts", "key": {"id": "1"}}))
+                .validate()
+                .is_err()
+        );
+        assert!(invalidation(json!({"subgraph": "accounts"}))
+            .validate()
+            .is_ok());
+        assert!(invalidation(json!({"type": "User", "key": {"id": "1"}}))
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn it_builds_redis_patterns() {
+        assert_eq!(
+            invalidation(json!({"subgraph": "accounts"})).redis_pattern(),
+            "subgraph.accounts|*|*"
+        );
+        assert_eq!(
+            invalidation(json!({"type": "User", "key": {"id": "1"}})).redis_pattern(),
+            r#"subgraph.*|User|{"id":"1"}|*"#
+        );
+        assert_eq!(
+            invalidation(json!({"subgraph": "a*", "type": "User", "key": {"id": "[1]"}}))
+                .redis_pattern(),
+            r#"subgraph.a\*|User|{"id":"\[1\]"}|*"#
+        );
+    }
+
+    #[test]
+    fn it_matches_keys() {
+        let key = r#"subgraph.accounts|User|{"id":"1"}|abcd"#;
+
+        assert!(invalidation(json!({"subgraph": "accounts"})).matches(key));
+        assert!(invalidation(json!({"type": "User"})).matches(key));
+        assert!(invalidation(json!({"type": "User", "key": {"id": "1"}})).matches(key));
+        assert!(!invalidation(json!({"subgraph": "products"})).matches(key));
+        assert!(!invalidation(json!({"type": "User", "key": {"id": "2"}})).matches(key));
+        assert!(!invalidation(json!({"subgraph": "accounts"})).matches("other"));
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn it_invalidates_entity_cache_entries() {
+        use std::num::NonZeroUsize;
+        use std::time::Duration;
+
+        use tower::ServiceExt;
+
+        use super::super::cache::entity_key;
+        use super::super::InMemoryEntityCache;
+        use crate::cache::redis::RedisCacheStorage;
+
+        let redis = RedisCacheStorage::new(vec!["redis://127.0.0.1:6379".parse().unwrap()], None)
+            .await
+            .unwrap();
+        let cache = EntityCache::new(
+            redis,
+            Some(&InMemoryEntityCache {
+                limit: NonZeroUsize::new(10).unwrap(),
+                ttl: Duration::from_secs(60),
+            }),
+        );
+        let service = InvalidationService {
+            cache: cache.clone(),
+            shared_key: Arc::new("secret".to_string()),
+        };
+
+        let subgraph = uuid::Uuid::new_v4().to_string();
+        let keys = vec![
+            entity_key(&subgraph, "User", r#"{"id":"1"}"#, "hash"),
+            entity_key(&subgraph, "User", r#"{"id":"2"}"#, "hash"),
+            entity_key(&subgraph, "Product", r#"{"upc":"1"}"#, "hash"),
+        ];
+        cache
+            .insert_multiple(
+                keys.iter()
+                    .map(|key| {
+                        (
+                            key.clone(),
+                            serde_json_bytes::json!({}),
+                            Duration::from_secs(60),
+                        )
+                    })
+                    .collect(),
+            )
+            .await;
+
+        let invalidate = |authorization: &'static str, body: serde_json::Value| {
+            let request = http::Request::builder()
+                .method(Method::POST)
+                .header(AUTHORIZATION, authorization)
+                .body(hyper::Body::from(body.to_string()))
+                .unwrap();
+            let service = service.clone();
+            async move {
+                let response = service.oneshot(request.into()).await.unwrap().response;
+                let status = response.status();
+                let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+                (status, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+        let cached = || async {
+            cache
+                .get_multiple(&keys)
+                .await
+                .iter()
+                .map(Option::is_some)
+                .collect::<Vec<_>>()
+        };
+
+        let (status, _) = invalidate("wrong", json!({ "subgraph": subgraph })).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(cached().await, vec![true, true, true]);
+
+        // both tiers are cleared
+        let (status, body) = invalidate(
+            "secret",
+            json!({ "subgraph": subgraph, "type": "User", "key": {"id": "1"} }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"{"count":1}"#);
+        assert_eq!(cached().await, vec![false, true, true]);
+
+        let (status, body) = invalidate("secret", json!({ "subgraph": subgraph })).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"{"count":2}"#);
+        assert_eq!(cached().await, vec![false, false, false]);
+    }
+
+    #[test]
+    fn it_compares_keys() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+    }
+}
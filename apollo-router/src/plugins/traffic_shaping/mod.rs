@@ -5,22 +5,29 @@
 //! * Timeout
 //! * Compression
 //! * Rate limiting
+//! * Circuit breaking
 //!
 // With regards to ELv2 licensing, this entire file is license key functionality
 mod cache;
+mod circuit_breaker;
 mod deduplication;
+mod invalidation;
 mod rate;
 mod retry;
 mod timeout;
 
 use std::collections::HashMap;
+use std::num::NonZeroU32;
 use std::num::NonZeroU64;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
 use std::sync::Mutex;
 use std::time::Duration;
 
 use futures::future::BoxFuture;
 use http::header::CONTENT_ENCODING;
 use http::HeaderValue;
+use multimap::MultiMap;
 use schemars::JsonSchema;
 use serde::Deserialize;
 use tower::retry::Retry;
@@ -31,8 +38,13 @@ use tower::Service;
 use tower::ServiceBuilder;
 use tower::ServiceExt;
 
+use self::cache::EntityCache;
+use self::cache::EntityTtl;
 use self::cache::SubgraphCacheLayer;
+use self::circuit_breaker::CircuitBreakerLayer;
+use self::circuit_breaker::CircuitBreakerSettings;
 use self::deduplication::QueryDeduplicationLayer;
+use self::invalidation::InvalidationService;
 use self::rate::RateLimitLayer;
 pub(crate) use self::rate::RateLimited;
 use self::retry::RetryPolicy;
@@ -44,12 +56,17 @@ use crate::error::ConfigurationError;
 use crate::plugin::Plugin;
 use crate::plugin::PluginInit;
 use crate::register_plugin;
+use crate::router_factory::Endpoint;
 use crate::services::subgraph;
 use crate::services::subgraph_service::Compression;
 use crate::services::supergraph;
 use crate::services::SubgraphRequest;
+use crate::ListenAddr;
 
 const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_CIRCUIT_BREAKER_MINIMUM_REQUESTS: u32 = 10;
+const DEFAULT_CIRCUIT_BREAKER_WINDOW: Duration = Duration::from_secs(10);
+const DEFAULT_CIRCUIT_BREAKER_OPEN_DURATION: Duration = Duration::from_secs(30);
 pub(crate) const APOLLO_TRAFFIC_SHAPING: &str = "apollo.traffic_shaping";
 
 trait Merge {
@@ -75,6 +92,9 @@ struct Shaping {
     experimental_retry: Option<RetryConfig>,
     /// Enable HTTP2 for subgraphs
     experimental_enable_http2: Option<bool>,
+    /// Circuit breaker configuration
+    //  *experimental feature*: Stops sending requests to failing subgraphs
+    experimental_circuit_breaker: Option<CircuitBreakerConfig>,
 }
 
 impl Merge for Shaping {
@@ -100,6 +120,11 @@ impl Merge for Shaping {
                     .as_ref()
                     .or(fallback.experimental_enable_http2.as_ref())
                     .cloned(),
+                experimental_circuit_breaker: self
+                    .experimental_circuit_breaker
+                    .as_ref()
+                    .or(fallback.experimental_circuit_breaker.as_ref())
+                    .cloned(),
             },
         }
     }
@@ -141,6 +166,80 @@ impl Merge for RetryConfig {
     }
 }
 
+/// Circuit breaker configuration
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct CircuitBreakerConfig {
+    /// open the circuit after this number of consecutive failed requests. At least one of
+    /// consecutive_failures and error_rate must be set
+    consecutive_failures: Option<NonZeroU32>,
+    /// open the circuit when the proportion of failed requests in the window reaches
+    /// this value, between 0 and 1
+    error_rate: Option<f64>,
+    /// minimum number of requests in the window before the error rate is checked. The
+    /// default value is 10
+    minimum_requests: Option<u32>,
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    /// duration of the window used to calculate the error rate. The default value is 10
+    /// seconds
+    window: Option<Duration>,
+    #[serde(deserialize_with = "humantime_serde::deserialize", default)]
+    #[schemars(with = "String", default)]
+    /// how long requests fail immediately once the circuit is open, before a probe request
+    /// is sent to the subgraph. The default value is 30 seconds
+    open_duration: Option<Duration>,
+}
+
+impl Merge for CircuitBreakerConfig {
+    fn merge(&self, fallback: Option<&Self>) -> Self {
+        match fallback {
+            None => self.clone(),
+            Some(fallback) => CircuitBreakerConfig {
+                consecutive_failures: self.consecutive_failures.or(fallback.consecutive_failures),
+                error_rate: self.error_rate.or(fallback.error_rate),
+                minimum_requests: self.minimum_requests.or(fallback.minimum_requests),
+                window: self.window.or(fallback.window),
+                open_duration: self.open_duration.or(fallback.open_duration),
+            },
+        }
+    }
+}
+
+impl CircuitBreakerConfig {
+    fn validate(&self) -> Result<(), ConfigurationError> {
+        let error = if self.consecutive_failures.is_none() && self.error_rate.is_none() {
+            "experimental_circuit_breaker requires consecutive_failures or error_rate to be set"
+                .to_string()
+        } else {
+            match self.error_rate {
+                Some(error_rate) if !(0.0..=1.0).contains(&error_rate) => format!(
+                    "the circuit breaker error_rate must be between 0 and 1, got {error_rate}"
+                ),
+                _ => return Ok(()),
+            }
+        };
+        Err(ConfigurationError::InvalidConfiguration {
+            message: "bad configuration for traffic_shaping plugin",
+            error,
+        })
+    }
+
+    fn settings(&self) -> CircuitBreakerSettings {
+        CircuitBreakerSettings {
+            consecutive_failures: self.consecutive_failures.map(NonZeroU32::get),
+            error_rate: self.error_rate,
+            minimum_requests: self
+                .minimum_requests
+                .unwrap_or(DEFAULT_CIRCUIT_BREAKER_MINIMUM_REQUESTS),
+            window: self.window.unwrap_or(DEFAULT_CIRCUIT_BREAKER_WINDOW),
+            open_duration: self
+                .open_duration
+                .unwrap_or(DEFAULT_CIRCUIT_BREAKER_OPEN_DURATION),
+        }
+    }
+}
+
 // this is a wrapper struct to add subgraph specific options over Shaping
 #[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
@@ -170,10 +269,35 @@ impl Merge for SubgraphShaping {
 #[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 struct SubgraphEntityCaching {
-    /// expiration for all keys
+    /// expiration for all keys, used if the subgraph response has no `Cache-Control` max-age
     #[serde(deserialize_with = "humantime_serde::deserialize")]
     #[schemars(with = "String")]
     pub(crate) ttl: Duration,
+    /// Per type expiration, overriding `ttl` and the subgraph's `Cache-Control` max-age
+    #[serde(default)]
+    types: HashMap<String, EntityTypeCaching>,
+}
+
+impl SubgraphEntityCaching {
+    fn entity_ttl(&self) -> EntityTtl {
+        EntityTtl {
+            default: self.ttl,
+            types: self
+                .types
+                .iter()
+                .map(|(typename, config)| (typename.clone(), config.ttl))
+                .collect(),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct EntityTypeCaching {
+    /// expiration for entities of this type. A value of 0 disables caching for this type
+    #[serde(deserialize_with = "humantime_serde::deserialize")]
+    #[schemars(with = "String")]
+    ttl: Duration,
 }
 
 impl Merge for SubgraphEntityCaching {
@@ -212,6 +336,49 @@ pub(crate) struct Config {
     deduplicate_variables: Option<bool>,
     /// Experimental URLs of Redis cache used for subgraph response caching
     pub(crate) experimental_cache: Option<RedisCache>,
+    /// Experimental entity cache options
+    experimental_entity_cache: Option<EntityCacheConfig>,
+}
+
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct EntityCacheConfig {
+    /// In memory cache in front of Redis
+    in_memory: Option<InMemoryEntityCache>,
+    /// Endpoint removing entries from the entity cache
+    invalidation: Option<InvalidationEndpoint>,
+}
+
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct InMemoryEntityCache {
+    /// Number of entries in the in memory cache
+    pub(crate) limit: NonZeroUsize,
+    /// Maximum time an entry stays in memory, at most 30s. Entries are never kept longer than their TTL
+    #[serde(deserialize_with = "humantime_serde::deserialize")]
+    #[schemars(with = "String")]
+    pub(crate) ttl: Duration,
+}
+
+#[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct InvalidationEndpoint {
+    /// The listen address
+    #[serde(default = "invalidation_default_listen_addr")]
+    listen: ListenAddr,
+    /// The path of the invalidation endpoint
+    #[serde(default = "invalidation_default_path")]
+    path: String,
+    /// Value expected in the `Authorization` header of invalidation requests
+    shared_key: String,
+}
+
+fn invalidation_default_listen_addr() -> ListenAddr {
+    ListenAddr::SocketAddr("127.0.0.1:4000".parse().expect("valid listenAddr"))
+}
+
+fn invalidation_default_path() -> String {
+    "/invalidation".to_string()
 }
 
 #[derive(PartialEq, Debug, Clone, Deserialize, JsonSchema)]
@@ -223,6 +390,11 @@ struct RateLimitConf {
     #[schemars(with = "String")]
     /// Per interval
     interval: Duration,
+    /// Share the rate limit between router instances, by counting requests in the Redis
+    /// instance configured in `experimental_cache`. If Redis cannot be reached, each router
+    /// instance applies the rate limit locally
+    #[serde(default)]
+    experimental_distributed: bool,
 }
 
 impl Merge for RateLimitConf {
@@ -232,6 +404,7 @@ impl Merge for RateLimitConf {
             Some(fallback) => Self {
                 capacity: fallback.capacity,
                 interval: fallback.interval,
+                experimental_distributed: fallback.experimental_distributed,
             },
         }
     }
@@ -243,7 +416,9 @@ pub(crate) struct TrafficShaping {
     config: Config,
     rate_limit_router: Option<RateLimitLayer>,
     rate_limit_subgraphs: Mutex<HashMap<String, RateLimitLayer>>,
-    storage: Option<RedisCacheStorage>,
+    circuit_breakers: Mutex<HashMap<String, CircuitBreakerLayer>>,
+    redis: Option<RedisCacheStorage>,
+    entity_cache: Option<EntityCache>,
 }
 
 #[async_trait::async_trait]
@@ -251,6 +426,11 @@ impl Plugin for TrafficShaping {
     type Config = Config;
 
     async fn new(init: PluginInit<Self::Config>) -> Result<Self, BoxError> {
+        let redis = match init.config.experimental_cache.as_ref() {
+            Some(cache) => Some(RedisCacheStorage::new(cache.urls.clone(), None).await?),
+            None => None,
+        };
+
         let rate_limit_router = init
             .config
             .router
@@ -266,36 +446,110 @@ impl Plugin for TrafficShaping {
                         ),
                     })
                 } else {
-                    Ok(RateLimitLayer::new(
-                        router_rate_limit_conf.capacity,
-                        router_rate_limit_conf.interval,
-                    ))
+                    Self::rate_limit_layer(router_rate_limit_conf, redis.as_ref(), "router")
                 }
             })
             .transpose()?;
 
+        // subgraph rate limits are created lazily, so their configuration is checked here
+        let distributed_subgraph_rate_limit = init
+            .config
+            .all
+            .iter()
+            .chain(init.config.subgraphs.values())
+            .filter_map(|c| c.shaping.global_rate_limit.as_ref())
+            .any(|c| c.experimental_distributed);
+        if distributed_subgraph_rate_limit && redis.is_none() {
+            return Err(distributed_rate_limit_error().into());
+        }
+
+        // circuit breakers are created lazily, so their configuration is checked here
+        for circuit_breaker in init
+            .config
+            .all
+            .iter()
+            .chain(init.config.subgraphs.values())
+            .filter_map(|c| c.shaping.experimental_circuit_breaker.as_ref())
         {
-            let storage = if let Some(urls) = init
-                .config
-                .experimental_cache
-                .as_ref()
-                .map(|cache| cache.urls.clone())
-            {
-                Some(RedisCacheStorage::new(urls, None).await?)
-            } else {
-                None
-            };
-            Ok(Self {
-                config: init.config,
-                rate_limit_router,
-                rate_limit_subgraphs: Mutex::new(HashMap::new()),
+            circuit_breaker.validate()?;
+        }
+
+        let entity_cache_config = init.config.experimental_entity_cache.as_ref();
+        let entity_cache = match (redis.clone(), entity_cache_config) {
+            (Some(storage), entity_cache_config) => Some(EntityCache::new(
                 storage,
-            })
+                entity_cache_config.and_then(|c| c.in_memory.as_ref()),
+            )),
+            (None, Some(_)) => {
+                return Err(ConfigurationError::InvalidConfiguration {
+                    message: "bad configuration for traffic_shaping plugin",
+                    error: "experimental_entity_cache requires experimental_cache to be configured"
+                        .to_string(),
+                }
+                .into());
+            }
+            (None, None) => None,
+        };
+
+        Ok(Self {
+            config: init.config,
+            rate_limit_router,
+            rate_limit_subgraphs: Mutex::new(HashMap::new()),
+            circuit_breakers: Mutex::new(HashMap::new()),
+            redis,
+            entity_cache,
+        })
+    }
+
+    fn web_endpoints(&self) -> MultiMap<ListenAddr, Endpoint> {
+        let mut endpoints = MultiMap::new();
+        if let (Some(cache), Some(invalidation)) = (
+            self.entity_cache.as_ref(),
+            self.config
+                .experimental_entity_cache
+                .as_ref()
+                .and_then(|c| c.invalidation.as_ref()),
+        ) {
+            endpoints.insert(
+                invalidation.listen.clone(),
+                Endpoint::from_router_service(
+                    invalidation.path.clone(),
+                    InvalidationService {
+                        cache: cache.clone(),
+                        shared_key: Arc::new(invalidation.shared_key.clone()),
+                    }
+                    .boxed(),
+                ),
+            );
         }
+        endpoints
+    }
+}
+
+fn distributed_rate_limit_error() -> ConfigurationError {
+    ConfigurationError::InvalidConfiguration {
+        message: "bad configuration for traffic_shaping plugin",
+        error: "experimental_distributed rate limits require experimental_cache to be configured"
+            .to_string(),
     }
 }
 
 impl TrafficShaping {
+    fn rate_limit_layer(
+        conf: &RateLimitConf,
+        redis: Option<&RedisCacheStorage>,
+        key: &str,
+    ) -> Result<RateLimitLayer, ConfigurationError> {
+        let layer = RateLimitLayer::new(conf.capacity, conf.interval);
+        if !conf.experimental_distributed {
+            return Ok(layer);
+        }
+        match redis {
+            Some(storage) => Ok(layer.distributed(storage.clone(), key)),
+            None => Err(distributed_rate_limit_error()),
+        }
+    }
+
     fn merge_config<T: Merge + Clone>(
         all_config: Option<&T>,
         subgraph_config: Option<&T>,
@@ -324,7 +578,7 @@ impl TrafficShaping {
             + Send
             + Sync
             + 'static,
-        <S as Service<supergraph::Request>>::Future: std::marker::Send,
+        <S as Service<supergraph::Request>>::Future: std::marker::Send + 'static,
     {
         ServiceBuilder::new()
             .layer(TimeoutLayer::new(
@@ -351,13 +605,16 @@ impl TrafficShaping {
                 BoxFuture<'static, Result<subgraph::Response, BoxError>>,
                 Either<
                     BoxFuture<'static, Result<subgraph::Response, BoxError>>,
-                    timeout::future::ResponseFuture<
-                        Oneshot<
-                            Either<
-                                Retry<RetryPolicy, Either<rate::service::RateLimit<S>, S>>,
-                                Either<rate::service::RateLimit<S>, S>,
+                    Either<
+                        BoxFuture<'static, Result<subgraph::Response, BoxError>>,
+                        timeout::future::ResponseFuture<
+                            Oneshot<
+                                Either<
+                                    Retry<RetryPolicy, Either<rate::service::RateLimit<S>, S>>,
+                                    Either<rate::service::RateLimit<S>, S>,
+                                >,
+                                subgraph::Request,
                             >,
-                            subgraph::Request,
                         >,
                     >,
                 >,
@@ -374,23 +631,23 @@ impl TrafficShaping {
             + Send
             + Sync
             + 'static,
-        <S as Service<subgraph::Request>>::Future: std::marker::Send,
+        <S as Service<subgraph::Request>>::Future: std::marker::Send + 'static,
     {
         // Either we have the subgraph config and we merge it with the all config, or we just have the all config or we have nothing.
         let all_config = self.config.all.as_ref();
         let subgraph_config = self.config.subgraphs.get(name);
         let final_config = Self::merge_config(all_config, subgraph_config);
 
-        let entity_caching = if let (Some(storage), Some(caching_config)) = (
-            self.storage.clone(),
+        let entity_caching = if let (Some(cache), Some(caching_config)) = (
+            self.entity_cache.clone(),
             subgraph_config
                 .as_ref()
                 .and_then(|c| c.experimental_entity_caching.as_ref()),
         ) {
-            Some(SubgraphCacheLayer::new_with_storage(
+            Some(SubgraphCacheLayer::new(
                 name.to_string(),
-                storage,
-                caching_config.ttl,
+                cache,
+                caching_config.entity_ttl(),
             ))
         } else {
             None
@@ -407,11 +664,40 @@ impl TrafficShaping {
                         .unwrap()
                         .entry(name.to_string())
                         .or_insert_with(|| {
-                            RateLimitLayer::new(rate_limit_conf.capacity, rate_limit_conf.interval)
+                            let layer = RateLimitLayer::new(
+                                rate_limit_conf.capacity,
+                                rate_limit_conf.interval,
+                            );
+                            // Redis availability was checked when creating the plugin
+                            match self.redis.clone() {
+                                Some(storage) if rate_limit_conf.experimental_distributed => {
+                                    layer.distributed(storage, &format!("subgraph:{name}"))
+                                }
+                                _ => layer,
+                            }
                         })
                         .clone()
                 });
 
+            let circuit_breaker =
+                config
+                    .shaping
+                    .experimental_circuit_breaker
+                    .as_ref()
+                    .map(|circuit_breaker_conf| {
+                        self.circuit_breakers
+                            .lock()
+                            .unwrap()
+                            .entry(name.to_string())
+                            .or_insert_with(|| {
+                                CircuitBreakerLayer::new(
+                                    name.to_string(),
+                                    circuit_breaker_conf.settings(),
+                                )
+                            })
+                            .clone()
+                    });
+
             let retry = config.shaping.experimental_retry.as_ref().map(|config| {
                 let retry_policy = RetryPolicy::new(
                     config.ttl,
@@ -429,6 +715,7 @@ impl TrafficShaping {
                 .option_layer(config.shaping.deduplicate_query.unwrap_or_default().then(
                   QueryDeduplicationLayer::default
                 ))
+                    .option_layer(circuit_breaker)
                     .layer(TimeoutLayer::new(
                         config.shaping
                         .timeout
@@ -745,6 +1032,80 @@ mod test {
             .unwrap();
     }
 
+    #[tokio::test]
+    async fn distributed_rate_limit_requires_redis() {
+        let config = serde_yaml::from_str::<serde_json::Value>(
+            r#"
+        all:
+            global_rate_limit:
+                capacity: 1
+                interval: 100ms
+                experimental_distributed: true
+        "#,
+        )
+        .unwrap();
+
+        let err = crate::plugin::plugins()
+            .find(|factory| factory.name == APOLLO_TRAFFIC_SHAPING)
+            .expect("Plugin not found")
+            .create_instance_without_schema(&config)
+            .await
+            .expect_err("experimental_cache is not configured");
+        assert!(err.to_string().contains("experimental_cache"));
+    }
+
+    #[tokio::test]
+    async fn it_rejects_invalid_circuit_breaker_configurations() {
+        for (config, expected) in [
+            (
+                r#"
+        all:
+          experimental_circuit_breaker:
+            error_rate: 1.5
+        "#,
+                "between 0 and 1",
+            ),
+            (
+                r#"
+        subgraphs:
+          products:
+            experimental_circuit_breaker:
+              error_rate: -0.1
+        "#,
+                "between 0 and 1",
+            ),
+            (
+                r#"
+        subgraphs:
+          products:
+            experimental_circuit_breaker:
+              window: 10s
+        "#,
+                "consecutive_failures or error_rate",
+            ),
+        ] {
+            let config = serde_yaml::from_str::<serde_json::Value>(config).unwrap();
+            let err = crate::plugin::plugins()
+                .find(|factory| factory.name == APOLLO_TRAFFIC_SHAPING)
+                .expect("Plugin not found")
+                .create_instance_without_schema(&config)
+                .await
+                .expect_err("the circuit breaker configuration is invalid");
+            assert!(err.to_string().contains(expected), "{err}");
+        }
+
+        let config = serde_yaml::from_str::<serde_json::Value>(
+            r#"
+        subgraphs:
+          products:
+            experimental_circuit_breaker:
+              error_rate: 1.0
+        "#,
+        )
+        .unwrap();
+        get_traffic_shaping_plugin(&config).await;
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn it_rate_limit_router_requests() {
         let config = serde_yaml::from_str::<serde_json::Value>(
@@ -0,0 +1,104 @@
+//! Rate limit shared between router instances, using a sliding window counted in Redis.
+
+// This entire file is license key functionality
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use tower::BoxError;
+
+use super::Rate;
+use crate::cache::redis::RedisCacheStorage;
+
+/// Redis calls slower than this are considered failed, and the local rate limit is used instead
+const REDIS_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Clone)]
+pub(crate) struct DistributedRate {
+    storage: RedisCacheStorage,
+    key: Arc<String>,
+    rate: Rate,
+}
+
+impl fmt::Debug for DistributedRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DistributedRate")
+            .field("key", &self.key)
+            .field("rate", &self.rate)
+            .finish()
+    }
+}
+
+impl DistributedRate {
+    pub(crate) fn new(storage: RedisCacheStorage, key: &str, rate: Rate) -> Self {
+        Self {
+            storage,
+            // the hash tag keeps the windows of a key on the same cluster slot, so they
+            // can be used in the same pipeline
+            key: Arc::new(format!("{{rate_limit:{key}}}")),
+            rate,
+        }
+    }
+
+    /// Counts a request, and returns false if it exceeds the rate. Rejected requests are
+    /// not counted, so that traffic over the rate does not keep the window full
+    pub(crate) async fn acquire(&self) -> Result<bool, BoxError> {
+        let per = self.rate.per().as_millis().max(1) as u64;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time must be after EPOCH")
+            .as_millis() as u64;
+        let window = now / per;
+        let key = format!("{}:{}", self.key, window);
+
+        let (current, previous) = tokio::time::timeout(
+            REDIS_TIMEOUT,
+            self.storage.increment_window(
+                key.clone(),
+                format!("{}:{}", self.key, window.saturating_sub(1)),
+                // the counter is still read during the next window
+                self.rate.per() * 2,
+            ),
+        )
+        .await??;
+
+        if estimate(previous, current, now % per, per) <= self.rate.num() {
+            Ok(true)
+        } else {
+            // the request is rejected even if it cannot be taken back, the counter
+            // expires with its window anyway
+            let decremented: Result<(), BoxError> =
+                tokio::time::timeout(REDIS_TIMEOUT, self.storage.decrement(key))
+                    .await
+                    .map_err(BoxError::from)
+                    .and_then(|res| res.map_err(BoxError::from));
+            if let Err(e) = decremented {
+                tracing::debug!("could not decrement the rate limiting window: {}", e);
+            }
+            Ok(false)
+        }
+    }
+}
+
+/// Sliding window estimate: the previous window's count is weighted by how much of it
+/// still overlaps the sliding window
+fn estimate(previous: u64, current: u64, elapsed: u64, per: u64) -> u64 {
+    let overlap = per.saturating_sub(elapsed);
+    (previous as u128 * overlap as u128 / per as u128) as u64 + current
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_weights_the_previous_window() {
+        assert_eq!(estimate(10, 1, 0, 1000), 11);
+        assert_eq!(estimate(10, 1, 500, 1000), 6);
+        assert_eq!(estimate(10, 1, 999, 1000), 1);
+        assert_eq!(estimate(0, 5, 200, 1000), 5);
+    }
+}
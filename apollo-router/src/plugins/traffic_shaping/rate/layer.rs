@@ -8,16 +8,17 @@ use std::time::UNIX_EPOCH;
 
 use tower::Layer;
 
+use super::distributed::DistributedRate;
+use super::service::LocalRate;
 use super::Rate;
 use super::RateLimit;
+use crate::cache::redis::RedisCacheStorage;
 /// Enforces a rate limit on the number of requests the underlying
 /// service can handle over a period of time.
 #[derive(Debug, Clone)]
 pub(crate) struct RateLimitLayer {
-    rate: Rate,
-    window_start: Arc<AtomicU64>,
-    previous_nb_requests: Arc<AtomicUsize>,
-    current_nb_requests: Arc<AtomicUsize>,
+    local: LocalRate,
+    distributed: Option<DistributedRate>,
 }
 
 impl RateLimitLayer {
@@ -25,17 +26,26 @@ impl RateLimitLayer {
     pub(crate) fn new(num: NonZeroU64, per: Duration) -> Self {
         let rate = Rate::new(num, per);
         RateLimitLayer {
-            rate,
-            window_start: Arc::new(AtomicU64::new(
-                SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .expect("system time must be after EPOCH")
-                    .as_millis() as u64,
-            )),
-            previous_nb_requests: Arc::default(),
-            current_nb_requests: Arc::new(AtomicUsize::new(1)),
+            local: LocalRate {
+                rate,
+                window_start: Arc::new(AtomicU64::new(
+                    SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .expect("system time must be after EPOCH")
+                        .as_millis() as u64,
+                )),
+                previous_nb_requests: Arc::default(),
+                current_nb_requests: Arc::new(AtomicUsize::new(1)),
+            },
+            distributed: None,
         }
     }
+
+    /// Shares the rate limit between router instances, by counting requests in Redis under `key`.
+    pub(crate) fn distributed(mut self, storage: RedisCacheStorage, key: &str) -> Self {
+        self.distributed = Some(DistributedRate::new(storage, key, self.local.rate));
+        self
+    }
 }
 
 impl<S> Layer<S> for RateLimitLayer {
@@ -44,10 +54,8 @@ impl<S> Layer<S> for RateLimitLayer {
     fn layer(&self, service: S) -> Self::Service {
         RateLimit {
             inner: service,
-            rate: self.rate,
-            window_start: self.window_start.clone(),
-            previous_nb_requests: self.previous_nb_requests.clone(),
-            current_nb_requests: self.current_nb_requests.clone(),
+            local: self.local.clone(),
+            distributed: self.distributed.clone(),
         }
     }
 }
@@ -1,5 +1,6 @@
 //! Limit the rate at which requests are processed.
 
+mod distributed;
 mod error;
 pub(crate) mod future;
 mod layer;
@@ -7,9 +7,12 @@ use std::task::Poll;
 use std::time::SystemTime;
 use std::time::UNIX_EPOCH;
 
+use futures::future::BoxFuture;
 use futures::ready;
+use tower::util::Either;
 use tower::Service;
 
+use super::distributed::DistributedRate;
 use super::future::ResponseFuture;
 use super::Rate;
 use crate::plugins::traffic_shaping::rate::error::RateLimited;
@@ -17,6 +20,15 @@ use crate::plugins::traffic_shaping::rate::error::RateLimited;
 #[derive(Debug, Clone)]
 pub(crate) struct RateLimit<T> {
     pub(crate) inner: T,
+    pub(crate) local: LocalRate,
+    /// Rate shared between router instances. If set, the local rate is only used when Redis
+    /// cannot be reached
+    pub(crate) distributed: Option<DistributedRate>,
+}
+
+/// Rate limiting state of this router instance
+#[derive(Debug, Clone)]
+pub(crate) struct LocalRate {
     pub(crate) rate: Rate,
     /// We're using an atomic u64 because it's basically a timestamp in milliseconds for the start of the window
     /// Instead of using an Instant which is not thread safe we're using an atomic u64
@@ -26,16 +38,9 @@ pub(crate) struct RateLimit<T> {
     pub(crate) current_nb_requests: Arc<AtomicUsize>,
 }
 
-impl<S, Request> Service<Request> for RateLimit<S>
-where
-    S: Service<Request>,
-    S::Error: Into<tower::BoxError>,
-{
-    type Response = S::Response;
-    type Error = tower::BoxError;
-    type Future = ResponseFuture<S::Future>;
-
-    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+impl LocalRate {
+    /// Counts a request, and returns false if it exceeds the rate
+    pub(crate) fn acquire(&self) -> bool {
         let time_unit = self.rate.per().as_millis() as u64;
 
         let updated =
@@ -68,16 +73,67 @@ where
             + self.current_nb_requests.load(Ordering::SeqCst);
 
         if estimated_cap as u64 > self.rate.num() {
-            tracing::trace!("rate limit exceeded; sleeping.");
-            return Poll::Ready(Err(RateLimited::new().into()));
+            return false;
         }
 
         self.current_nb_requests.fetch_add(1, Ordering::SeqCst);
+        true
+    }
+}
+
+impl<S, Request> Service<Request> for RateLimit<S>
+where
+    S: Service<Request> + Clone + Send + 'static,
+    S::Error: Into<tower::BoxError>,
+    S::Future: Send + 'static,
+    Request: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = tower::BoxError;
+    type Future =
+        Either<ResponseFuture<S::Future>, BoxFuture<'static, Result<S::Response, tower::BoxError>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // the distributed rate is checked when the request is sent
+        if self.distributed.is_none() && !self.local.acquire() {
+            tracing::trace!("rate limit exceeded; sleeping.");
+            return Poll::Ready(Err(RateLimited::new().into()));
+        }
 
         Poll::Ready(ready!(self.inner.poll_ready(cx)).map_err(Into::into))
     }
 
     fn call(&mut self, request: Request) -> Self::Future {
-        ResponseFuture::new(self.inner.call(request))
+        let distributed = match self.distributed.clone() {
+            Some(distributed) => distributed,
+            None => return Either::A(ResponseFuture::new(self.inner.call(request))),
+        };
+
+        // the inner service was made ready by poll_ready, its clone takes its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let local = self.local.clone();
+
+        Either::B(Box::pin(async move {
+            let allowed = match distributed.acquire().await {
+                Ok(allowed) => allowed,
+                Err(e) => {
+                    tracing::debug!(
+                        "cannot check the distributed rate limit, using the local rate limit: {e}"
+                    );
+                    tracing::info!(
+                        monotonic_counter.apollo_router_distributed_rate_limit_fallback_count =
+                            1u64,
+                    );
+                    local.acquire()
+                }
+            };
+            if !allowed {
+                tracing::trace!("distributed rate limit exceeded");
+                return Err(RateLimited::new().into());
+            }
+
+            inner.call(request).await.map_err(Into::into)
+        }))
     }
 }
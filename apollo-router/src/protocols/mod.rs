@@ -0,0 +1,3 @@
+//! Protocols used by the router to communicate with subgraphs.
+
+pub(crate) mod websocket;
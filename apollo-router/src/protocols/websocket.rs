@@ -0,0 +1,351 @@
+//! Client side of the `graphql-transport-ws` protocol, used to forward subscriptions to subgraphs.
+//!
+//! See <https://github.com/enisdenjo/graphql-ws/blob/master/PROTOCOL.md>
+
+use std::time::Duration;
+
+use futures::Sink;
+use futures::SinkExt;
+use futures::Stream;
+use futures::StreamExt;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json_bytes::Value;
+use tokio_tungstenite::tungstenite::Error as WebSocketError;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::error::FetchError;
+use crate::graphql;
+
+const CONNECTION_ACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Messages sent by the router to the subgraph
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum ClientMessage {
+    ConnectionInit {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        payload: Option<Value>,
+    },
+    Subscribe {
+        id: String,
+        payload: graphql::Request,
+    },
+    Pong {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        payload: Option<Value>,
+    },
+}
+
+impl ClientMessage {
+    fn into_message(self) -> Message {
+        Message::Text(
+            serde_json::to_string(&self)
+                .expect("graphql-ws messages can always be serialized; qed"),
+        )
+    }
+}
+
+/// Messages sent by the subgraph to the router
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum ServerMessage {
+    ConnectionAck,
+    Next {
+        id: String,
+        payload: graphql::Response,
+    },
+    Error {
+        id: String,
+        payload: Vec<graphql::Error>,
+    },
+    Complete {
+        id: String,
+    },
+    Ping {
+        #[serde(default)]
+        payload: Option<Value>,
+    },
+    Pong,
+}
+
+enum Incoming {
+    Message(ServerMessage),
+    Closed,
+    Ignored,
+}
+
+fn decode(message: Message) -> Result<Incoming, serde_json::Error> {
+    match message {
+        Message::Text(text) => serde_json::from_str(&text).map(Incoming::Message),
+        Message::Binary(bytes) => serde_json::from_slice(&bytes).map(Incoming::Message),
+        Message::Close(_) => Ok(Incoming::Closed),
+        // websocket level pings are answered by tungstenite
+        Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => Ok(Incoming::Ignored),
+    }
+}
+
+/// Initializes the `graphql-transport-ws` connection on an open WebSocket, subscribes to
+/// the operation and returns the stream of responses sent by the subgraph.
+///
+/// The stream ends when the subgraph completes the subscription, sends an error or closes
+/// the connection. Dropping the stream closes the connection.
+pub(crate) async fn subscribe<S>(
+    mut socket: S,
+    service_name: &str,
+    request: graphql::Request,
+    connection_params: Option<Value>,
+) -> Result<impl Stream<Item = graphql::Response> + Send, FetchError>
+where
+    S: Stream<Item = Result<Message, WebSocketError>>
+        + Sink<Message, Error = WebSocketError>
+        + Send
+        + Unpin
+        + 'static,
+{
+    let ws_error = |reason: String| FetchError::SubrequestWsError {
+        service: service_name.to_string(),
+        reason,
+    };
+
+    socket
+        .send(
+            ClientMessage::ConnectionInit {
+                payload: connection_params,
+            }
+            .into_message(),
+        )
+        .await
+        .map_err(|e| ws_error(format!("cannot send connection_init: {e}")))?;
+
+    tokio::time::timeout(CONNECTION_ACK_TIMEOUT, wait_for_ack(&mut socket))
+        .await
+        .map_err(|_| ws_error("timed out waiting for connection_ack".to_string()))?
+        .map_err(ws_error)?;
+
+    let id = uuid::Uuid::new_v4().to_string();
+    socket
+        .send(
+            ClientMessage::Subscribe {
+                id: id.clone(),
+                payload: request,
+            }
+            .into_message(),
+        )
+        .await
+        .map_err(|e| ws_error(format!("cannot send subscribe: {e}")))?;
+
+    let service_name = service_name.to_string();
+    Ok(futures::stream::unfold(Some(socket), move |state| {
+        let id = id.clone();
+        let service_name = service_name.clone();
+        async move {
+            let mut socket = state?;
+            let ws_error = |reason: String| {
+                FetchError::SubrequestWsError {
+                    service: service_name.clone(),
+                    reason,
+                }
+                .to_response()
+            };
+
+            loop {
+                let message = match socket.next().await {
+                    None => return None,
+                    Some(Err(e)) => return Some((ws_error(e.to_string()), None)),
+                    Some(Ok(message)) => message,
+                };
+
+                match decode(message) {
+                    Err(e) => {
+                        let error = FetchError::SubrequestMalformedResponse {
+                            service: service_name.clone(),
+                            reason: e.to_string(),
+                        };
+                        return Some((error.to_response(), None));
+                    }
+                    Ok(Incoming::Ignored) => {}
+                    Ok(Incoming::Closed) => {
+                        return Some((
+                            ws_error("connection closed before the subscription completed".into()),
+                            None,
+                        ))
+                    }
+                    Ok(Incoming::Message(ServerMessage::Next {
+                        id: message_id,
+                        payload,
+                    })) if message_id == id => return Some((payload, Some(socket))),
+                    Ok(Incoming::Message(ServerMessage::Error {
+                        id: message_id,
+                        payload,
+                    })) if message_id == id => {
+                        return Some((graphql::Response::builder().errors(payload).build(), None))
+                    }
+                    Ok(Incoming::Message(ServerMessage::Complete { id: message_id }))
+                        if message_id == id =>
+                    {
+                        return None
+                    }
+                    Ok(Incoming::Message(ServerMessage::Ping { payload })) => {
+                        if let Err(e) = socket
+                            .send(ClientMessage::Pong { payload }.into_message())
+                            .await
+                        {
+                            return Some((ws_error(format!("cannot send pong: {e}")), None));
+                        }
+                    }
+                    Ok(Incoming::Message(message)) => {
+                        tracing::debug!(
+                            "ignoring unexpected graphql-ws message from subgraph {service_name}: {message:?}"
+                        );
+                    }
+                }
+            }
+        }
+    }))
+}
+
+async fn wait_for_ack<S>(socket: &mut S) -> Result<(), String>
+where
+    S: Stream<Item = Result<Message, WebSocketError>>
+        + Sink<Message, Error = WebSocketError>
+        + Unpin,
+{
+    while let Some(message) = socket.next().await {
+        let message = message.map_err(|e| e.to_string())?;
+        match decode(message).map_err(|e| format!("invalid message: {e}"))? {
+            Incoming::Message(ServerMessage::ConnectionAck) => return Ok(()),
+            Incoming::Message(ServerMessage::Ping { payload }) => socket
+                .send(ClientMessage::Pong { payload }.into_message())
+                .await
+                .map_err(|e| format!("cannot send pong: {e}"))?,
+            Incoming::Message(message) => {
+                return Err(format!(
+                    "unexpected message before connection_ack: {message:?}"
+                ))
+            }
+            Incoming::Closed => return Err("connection closed before connection_ack".into()),
+            Incoming::Ignored => {}
+        }
+    }
+
+    Err("connection closed before connection_ack".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use serde_json_bytes::json;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    // starts a subgraph answering a single subscription with the provided messages
+    async fn emulate_subgraph(messages: Vec<serde_json::Value>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::task::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+
+            let init: serde_json::Value =
+                serde_json::from_str(socket.next().await.unwrap().unwrap().to_text().unwrap())
+                    .unwrap();
+            assert_eq!(init["type"], "connection_init");
+            socket
+                .send(Message::Text(
+                    serde_json::json!({"type": "connection_ack"}).to_string(),
+                ))
+                .await
+                .unwrap();
+
+            let subscribe: serde_json::Value =
+                serde_json::from_str(socket.next().await.unwrap().unwrap().to_text().unwrap())
+                    .unwrap();
+            assert_eq!(subscribe["type"], "subscribe");
+            assert_eq!(
+                subscribe["payload"]["query"],
+                "subscription { userWasCreated { id } }"
+            );
+            let id = subscribe["id"].as_str().unwrap().to_string();
+
+            for mut message in messages {
+                message["id"] = id.clone().into();
+                socket
+                    .send(Message::Text(message.to_string()))
+                    .await
+                    .unwrap();
+            }
+        });
+
+        addr
+    }
+
+    async fn subscribe_to(addr: SocketAddr) -> Vec<graphql::Response> {
+        let (socket, _) = tokio_tungstenite::connect_async(format!("ws://{addr}"))
+            .await
+            .unwrap();
+        subscribe(
+            socket,
+            "accounts",
+            graphql::Request::builder()
+                .query("subscription { userWasCreated { id } }")
+                .build(),
+            None,
+        )
+        .await
+        .unwrap()
+        .collect()
+        .await
+    }
+
+    #[tokio::test]
+    async fn it_streams_events_until_complete() {
+        let addr = emulate_subgraph(vec![
+            serde_json::json!({"type": "next", "payload": {"data": {"userWasCreated": {"id": "1"}}}}),
+            serde_json::json!({"type": "next", "payload": {"data": {"userWasCreated": {"id": "2"}}}}),
+            serde_json::json!({"type": "complete"}),
+        ])
+        .await;
+
+        let responses = subscribe_to(addr).await;
+        assert_eq!(responses.len(), 2);
+        assert_eq!(
+            responses[0].data,
+            Some(json!({"userWasCreated": {"id": "1"}}))
+        );
+        assert_eq!(
+            responses[1].data,
+            Some(json!({"userWasCreated": {"id": "2"}}))
+        );
+    }
+
+    #[tokio::test]
+    async fn it_forwards_subscription_errors() {
+        let addr = emulate_subgraph(vec![
+            serde_json::json!({"type": "error", "payload": [{"message": "not allowed"}]}),
+        ])
+        .await;
+
+        let responses = subscribe_to(addr).await;
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].errors[0].message, "not allowed");
+    }
+
+    #[test]
+    fn it_serializes_client_messages() {
+        assert_eq!(
+            ClientMessage::ConnectionInit { payload: None }.into_message(),
+            Message::Text(r#"{"type":"connection_init"}"#.to_string())
+        );
+        assert_eq!(
+            ClientMessage::Pong {
+                payload: Some(json!({"a": 1}))
+            }
+            .into_message(),
+            Message::Text(r#"{"type":"pong","payload":{"a":1}}"#.to_string())
+        );
+    }
+}
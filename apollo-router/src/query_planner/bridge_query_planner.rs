@@ -5,17 +5,16 @@ use std::fmt::Debug;
 use std::sync::Arc;
 
 use futures::future::BoxFuture;
-use router_bridge::planner::IncrementalDeliverySupport;
 use router_bridge::planner::PlanSuccess;
-use router_bridge::planner::Planner;
-use router_bridge::planner::QueryPlannerConfig;
 use router_bridge::planner::UsageReporting;
 use serde::Deserialize;
 use serde_json_bytes::Map;
 use serde_json_bytes::Value;
+use tower::BoxError;
 use tower::Service;
 use tracing::Instrument;
 
+use super::planner_pool::PlannerPool;
 use super::PlanNode;
 use super::QueryKey;
 use crate::error::QueryPlannerError;
@@ -36,7 +35,7 @@ pub(crate) static USAGE_REPORTING: &str = "apollo_telemetry::usage_reporting";
 ///
 /// No caching is performed. To cache, wrap in a [`CachingQueryPlanner`].
 pub(crate) struct BridgeQueryPlanner {
-    planner: Arc<Planner<QueryPlanResult>>,
+    planner: Arc<PlannerPool>,
     schema: Arc<Schema>,
     introspection: Option<Arc<Introspection>>,
     configuration: Arc<Configuration>,
@@ -47,70 +46,36 @@ impl BridgeQueryPlanner {
         schema: String,
         configuration: Arc<Configuration>,
     ) -> Result<Self, ServiceBuildError> {
-        let planner = Arc::new(
-            Planner::new(
-                schema.clone(),
-                QueryPlannerConfig {
-                    incremental_delivery: Some(IncrementalDeliverySupport {
-                        enable_defer: Some(configuration.supergraph.defer_support),
-                    }),
-                },
-            )
-            .await?,
-        );
-
-        let api_schema = planner.api_schema().await?;
-        let api_schema = Schema::parse(&api_schema.schema, &configuration, None)?;
-        let schema = Arc::new(Schema::parse(
-            &schema,
-            &configuration,
-            Some(Box::new(api_schema)),
-        )?);
-        let introspection = if configuration.supergraph.introspection {
-            Some(Arc::new(Introspection::new(planner.clone()).await))
-        } else {
-            None
-        };
-        Ok(Self {
-            planner,
-            schema,
-            introspection,
-            configuration,
-        })
+        let planner = Arc::new(PlannerPool::new(&schema, &configuration).await?);
+        Self::from_pool(planner, schema, configuration).await
     }
 
     pub(crate) async fn new_from_planner(
-        old_planner: Arc<Planner<QueryPlanResult>>,
+        old_planner: Arc<PlannerPool>,
         schema: String,
         configuration: Arc<Configuration>,
     ) -> Result<Self, ServiceBuildError> {
-        let planner = Arc::new(
-            old_planner
-                .update(
-                    schema.clone(),
-                    QueryPlannerConfig {
-                        incremental_delivery: Some(IncrementalDeliverySupport {
-                            enable_defer: Some(configuration.supergraph.defer_support),
-                        }),
-                    },
-                )
-                .await?,
-        );
+        let planner = Arc::new(PlannerPool::update(&old_planner, &schema, &configuration).await?);
+        Self::from_pool(planner, schema, configuration).await
+    }
 
-        let api_schema = planner.api_schema().await?;
+    async fn from_pool(
+        planner: Arc<PlannerPool>,
+        schema: String,
+        configuration: Arc<Configuration>,
+    ) -> Result<Self, ServiceBuildError> {
+        let api_schema = planner.first().api_schema().await?;
         let api_schema = Schema::parse(&api_schema.schema, &configuration, None)?;
         let schema = Arc::new(Schema::parse(
             &schema,
             &configuration,
             Some(Box::new(api_schema)),
         )?);
-
         let introspection = if configuration.supergraph.introspection {
-            Some(Arc::new(Introspection::new(planner.clone()).await))
+            Some(Arc::new(Introspection::new(planner.first()).await))
         } else {
             None
         };
-
         Ok(Self {
             planner,
             schema,
@@ -119,7 +84,7 @@ impl BridgeQueryPlanner {
         })
     }
 
-    pub(crate) fn planner(&self) -> Arc<Planner<QueryPlanResult>> {
+    pub(crate) fn planner(&self) -> Arc<PlannerPool> {
         self.planner.clone()
     }
 
@@ -164,13 +129,7 @@ impl BridgeQueryPlanner {
         operation: Option<String>,
         mut selections: Query,
     ) -> Result<QueryPlannerContent, QueryPlannerError> {
-        let planner_result = self
-            .planner
-            .plan(query, operation)
-            .await
-            .map_err(QueryPlannerError::RouterBridgeError)?
-            .into_result()
-            .map_err(QueryPlannerError::from)?;
+        let planner_result = self.planner.plan(query, operation).await?;
 
         match planner_result {
             PlanSuccess {
@@ -301,6 +260,22 @@ impl BridgeQueryPlanner {
 
         self.plan(key.0, key.1, selections).await
     }
+
+    /// Plans an operation outside of a running router, for `router plan`
+    pub(crate) async fn plan_offline(
+        schema: String,
+        query: String,
+        operation_name: Option<String>,
+    ) -> Result<Arc<super::QueryPlan>, BoxError> {
+        let planner = Self::new(schema, Default::default()).await?;
+        match planner.get((query, operation_name)).await? {
+            QueryPlannerContent::Plan { plan } => Ok(plan),
+            QueryPlannerContent::Introspection { .. }
+            | QueryPlannerContent::IntrospectionDisabled => {
+                Err("introspection queries are answered by the router without a query plan".into())
+            }
+        }
+    }
 }
 
 /// Data coming from the `plan` method on the router_bridge
@@ -328,6 +303,38 @@ mod tests {
 
     const EXAMPLE_SCHEMA: &str = include_str!("testdata/schema.graphql");
 
+    #[test(tokio::test)]
+    async fn test_plan_offline() {
+        let plan = BridgeQueryPlanner::plan_offline(
+            EXAMPLE_SCHEMA.to_string(),
+            include_str!("testdata/query.graphql").to_string(),
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(plan.formatted_query_plan.is_some());
+
+        let mut operations = Vec::new();
+        plan.root
+            .subgraph_operations(&Default::default(), &mut operations);
+        assert_eq!(operations.len(), 1);
+        assert_eq!(operations[0].service_name, "accounts");
+        assert_eq!(operations[0].operation, "{me{name{first last}}}");
+        assert!(operations[0].path.is_empty());
+
+        let err = BridgeQueryPlanner::plan_offline(
+            EXAMPLE_SCHEMA.to_string(),
+            "{ __schema { types { name } } }".to_string(),
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "introspection queries are answered by the router without a query plan"
+        );
+    }
+
     #[test(tokio::test)]
     async fn test_plan() {
         let planner = BridgeQueryPlanner::new(EXAMPLE_SCHEMA.to_string(), Default::default())
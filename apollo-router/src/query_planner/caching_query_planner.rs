@@ -2,14 +2,15 @@
 
 use std::collections::HashMap;
 use std::ops::Deref;
+use std::path::Path;
 use std::sync::Arc;
 use std::task;
 
 use futures::future::BoxFuture;
-use router_bridge::planner::Planner;
 use router_bridge::planner::UsageReporting;
 use serde::Serialize;
 use serde_json_bytes::value::Serializer;
+use tower::BoxError;
 use tower::ServiceExt;
 use tracing::Instrument;
 
@@ -17,8 +18,9 @@ use super::USAGE_REPORTING;
 use crate::cache::DeduplicatingCache;
 use crate::error::CacheResolverError;
 use crate::error::QueryPlannerError;
+use crate::graphql;
 use crate::query_planner::BridgeQueryPlanner;
-use crate::query_planner::QueryPlanResult;
+use crate::query_planner::PlannerPool;
 use crate::services::QueryPlannerContent;
 use crate::services::QueryPlannerRequest;
 use crate::services::QueryPlannerResponse;
@@ -50,10 +52,15 @@ where
         schema_id: Option<String>,
         config: &crate::configuration::QueryPlanning,
     ) -> CachingQueryPlanner<T> {
-        let cache = Arc::new(
-            DeduplicatingCache::from_configuration(&config.experimental_cache, "query planner")
-                .await,
-        );
+        let mut cache = DeduplicatingCache::from_configuration(
+            &config.experimental_cache.shared(),
+            "query planner",
+        )
+        .await;
+        if let Some(file) = &config.experimental_cache.experimental_file {
+            cache = cache.with_file_storage(file).await;
+        }
+        let cache = Arc::new(cache);
         Self {
             cache,
             delegate,
@@ -101,6 +108,9 @@ where
                             entry.insert(Ok(content.clone())).await;
                         }
                     }
+                    Err(error) if error.is_transient() => {
+                        entry.send(Err(Arc::new(error))).await;
+                    }
                     Err(error) => {
                         count += 1;
                         let e = Arc::new(error);
@@ -114,8 +124,28 @@ where
     }
 }
 
+/// Reads the operations to plan on startup from a JSON list of GraphQL requests
+pub(crate) fn warm_up_operations(path: &Path) -> Result<Vec<(String, Option<String>)>, BoxError> {
+    let content = std::fs::read_to_string(path).map_err(|e| {
+        format!(
+            "could not read the query planner warm up file {}: {e}",
+            path.display()
+        )
+    })?;
+    let requests: Vec<graphql::Request> = serde_json::from_str(&content).map_err(|e| {
+        format!(
+            "could not parse the query planner warm up file {}: {e}",
+            path.display()
+        )
+    })?;
+    Ok(requests
+        .into_iter()
+        .filter_map(|request| Some((request.query?, request.operation_name)))
+        .collect())
+}
+
 impl CachingQueryPlanner<BridgeQueryPlanner> {
-    pub(crate) fn planner(&self) -> Arc<Planner<QueryPlanResult>> {
+    pub(crate) fn planner(&self) -> Arc<PlannerPool> {
         self.delegate.planner()
     }
 }
@@ -189,7 +219,12 @@ where
                             }
                             Err(error) => {
                                 let e = Arc::new(error);
-                                entry.insert(Err(e.clone())).await;
+                                if e.is_transient() {
+                                    // planning can succeed once the router is less loaded
+                                    entry.send(Err(e.clone())).await;
+                                } else {
+                                    entry.insert(Err(e.clone())).await;
+                                }
                                 Err(CacheResolverError::RetrievalError(e))
                             }
                         }
@@ -278,7 +313,9 @@ impl std::fmt::Display for CachingQueryKey {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "plan\0{}\0{}\0{}",
+            "plan\0{}\0{}\0{}\0{}",
+            // plans are persisted across deployments, and may change with the planner version
+            env!("CARGO_PKG_VERSION"),
             self.schema_id.as_deref().unwrap_or("-"),
             self.query,
             self.operation.as_deref().unwrap_or("-")
@@ -377,6 +414,32 @@ mod tests {
             .is_err());
     }
 
+    #[test]
+    fn test_warm_up_operations() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(
+            &mut file,
+            br#"[
+                {"query": "{ me { id } }"},
+                {"query": "query A { a } query B { b }", "operationName": "B"},
+                {"operationName": "MissingQuery"}
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            warm_up_operations(file.path()).unwrap(),
+            vec![
+                ("{ me { id } }".to_string(), None),
+                (
+                    "query A { a } query B { b }".to_string(),
+                    Some("B".to_string())
+                ),
+            ]
+        );
+        assert!(warm_up_operations(Path::new("does/not/exist.json")).is_err());
+    }
+
     macro_rules! test_query_plan {
         () => {
             include_str!("testdata/query_plan.json")
@@ -29,6 +29,7 @@ use crate::query_planner::FETCH_SPAN_NAME;
 use crate::query_planner::FLATTEN_SPAN_NAME;
 use crate::query_planner::PARALLEL_SPAN_NAME;
 use crate::query_planner::SEQUENCE_SPAN_NAME;
+use crate::query_planner::SUBSCRIBE_SPAN_NAME;
 use crate::services::SubgraphServiceFactory;
 use crate::spec::Query;
 use crate::spec::Schema;
@@ -88,7 +89,7 @@ pub(crate) struct ExecutionParameters<'a> {
 }
 
 impl PlanNode {
-    fn execute_recursively<'a>(
+    pub(crate) fn execute_recursively<'a>(
         &'a self,
         parameters: &'a ExecutionParameters<'a>,
         current_dir: &'a Path,
@@ -337,6 +338,26 @@ impl PlanNode {
                     ))
                     .await
                 }
+                PlanNode::Subscription { primary, rest } => {
+                    value = Value::default();
+                    match primary
+                        .execute(parameters, current_dir, rest, sender)
+                        .instrument(tracing::info_span!(
+                            SUBSCRIBE_SPAN_NAME,
+                            "otel.kind" = "INTERNAL",
+                            "apollo.subgraph.name" = primary.service_name.as_str(),
+                        ))
+                        .await
+                    {
+                        Ok(e) => {
+                            errors = e;
+                        }
+                        Err(err) => {
+                            failfast_error!("Subscription error: {}", err);
+                            errors = vec![err.to_graphql_error(Some(current_dir.to_owned()))];
+                        }
+                    }
+                }
             }
 
             (value, subselection, errors)
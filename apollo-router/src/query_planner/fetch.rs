@@ -257,10 +257,18 @@ impl FetchNode {
             // when errors have been redacted in the include_subgraph_errors module.
             // Unfortunately, not easy to fix here, because at this point we don't
             // know if we should be redacting errors for this subgraph...
-            .map_err(|e| FetchError::SubrequestHttpError {
-                status_code: None,
-                service: service_name.to_string(),
-                reason: e.to_string(),
+            .map_err(|e| match e.downcast::<FetchError>() {
+                Ok(error) if matches!(*error, FetchError::SubrequestCircuitOpen { .. }) => *error,
+                Ok(error) => FetchError::SubrequestHttpError {
+                    status_code: None,
+                    service: service_name.to_string(),
+                    reason: error.to_string(),
+                },
+                Err(e) => FetchError::SubrequestHttpError {
+                    status_code: None,
+                    service: service_name.to_string(),
+                    reason: e.to_string(),
+                },
             })?
             .response
             .into_parts();
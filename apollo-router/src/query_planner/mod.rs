@@ -4,6 +4,7 @@
 
 pub(crate) use bridge_query_planner::*;
 pub(crate) use caching_query_planner::*;
+pub(crate) use planner_pool::PlannerPool;
 
 pub(crate) use self::fetch::OperationKind;
 
@@ -12,8 +13,10 @@ mod caching_query_planner;
 mod execution;
 pub(crate) mod fetch;
 mod plan;
+mod planner_pool;
 pub(crate) mod rewrites;
 mod selection;
+mod subscription;
 pub use plan::*;
 
 pub(crate) const FETCH_SPAN_NAME: &str = "fetch";
@@ -26,6 +29,7 @@ pub(crate) const DEFER_DEFERRED_SPAN_NAME: &str = "defer_deferred";
 pub(crate) const CONDITION_SPAN_NAME: &str = "condition";
 pub(crate) const CONDITION_IF_SPAN_NAME: &str = "condition_if";
 pub(crate) const CONDITION_ELSE_SPAN_NAME: &str = "condition_else";
+pub(crate) const SUBSCRIBE_SPAN_NAME: &str = "subscribe";
 
 // The code resides in a separate submodule to allow writing a log filter activating it
 // separately from the query planner logs, as follows:
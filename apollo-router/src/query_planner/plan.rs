@@ -8,6 +8,7 @@ use serde::Serialize;
 
 pub(crate) use self::fetch::OperationKind;
 use super::fetch;
+use super::subscription::SubscriptionNode;
 use crate::error::QueryPlannerError;
 use crate::json_ext;
 use crate::json_ext::Object;
@@ -57,6 +58,10 @@ impl QueryPlan {
     pub(crate) fn is_deferred(&self, operation: Option<&str>, variables: &Object) -> bool {
         self.root.is_deferred(operation, variables, &self.query)
     }
+
+    pub(crate) fn is_subscription(&self) -> bool {
+        matches!(self.root, PlanNode::Subscription { .. })
+    }
 }
 
 /// Query plans are composed of a set of nodes.
@@ -92,9 +97,51 @@ pub(crate) enum PlanNode {
         if_clause: Option<Box<PlanNode>>,
         else_clause: Option<Box<PlanNode>>,
     },
+
+    /// Subscribe to a subgraph, then execute `rest` for every event.
+    Subscription {
+        primary: SubscriptionNode,
+        rest: Option<Box<PlanNode>>,
+    },
 }
 
 impl PlanNode {
+    /// Maximum number of subgraph requests executed for this node
+    pub(crate) fn subgraph_fetches(&self) -> usize {
+        match self {
+            Self::Sequence { nodes } | Self::Parallel { nodes } => {
+                nodes.iter().map(|n| n.subgraph_fetches()).sum()
+            }
+            Self::Fetch(_) => 1,
+            Self::Flatten(flatten) => flatten.node.subgraph_fetches(),
+            Self::Defer { primary, deferred } => {
+                primary
+                    .node
+                    .as_ref()
+                    .map(|n| n.subgraph_fetches())
+                    .unwrap_or(0)
+                    + deferred
+                        .iter()
+                        .filter_map(|d| d.node.as_ref())
+                        .map(|n| n.subgraph_fetches())
+                        .sum::<usize>()
+            }
+            Self::Condition {
+                if_clause,
+                else_clause,
+                ..
+            } => if_clause
+                .iter()
+                .chain(else_clause.iter())
+                .map(|n| n.subgraph_fetches())
+                .max()
+                .unwrap_or(0),
+            Self::Subscription { rest, .. } => {
+                1 + rest.as_ref().map(|n| n.subgraph_fetches()).unwrap_or(0)
+            }
+        }
+    }
+
     pub(crate) fn contains_mutations(&self) -> bool {
         match self {
             Self::Sequence { nodes } => nodes.iter().any(|n| n.contains_mutations()),
@@ -106,6 +153,7 @@ impl PlanNode {
                 .map(|n| n.contains_mutations())
                 .unwrap_or(false),
             Self::Flatten(_) => false,
+            Self::Subscription { .. } => false,
             Self::Condition {
                 if_clause,
                 else_clause,
@@ -126,6 +174,54 @@ impl PlanNode {
         }
     }
 
+    /// Lists the requests sent to subgraphs, with the path where their response is merged
+    pub(crate) fn subgraph_operations<'a>(
+        &'a self,
+        path: &Path,
+        operations: &mut Vec<SubgraphOperation<'a>>,
+    ) {
+        match self {
+            Self::Sequence { nodes } | Self::Parallel { nodes } => {
+                for node in nodes {
+                    node.subgraph_operations(path, operations);
+                }
+            }
+            Self::Fetch(fetch) => operations.push(SubgraphOperation {
+                path: path.clone(),
+                service_name: &fetch.service_name,
+                operation: &fetch.operation,
+            }),
+            Self::Flatten(flatten) => flatten.node.subgraph_operations(&flatten.path, operations),
+            Self::Defer { primary, deferred } => {
+                if let Some(node) = &primary.node {
+                    node.subgraph_operations(path, operations);
+                }
+                for node in deferred.iter().filter_map(|d| d.node.as_ref()) {
+                    node.subgraph_operations(path, operations);
+                }
+            }
+            Self::Condition {
+                if_clause,
+                else_clause,
+                ..
+            } => {
+                for node in if_clause.iter().chain(else_clause.iter()) {
+                    node.subgraph_operations(path, operations);
+                }
+            }
+            Self::Subscription { primary, rest } => {
+                operations.push(SubgraphOperation {
+                    path: path.clone(),
+                    service_name: &primary.service_name,
+                    operation: &primary.operation,
+                });
+                if let Some(rest) = rest {
+                    rest.subgraph_operations(path, operations);
+                }
+            }
+        }
+    }
+
     pub(crate) fn is_deferred(
         &self,
         operation: Option<&str>,
@@ -142,6 +238,10 @@ impl PlanNode {
             Self::Flatten(node) => node.node.is_deferred(operation, variables, query),
             Self::Fetch(..) => false,
             Self::Defer { .. } => true,
+            Self::Subscription { rest, .. } => rest
+                .as_ref()
+                .map(|n| n.is_deferred(operation, variables, query))
+                .unwrap_or(false),
             Self::Condition {
                 if_clause,
                 else_clause,
@@ -180,6 +280,8 @@ impl PlanNode {
 
         let operation_kind = if self.contains_mutations() {
             OperationKind::Mutation
+        } else if matches!(self, PlanNode::Subscription { .. }) {
+            OperationKind::Subscription
         } else {
             OperationKind::Query
         };
@@ -264,6 +366,10 @@ impl PlanNode {
                 Ok(())
             }
             Self::Fetch(..) => Ok(()),
+            Self::Subscription { rest, .. } => match rest {
+                Some(node) => node.collect_subselections(schema, initial_path, kind, subselections),
+                None => Ok(()),
+            },
             Self::Condition {
                 if_clause,
                 else_clause,
@@ -290,6 +396,13 @@ impl PlanNode {
                 Box::new(nodes.iter().flat_map(|x| x.service_usage()))
             }
             Self::Fetch(fetch) => Box::new(Some(fetch.service_name()).into_iter()),
+            Self::Subscription { primary, rest } => match rest {
+                Some(rest) => Box::new(
+                    rest.service_usage()
+                        .chain(Some(primary.service_name.as_str()).into_iter()),
+                ) as Box<dyn Iterator<Item = &'a str> + 'a>,
+                None => Box::new(Some(primary.service_name.as_str()).into_iter()),
+            },
             Self::Flatten(flatten) => flatten.node.service_usage(),
             Self::Defer { primary, deferred } => primary
                 .node
@@ -353,6 +466,15 @@ fn reconstruct_full_query(path: &Path, kind: &OperationKind, subselection: &str)
     query
 }
 
+/// A request sent to a subgraph by a query plan
+#[derive(Debug, PartialEq)]
+pub(crate) struct SubgraphOperation<'a> {
+    /// The path where the response is merged
+    pub(crate) path: Path,
+    pub(crate) service_name: &'a str,
+    pub(crate) operation: &'a str,
+}
+
 /// A flatten node.
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -0,0 +1,262 @@
+//! A pool of nodejs query planners, planning queries in parallel
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use router_bridge::planner::IncrementalDeliverySupport;
+use router_bridge::planner::PlanSuccess;
+use router_bridge::planner::Planner;
+use router_bridge::planner::QueryPlannerConfig;
+use tokio::sync::Semaphore;
+use tracing::Instrument;
+
+use super::QueryPlanResult;
+use crate::error::QueryPlannerError;
+use crate::error::ServiceBuildError;
+use crate::Configuration;
+
+/// A fixed size pool of query planners.
+///
+/// Each planner runs in its own JavaScript runtime and plans one query at a time, so a slow
+/// query only blocks the planner it runs on. Queries wait for an idle planner in a queue that
+/// can be bounded, and planning can be limited in time.
+pub(crate) struct PlannerPool {
+    planners: Vec<Arc<Planner<QueryPlanResult>>>,
+    idle: Arc<Mutex<Vec<Arc<Planner<QueryPlanResult>>>>>,
+    /// One permit per idle planner
+    permits: Arc<Semaphore>,
+    waiting: Arc<AtomicUsize>,
+    timeout: Option<Duration>,
+    queue_size: Option<usize>,
+}
+
+fn planner_config(configuration: &Configuration) -> QueryPlannerConfig {
+    QueryPlannerConfig {
+        incremental_delivery: Some(IncrementalDeliverySupport {
+            enable_defer: Some(configuration.supergraph.defer_support),
+        }),
+    }
+}
+
+impl PlannerPool {
+    pub(crate) async fn new(
+        schema: &str,
+        configuration: &Configuration,
+    ) -> Result<Self, ServiceBuildError> {
+        let size = configuration
+            .supergraph
+            .query_planning
+            .experimental_planner_pool
+            .size
+            .get();
+        let planners = futures::future::try_join_all(
+            (0..size).map(|_| Planner::new(schema.to_string(), planner_config(configuration))),
+        )
+        .await?;
+
+        Ok(Self::from_planners(planners, configuration))
+    }
+
+    /// Creates a pool for a new schema, reusing the runtimes of the planners of `old`
+    pub(crate) async fn update(
+        old: &PlannerPool,
+        schema: &str,
+        configuration: &Configuration,
+    ) -> Result<Self, ServiceBuildError> {
+        let size = configuration
+            .supergraph
+            .query_planning
+            .experimental_planner_pool
+            .size
+            .get();
+        let mut planners = Vec::with_capacity(size);
+        for index in 0..size {
+            let planner = match old.planners.get(index) {
+                Some(planner) => {
+                    planner
+                        .update(schema.to_string(), planner_config(configuration))
+                        .await?
+                }
+                None => Planner::new(schema.to_string(), planner_config(configuration)).await?,
+            };
+            planners.push(planner);
+        }
+
+        Ok(Self::from_planners(planners, configuration))
+    }
+
+    fn from_planners(
+        planners: Vec<Planner<QueryPlanResult>>,
+        configuration: &Configuration,
+    ) -> Self {
+        let config = &configuration
+            .supergraph
+            .query_planning
+            .experimental_planner_pool;
+        let planners: Vec<_> = planners.into_iter().map(Arc::new).collect();
+        Self {
+            permits: Arc::new(Semaphore::new(planners.len())),
+            idle: Arc::new(Mutex::new(planners.clone())),
+            planners,
+            waiting: Default::default(),
+            timeout: config.timeout,
+            queue_size: config.queue_size,
+        }
+    }
+
+    /// The planner used for everything but query planning, like introspection
+    pub(crate) fn first(&self) -> Arc<Planner<QueryPlanResult>> {
+        self.planners[0].clone()
+    }
+
+    pub(crate) async fn plan(
+        &self,
+        query: String,
+        operation: Option<String>,
+    ) -> Result<PlanSuccess<QueryPlanResult>, QueryPlannerError> {
+        let permit = match self.permits.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                let waiting = WaitingGuard::enter(self.waiting.clone());
+                if matches!(self.queue_size, Some(queue_size) if waiting.position > queue_size) {
+                    return Err(QueryPlannerError::Overloaded);
+                }
+                self.permits
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("the semaphore is never closed")
+            }
+        };
+        let planner = self
+            .idle
+            .lock()
+            .expect("lock poisoned")
+            .pop()
+            .expect("a permit guarantees that a planner is idle");
+
+        // planning runs in its own task: if it times out, the planner goes back to the pool
+        // only once it is done with the query
+        let idle = self.idle.clone();
+        let task = tokio::task::spawn(
+            async move {
+                let start = Instant::now();
+                let result = planner.plan(query, operation).await;
+                tracing::info!(
+                    histogram.apollo_router_query_planning_time = start.elapsed().as_secs_f64()
+                );
+                idle.lock().expect("lock poisoned").push(planner);
+                drop(permit);
+                result
+            }
+            .in_current_span(),
+        );
+
+        let result = match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, task)
+                .await
+                .map_err(|_| QueryPlannerError::Timeout(timeout))?,
+            None => task.await,
+        };
+        result
+            .map_err(|e| QueryPlannerError::JoinError(e.to_string()))?
+            .map_err(QueryPlannerError::RouterBridgeError)?
+            .into_result()
+            .map_err(QueryPlannerError::from)
+    }
+}
+
+/// Counts a query waiting for a planner, until it is dropped
+struct WaitingGuard {
+    waiting: Arc<AtomicUsize>,
+    position: usize,
+}
+
+impl WaitingGuard {
+    fn enter(waiting: Arc<AtomicUsize>) -> Self {
+        let position = waiting.fetch_add(1, Ordering::SeqCst) + 1;
+        tracing::info!(value.apollo_router_query_planning_queue_size = position as u64);
+        Self { waiting, position }
+    }
+}
+
+impl Drop for WaitingGuard {
+    fn drop(&mut self) {
+        let waiting = self.waiting.fetch_sub(1, Ordering::SeqCst) - 1;
+        tracing::info!(value.apollo_router_query_planning_queue_size = waiting as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use test_log::test;
+
+    use super::*;
+
+    const EXAMPLE_SCHEMA: &str = include_str!("testdata/schema.graphql");
+    const EXAMPLE_QUERY: &str = include_str!("testdata/query.graphql");
+
+    async fn pool(
+        size: usize,
+        timeout: Option<Duration>,
+        queue_size: Option<usize>,
+    ) -> PlannerPool {
+        let mut configuration = Configuration::default();
+        let pool = &mut configuration
+            .supergraph
+            .query_planning
+            .experimental_planner_pool;
+        pool.size = NonZeroUsize::new(size).unwrap();
+        pool.timeout = timeout;
+        pool.queue_size = queue_size;
+        PlannerPool::new(EXAMPLE_SCHEMA, &configuration)
+            .await
+            .unwrap()
+    }
+
+    #[test(tokio::test)]
+    async fn it_plans_in_parallel() {
+        let pool = pool(2, None, None).await;
+        let (first, second) = tokio::join!(
+            pool.plan(EXAMPLE_QUERY.to_string(), None),
+            pool.plan(EXAMPLE_QUERY.to_string(), None)
+        );
+        assert_eq!(first.unwrap().data, second.unwrap().data);
+        assert_eq!(pool.idle.lock().unwrap().len(), 2);
+    }
+
+    #[test(tokio::test)]
+    async fn it_times_out() {
+        let pool = pool(1, Some(Duration::from_nanos(1)), None).await;
+        let err = pool
+            .plan(EXAMPLE_QUERY.to_string(), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, QueryPlannerError::Timeout(_)));
+
+        // the planner comes back to the pool once it has finished planning
+        let _permit = pool.permits.clone().acquire_owned().await.unwrap();
+        assert_eq!(pool.idle.lock().unwrap().len(), 1);
+    }
+
+    #[test(tokio::test)]
+    async fn it_sheds_load_when_the_queue_is_full() {
+        let pool = pool(1, None, Some(0)).await;
+        let permit = pool.permits.clone().acquire_owned().await.unwrap();
+        let err = pool
+            .plan(EXAMPLE_QUERY.to_string(), None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, QueryPlannerError::Overloaded));
+        assert_eq!(pool.waiting.load(Ordering::SeqCst), 0);
+
+        drop(permit);
+        assert!(pool.plan(EXAMPLE_QUERY.to_string(), None).await.is_ok());
+    }
+}
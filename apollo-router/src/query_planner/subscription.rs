@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+use futures::SinkExt;
+use futures::StreamExt;
+use serde::Deserialize;
+use serde::Serialize;
+use tower::ServiceExt;
+use tracing::Instrument;
+
+use super::execution::ExecutionParameters;
+use super::rewrites;
+use super::OperationKind;
+use super::PlanNode;
+use crate::error::Error;
+use crate::error::FetchError;
+use crate::graphql::Request;
+use crate::graphql::Response;
+use crate::http_ext;
+use crate::json_ext::Object;
+use crate::json_ext::Path;
+use crate::json_ext::ValueExt;
+use crate::services::SubgraphRequest;
+
+/// A subscription node.
+///
+/// The subscription is forwarded to `service_name`, then every event it sends is
+/// completed by the rest of the query plan and streamed to the client.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SubscriptionNode {
+    /// The name of the service or subgraph that the subscription is querying.
+    pub(crate) service_name: String,
+
+    /// The variables that are used for the subgraph subscription.
+    pub(crate) variable_usages: Vec<String>,
+
+    /// The GraphQL subquery that is used for the subscription.
+    pub(crate) operation: String,
+
+    /// The GraphQL subquery operation name.
+    pub(crate) operation_name: Option<String>,
+
+    /// The GraphQL operation kind that is used for the fetch.
+    pub(crate) operation_kind: OperationKind,
+
+    // Optionally describes a number of "rewrites" that query plan executors should apply to the data that is sent as input of this subscription.
+    pub(crate) input_rewrites: Option<Vec<rewrites::DataRewrite>>,
+
+    // Optionally describes a number of "rewrites" to apply to the data that received from a subscription event (and before it is applied to the current in-memory results).
+    pub(crate) output_rewrites: Option<Vec<rewrites::DataRewrite>>,
+}
+
+impl SubscriptionNode {
+    /// Opens the subscription on the subgraph, then spawns a task executing the `rest` of
+    /// the query plan for every event, and sending the results through `sender`.
+    pub(crate) async fn execute<'a>(
+        &'a self,
+        parameters: &'a ExecutionParameters<'a>,
+        current_dir: &'a Path,
+        rest: &'a Option<Box<PlanNode>>,
+        mut sender: futures::channel::mpsc::Sender<Response>,
+    ) -> Result<Vec<Error>, FetchError> {
+        let SubscriptionNode {
+            service_name,
+            variable_usages,
+            operation,
+            operation_name,
+            ..
+        } = self;
+
+        let body = parameters.supergraph_request.body();
+        let variables = variable_usages
+            .iter()
+            .filter_map(|key| {
+                body.variables
+                    .get_key_value(key.as_str())
+                    .map(|(variable_key, value)| (variable_key.clone(), value.clone()))
+            })
+            .collect::<Object>();
+
+        let (subscription_stream_tx, mut subscription_stream_rx) = mpsc::channel(1);
+        let subgraph_request = SubgraphRequest::subscription_builder()
+            .supergraph_request(parameters.supergraph_request.clone())
+            .subgraph_request(
+                http_ext::Request::builder()
+                    .method(http::Method::POST)
+                    .uri(
+                        parameters
+                            .schema
+                            .subgraphs()
+                            .find_map(|(name, url)| (name == service_name).then_some(url))
+                            .unwrap_or_else(|| {
+                                panic!(
+                                    "schema uri for subgraph '{service_name}' should already have been checked"
+                                )
+                            })
+                            .clone(),
+                    )
+                    .body(
+                        Request::builder()
+                            .query(operation)
+                            .and_operation_name(operation_name.clone())
+                            .variables(variables)
+                            .build(),
+                    )
+                    .build()
+                    .expect("it won't fail because the url is correct and already checked; qed"),
+            )
+            .context(parameters.context.clone())
+            .subscription_stream(subscription_stream_tx)
+            .build();
+
+        let service = parameters
+            .service_factory
+            .create(service_name)
+            .expect("we already checked that the service exists during planning; qed");
+
+        let (_parts, response) = service
+            .oneshot(subgraph_request)
+            .await
+            .map_err(|e| match e.downcast::<FetchError>() {
+                Ok(error) => *error,
+                Err(e) => FetchError::SubrequestWsError {
+                    service: service_name.to_string(),
+                    reason: e.to_string(),
+                },
+            })?
+            .response
+            .into_parts();
+
+        // a plugin might have answered in place of the subgraph
+        if !response.errors.is_empty() {
+            return Ok(response.errors);
+        }
+
+        // the subgraph service sends the stream before returning its response
+        let mut stream = match subscription_stream_rx.try_next() {
+            Ok(Some(stream)) => stream,
+            _ => {
+                return Err(FetchError::SubrequestWsError {
+                    service: service_name.to_string(),
+                    reason: "the subgraph service did not open the subscription".to_string(),
+                })
+            }
+        };
+
+        let rest = rest.clone();
+        let output_rewrites = self.output_rewrites.clone();
+        let current_dir = current_dir.clone();
+        let context = parameters.context.clone();
+        let service_factory = parameters.service_factory.clone();
+        let schema = parameters.schema.clone();
+        let supergraph_request = parameters.supergraph_request.clone();
+        let query = parameters.query.clone();
+
+        tokio::task::spawn(
+            async move {
+                let deferred_fetches = HashMap::new();
+                let parameters = ExecutionParameters {
+                    context: &context,
+                    service_factory: &service_factory,
+                    schema: &schema,
+                    supergraph_request: &supergraph_request,
+                    deferred_fetches: &deferred_fetches,
+                    query: &query,
+                };
+
+                while let Some(mut response) = stream.next().await {
+                    let mut data = response.data.take().unwrap_or_default();
+                    rewrites::apply_rewrites(&schema, &mut data, &output_rewrites);
+
+                    if let Some(rest) = &rest {
+                        let (value, _subselection, errors) = rest
+                            .execute_recursively(&parameters, &current_dir, &data, sender.clone())
+                            .in_current_span()
+                            .await;
+                        data.deep_merge(value);
+                        response.errors.extend(errors.into_iter());
+                    }
+
+                    response.data = Some(data);
+                    response.has_next = Some(true);
+
+                    // the client went away, dropping the stream closes the subgraph connection
+                    if sender.send(response).await.is_err() {
+                        return;
+                    }
+                }
+
+                let _ = sender
+                    .send(Response::builder().has_next(false).build())
+                    .await;
+            }
+            .in_current_span(),
+        );
+
+        Ok(Vec::new())
+    }
+}
@@ -59,6 +59,26 @@ fn service_usage() {
     );
 }
 
+#[test]
+fn subgraph_operations() {
+    let query_plan: PlanNode = serde_json::from_str(test_query_plan!()).unwrap();
+    let mut operations = Vec::new();
+    query_plan.subgraph_operations(&Path::default(), &mut operations);
+    assert_eq!(
+        operations
+            .iter()
+            .map(|operation| (operation.service_name, operation.path.to_string()))
+            .collect::<Vec<_>>(),
+        vec![
+            ("product", "".to_string()),
+            ("books", "/topProducts/@".to_string()),
+            ("product", "/topProducts/@".to_string()),
+            ("books", "/product".to_string()),
+            ("product", "/product".to_string()),
+        ]
+    );
+}
+
 /// This test panics in the product subgraph. HOWEVER, this does not result in a panic in the
 /// test, since the buffer() functionality in the tower stack "loses" the panic and we end up
 /// with a closed service.
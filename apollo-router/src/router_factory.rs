@@ -22,9 +22,14 @@ use crate::configuration::TlsSubgraph;
 use crate::plugin::DynPlugin;
 use crate::plugin::Handler;
 use crate::plugin::PluginFactory;
+use crate::plugins::subscription::Subscription;
+use crate::plugins::subscription::APOLLO_SUBSCRIPTION_PLUGIN;
 use crate::plugins::traffic_shaping::TrafficShaping;
 use crate::plugins::traffic_shaping::APOLLO_TRAFFIC_SHAPING;
+use crate::query_planner::warm_up_operations;
 use crate::query_planner::BridgeQueryPlanner;
+use crate::services::client_certificate::ClientCertificateResolver;
+use crate::services::generate_tls_client_config;
 use crate::services::new_service::ServiceFactory;
 use crate::services::router;
 use crate::services::router_service::RouterCreator;
@@ -160,6 +165,12 @@ impl RouterSuperServiceFactory for YamlRouterFactory {
             .all
             .create_certificate_store()
             .transpose()?;
+        let tls_client_certificate = configuration
+            .tls
+            .subgraph
+            .all
+            .create_client_certificate()
+            .transpose()?;
 
         let mut builder = PluggableSupergraphServiceBuilder::new(bridge_query_planner);
         builder = builder.with_configuration(configuration.clone());
@@ -174,6 +185,23 @@ impl RouterSuperServiceFactory for YamlRouterFactory {
                 .and_then(|subgraph| subgraph.create_certificate_store())
                 .transpose()?
                 .or_else(|| tls_root_store.clone());
+            let subgraph_client_certificate = configuration
+                .tls
+                .subgraph
+                .subgraphs
+                .get(name)
+                .as_ref()
+                .and_then(|subgraph| subgraph.create_client_certificate())
+                .transpose()?
+                .or_else(|| tls_client_certificate.clone());
+            let subgraph_tls_config =
+                generate_tls_client_config(subgraph_root_store, subgraph_client_certificate);
+
+            let subscription_config = plugins
+                .iter()
+                .find(|i| i.0.as_str() == APOLLO_SUBSCRIPTION_PLUGIN)
+                .and_then(|plugin| (*plugin.1).as_any().downcast_ref::<Subscription>())
+                .and_then(|subscription| subscription.subgraph_configuration(name));
 
             let subgraph_service = match plugins
                 .iter()
@@ -183,7 +211,7 @@ impl RouterSuperServiceFactory for YamlRouterFactory {
                 Some(shaping) => Either::A(
                     shaping.subgraph_service_internal(
                         name,
-                        SubgraphService::new(
+                        SubgraphService::from_tls_config(
                             name,
                             configuration
                                 .apq
@@ -192,12 +220,19 @@ impl RouterSuperServiceFactory for YamlRouterFactory {
                                 .get(name)
                                 .map(|apq| apq.enabled)
                                 .unwrap_or(configuration.apq.subgraph.all.enabled),
-                            subgraph_root_store,
+                            subgraph_tls_config,
                             shaping.enable_subgraph_http2(name),
+                            subscription_config,
                         ),
                     ),
                 ),
-                None => Either::B(SubgraphService::new(name, false, subgraph_root_store, true)),
+                None => Either::B(SubgraphService::from_tls_config(
+                    name,
+                    false,
+                    subgraph_tls_config,
+                    true,
+                    subscription_config,
+                )),
             };
             builder = builder.with_subgraph_service(name, subgraph_service);
         }
@@ -209,21 +244,24 @@ impl RouterSuperServiceFactory for YamlRouterFactory {
         // Final creation after this line we must NOT fail to go live with the new router from this point as some plugins may interact with globals.
         let mut supergraph_creator = builder.build().await?;
 
-        if let Some(router) = previous_router {
-            if configuration.supergraph.query_planning.warmed_up_queries > 0 {
-                let cache_keys = router
-                    .cache_keys(configuration.supergraph.query_planning.warmed_up_queries)
-                    .await;
+        let query_planning = &configuration.supergraph.query_planning;
+        let mut cache_keys = match previous_router {
+            Some(router) if query_planning.warmed_up_queries > 0 => {
+                router.cache_keys(query_planning.warmed_up_queries).await
+            }
+            _ => Vec::new(),
+        };
+        if let Some(path) = &query_planning.experimental_warm_up_operations {
+            cache_keys.extend(warm_up_operations(path)?);
+        }
 
-                if !cache_keys.is_empty() {
-                    tracing::info!(
-                        "warming up the query plan cache with {} queries, this might take a while",
-                        cache_keys.len()
-                    );
+        if !cache_keys.is_empty() {
+            tracing::info!(
+                "warming up the query plan cache with {} queries, this might take a while",
+                cache_keys.len()
+            );
 
-                    supergraph_creator.warm_up_query_planner(cache_keys).await;
-                }
-            }
+            supergraph_creator.warm_up_query_planner(cache_keys).await;
         }
 
         Ok(Self::RouterFactory::new(Arc::new(supergraph_creator), &configuration).await)
@@ -258,6 +296,12 @@ impl YamlRouterFactory {
             .all
             .create_certificate_store()
             .transpose()?;
+        let tls_client_certificate = configuration
+            .tls
+            .subgraph
+            .all
+            .create_client_certificate()
+            .transpose()?;
 
         let mut builder = PluggableSupergraphServiceBuilder::new(bridge_query_planner);
         builder = builder.with_configuration(configuration.clone());
@@ -272,6 +316,23 @@ impl YamlRouterFactory {
                 .and_then(|subgraph| subgraph.create_certificate_store())
                 .transpose()?
                 .or_else(|| tls_root_store.clone());
+            let subgraph_client_certificate = configuration
+                .tls
+                .subgraph
+                .subgraphs
+                .get(name)
+                .as_ref()
+                .and_then(|subgraph| subgraph.create_client_certificate())
+                .transpose()?
+                .or_else(|| tls_client_certificate.clone());
+            let subgraph_tls_config =
+                generate_tls_client_config(subgraph_root_store, subgraph_client_certificate);
+
+            let subscription_config = plugins
+                .iter()
+                .find(|i| i.0.as_str() == APOLLO_SUBSCRIPTION_PLUGIN)
+                .and_then(|plugin| (*plugin.1).as_any().downcast_ref::<Subscription>())
+                .and_then(|subscription| subscription.subgraph_configuration(name));
 
             let subgraph_service = match plugins
                 .iter()
@@ -281,7 +342,7 @@ impl YamlRouterFactory {
                 Some(shaping) => Either::A(
                     shaping.subgraph_service_internal(
                         name,
-                        SubgraphService::new(
+                        SubgraphService::from_tls_config(
                             name,
                             configuration
                                 .apq
@@ -290,12 +351,19 @@ impl YamlRouterFactory {
                                 .get(name)
                                 .map(|apq| apq.enabled)
                                 .unwrap_or(configuration.apq.subgraph.all.enabled),
-                            subgraph_root_store,
+                            subgraph_tls_config,
                             shaping.enable_subgraph_http2(name),
+                            subscription_config,
                         ),
                     ),
                 ),
-                None => Either::B(SubgraphService::new(name, false, subgraph_root_store, true)),
+                None => Either::B(SubgraphService::from_tls_config(
+                    name,
+                    false,
+                    subgraph_tls_config,
+                    true,
+                    subscription_config,
+                )),
             };
             builder = builder.with_subgraph_service(name, subgraph_service);
         }
@@ -314,6 +382,14 @@ impl TlsSubgraph {
             .as_deref()
             .map(create_certificate_store)
     }
+
+    fn create_client_certificate(
+        &self,
+    ) -> Option<Result<Arc<ClientCertificateResolver>, ConfigurationError>> {
+        self.client_authentication
+            .as_ref()
+            .map(|config| ClientCertificateResolver::new(config).map(Arc::new))
+    }
 }
 
 fn create_certificate_store(
@@ -0,0 +1,160 @@
+//! TLS client certificate used to authenticate to subgraphs.
+//!
+//! The certificate chain and key are loaded from the configuration, and reloaded when they are
+//! read from files that change, so rotated certificates are used without restarting the router.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+use futures::stream::select;
+use futures::StreamExt;
+use rustls::client::ResolvesClientCert;
+use rustls::sign::CertifiedKey;
+use rustls::SignatureScheme;
+use tokio::task::JoinHandle;
+
+use crate::configuration::check_key_pair;
+use crate::configuration::load_certs;
+use crate::configuration::load_keys;
+use crate::configuration::ConfigurationError;
+use crate::configuration::TlsClientAuth;
+
+pub(crate) struct ClientCertificateResolver {
+    certified_key: Arc<RwLock<Arc<CertifiedKey>>>,
+    watcher: Option<JoinHandle<()>>,
+}
+
+impl ClientCertificateResolver {
+    /// Loads the client certificate, and watches its files if there are some
+    pub(crate) fn new(config: &TlsClientAuth) -> Result<Self, ConfigurationError> {
+        let certified_key = Arc::new(RwLock::new(Arc::new(load_certified_key(config)?)));
+
+        let files: Vec<PathBuf> = [config.certificate_chain.file(), config.key.file()]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect();
+        let watcher = (!files.is_empty()).then(|| {
+            tokio::spawn(watch_certificate(
+                files,
+                config.clone(),
+                certified_key.clone(),
+            ))
+        });
+
+        Ok(Self {
+            certified_key,
+            watcher,
+        })
+    }
+}
+
+impl Drop for ClientCertificateResolver {
+    fn drop(&mut self) {
+        if let Some(watcher) = self.watcher.take() {
+            watcher.abort();
+        }
+    }
+}
+
+impl ResolvesClientCert for ClientCertificateResolver {
+    fn resolve(
+        &self,
+        _acceptable_issuers: &[&[u8]],
+        _sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<CertifiedKey>> {
+        Some(self.certified_key.read().expect("lock poisoned").clone())
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+fn load_certified_key(config: &TlsClientAuth) -> Result<CertifiedKey, ConfigurationError> {
+    let error = |error: String| ConfigurationError::ClientCertificate { error };
+
+    let certificates = config
+        .certificate_chain
+        .load()
+        .and_then(|data| load_certs(&data))
+        .map_err(|e| error(format!("could not load the certificate chain: {e}")))?;
+    if certificates.is_empty() {
+        return Err(error("the certificate chain is empty".to_string()));
+    }
+    let key = config
+        .key
+        .load()
+        .and_then(|data| load_keys(&data))
+        .map_err(|e| error(format!("could not load the key: {e}")))?;
+    let signing_key = rustls::sign::any_supported_type(&key)
+        .map_err(|e| error(format!("unsupported key: {e}")))?;
+    check_key_pair(&certificates[0], &*signing_key).map_err(error)?;
+
+    Ok(CertifiedKey::new(certificates, signing_key))
+}
+
+async fn watch_certificate(
+    files: Vec<PathBuf>,
+    config: TlsClientAuth,
+    certified_key: Arc<RwLock<Arc<CertifiedKey>>>,
+) {
+    let mut changes = files
+        .iter()
+        .map(|file| crate::files::watch(file).boxed())
+        .reduce(|changes, file_changes| select(changes, file_changes).boxed())
+        .expect("there is at least one file to watch");
+
+    while changes.next().await.is_some() {
+        match load_certified_key(&config) {
+            Ok(new_key) => {
+                tracing::info!("reloaded the subgraph client certificate");
+                *certified_key.write().expect("lock poisoned") = Arc::new(new_key);
+            }
+            // the certificate and key files might not be updated at the same time, the
+            // previous certificate is kept until the new key matches the new certificate
+            Err(e) => tracing::error!("{e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::PemSource;
+
+    fn testdata(file: &str) -> PathBuf {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("src/configuration/testdata");
+        path.push(file);
+        path
+    }
+
+    #[tokio::test]
+    async fn it_loads_client_certificates() {
+        let config = TlsClientAuth {
+            certificate_chain: PemSource::File {
+                file: testdata("server.crt"),
+            },
+            key: PemSource::Inline(std::fs::read_to_string(testdata("server.key")).unwrap()),
+        };
+        let resolver = ClientCertificateResolver::new(&config).unwrap();
+        assert!(resolver.watcher.is_some());
+        assert_eq!(resolver.resolve(&[], &[]).unwrap().cert.len(), 1);
+
+        let invalid_key = TlsClientAuth {
+            key: PemSource::Inline("not a key".to_string()),
+            ..config.clone()
+        };
+        assert!(ClientCertificateResolver::new(&invalid_key).is_err());
+
+        let mismatched_key = TlsClientAuth {
+            key: PemSource::File {
+                file: testdata("server_rotated.key"),
+            },
+            ..config
+        };
+        assert!(ClientCertificateResolver::new(&mismatched_key).is_err());
+    }
+}
@@ -73,8 +73,9 @@ impl Service<ExecutionRequest> for ExecutionService {
             let is_deferred = req
                 .query_plan
                 .is_deferred(operation_name.as_deref(), &variables);
+            let is_subscription = req.query_plan.is_subscription();
 
-            let first = req
+            let mut first = req
                 .query_plan
                 .execute(
                     &context,
@@ -86,7 +87,16 @@ impl Service<ExecutionRequest> for ExecutionService {
                 .await;
 
             let query = req.query_plan.query.clone();
-            let stream = if is_deferred {
+            let stream = if is_subscription {
+                // the events are sent through the receiver, the first response
+                // is only sent if the subscription could not be opened
+                if first.errors.is_empty() {
+                    receiver.boxed()
+                } else {
+                    first.has_next = Some(false);
+                    once(ready(first)).boxed()
+                }
+            } else if is_deferred {
                 filter_stream(first, receiver).boxed()
             } else {
                 once(ready(first)).chain(receiver).boxed()
@@ -124,7 +134,7 @@ impl Service<ExecutionRequest> for ExecutionService {
 
                     match (response.path.as_ref(), response.data.as_ref()) {
                         (None, _) | (_, None) => {
-                            if is_deferred {
+                            if is_deferred || is_subscription {
                                 response.has_next = Some(has_next);
                             }
 
@@ -0,0 +1,332 @@
+//! Persisted queries loaded from an operation manifest.
+//!
+//! Clients send the operation id in the `persistedQuery` extension instead of the query string.
+//! Depending on the configured mode, operations sent as a free-form `query` can be rejected
+//! (safelisting) or logged if they are not in the manifest.
+
+// This entire file is license key functionality
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+use futures::StreamExt;
+use http::StatusCode;
+use serde::Deserialize;
+use tokio::task::JoinHandle;
+
+use crate::configuration::PersistedQueries;
+use crate::configuration::PersistedQueriesMode;
+use crate::services::SupergraphRequest;
+use crate::services::SupergraphResponse;
+
+pub(crate) const PERSISTED_QUERY_ID_CONTEXT_KEY: &str = "apollo_persisted_queries::id";
+
+/// Operation manifest, in the Apollo persisted query manifest format, or as a map from ids
+/// to documents
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ManifestFile {
+    Apollo { operations: Vec<ManifestOperation> },
+    Map(HashMap<String, String>),
+}
+
+#[derive(Deserialize)]
+struct ManifestOperation {
+    id: String,
+    body: String,
+}
+
+#[derive(Debug, Default)]
+struct Manifest {
+    by_id: HashMap<String, String>,
+    documents: HashSet<String>,
+}
+
+impl Manifest {
+    fn parse(contents: &str) -> Result<Self, serde_json::Error> {
+        let by_id: HashMap<String, String> = match serde_json::from_str(contents)? {
+            ManifestFile::Apollo { operations } => operations
+                .into_iter()
+                .map(|operation| (operation.id, operation.body))
+                .collect(),
+            ManifestFile::Map(map) => map,
+        };
+        let documents = by_id.values().cloned().collect();
+
+        Ok(Manifest { by_id, documents })
+    }
+
+    async fn load(path: &Path) -> Result<Self, String> {
+        let contents = tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| format!("could not read {}: {e}", path.display()))?;
+        Manifest::parse(&contents).map_err(|e| format!("invalid manifest {}: {e}", path.display()))
+    }
+}
+
+struct ManifestState {
+    mode: PersistedQueriesMode,
+    manifest: Arc<RwLock<Arc<Manifest>>>,
+    watcher: Option<JoinHandle<()>>,
+}
+
+impl Drop for ManifestState {
+    fn drop(&mut self) {
+        if let Some(watcher) = self.watcher.take() {
+            watcher.abort();
+        }
+    }
+}
+
+/// [`Layer`] for persisted queries.
+#[derive(Clone)]
+pub(crate) struct PersistedQueryLayer {
+    /// set to None if persisted queries are disabled
+    state: Option<Arc<ManifestState>>,
+}
+
+impl PersistedQueryLayer {
+    pub(crate) async fn new(configuration: &PersistedQueries) -> Self {
+        let path = match (configuration.enabled, configuration.manifest.as_ref()) {
+            (true, Some(path)) => path.clone(),
+            _ => return Self { state: None },
+        };
+
+        // in safelist mode, an unreadable manifest rejects every operation
+        let manifest = Manifest::load(&path).await.unwrap_or_else(|e| {
+            tracing::error!("persisted queries: {e}");
+            Manifest::default()
+        });
+        let manifest = Arc::new(RwLock::new(Arc::new(manifest)));
+        let watcher = tokio::spawn(watch_manifest(path, manifest.clone()));
+
+        Self {
+            state: Some(Arc::new(ManifestState {
+                mode: configuration.mode,
+                manifest,
+                watcher: Some(watcher),
+            })),
+        }
+    }
+
+    #[cfg(test)]
+    fn with_manifest(mode: PersistedQueriesMode, manifest: Manifest) -> Self {
+        Self {
+            state: Some(Arc::new(ManifestState {
+                mode,
+                manifest: Arc::new(RwLock::new(Arc::new(manifest))),
+                watcher: None,
+            })),
+        }
+    }
+
+    pub(crate) fn supergraph_request(
+        &self,
+        mut request: SupergraphRequest,
+    ) -> Result<SupergraphRequest, SupergraphResponse> {
+        let state = match self.state.as_ref() {
+            Some(state) => state,
+            None => return Ok(request),
+        };
+        let manifest = state.manifest.read().expect("lock poisoned").clone();
+
+        let body = request.supergraph_request.body_mut();
+        let id = body
+            .extensions
+            .get("persistedQuery")
+            .and_then(|value| value.as_object())
+            .and_then(|persisted_query| persisted_query.get("sha256Hash"))
+            .and_then(|id| id.as_str())
+            .map(str::to_string);
+
+        if let Some(document) = id.as_ref().and_then(|id| manifest.by_id.get(id)) {
+            // the operation is resolved here, automatic persisted queries must not handle it
+            body.query = Some(document.clone());
+            body.extensions.remove("persistedQuery");
+            let _ = request
+                .context
+                .insert(PERSISTED_QUERY_ID_CONTEXT_KEY, id.unwrap_or_default());
+            return Ok(request);
+        }
+
+        let known = body
+            .query
+            .as_ref()
+            .map(|query| manifest.documents.contains(query));
+        match (state.mode, known) {
+            (_, Some(true)) => Ok(request),
+            (PersistedQueriesMode::Safelist, Some(false)) => {
+                tracing::info!(
+                    monotonic_counter.apollo_router_persisted_queries_rejected_count = 1u64,
+                );
+                Err(error_response(
+                    request,
+                    "operation is not in the persisted query list",
+                    "QUERY_NOT_IN_SAFELIST",
+                ))
+            }
+            // in safelist mode, ids missing from the manifest cannot be resolved by
+            // automatic persisted queries either
+            (PersistedQueriesMode::Safelist, None) if id.is_some() => {
+                tracing::info!(
+                    monotonic_counter.apollo_router_persisted_queries_rejected_count = 1u64,
+                );
+                Err(error_response(
+                    request,
+                    "persisted query not found",
+                    "PERSISTED_QUERY_NOT_IN_LIST",
+                ))
+            }
+            (PersistedQueriesMode::LogUnknown, Some(false)) => {
+                tracing::info!(
+                    monotonic_counter.apollo_router_persisted_queries_unknown_count = 1u64,
+                );
+                tracing::warn!(
+                    operation_name = body.operation_name.as_deref().unwrap_or_default(),
+                    query = body.query.as_deref().unwrap_or_default(),
+                    "persisted queries: operation not in the manifest"
+                );
+                Ok(request)
+            }
+            _ => Ok(request),
+        }
+    }
+}
+
+fn error_response(request: SupergraphRequest, message: &str, code: &str) -> SupergraphResponse {
+    SupergraphResponse::builder()
+        .error(
+            crate::error::Error::builder()
+                .message(message)
+                .extension_code(code)
+                .build(),
+        )
+        .status_code(StatusCode::BAD_REQUEST)
+        .context(request.context)
+        .build()
+        .expect("response is valid")
+}
+
+async fn watch_manifest(path: PathBuf, manifest: Arc<RwLock<Arc<Manifest>>>) {
+    let mut changes = crate::files::watch(&path).boxed();
+    while changes.next().await.is_some() {
+        match Manifest::load(&path).await {
+            Ok(new_manifest) => {
+                tracing::info!(
+                    "persisted queries: reloaded {} operations from {}",
+                    new_manifest.by_id.len(),
+                    path.display()
+                );
+                *manifest.write().expect("lock poisoned") = Arc::new(new_manifest);
+            }
+            // keep the previous manifest
+            Err(e) => tracing::error!("persisted queries: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json_bytes::json;
+
+    use super::*;
+
+    const MANIFEST: &str = r#"{
+        "format": "apollo-persisted-query-manifest",
+        "version": 1,
+        "operations": [
+            {"id": "me", "name": "Me", "type": "query", "body": "query Me { me { name } }"}
+        ]
+    }"#;
+
+    fn layer(mode: PersistedQueriesMode) -> PersistedQueryLayer {
+        PersistedQueryLayer::with_manifest(mode, Manifest::parse(MANIFEST).unwrap())
+    }
+
+    fn request_with_id(id: &str) -> SupergraphRequest {
+        SupergraphRequest::fake_builder()
+            .extension("persistedQuery", json!({"version": 1, "sha256Hash": id}))
+            .build()
+            .unwrap()
+    }
+
+    fn request_with_query(query: &str) -> SupergraphRequest {
+        SupergraphRequest::fake_builder()
+            .query(query)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn it_parses_manifests() {
+        let manifest = Manifest::parse(r#"{"a": "{ me { name } }"}"#).unwrap();
+        assert_eq!(manifest.by_id.get("a").unwrap(), "{ me { name } }");
+        assert!(Manifest::parse(MANIFEST)
+            .unwrap()
+            .documents
+            .contains("query Me { me { name } }"));
+    }
+
+    #[test]
+    fn it_resolves_ids() {
+        let request = layer(PersistedQueriesMode::AllowAll)
+            .supergraph_request(request_with_id("me"))
+            .ok()
+            .unwrap();
+        let body = request.supergraph_request.body();
+        assert_eq!(body.query.as_deref(), Some("query Me { me { name } }"));
+        assert!(!body.extensions.contains_key("persistedQuery"));
+        assert_eq!(
+            request
+                .context
+                .get::<_, String>(PERSISTED_QUERY_ID_CONTEXT_KEY)
+                .unwrap()
+                .unwrap(),
+            "me"
+        );
+    }
+
+    #[test]
+    fn safelist_rejects_unknown_operations() {
+        let layer = layer(PersistedQueriesMode::Safelist);
+
+        assert!(layer
+            .supergraph_request(request_with_query("query Me { me { name } }"))
+            .is_ok());
+        let response = layer
+            .supergraph_request(request_with_query("{ me { id } }"))
+            .err()
+            .unwrap();
+        assert_eq!(response.response.status(), StatusCode::BAD_REQUEST);
+        assert!(layer
+            .supergraph_request(request_with_id("unknown"))
+            .is_err());
+    }
+
+    #[test]
+    fn other_modes_allow_unknown_operations() {
+        for mode in [
+            PersistedQueriesMode::AllowAll,
+            PersistedQueriesMode::LogUnknown,
+        ] {
+            let layer = layer(mode);
+            assert!(layer
+                .supergraph_request(request_with_query("{ me { id } }"))
+                .is_ok());
+            // unknown ids are left to automatic persisted queries
+            let request = layer
+                .supergraph_request(request_with_id("unknown"))
+                .ok()
+                .unwrap();
+            assert!(request
+                .supergraph_request
+                .body()
+                .extensions
+                .contains_key("persistedQuery"));
+        }
+    }
+}
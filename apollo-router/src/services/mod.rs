@@ -24,6 +24,7 @@ pub(crate) use crate::services::supergraph::Request as SupergraphRequest;
 pub(crate) use crate::services::supergraph::Response as SupergraphResponse;
 pub(crate) use crate::services::supergraph_service::SupergraphCreator;
 
+pub(crate) mod client_certificate;
 pub mod execution;
 mod execution_service;
 pub(crate) mod external;
@@ -37,6 +38,7 @@ pub(crate) mod subgraph_service;
 pub mod supergraph;
 mod supergraph_service;
 pub mod transport;
+pub(crate) mod unix_socket;
 
 impl AsRef<Request> for http_ext::Request<Request> {
     fn as_ref(&self) -> &Request {
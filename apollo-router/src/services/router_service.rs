@@ -24,7 +24,6 @@ use http_body::Body as _;
 use hyper::Body;
 use mime::APPLICATION_JSON;
 use multimap::MultiMap;
-use router_bridge::planner::Planner;
 use tower::BoxError;
 use tower::Layer;
 use tower::ServiceBuilder;
@@ -37,6 +36,7 @@ use super::layers::content_negociation;
 use super::layers::content_negociation::ACCEPTS_JSON_CONTEXT_KEY;
 use super::layers::content_negociation::ACCEPTS_MULTIPART_CONTEXT_KEY;
 use super::layers::content_negociation::ACCEPTS_WILDCARD_CONTEXT_KEY;
+use super::layers::persisted_queries::PersistedQueryLayer;
 use super::layers::static_page::StaticPageLayer;
 use super::new_service::ServiceFactory;
 use super::router;
@@ -49,7 +49,7 @@ use crate::cache::DeduplicatingCache;
 use crate::graphql;
 #[cfg(test)]
 use crate::plugin::test::MockSupergraphService;
-use crate::query_planner::QueryPlanResult;
+use crate::query_planner::PlannerPool;
 use crate::router_factory::RouterFactory;
 use crate::services::layers::content_negociation::GRAPHQL_JSON_RESPONSE_HEADER_VALUE;
 use crate::services::RouterRequest;
@@ -68,16 +68,22 @@ where
 {
     supergraph_creator: Arc<SF>,
     apq_layer: APQLayer,
+    persisted_query_layer: PersistedQueryLayer,
 }
 
 impl<SF> RouterService<SF>
 where
     SF: ServiceFactory<supergraph::Request> + Clone + Send + Sync + 'static,
 {
-    pub(crate) fn new(supergraph_creator: Arc<SF>, apq_layer: APQLayer) -> Self {
+    pub(crate) fn new(
+        supergraph_creator: Arc<SF>,
+        apq_layer: APQLayer,
+        persisted_query_layer: PersistedQueryLayer,
+    ) -> Self {
         RouterService {
             supergraph_creator,
             apq_layer,
+            persisted_query_layer,
         }
     }
 }
@@ -179,6 +185,7 @@ where
 
         let supergraph_creator = self.supergraph_creator.clone();
         let apq = self.apq_layer.clone();
+        let persisted_queries = self.persisted_query_layer.clone();
 
         let fut = async move {
             let graphql_request: Result<graphql::Request, (&str, String)> = if parts.method
@@ -225,7 +232,11 @@ where
                         context,
                     };
 
-                    let request_res = apq.supergraph_request(request).await;
+                    // persisted queries are resolved before automatic persisted queries
+                    let request_res = match persisted_queries.supergraph_request(request) {
+                        Ok(request) => apq.supergraph_request(request).await,
+                        Err(response) => Err(response),
+                    };
 
                     let SupergraphResponse { response, context } =
                         match request_res.and_then(|request| {
@@ -427,6 +438,7 @@ where
     supergraph_creator: Arc<SF>,
     static_page: StaticPageLayer,
     apq_layer: APQLayer,
+    persisted_query_layer: PersistedQueryLayer,
 }
 
 impl<SF> ServiceFactory<router::Request> for RouterCreator<SF>
@@ -485,11 +497,14 @@ where
         } else {
             APQLayer::disabled()
         };
+        let persisted_query_layer =
+            PersistedQueryLayer::new(&configuration.preview_persisted_queries).await;
 
         Self {
             supergraph_creator,
             static_page,
             apq_layer,
+            persisted_query_layer,
         }
     }
 
@@ -504,6 +519,7 @@ where
         let router_service = content_negociation::RouterLayer::default().layer(RouterService::new(
             self.supergraph_creator.clone(),
             self.apq_layer.clone(),
+            self.persisted_query_layer.clone(),
         ));
 
         ServiceBuilder::new()
@@ -523,7 +539,7 @@ impl RouterCreator<crate::services::supergraph_service::SupergraphCreator> {
         self.supergraph_creator.cache_keys(count).await
     }
 
-    pub(crate) fn planner(&self) -> Arc<Planner<QueryPlanResult>> {
+    pub(crate) fn planner(&self) -> Arc<PlannerPool> {
         self.supergraph_creator.planner()
     }
 }
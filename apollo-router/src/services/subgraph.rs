@@ -2,8 +2,11 @@
 
 #![allow(missing_docs)] // FIXME
 
+use std::pin::Pin;
 use std::sync::Arc;
 
+use futures::channel::mpsc;
+use futures::Stream;
 use http::StatusCode;
 use serde_json_bytes::ByteString;
 use serde_json_bytes::Map as JsonMap;
@@ -21,6 +24,7 @@ use crate::Context;
 pub type BoxService = tower::util::BoxService<Request, Response, BoxError>;
 pub type BoxCloneService = tower::util::BoxCloneService<Request, Response, BoxError>;
 pub type ServiceResult = Result<Response, BoxError>;
+pub(crate) type SubscriptionStream = Pin<Box<dyn Stream<Item = graphql::Response> + Send>>;
 
 assert_impl_all!(Request: Send);
 #[non_exhaustive]
@@ -35,6 +39,13 @@ pub struct Request {
     pub context: Context,
 }
 
+/// Channel used by the subgraph service to hand over the stream of events of a subscription.
+///
+/// It is stored in the extensions of the subgraph http request, so that plugins rebuilding a
+/// [`Request`] from its parts keep it.
+#[derive(Clone)]
+pub(crate) struct SubscriptionStreamSender(pub(crate) mpsc::Sender<SubscriptionStream>);
+
 #[buildstructor::buildstructor]
 impl Request {
     /// This is the constructor (or builder) to use when constructing a real Request.
@@ -55,6 +66,27 @@ impl Request {
         }
     }
 
+    /// This is the constructor used by the query plan execution for subscriptions: the subgraph
+    /// service sends the stream of subscription events through `subscription_stream`.
+    #[builder(visibility = "pub(crate)")]
+    fn subscription_new(
+        supergraph_request: Arc<http::Request<graphql::Request>>,
+        subgraph_request: http::Request<graphql::Request>,
+        context: Context,
+        subscription_stream: mpsc::Sender<SubscriptionStream>,
+    ) -> Request {
+        let mut subgraph_request = subgraph_request;
+        subgraph_request
+            .extensions_mut()
+            .insert(SubscriptionStreamSender(subscription_stream));
+        Self {
+            supergraph_request,
+            subgraph_request,
+            operation_kind: OperationKind::Subscription,
+            context,
+        }
+    }
+
     /// This is the constructor (or builder) to use when constructing a "fake" Request.
     ///
     /// This does not enforce the provision of the data that is required for a fully functional
@@ -79,7 +111,7 @@ impl Request {
 impl Clone for Request {
     fn clone(&self) -> Self {
         // http::Request is not clonable so we have to rebuild a new one
-        // we don't use the extensions field for now
+        // the only extension we use is the subscription stream sender
         let mut builder = http::Request::builder()
             .method(self.subgraph_request.method())
             .version(self.subgraph_request.version())
@@ -94,6 +126,13 @@ impl Clone for Request {
                     .map(|(name, value)| (name.clone(), value.clone())),
             );
         }
+        if let Some(sender) = self
+            .subgraph_request
+            .extensions()
+            .get::<SubscriptionStreamSender>()
+        {
+            builder = builder.extension(sender.clone());
+        }
         let subgraph_request = builder.body(self.subgraph_request.body().clone()).unwrap();
 
         Self {
@@ -105,6 +144,16 @@ impl Clone for Request {
     }
 }
 
+impl Request {
+    /// Channel through which the subgraph service sends the stream of events of a subscription
+    pub(crate) fn subscription_stream(&self) -> Option<mpsc::Sender<SubscriptionStream>> {
+        self.subgraph_request
+            .extensions()
+            .get::<SubscriptionStreamSender>()
+            .map(|sender| sender.0.clone())
+    }
+}
+
 assert_impl_all!(Response: Send);
 #[derive(Debug)]
 #[non_exhaustive]
@@ -212,3 +261,34 @@ impl Response {
         ))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rebuilt_requests_keep_the_subscription_stream() {
+        let (sender, _receiver) = mpsc::channel(1);
+        let request = Request::subscription_builder()
+            .supergraph_request(Default::default())
+            .subgraph_request(Default::default())
+            .context(Context::new())
+            .subscription_stream(sender)
+            .build();
+        assert!(request.clone().subscription_stream().is_some());
+
+        // like a plugin rebuilding the request from its parts
+        let request = Request::builder()
+            .supergraph_request(request.supergraph_request)
+            .subgraph_request(request.subgraph_request)
+            .operation_kind(request.operation_kind)
+            .context(request.context)
+            .build();
+        assert!(request.subscription_stream().is_some());
+
+        assert!(Request::fake_builder()
+            .build()
+            .subscription_stream()
+            .is_none());
+    }
+}
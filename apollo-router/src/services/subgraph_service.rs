@@ -14,6 +14,7 @@ use async_compression::tokio::write::BrotliEncoder;
 use async_compression::tokio::write::GzipEncoder;
 use async_compression::tokio::write::ZlibEncoder;
 use futures::future::BoxFuture;
+use futures::SinkExt;
 use global::get_text_map_propagator;
 use http::header::ACCEPT;
 use http::header::ACCEPT_ENCODING;
@@ -22,6 +23,7 @@ use http::header::CONTENT_TYPE;
 use http::header::{self};
 use http::HeaderMap;
 use http::HeaderValue;
+use http::Uri;
 use hyper::client::HttpConnector;
 use hyper::Client;
 use hyper_rustls::ConfigBuilderExt;
@@ -31,6 +33,7 @@ use opentelemetry::global;
 use rustls::RootCertStore;
 use schemars::JsonSchema;
 use tokio::io::AsyncWriteExt;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
 use tower::util::BoxService;
 use tower::BoxError;
 use tower::Service;
@@ -41,12 +44,18 @@ use tower_http::decompression::DecompressionLayer;
 use tracing::Instrument;
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
+use super::client_certificate::ClientCertificateResolver;
 use super::layers::content_negociation::GRAPHQL_JSON_RESPONSE_HEADER_VALUE;
+use super::unix_socket;
+use super::unix_socket::UnixSocketConnector;
 use super::Plugins;
 use crate::error::FetchError;
 use crate::graphql;
+use crate::plugins::subscription::WebSocketConfiguration;
 use crate::plugins::telemetry::LOGGING_DISPLAY_BODY;
 use crate::plugins::telemetry::LOGGING_DISPLAY_HEADERS;
+use crate::protocols::websocket;
+use crate::query_planner::OperationKind;
 use crate::services::layers::apq;
 use crate::services::SubgraphRequest;
 use crate::services::SubgraphResponse;
@@ -98,7 +107,7 @@ pub(crate) struct SubgraphService {
     // Note: We use hyper::Client here in preference to reqwest to avoid expensive URL translation
     // in the hot path. We use reqwest elsewhere because it's convenient and some of the
     // opentelemetry crate require reqwest clients to work correctly (at time of writing).
-    client: Decompression<hyper::Client<HttpsConnector<HttpConnector>>>,
+    client: Decompression<hyper::Client<UnixSocketConnector<HttpsConnector<HttpConnector>>>>,
     service: Arc<String>,
 
     /// Whether apq is enabled in the router for subgraph calls
@@ -108,6 +117,13 @@ pub(crate) struct SubgraphService {
     /// If a subgraph sends the error message PERSISTED_QUERY_NOT_SUPPORTED,
     /// apq is set to false
     apq: Arc<AtomicBool>,
+
+    /// WebSocket configuration used to forward subscriptions to this subgraph,
+    /// set if subscriptions are enabled for it
+    subscription_config: Option<Arc<WebSocketConfiguration>>,
+
+    /// TLS configuration of WebSocket connections, the same as for HTTP requests
+    websocket_tls_config: Arc<rustls::ClientConfig>,
 }
 
 impl SubgraphService {
@@ -116,31 +132,39 @@ impl SubgraphService {
         enable_apq: bool,
         tls_cert_store: Option<RootCertStore>,
         enable_http2: bool,
+        subscription_config: Option<WebSocketConfiguration>,
     ) -> Self {
+        Self::from_tls_config(
+            service,
+            enable_apq,
+            generate_tls_client_config(tls_cert_store, None),
+            enable_http2,
+            subscription_config,
+        )
+    }
+
+    pub(crate) fn from_tls_config(
+        service: impl Into<String>,
+        enable_apq: bool,
+        tls_config: rustls::ClientConfig,
+        enable_http2: bool,
+        subscription_config: Option<WebSocketConfiguration>,
+    ) -> Self {
+        let websocket_tls_config = Arc::new(tls_config.clone());
         let mut http_connector = HttpConnector::new();
         http_connector.set_nodelay(true);
         http_connector.set_keepalive(Some(std::time::Duration::from_secs(60)));
         http_connector.enforce_http(false);
-        let tls_config = match tls_cert_store {
-            None => rustls::ClientConfig::builder()
-                .with_safe_defaults()
-                .with_native_roots()
-                .with_no_client_auth(),
-            Some(store) => rustls::ClientConfig::builder()
-                .with_safe_defaults()
-                .with_root_certificates(store)
-                .with_no_client_auth(),
-        };
         let builder = hyper_rustls::HttpsConnectorBuilder::new()
             .with_tls_config(tls_config)
             .https_or_http()
             .enable_http1();
 
-        let connector = if enable_http2 {
+        let connector = UnixSocketConnector::new(if enable_http2 {
             builder.enable_http2().wrap_connector(http_connector)
         } else {
             builder.wrap_connector(http_connector)
-        };
+        });
 
         Self {
             client: ServiceBuilder::new()
@@ -148,10 +172,32 @@ impl SubgraphService {
                 .service(hyper::Client::builder().build(connector)),
             service: Arc::new(service.into()),
             apq: Arc::new(<AtomicBool>::new(enable_apq)),
+            subscription_config: subscription_config.map(Arc::new),
+            websocket_tls_config,
         }
     }
 }
 
+/// TLS configuration of subgraph connections, with the router's client certificate if the
+/// subgraph requires client authentication
+pub(crate) fn generate_tls_client_config(
+    tls_cert_store: Option<RootCertStore>,
+    client_certificate: Option<Arc<ClientCertificateResolver>>,
+) -> rustls::ClientConfig {
+    let builder = match tls_cert_store {
+        None => rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_native_roots(),
+        Some(store) => rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(store),
+    };
+    match client_certificate {
+        Some(resolver) => builder.with_client_cert_resolver(resolver),
+        None => builder.with_no_client_auth(),
+    }
+}
+
 impl tower::Service<SubgraphRequest> for SubgraphService {
     type Response = SubgraphResponse;
     type Error = BoxError;
@@ -176,6 +222,24 @@ impl tower::Service<SubgraphRequest> for SubgraphService {
         let client = std::mem::replace(&mut self.client, clone);
         let service_name = (*self.service).to_owned();
 
+        if request.operation_kind == OperationKind::Subscription {
+            let subscription_config = self.subscription_config.clone();
+            let tls_config = self.websocket_tls_config.clone();
+            return Box::pin(async move {
+                match subscription_config {
+                    Some(config) => {
+                        call_websocket(request, body, context, service_name, &config, tls_config)
+                            .await
+                    }
+                    None => Err(FetchError::SubrequestWsError {
+                        service: service_name,
+                        reason: "subscriptions are not enabled for this subgraph".to_string(),
+                    }
+                    .into()),
+                }
+            });
+        }
+
         let arc_apq_enabled = self.apq.clone();
 
         let make_calls = async move {
@@ -244,12 +308,152 @@ impl tower::Service<SubgraphRequest> for SubgraphService {
     }
 }
 
+/// call_websocket opens a WebSocket connection to the subgraph for a subscription, and sends
+/// the stream of events through the request's `subscription_stream`
+async fn call_websocket(
+    request: SubgraphRequest,
+    body: graphql::Request,
+    context: Context,
+    service_name: String,
+    subscription_config: &WebSocketConfiguration,
+    tls_config: Arc<rustls::ClientConfig>,
+) -> Result<SubgraphResponse, BoxError> {
+    let subscription_stream = request.subscription_stream();
+    let SubgraphRequest {
+        subgraph_request, ..
+    } = request;
+
+    let mut subscription_stream =
+        subscription_stream.ok_or_else(|| FetchError::SubrequestWsError {
+            service: service_name.clone(),
+            reason: "cannot forward a subscription without a subscription stream".to_string(),
+        })?;
+
+    let operation_name = body.operation_name.clone().unwrap_or_default();
+    let (parts, _) = subgraph_request.into_parts();
+    let mut request =
+        websocket_request(&parts.uri, &parts.headers, subscription_config).map_err(|reason| {
+            FetchError::SubrequestWsError {
+                service: service_name.clone(),
+                reason,
+            }
+        })?;
+
+    let uri = request.uri().clone();
+    let subgraph_req_span = tracing::info_span!("subgraph_request",
+        "otel.kind" = "CLIENT",
+        "net.peer.name" = %uri.host().unwrap_or_default(),
+        "http.route" = %uri.path(),
+        "http.url" = %uri,
+        "net.transport" = "ip_tcp",
+        "apollo.subgraph.name" = %service_name,
+        "graphql.operation.name" = %operation_name,
+    );
+    get_text_map_propagator(|propagator| {
+        propagator.inject_context(
+            &subgraph_req_span.context(),
+            &mut opentelemetry_http::HeaderInjector(request.headers_mut()),
+        );
+    });
+
+    if context.contains_key(LOGGING_DISPLAY_HEADERS) {
+        tracing::info!(http.request.headers = ?request.headers(), apollo.subgraph.name = %service_name, "Websocket request headers to subgraph {service_name:?}");
+    }
+
+    // wss connections use the certificate authorities and client certificate configured for
+    // the subgraph, like http requests
+    let (socket, response) = tokio_tungstenite::connect_async_tls_with_config(
+        request,
+        None,
+        Some(tokio_tungstenite::Connector::Rustls(tls_config)),
+    )
+    .instrument(subgraph_req_span.clone())
+    .await
+    .map_err(|e| FetchError::SubrequestWsError {
+        service: service_name.clone(),
+        reason: format!("cannot connect websocket on subgraph: {e}"),
+    })?;
+
+    let stream = websocket::subscribe(socket, &service_name, body, None)
+        .instrument(subgraph_req_span)
+        .await?;
+
+    subscription_stream
+        .send(Box::pin(stream))
+        .await
+        .map_err(|e| FetchError::SubrequestWsError {
+            service: service_name.clone(),
+            reason: format!("cannot send the subscription stream: {e}"),
+        })?;
+
+    let (parts, _) = response.into_parts();
+    let resp = http::Response::from_parts(parts, graphql::Response::default());
+
+    Ok(SubgraphResponse::new_from_response(resp, context))
+}
+
+/// Builds the WebSocket handshake request from the subgraph http request
+fn websocket_request(
+    uri: &Uri,
+    headers: &HeaderMap,
+    subscription_config: &WebSocketConfiguration,
+) -> Result<http::Request<()>, String> {
+    let mut uri_parts = uri.clone().into_parts();
+    uri_parts.scheme = Some(
+        match uri.scheme_str() {
+            Some("https") | Some("wss") => "wss",
+            _ => "ws",
+        }
+        .parse()
+        .expect("ws and wss are valid schemes; qed"),
+    );
+    if let Some(path) = &subscription_config.path {
+        uri_parts.path_and_query = Some(
+            path.parse()
+                .map_err(|e| format!("invalid websocket path '{path}': {e}"))?,
+        );
+    }
+    let uri = Uri::from_parts(uri_parts).map_err(|e| format!("invalid websocket url: {e}"))?;
+
+    let mut request = uri
+        .into_client_request()
+        .map_err(|e| format!("invalid websocket request: {e}"))?;
+
+    // headers set by the plugins (like the headers plugin) are forwarded, except
+    // those describing the http body or the websocket handshake itself
+    for (name, value) in headers {
+        if ![
+            header::HOST,
+            header::CONTENT_LENGTH,
+            header::CONTENT_TYPE,
+            header::CONTENT_ENCODING,
+            header::ACCEPT,
+            header::ACCEPT_ENCODING,
+            header::CONNECTION,
+            header::UPGRADE,
+            header::SEC_WEBSOCKET_KEY,
+            header::SEC_WEBSOCKET_VERSION,
+            header::SEC_WEBSOCKET_PROTOCOL,
+        ]
+        .contains(name)
+        {
+            request.headers_mut().append(name.clone(), value.clone());
+        }
+    }
+    request.headers_mut().insert(
+        header::SEC_WEBSOCKET_PROTOCOL,
+        HeaderValue::from_static(subscription_config.protocol.as_str()),
+    );
+
+    Ok(request)
+}
+
 /// call_http makes http calls with modified graphql::Request (body)
 async fn call_http(
     request: SubgraphRequest,
     body: graphql::Request,
     context: Context,
-    mut client: Decompression<Client<HttpsConnector<HttpConnector>>>,
+    mut client: Decompression<Client<UnixSocketConnector<HttpsConnector<HttpConnector>>>>,
     service_name: String,
 ) -> Result<SubgraphResponse, BoxError> {
     let SubgraphRequest {
@@ -288,7 +492,11 @@ async fn call_http(
         .insert(ACCEPT_ENCODING, ACCEPTED_ENCODINGS);
 
     let schema_uri = request.uri();
-    let host = schema_uri.host().unwrap_or_default();
+    let socket_path = unix_socket::socket_path(schema_uri);
+    let host = match &socket_path {
+        Some(socket_path) => socket_path.as_str(),
+        None => schema_uri.host().unwrap_or_default(),
+    };
     let port = schema_uri.port_u16().unwrap_or_else(|| {
         let scheme = schema_uri.scheme_str();
         if scheme == Some("https") {
@@ -316,7 +524,7 @@ async fn call_http(
         "net.peer.port" = %port,
         "http.route" = %path,
         "http.url" = %schema_uri,
-        "net.transport" = "ip_tcp",
+        "net.transport" = if socket_path.is_some() { "unix" } else { "ip_tcp" },
         "apollo.subgraph.name" = %service_name,
         "graphql.operation.name" = %operation_name,
     );
@@ -987,7 +1195,7 @@ mod tests {
     async fn test_bad_status_code_should_not_fail() {
         let socket_addr = SocketAddr::from_str("127.0.0.1:2626").unwrap();
         tokio::task::spawn(emulate_subgraph_bad_request(socket_addr));
-        let subgraph_service = SubgraphService::new("test", true, None, true);
+        let subgraph_service = SubgraphService::new("test", true, None, true, None);
 
         let url = Uri::from_str(&format!("http://{socket_addr}")).unwrap();
         let response = subgraph_service
@@ -1021,7 +1229,7 @@ mod tests {
         let socket_addr = SocketAddr::from_str("127.0.0.1:2525").unwrap();
         tokio::task::spawn(emulate_subgraph_bad_response_format(socket_addr));
 
-        let subgraph_service = SubgraphService::new("test", true, None, true);
+        let subgraph_service = SubgraphService::new("test", true, None, true, None);
 
         let url = Uri::from_str(&format!("http://{socket_addr}")).unwrap();
         let err = subgraph_service
@@ -1054,7 +1262,7 @@ mod tests {
     async fn test_compressed_request_response_body() {
         let socket_addr = SocketAddr::from_str("127.0.0.1:2727").unwrap();
         tokio::task::spawn(emulate_subgraph_compressed_response(socket_addr));
-        let subgraph_service = SubgraphService::new("test", false, None, true);
+        let subgraph_service = SubgraphService::new("test", false, None, true, None);
 
         let url = Uri::from_str(&format!("http://{socket_addr}")).unwrap();
         let resp = subgraph_service
@@ -1091,7 +1299,7 @@ mod tests {
     async fn test_unauthorized() {
         let socket_addr = SocketAddr::from_str("127.0.0.1:2828").unwrap();
         tokio::task::spawn(emulate_subgraph_unauthorized(socket_addr));
-        let subgraph_service = SubgraphService::new("test", true, None, true);
+        let subgraph_service = SubgraphService::new("test", true, None, true, None);
 
         let url = Uri::from_str(&format!("http://{socket_addr}")).unwrap();
         let err = subgraph_service
@@ -1124,7 +1332,7 @@ mod tests {
     async fn test_persisted_query_not_supported_message() {
         let socket_addr = SocketAddr::from_str("127.0.0.1:2929").unwrap();
         tokio::task::spawn(emulate_persisted_query_not_supported_message(socket_addr));
-        let subgraph_service = SubgraphService::new("test", true, None, true);
+        let subgraph_service = SubgraphService::new("test", true, None, true, None);
 
         assert!(subgraph_service.clone().apq.as_ref().load(Relaxed));
 
@@ -1166,7 +1374,7 @@ mod tests {
         tokio::task::spawn(emulate_persisted_query_not_supported_extension_code(
             socket_addr,
         ));
-        let subgraph_service = SubgraphService::new("test", true, None, true);
+        let subgraph_service = SubgraphService::new("test", true, None, true, None);
 
         assert!(subgraph_service.clone().apq.as_ref().load(Relaxed));
 
@@ -1206,7 +1414,7 @@ mod tests {
     async fn test_persisted_query_not_found_message() {
         let socket_addr = SocketAddr::from_str("127.0.0.1:3131").unwrap();
         tokio::task::spawn(emulate_persisted_query_not_found_message(socket_addr));
-        let subgraph_service = SubgraphService::new("test", true, None, true);
+        let subgraph_service = SubgraphService::new("test", true, None, true, None);
 
         let url = Uri::from_str(&format!("http://{socket_addr}")).unwrap();
         let resp = subgraph_service
@@ -1245,7 +1453,7 @@ mod tests {
         tokio::task::spawn(emulate_persisted_query_not_found_extension_code(
             socket_addr,
         ));
-        let subgraph_service = SubgraphService::new("test", true, None, true);
+        let subgraph_service = SubgraphService::new("test", true, None, true, None);
 
         let url = Uri::from_str(&format!("http://{socket_addr}")).unwrap();
         let resp = subgraph_service
@@ -1282,7 +1490,7 @@ mod tests {
     async fn test_apq_enabled_subgraph_configuration() {
         let socket_addr = SocketAddr::from_str("127.0.0.1:3333").unwrap();
         tokio::task::spawn(emulate_expected_apq_enabled_configuration(socket_addr));
-        let subgraph_service = SubgraphService::new("test", true, None, true);
+        let subgraph_service = SubgraphService::new("test", true, None, true, None);
 
         let url = Uri::from_str(&format!("http://{socket_addr}")).unwrap();
         let resp = subgraph_service
@@ -1319,7 +1527,7 @@ mod tests {
     async fn test_apq_disabled_subgraph_configuration() {
         let socket_addr = SocketAddr::from_str("127.0.0.1:3434").unwrap();
         tokio::task::spawn(emulate_expected_apq_disabled_configuration(socket_addr));
-        let subgraph_service = SubgraphService::new("test", false, None, true);
+        let subgraph_service = SubgraphService::new("test", false, None, true, None);
 
         let url = Uri::from_str(&format!("http://{socket_addr}")).unwrap();
         let resp = subgraph_service
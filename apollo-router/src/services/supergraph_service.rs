@@ -2,6 +2,7 @@
 
 //! Implements the router phase of the request lifecycle.
 
+use std::ops::Deref;
 use std::sync::Arc;
 use std::task::Poll;
 
@@ -11,7 +12,6 @@ use futures::TryFutureExt;
 use http::StatusCode;
 use indexmap::IndexMap;
 use multimap::MultiMap;
-use router_bridge::planner::Planner;
 use tower::util::Either;
 use tower::BoxError;
 use tower::ServiceBuilder;
@@ -27,6 +27,7 @@ use super::subgraph_service::SubgraphServiceFactory;
 use super::ExecutionServiceFactory;
 use super::QueryPlannerContent;
 use crate::error::CacheResolverError;
+use crate::error::QueryPlannerError;
 use crate::graphql;
 use crate::graphql::IntoGraphQLErrors;
 #[cfg(test)]
@@ -37,7 +38,7 @@ use crate::plugins::traffic_shaping::TrafficShaping;
 use crate::plugins::traffic_shaping::APOLLO_TRAFFIC_SHAPING;
 use crate::query_planner::BridgeQueryPlanner;
 use crate::query_planner::CachingQueryPlanner;
-use crate::query_planner::QueryPlanResult;
+use crate::query_planner::PlannerPool;
 use crate::services::supergraph;
 use crate::services::ExecutionRequest;
 use crate::services::ExecutionResponse;
@@ -45,6 +46,9 @@ use crate::services::QueryPlannerRequest;
 use crate::services::QueryPlannerResponse;
 use crate::services::SupergraphRequest;
 use crate::services::SupergraphResponse;
+use crate::spec::operation_limits::ExceededLimit;
+use crate::spec::operation_limits::OperationMeasurements;
+use crate::spec::Query;
 use crate::spec::Schema;
 use crate::Configuration;
 use crate::Context;
@@ -62,6 +66,7 @@ pub(crate) struct SupergraphService {
     execution_service_factory: ExecutionServiceFactory,
     query_planner_service: CachingQueryPlanner<BridgeQueryPlanner>,
     schema: Arc<Schema>,
+    configuration: Arc<Configuration>,
 }
 
 #[buildstructor::buildstructor]
@@ -71,11 +76,13 @@ impl SupergraphService {
         query_planner_service: CachingQueryPlanner<BridgeQueryPlanner>,
         execution_service_factory: ExecutionServiceFactory,
         schema: Arc<Schema>,
+        configuration: Arc<Configuration>,
     ) -> Self {
         SupergraphService {
             query_planner_service,
             execution_service_factory,
             schema,
+            configuration,
         }
     }
 }
@@ -99,10 +106,11 @@ impl Service<SupergraphRequest> for SupergraphService {
         let execution = self.execution_service_factory.create();
 
         let schema = self.schema.clone();
+        let configuration = self.configuration.clone();
 
         let context_cloned = req.context.clone();
-        let fut =
-            service_call(planning, execution, schema, req).or_else(|error: BoxError| async move {
+        let fut = service_call(planning, execution, schema, configuration, req).or_else(
+            |error: BoxError| async move {
                 let errors = vec![crate::error::Error {
                     message: error.to_string(),
                     extensions: serde_json_bytes::json!({
@@ -120,7 +128,8 @@ impl Service<SupergraphRequest> for SupergraphService {
                     .context(context_cloned)
                     .build()
                     .expect("building a response like this should not fail"))
-            });
+            },
+        );
 
         Box::pin(fut)
     }
@@ -130,6 +139,7 @@ async fn service_call<ExecutionService>(
     planning: CachingQueryPlanner<BridgeQueryPlanner>,
     execution: ExecutionService,
     schema: Arc<Schema>,
+    configuration: Arc<Configuration>,
     req: SupergraphRequest,
 ) -> Result<SupergraphResponse, BoxError>
 where
@@ -139,23 +149,43 @@ where
     let context = req.context;
     let body = req.supergraph_request.body();
     let variables = body.variables.clone();
+
+    let errors = check_operation_limits(&configuration, &schema, body).await;
+    if !errors.is_empty() {
+        return Ok(SupergraphResponse::builder()
+            .context(context)
+            .errors(errors)
+            .status_code(StatusCode::BAD_REQUEST)
+            .build()
+            .expect("this response build must not fail"));
+    }
+
     let QueryPlannerResponse {
         content,
         context,
         errors,
     } = match plan_query(planning, body, context.clone()).await {
         Ok(resp) => resp,
-        Err(err) => match err.into_graphql_errors() {
-            Ok(gql_errors) => {
-                return Ok(SupergraphResponse::builder()
-                    .context(context)
-                    .errors(gql_errors)
-                    .status_code(StatusCode::BAD_REQUEST) // If it's a graphql error we return a status code 400
-                    .build()
-                    .expect("this response build must not fail"));
+        Err(err) => {
+            let CacheResolverError::RetrievalError(planner_error) = &err;
+            let status_code = match planner_error.deref() {
+                QueryPlannerError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+                QueryPlannerError::Overloaded => StatusCode::SERVICE_UNAVAILABLE,
+                // If it's a graphql error we return a status code 400
+                _ => StatusCode::BAD_REQUEST,
+            };
+            match err.into_graphql_errors() {
+                Ok(gql_errors) => {
+                    return Ok(SupergraphResponse::builder()
+                        .context(context)
+                        .errors(gql_errors)
+                        .status_code(status_code)
+                        .build()
+                        .expect("this response build must not fail"));
+                }
+                Err(err) => return Err(err.into()),
             }
-            Err(err) => return Err(err.into()),
-        },
+        }
     };
 
     if !errors.is_empty() {
@@ -188,6 +218,7 @@ where
         Some(QueryPlannerContent::Plan { plan }) => {
             let operation_name = body.operation_name.clone();
             let is_deferred = plan.is_deferred(operation_name.as_deref(), &variables);
+            let is_subscription = plan.is_subscription();
 
             let accepts_multipart: bool = context
                 .get(ACCEPTS_MULTIPART_CONTEXT_KEY)
@@ -203,6 +234,15 @@ where
                     .build(), context);
                 *response.response.status_mut() = StatusCode::NOT_ACCEPTABLE;
                 Ok(response)
+            } else if is_subscription && !accepts_multipart {
+                let mut response = SupergraphResponse::new_from_graphql_response(graphql::Response::builder()
+                    .errors(vec![crate::error::Error::builder()
+                        .message(String::from("the router received a subscription but the client does not accept multipart/mixed HTTP responses. To enable subscription support, add the HTTP header 'Accept: multipart/mixed; deferSpec=20220824'"))
+                        .extension_code("SUBSCRIPTION_BAD_HEADER")
+                        .build()])
+                    .build(), context);
+                *response.response.status_mut() = StatusCode::NOT_ACCEPTABLE;
+                Ok(response)
             } else if let Some(err) = plan.query.validate_variables(body, &schema).err() {
                 let mut res = SupergraphResponse::new_from_graphql_response(err, context);
                 *res.response.status_mut() = StatusCode::BAD_REQUEST;
@@ -235,6 +275,74 @@ where
     }
 }
 
+/// Returns the errors to send back if the operation exceeds the configured limits. The
+/// operation is measured before it reaches the query planner, so that rejected operations are
+/// never planned. In warn only mode, violations are recorded but no error is returned
+async fn check_operation_limits(
+    configuration: &Arc<Configuration>,
+    schema: &Arc<Schema>,
+    body: &graphql::Request,
+) -> Vec<crate::error::Error> {
+    let limits = &configuration.preview_operation_limits;
+    if !limits.is_enabled() {
+        return Vec::new();
+    }
+    let query = match body.query.clone() {
+        Some(query) => query,
+        None => return Vec::new(),
+    };
+    let operation_name = body.operation_name.clone();
+    let configuration_cloned = configuration.clone();
+    let schema = schema.clone();
+
+    // parsing and validation errors are reported by the query planner
+    let measurements = match tokio::task::spawn_blocking(move || {
+        Query::parse(query, &schema, &configuration_cloned)
+            .ok()
+            .and_then(|query| {
+                OperationMeasurements::measure(
+                    &query,
+                    operation_name.as_deref(),
+                    &configuration_cloned.preview_operation_limits,
+                )
+            })
+    })
+    .await
+    {
+        Ok(Some(measurements)) => measurements,
+        _ => return Vec::new(),
+    };
+
+    let exceeded = measurements.exceeded(limits);
+    for ExceededLimit { kind, measured, .. } in &exceeded {
+        tracing::info!(
+            monotonic_counter.apollo_router_operation_limits_exceeded_count = 1u64,
+            limit = kind.name(),
+            warn_only = limits.warn_only,
+        );
+        tracing::Span::current().record(kind.span_attribute(), *measured as i64);
+    }
+
+    if limits.warn_only {
+        return Vec::new();
+    }
+
+    exceeded
+        .into_iter()
+        .map(|exceeded| {
+            crate::error::Error::builder()
+                .message(format!(
+                    "the operation exceeds the {} limit: {} > {}",
+                    exceeded.kind.name(),
+                    exceeded.measured,
+                    exceeded.limit
+                ))
+                .extension_code(exceeded.kind.error_code())
+                .build()
+        })
+        .collect()
+}
+
 async fn plan_query(
     mut planning: CachingQueryPlanner<BridgeQueryPlanner>,
     body: &graphql::Request,
@@ -344,6 +452,7 @@ impl PluggableSupergraphServiceBuilder {
             subgraph_service_factory,
             schema,
             plugins,
+            configuration,
         })
     }
 }
@@ -377,6 +486,7 @@ pub(crate) struct SupergraphCreator {
     subgraph_service_factory: Arc<SubgraphServiceFactory>,
     schema: Arc<Schema>,
     plugins: Arc<Plugins>,
+    configuration: Arc<Configuration>,
 }
 
 pub(crate) trait HasPlugins {
@@ -413,6 +523,7 @@ impl SupergraphCreator {
                 subgraph_service_factory: self.subgraph_service_factory.clone(),
             })
             .schema(self.schema.clone())
+            .configuration(self.configuration.clone())
             .build();
 
         let supergraph_service = match self
@@ -441,7 +552,7 @@ impl SupergraphCreator {
         self.query_planner_service.cache_keys(count).await
     }
 
-    pub(crate) fn planner(&self) -> Arc<Planner<QueryPlanResult>> {
+    pub(crate) fn planner(&self) -> Arc<PlannerPool> {
         self.query_planner_service.planner()
     }
 
@@ -2346,4 +2457,226 @@ mod tests {
 
         insta::assert_json_snapshot!(stream.next_response().await.unwrap());
     }
+
+    const SUBSCRIPTION_SCHEMA: &str = r#"
+        schema
+          @link(url: "https://specs.apollo.dev/link/v1.0")
+          @link(url: "https://specs.apollo.dev/join/v0.2", for: EXECUTION)
+        {
+          query: Query
+          subscription: Subscription
+        }
+
+        directive @join__field(graph: join__Graph!, requires: join__FieldSet, provides: join__FieldSet, type: String, external: Boolean, override: String, usedOverridden: Boolean) repeatable on FIELD_DEFINITION | INPUT_FIELD_DEFINITION
+        directive @join__graph(name: String!, url: String!) on ENUM_VALUE
+        directive @join__implements(graph: join__Graph!, interface: String!) repeatable on OBJECT | INTERFACE
+        directive @join__type(graph: join__Graph!, key: join__FieldSet, extension: Boolean! = false, resolvable: Boolean! = true) repeatable on OBJECT | INTERFACE | UNION | ENUM | INPUT_OBJECT | SCALAR
+        directive @link(url: String, as: String, for: link__Purpose, import: [link__Import]) repeatable on SCHEMA
+
+        scalar link__Import
+        enum link__Purpose {
+          SECURITY
+          EXECUTION
+        }
+
+        scalar join__FieldSet
+
+        enum join__Graph {
+          USER @join__graph(name: "user", url: "http://localhost:4001/")
+        }
+
+        type Query
+          @join__type(graph: USER)
+        {
+          currentUser: User
+        }
+
+        type Subscription
+          @join__type(graph: USER)
+        {
+          userWasCreated: User
+        }
+
+        type User
+          @join__type(graph: USER, key: "id")
+        {
+          id: ID!
+          name: String
+        }"#;
+
+    #[tokio::test]
+    async fn subscription_streams_events() {
+        let subgraphs = MockedSubgraphs(
+            [(
+                "user",
+                MockSubgraph::builder()
+                    .with_subscription_json(
+                        serde_json::json! {{"query":"subscription{userWasCreated{name}}"}},
+                        serde_json::json! {[
+                            {"data": {"userWasCreated": { "name": "Ada" }}},
+                            {"data": {"userWasCreated": { "name": "Grace" }}}
+                        ]},
+                    )
+                    .build(),
+            )]
+            .into_iter()
+            .collect(),
+        );
+
+        let service = TestHarness::builder()
+            .configuration_json(serde_json::json!({
+                "include_subgraph_errors": { "all": true },
+                "subscription": { "enabled": true }
+            }))
+            .unwrap()
+            .schema(SUBSCRIPTION_SCHEMA)
+            .extra_plugin(subgraphs)
+            .build_supergraph()
+            .await
+            .unwrap();
+
+        let request = supergraph::Request::fake_builder()
+            .context(defer_context())
+            .query("subscription { userWasCreated { name } }")
+            .build()
+            .unwrap();
+
+        let mut stream = service.oneshot(request).await.unwrap();
+
+        let first = stream.next_response().await.unwrap();
+        assert_eq!(
+            first.data,
+            Some(serde_json_bytes::json!({"userWasCreated": { "name": "Ada" }}))
+        );
+        assert_eq!(first.has_next, Some(true));
+
+        let second = stream.next_response().await.unwrap();
+        assert_eq!(
+            second.data,
+            Some(serde_json_bytes::json!({"userWasCreated": { "name": "Grace" }}))
+        );
+        assert_eq!(second.has_next, Some(true));
+
+        let last = stream.next_response().await.unwrap();
+        assert_eq!(last.has_next, Some(false));
+        assert!(stream.next_response().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn subscription_requires_multipart() {
+        let service = TestHarness::builder()
+            .configuration_json(serde_json::json!({
+                "subscription": { "enabled": true }
+            }))
+            .unwrap()
+            .schema(SUBSCRIPTION_SCHEMA)
+            .extra_plugin(MockedSubgraphs(Default::default()))
+            .build_supergraph()
+            .await
+            .unwrap();
+
+        let request = supergraph::Request::fake_builder()
+            .query("subscription { userWasCreated { name } }")
+            .build()
+            .unwrap();
+
+        let mut response = service.oneshot(request).await.unwrap();
+        assert_eq!(response.response.status(), StatusCode::NOT_ACCEPTABLE);
+        let response = response.next_response().await.unwrap();
+        assert_eq!(
+            response.errors[0].extensions.get("code").unwrap(),
+            "SUBSCRIPTION_BAD_HEADER"
+        );
+    }
+
+    #[tokio::test]
+    async fn subscription_disabled() {
+        let service = TestHarness::builder()
+            .schema(SUBSCRIPTION_SCHEMA)
+            .extra_plugin(MockedSubgraphs(Default::default()))
+            .build_supergraph()
+            .await
+            .unwrap();
+
+        let request = supergraph::Request::fake_builder()
+            .context(defer_context())
+            .query("subscription { userWasCreated { name } }")
+            .build()
+            .unwrap();
+
+        let response = service
+            .oneshot(request)
+            .await
+            .unwrap()
+            .next_response()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.errors[0].extensions.get("code").unwrap(),
+            "SUBSCRIPTION_NOT_SUPPORTED"
+        );
+    }
+
+    #[tokio::test]
+    async fn operation_limits() {
+        let service = |warn_only: bool| async move {
+            let subgraphs = MockedSubgraphs([
+                ("user", MockSubgraph::builder().with_json(
+                    serde_json::json!{{"query":"{currentUser{activeOrganization{__typename id}}}"}},
+                    serde_json::json!{{"data": {"currentUser": { "activeOrganization": { "__typename": "Organization", "id": "0" } }}}}
+                ).build()),
+                ("orga", MockSubgraph::default()),
+            ].into_iter().collect());
+
+            TestHarness::builder()
+                .configuration_json(serde_json::json!({
+                    "preview_operation_limits": {
+                        "max_depth": 1,
+                        "warn_only": warn_only
+                    }
+                }))
+                .unwrap()
+                .schema(SCHEMA)
+                .extra_plugin(subgraphs)
+                .build_supergraph()
+                .await
+                .unwrap()
+        };
+
+        let request = || {
+            supergraph::Request::fake_builder()
+                .query("query { currentUser { activeOrganization { id } } }")
+                .build()
+                .unwrap()
+        };
+
+        let response = service(false)
+            .await
+            .oneshot(request())
+            .await
+            .unwrap()
+            .next_response()
+            .await
+            .unwrap();
+        assert!(response.data.is_none());
+        assert_eq!(response.errors.len(), 1);
+        assert_eq!(
+            response.errors[0].extensions.get("code").unwrap(),
+            "MAX_DEPTH_LIMIT"
+        );
+
+        let response = service(true)
+            .await
+            .oneshot(request())
+            .await
+            .unwrap()
+            .next_response()
+            .await
+            .unwrap();
+        assert!(response.errors.iter().all(|error| error
+            .extensions
+            .get("code")
+            .and_then(|c| c.as_str())
+            != Some("MAX_DEPTH_LIMIT")));
+    }
 }
@@ -0,0 +1,278 @@
+//! Subgraph connections over Unix domain sockets.
+//!
+//! A subgraph routing URL like `unix:///var/run/products.sock` cannot be represented as a
+//! [`Uri`], because it has no authority. The socket path is hex encoded in the authority
+//! instead (`unix://2f7661722f.../`), so the URL goes through the rest of the pipeline like any
+//! other subgraph URL, and [`UnixSocketConnector`] decodes it when opening the connection.
+//! The HTTP request path can be set with the `path` query parameter, which must be absolute and
+//! is the only query parameter accepted: `unix:///var/run/products.sock?path=/graphql`.
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::Context;
+use std::task::Poll;
+
+use displaydoc::Display;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use http::uri::InvalidUri;
+use http::Uri;
+use hyper::client::connect::Connected;
+use hyper::client::connect::Connection;
+use pin_project_lite::pin_project;
+use thiserror::Error;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::io::ReadBuf;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tower::BoxError;
+use tower::Service;
+
+const UNIX_SCHEME: &str = "unix";
+
+/// Error parsing a subgraph routing URL
+#[derive(Debug, Error, Display)]
+pub(crate) enum SubgraphUrlError {
+    /// {0}
+    InvalidUri(#[from] InvalidUri),
+    /// the `path` query parameter of a unix socket URL must start with `/`, got '{0}'
+    RelativePath(String),
+    /// a unix socket URL only accepts the `path` query parameter, got '{0}'
+    UnsupportedQuery(String),
+}
+
+/// Parses a subgraph routing URL, encoding the socket path of `unix://` URLs in the authority
+pub(crate) fn parse_subgraph_url(url: &str) -> Result<Uri, SubgraphUrlError> {
+    let socket = match url.strip_prefix("unix://") {
+        Some(socket) => socket,
+        None => return Ok(Uri::from_str(url)?),
+    };
+    let (socket_path, request_path) = match socket.split_once('?') {
+        Some((socket_path, query)) => {
+            let request_path = query
+                .strip_prefix("path=")
+                .filter(|request_path| !request_path.contains('&'))
+                .ok_or_else(|| SubgraphUrlError::UnsupportedQuery(query.to_string()))?;
+            if !request_path.starts_with('/') {
+                return Err(SubgraphUrlError::RelativePath(request_path.to_string()));
+            }
+            (socket_path, request_path)
+        }
+        None => (socket, "/"),
+    };
+
+    Ok(Uri::from_str(&format!(
+        "{UNIX_SCHEME}://{}{request_path}",
+        hex::encode(socket_path)
+    ))?)
+}
+
+/// Returns the socket path of a `unix://` URL parsed by [`parse_subgraph_url`]
+pub(crate) fn socket_path(uri: &Uri) -> Option<String> {
+    if uri.scheme_str() != Some(UNIX_SCHEME) {
+        return None;
+    }
+    uri.host()
+        .and_then(|host| hex::decode(host).ok())
+        .and_then(|path| String::from_utf8(path).ok())
+}
+
+/// Connects to `unix://` URLs over a Unix domain socket, and to other URLs with the inner
+/// connector
+#[derive(Clone)]
+pub(crate) struct UnixSocketConnector<C> {
+    inner: C,
+}
+
+impl<C> UnixSocketConnector<C> {
+    pub(crate) fn new(inner: C) -> Self {
+        Self { inner }
+    }
+}
+
+impl<C> Service<Uri> for UnixSocketConnector<C>
+where
+    C: Service<Uri>,
+    C::Error: Into<BoxError>,
+    C::Future: Send + 'static,
+{
+    type Response = MaybeUnixStream<C::Response>;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        if uri.scheme_str() == Some(UNIX_SCHEME) {
+            return connect_unix(uri).boxed();
+        }
+
+        let connecting = self.inner.call(uri);
+        async move {
+            connecting
+                .await
+                .map(MaybeUnixStream::Other)
+                .map_err(Into::into)
+        }
+        .boxed()
+    }
+}
+
+#[cfg(unix)]
+fn connect_unix<S>(uri: Uri) -> impl Future<Output = Result<MaybeUnixStream<S>, BoxError>> {
+    async move {
+        let path = socket_path(&uri).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid unix socket URL: {uri}"),
+            )
+        })?;
+        let stream = UnixStream::connect(path).await?;
+        Ok(MaybeUnixStream::Unix { stream })
+    }
+}
+
+#[cfg(not(unix))]
+fn connect_unix<S>(uri: Uri) -> impl Future<Output = Result<MaybeUnixStream<S>, BoxError>> {
+    async move { Err(format!("unix sockets are not supported on this platform: {uri}").into()) }
+}
+
+pin_project! {
+    #[project = MaybeUnixStreamProj]
+    pub(crate) enum MaybeUnixStream<S> {
+        Other {
+            #[pin]
+            stream: S,
+        },
+        #[cfg(unix)]
+        Unix {
+            #[pin]
+            stream: UnixStream,
+        },
+    }
+}
+
+#[allow(non_snake_case)]
+impl<S> MaybeUnixStream<S> {
+    fn Other(stream: S) -> Self {
+        MaybeUnixStream::Other { stream }
+    }
+}
+
+impl<S: Connection> Connection for MaybeUnixStream<S> {
+    fn connected(&self) -> Connected {
+        match self {
+            MaybeUnixStream::Other { stream } => stream.connected(),
+            #[cfg(unix)]
+            MaybeUnixStream::Unix { .. } => Connected::new(),
+        }
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for MaybeUnixStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.project() {
+            MaybeUnixStreamProj::Other { stream } => stream.poll_read(cx, buf),
+            #[cfg(unix)]
+            MaybeUnixStreamProj::Unix { stream } => stream.poll_read(cx, buf),
+        }
+    }
+}
+
+impl<S: AsyncWrite> AsyncWrite for MaybeUnixStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.project() {
+            MaybeUnixStreamProj::Other { stream } => stream.poll_write(cx, buf),
+            #[cfg(unix)]
+            MaybeUnixStreamProj::Unix { stream } => stream.poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.project() {
+            MaybeUnixStreamProj::Other { stream } => stream.poll_flush(cx),
+            #[cfg(unix)]
+            MaybeUnixStreamProj::Unix { stream } => stream.poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.project() {
+            MaybeUnixStreamProj::Other { stream } => stream.poll_shutdown(cx),
+            #[cfg(unix)]
+            MaybeUnixStreamProj::Unix { stream } => stream.poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_encodes_the_socket_path() {
+        let uri = parse_subgraph_url("unix:///var/run/products.sock").unwrap();
+        assert_eq!(uri.scheme_str(), Some(UNIX_SCHEME));
+        assert_eq!(uri.path(), "/");
+        assert_eq!(socket_path(&uri).as_deref(), Some("/var/run/products.sock"));
+
+        let uri = parse_subgraph_url("unix:///var/run/products.sock?path=/graphql").unwrap();
+        assert_eq!(uri.path(), "/graphql");
+        assert_eq!(socket_path(&uri).as_deref(), Some("/var/run/products.sock"));
+
+        let uri = parse_subgraph_url("http://localhost:4001/graphql").unwrap();
+        assert_eq!(uri.host(), Some("localhost"));
+        assert_eq!(socket_path(&uri), None);
+    }
+
+    #[test]
+    fn it_rejects_invalid_request_paths() {
+        assert!(matches!(
+            parse_subgraph_url("unix:///var/run/products.sock?path=graphql"),
+            Err(SubgraphUrlError::RelativePath(path)) if path == "graphql"
+        ));
+        assert!(matches!(
+            parse_subgraph_url("unix:///var/run/products.sock?path=/graphql&timeout=1"),
+            Err(SubgraphUrlError::UnsupportedQuery(_))
+        ));
+        assert!(matches!(
+            parse_subgraph_url("unix:///var/run/products.sock?timeout=1"),
+            Err(SubgraphUrlError::UnsupportedQuery(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn it_connects_to_unix_sockets() {
+        use tokio::io::AsyncReadExt;
+        use tokio::io::AsyncWriteExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("subgraph.sock");
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.write_all(b"hello").await.unwrap();
+        });
+
+        let mut connector = UnixSocketConnector::new(hyper::client::HttpConnector::new());
+        let uri = parse_subgraph_url(&format!("unix://{}", path.display())).unwrap();
+        let mut stream = connector.call(uri).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert_eq!(response, "hello");
+    }
+}
@@ -0,0 +1,508 @@
+//! Removes the fields a request is not allowed to query from an operation, following the
+//! `@authenticated` and `@requiresScopes` directives of the schema.
+//!
+//! The filtered operation is printed back from the source document, so it can go through query
+//! planning like any other operation.
+
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+
+use apollo_compiler::hir;
+use apollo_compiler::ApolloCompiler;
+use apollo_compiler::AstDatabase;
+use apollo_compiler::HirDatabase;
+
+use super::FieldType;
+use super::Schema;
+use crate::json_ext::Path;
+use crate::json_ext::PathElement;
+
+/// What a request is allowed to access
+#[derive(Debug, Default, Clone)]
+pub(crate) struct RequestAccess {
+    pub(crate) authenticated: bool,
+    pub(crate) scopes: HashSet<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FilteredOperation {
+    /// Filtered document, `None` if none of the operation's root fields can be queried
+    pub(crate) query: Option<String>,
+    /// Paths of the removed fields. They go through lists with `@` and through type
+    /// conditions with fragment elements
+    pub(crate) unauthorized_paths: Vec<Path>,
+}
+
+impl FilteredOperation {
+    /// Returns `None` if the request can query every field of the operation. Invalid
+    /// documents are left to the query planner, which reports their errors
+    pub(crate) fn filter(
+        schema: &Schema,
+        query: &str,
+        operation_name: Option<&str>,
+        access: &RequestAccess,
+    ) -> Option<Self> {
+        if schema.authorization.is_empty() {
+            return None;
+        }
+
+        let mut compiler = ApolloCompiler::new();
+        let file_id = compiler.add_executable(query, "query");
+        if compiler.db.ast(file_id).errors().next().is_some() {
+            return None;
+        }
+        let operation = compiler
+            .db
+            .find_operation(file_id, operation_name.map(str::to_string))?;
+
+        let mut filter = OperationFilter {
+            schema,
+            access,
+            db: &compiler.db,
+            source: query,
+            fragments: BTreeMap::new(),
+        };
+        let root_type = schema.root_operation_name(operation.operation_ty().into());
+        let mut unauthorized_paths = Vec::new();
+        let selection_set = filter.selection_set(
+            root_type,
+            operation.selection_set(),
+            &mut unauthorized_paths,
+        );
+
+        if unauthorized_paths.is_empty() {
+            return None;
+        }
+        Some(FilteredOperation {
+            query: selection_set.map(|selection_set| filter.document(&operation, selection_set)),
+            unauthorized_paths,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct FilteredFragment {
+    type_condition: String,
+    directives: String,
+    /// `None` if every selection was removed
+    selection_set: Option<String>,
+    unauthorized_paths: Vec<Path>,
+}
+
+struct OperationFilter<'a> {
+    schema: &'a Schema,
+    access: &'a RequestAccess,
+    db: &'a dyn HirDatabase,
+    source: &'a str,
+    /// filtered fragment definitions, sorted by name so the printed document is stable.
+    /// The value is `None` while the fragment is filtered, to stop on (invalid) fragment cycles
+    fragments: BTreeMap<String, Option<FilteredFragment>>,
+}
+
+impl<'a> OperationFilter<'a> {
+    fn slice(&self, loc: hir::HirNodeLocation) -> &'a str {
+        &self.source[loc.offset()..loc.end_offset()]
+    }
+
+    fn directives(&self, directives: &[hir::Directive]) -> String {
+        directives
+            .iter()
+            .map(|directive| format!(" {}", self.slice(directive.loc())))
+            .collect()
+    }
+
+    fn is_authorized(&self, coordinate: &str) -> bool {
+        self.schema.authorization.is_authorized(
+            coordinate,
+            self.access.authenticated,
+            &self.access.scopes,
+        )
+    }
+
+    fn is_field_authorized(&self, parent_type: &str, name: &str, field_type: &FieldType) -> bool {
+        field_type
+            .inner_type_name()
+            .map_or(true, |type_name| self.is_authorized(type_name))
+            && self.is_authorized(&format!("{parent_type}.{name}"))
+            // a field queried on an interface is also checked on its implementations
+            && self
+                .schema
+                .type_system
+                .subtype_map
+                .get(parent_type)
+                .map_or(true, |subtypes| {
+                    subtypes
+                        .iter()
+                        .all(|subtype| self.is_authorized(&format!("{subtype}.{name}")))
+                })
+    }
+
+    fn field_type(&self, parent_type: &str, name: &str) -> Option<&'a FieldType> {
+        self.schema
+            .object_types
+            .get(parent_type)
+            .and_then(|object| object.fields.get(name))
+            .or_else(|| {
+                self.schema
+                    .interfaces
+                    .get(parent_type)
+                    .and_then(|interface| interface.fields.get(name))
+            })
+    }
+
+    /// Returns the filtered selection set, or `None` if every selection was removed, and adds
+    /// the paths of the removed fields, relative to the selection set, to `unauthorized_paths`
+    fn selection_set(
+        &mut self,
+        parent_type: &str,
+        selection_set: &hir::SelectionSet,
+        unauthorized_paths: &mut Vec<Path>,
+    ) -> Option<String> {
+        let mut selections = Vec::new();
+        for selection in selection_set.selection() {
+            match selection {
+                hir::Selection::Field(field) => {
+                    if let Some(field) = self.field(parent_type, field, unauthorized_paths) {
+                        selections.push(field);
+                    }
+                }
+                hir::Selection::InlineFragment(fragment) => {
+                    let type_condition = fragment.type_condition().unwrap_or(parent_type);
+                    let mut paths = Vec::new();
+                    if self.is_authorized(type_condition) {
+                        if let Some(selection_set) =
+                            self.selection_set(type_condition, fragment.selection_set(), &mut paths)
+                        {
+                            selections.push(format!(
+                                "...{}{} {selection_set}",
+                                fragment
+                                    .type_condition()
+                                    .map(|type_condition| format!(" on {type_condition}"))
+                                    .unwrap_or_default(),
+                                self.directives(fragment.directives())
+                            ));
+                        }
+                    } else {
+                        self.response_keys(fragment.selection_set(), &mut paths);
+                    }
+                    unauthorized_paths.extend(prefix_paths(type_condition, paths));
+                }
+                hir::Selection::FragmentSpread(spread) => {
+                    let fragment = match spread.fragment(self.db) {
+                        Some(fragment) => fragment,
+                        None => {
+                            selections.push(self.slice(spread.loc()).to_string());
+                            continue;
+                        }
+                    };
+                    let type_condition = fragment.type_condition();
+
+                    if !self.is_authorized(type_condition) {
+                        let mut paths = Vec::new();
+                        self.response_keys(fragment.selection_set(), &mut paths);
+                        unauthorized_paths.extend(prefix_paths(type_condition, paths));
+                        continue;
+                    }
+
+                    match self.fragment(&fragment) {
+                        Some(filtered) => {
+                            if filtered.selection_set.is_some() {
+                                selections.push(format!(
+                                    "...{}{}",
+                                    spread.name(),
+                                    self.directives(spread.directives())
+                                ));
+                            }
+                            unauthorized_paths
+                                .extend(prefix_paths(type_condition, filtered.unauthorized_paths));
+                        }
+                        // fragment cycle
+                        None => selections.push(self.slice(spread.loc()).to_string()),
+                    }
+                }
+            }
+        }
+
+        if selections.is_empty() {
+            None
+        } else {
+            Some(format!("{{ {} }}", selections.join(" ")))
+        }
+    }
+
+    fn field(
+        &mut self,
+        parent_type: &str,
+        field: &hir::Field,
+        unauthorized_paths: &mut Vec<Path>,
+    ) -> Option<String> {
+        let name = field.name();
+        let key = PathElement::Key(field.response_name().to_string());
+
+        // introspection fields are not covered by authorization directives, and unknown
+        // fields are reported by the query planner
+        let field_type = match self.field_type(parent_type, name) {
+            Some(field_type) if !name.starts_with("__") => field_type,
+            _ => return Some(self.slice(field.loc()).to_string()),
+        };
+
+        if !self.is_field_authorized(parent_type, name, field_type) {
+            unauthorized_paths.push(Path(vec![key]));
+            return None;
+        }
+
+        let type_name = match field_type.inner_type_name() {
+            Some(type_name) if !field.selection_set().selection().is_empty() => type_name,
+            _ => return Some(self.slice(field.loc()).to_string()),
+        };
+
+        let mut paths = Vec::new();
+        match self.selection_set(type_name, field.selection_set(), &mut paths) {
+            Some(selection_set) => {
+                let mut prefix = vec![key];
+                prefix.extend(std::iter::repeat(PathElement::Flatten).take(list_depth(field_type)));
+                let prefix = Path(prefix);
+                unauthorized_paths.extend(paths.into_iter().map(|path| prefix.join(path)));
+
+                let arguments = if field.arguments().is_empty() {
+                    String::new()
+                } else {
+                    format!(
+                        "({})",
+                        field
+                            .arguments()
+                            .iter()
+                            .map(|argument| self.slice(argument.loc()))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                };
+                Some(format!(
+                    "{}{name}{arguments}{} {selection_set}",
+                    field
+                        .alias()
+                        .map(|alias| format!("{}: ", alias.name()))
+                        .unwrap_or_default(),
+                    self.directives(field.directives())
+                ))
+            }
+            // a field cannot have an empty selection set, so it is removed too
+            None => {
+                unauthorized_paths.push(Path(vec![key]));
+                None
+            }
+        }
+    }
+
+    fn fragment(&mut self, fragment: &hir::FragmentDefinition) -> Option<FilteredFragment> {
+        if let Some(filtered) = self.fragments.get(fragment.name()) {
+            return filtered.clone();
+        }
+
+        self.fragments.insert(fragment.name().to_string(), None);
+        let mut unauthorized_paths = Vec::new();
+        let selection_set = self.selection_set(
+            fragment.type_condition(),
+            fragment.selection_set(),
+            &mut unauthorized_paths,
+        );
+        let filtered = FilteredFragment {
+            type_condition: fragment.type_condition().to_string(),
+            directives: self.directives(fragment.directives()),
+            selection_set,
+            unauthorized_paths,
+        };
+        self.fragments
+            .insert(fragment.name().to_string(), Some(filtered.clone()));
+        Some(filtered)
+    }
+
+    /// Adds the paths of the fields selected by a removed fragment
+    fn response_keys(&self, selection_set: &hir::SelectionSet, paths: &mut Vec<Path>) {
+        let mut keys = HashSet::new();
+        for field in selection_set.fields() {
+            if keys.insert(field.response_name().to_string()) {
+                paths.push(Path(vec![PathElement::Key(
+                    field.response_name().to_string(),
+                )]));
+            }
+        }
+        for fragment in selection_set.inline_fragments() {
+            self.response_keys(fragment.selection_set(), paths);
+        }
+        for spread in selection_set.fragment_spreads() {
+            if let Some(fragment) = spread.fragment(self.db) {
+                self.response_keys(fragment.selection_set(), paths);
+            }
+        }
+    }
+
+    /// Prints the filtered operation with the fragments it uses, and the variables that are
+    /// still used once fields are removed
+    fn document(&self, operation: &hir::OperationDefinition, selection_set: String) -> String {
+        let keyword = match operation.operation_ty() {
+            hir::OperationType::Query => "query",
+            hir::OperationType::Mutation => "mutation",
+            hir::OperationType::Subscription => "subscription",
+        };
+
+        let fragments: Vec<String> = self
+            .fragments
+            .iter()
+            .filter_map(|(name, filtered)| {
+                let filtered = filtered.as_ref()?;
+                Some(format!(
+                    "fragment {name} on {}{} {}",
+                    filtered.type_condition,
+                    filtered.directives,
+                    filtered.selection_set.as_ref()?
+                ))
+            })
+            .collect();
+        let body = format!("{keyword} {selection_set}\n{}", fragments.join("\n"));
+
+        let mut used_variables: HashSet<String> = operation
+            .directives()
+            .iter()
+            .flat_map(|directive| directive.arguments())
+            .flat_map(|argument| argument.value().variables())
+            .map(|variable| variable.name().to_string())
+            .collect();
+        let mut compiler = ApolloCompiler::new();
+        let file_id = compiler.add_executable(&body, "filtered_query");
+        if let Some(filtered_operation) = compiler.db.find_operation(file_id, None) {
+            used_variables.extend(
+                filtered_operation
+                    .selection_set()
+                    .variables(&compiler.db)
+                    .iter()
+                    .map(|variable| variable.name().to_string()),
+            );
+        }
+        let variables: Vec<&str> = operation
+            .variables()
+            .iter()
+            .filter(|variable| used_variables.contains(variable.name()))
+            .map(|variable| self.slice(variable.loc()))
+            .collect();
+
+        let mut document = keyword.to_string();
+        if let Some(name) = operation.name() {
+            document.push(' ');
+            document.push_str(name);
+        }
+        if !variables.is_empty() {
+            document.push_str(&format!("({})", variables.join(", ")));
+        }
+        document.push_str(&self.directives(operation.directives()));
+        document.push(' ');
+        document.push_str(&selection_set);
+        for fragment in fragments {
+            document.push_str("\n\n");
+            document.push_str(&fragment);
+        }
+        document
+    }
+}
+
+fn prefix_paths(type_condition: &str, paths: Vec<Path>) -> impl Iterator<Item = Path> + '_ {
+    paths
+        .into_iter()
+        .map(move |path| Path(vec![PathElement::Fragment(type_condition.to_string())]).join(path))
+}
+
+fn list_depth(field_type: &FieldType) -> usize {
+    match field_type {
+        FieldType::List(inner) => 1 + list_depth(inner),
+        FieldType::NonNull(inner) => list_depth(inner),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str = r#"
+    schema { query: Query }
+    directive @authenticated on OBJECT | FIELD_DEFINITION | INTERFACE | SCALAR | ENUM
+    directive @requiresScopes(scopes: [[String!]!]!) on OBJECT | FIELD_DEFINITION | INTERFACE | SCALAR | ENUM
+
+    type Query {
+      me: User @authenticated
+      products(first: Int): [Product]
+    }
+
+    type User {
+      id: ID!
+      name: String
+    }
+
+    type Product {
+      upc: String
+      price: Int
+      reviews: [Review] @requiresScopes(scopes: [["read:reviews"]])
+    }
+
+    type Review {
+      body: String
+    }
+    "#;
+
+    fn filter(query: &str, access: RequestAccess) -> Option<FilteredOperation> {
+        let schema = Schema::parse(SCHEMA, &Default::default(), None).unwrap();
+        FilteredOperation::filter(&schema, query, None, &access)
+    }
+
+    fn paths(filtered: &FilteredOperation) -> Vec<String> {
+        filtered
+            .unauthorized_paths
+            .iter()
+            .map(|path| path.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn authorized_operations_are_not_modified() {
+        let access = RequestAccess {
+            authenticated: true,
+            scopes: ["read:reviews".to_string()].into_iter().collect(),
+        };
+        assert_eq!(
+            filter("{ me { id } products { reviews { body } } }", access),
+            None
+        );
+    }
+
+    #[test]
+    fn it_removes_unauthorized_fields() {
+        let filtered = filter(
+            "query Q($n: Int, $skip: Boolean!) { me { id } products(first: $n) { upc reviews @skip(if: $skip) { body } } }",
+            RequestAccess::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            filtered.query.as_deref(),
+            Some("query Q($n: Int) { products(first: $n) { upc } }")
+        );
+        assert_eq!(paths(&filtered), vec!["/me", "/products/@/reviews"]);
+    }
+
+    #[test]
+    fn it_removes_fields_left_empty() {
+        let filtered = filter(
+            "{ me { id } products { ...ProductReviews } }
+            fragment ProductReviews on Product { reviews { body } }",
+            RequestAccess {
+                authenticated: true,
+                scopes: HashSet::new(),
+            },
+        )
+        .unwrap();
+        assert_eq!(filtered.query.as_deref(), Some("query { me { id } }"));
+        assert_eq!(paths(&filtered), vec!["/products"]);
+
+        let filtered = filter("{ me { id } }", RequestAccess::default()).unwrap();
+        assert_eq!(filtered.query, None);
+    }
+}
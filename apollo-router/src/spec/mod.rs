@@ -2,8 +2,10 @@
 #![cfg_attr(not(test), deny(clippy::expect_used))]
 #![cfg_attr(not(test), deny(clippy::panic))]
 
+pub(crate) mod authorization;
 mod field_type;
 mod fragments;
+pub(crate) mod operation_limits;
 pub(crate) mod query;
 mod schema;
 mod selection;
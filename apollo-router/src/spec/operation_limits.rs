@@ -0,0 +1,334 @@
+//! Measures the shape of an operation, to enforce the `preview_operation_limits` configuration.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use super::Fragments;
+use super::Query;
+use super::Selection;
+use crate::configuration::OperationLimits;
+
+/// Measurements of an operation, with fragments expanded
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct OperationMeasurements {
+    pub(crate) depth: u32,
+    pub(crate) height: u32,
+    pub(crate) root_fields: u32,
+    pub(crate) aliases: u32,
+}
+
+impl OperationMeasurements {
+    /// Returns `None` if the operation does not exist in the query.
+    ///
+    /// The height is only measured when `limits.max_height` is set, and stops being counted
+    /// once it is above that limit.
+    pub(crate) fn measure(
+        query: &Query,
+        operation_name: Option<&str>,
+        limits: &OperationLimits,
+    ) -> Option<Self> {
+        let operation = query.operation(operation_name)?;
+
+        let mut visitor = MeasurementVisitor {
+            fragments: &query.fragments,
+            measured_fragments: HashMap::new(),
+            active_fragments: Vec::new(),
+            max_fields: limits.max_height.map(|max_height| max_height as usize + 1),
+        };
+        let measured = visitor.selection_set(operation.selection_set());
+
+        Some(OperationMeasurements {
+            depth: measured.depth,
+            height: measured.fields.len() as u32,
+            root_fields: measured.root_fields,
+            aliases: measured.aliases,
+        })
+    }
+
+    /// Returns the limits exceeded by these measurements, with the measured value
+    pub(crate) fn exceeded(&self, limits: &OperationLimits) -> Vec<ExceededLimit> {
+        [
+            (LimitKind::Depth, limits.max_depth, self.depth),
+            (LimitKind::Height, limits.max_height, self.height),
+            (
+                LimitKind::RootFields,
+                limits.max_root_fields,
+                self.root_fields,
+            ),
+            (LimitKind::Aliases, limits.max_aliases, self.aliases),
+        ]
+        .into_iter()
+        .filter_map(|(kind, limit, measured)| {
+            limit
+                .filter(|limit| measured > *limit)
+                .map(|limit| ExceededLimit {
+                    kind,
+                    limit,
+                    measured,
+                })
+        })
+        .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LimitKind {
+    Depth,
+    Height,
+    RootFields,
+    Aliases,
+}
+
+impl LimitKind {
+    /// Name used in metrics and span attributes
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            LimitKind::Depth => "max_depth",
+            LimitKind::Height => "max_height",
+            LimitKind::RootFields => "max_root_fields",
+            LimitKind::Aliases => "max_aliases",
+        }
+    }
+
+    /// Supergraph span attribute recording the measured value when the limit is exceeded
+    pub(crate) fn span_attribute(&self) -> &'static str {
+        match self {
+            LimitKind::Depth => "apollo_router.operation_limits.max_depth",
+            LimitKind::Height => "apollo_router.operation_limits.max_height",
+            LimitKind::RootFields => "apollo_router.operation_limits.max_root_fields",
+            LimitKind::Aliases => "apollo_router.operation_limits.max_aliases",
+        }
+    }
+
+    pub(crate) fn error_code(&self) -> &'static str {
+        match self {
+            LimitKind::Depth => "MAX_DEPTH_LIMIT",
+            LimitKind::Height => "MAX_HEIGHT_LIMIT",
+            LimitKind::RootFields => "MAX_ROOT_FIELDS_LIMIT",
+            LimitKind::Aliases => "MAX_ALIASES_LIMIT",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ExceededLimit {
+    pub(crate) kind: LimitKind,
+    pub(crate) limit: u32,
+    pub(crate) measured: u32,
+}
+
+/// Measurements of a selection set, relative to the field it is selected on
+#[derive(Default)]
+struct SelectionSetMeasurements<'a> {
+    depth: u32,
+    aliases: u32,
+    /// fields selected directly in this selection set, through fragments included
+    root_fields: u32,
+    /// fields identified by the path of field names (not aliases) leading to them
+    fields: HashSet<Vec<&'a str>>,
+}
+
+struct MeasurementVisitor<'a> {
+    fragments: &'a Fragments,
+    /// fragments are measured once, however many times they are spread
+    measured_fragments: HashMap<&'a str, Rc<SelectionSetMeasurements<'a>>>,
+    /// fragments being measured, to stop on (invalid) fragment cycles
+    active_fragments: Vec<&'a str>,
+    /// number of unique fields above which they are not recorded anymore, `None` if the height
+    /// is not measured
+    max_fields: Option<usize>,
+}
+
+impl<'a> MeasurementVisitor<'a> {
+    fn selection_set(&mut self, selection_set: &'a [Selection]) -> SelectionSetMeasurements<'a> {
+        let mut measured = SelectionSetMeasurements::default();
+        for selection in selection_set {
+            match selection {
+                Selection::Field {
+                    name,
+                    alias,
+                    selection_set,
+                    ..
+                } => {
+                    let sub_selection = match selection_set {
+                        Some(selection_set) => self.selection_set(selection_set),
+                        None => SelectionSetMeasurements::default(),
+                    };
+
+                    measured.depth = measured.depth.max(sub_selection.depth.saturating_add(1));
+                    measured.aliases = measured
+                        .aliases
+                        .saturating_add(sub_selection.aliases)
+                        .saturating_add(alias.is_some() as u32);
+                    measured.root_fields = measured.root_fields.saturating_add(1);
+
+                    self.record_field(&mut measured.fields, vec![name.as_str()]);
+                    for path in &sub_selection.fields {
+                        let mut field_path = Vec::with_capacity(path.len() + 1);
+                        field_path.push(name.as_str());
+                        field_path.extend_from_slice(path);
+                        self.record_field(&mut measured.fields, field_path);
+                    }
+                }
+                Selection::InlineFragment { selection_set, .. } => {
+                    let fragment = self.selection_set(selection_set);
+                    self.merge(&mut measured, &fragment);
+                }
+                Selection::FragmentSpread { name, .. } => {
+                    if let Some(fragment) = self.fragment(name) {
+                        self.merge(&mut measured, &fragment);
+                    }
+                }
+            }
+        }
+        measured
+    }
+
+    fn fragment(&mut self, name: &str) -> Option<Rc<SelectionSetMeasurements<'a>>> {
+        let (name, fragment) = self.fragments.map.get_key_value(name)?;
+        if let Some(measured) = self.measured_fragments.get(name.as_str()) {
+            return Some(measured.clone());
+        }
+        if self.active_fragments.contains(&name.as_str()) {
+            return None;
+        }
+
+        self.active_fragments.push(name.as_str());
+        let measured = Rc::new(self.selection_set(&fragment.selection_set));
+        self.active_fragments.pop();
+        self.measured_fragments
+            .insert(name.as_str(), measured.clone());
+        Some(measured)
+    }
+
+    fn merge(
+        &self,
+        measured: &mut SelectionSetMeasurements<'a>,
+        other: &SelectionSetMeasurements<'a>,
+    ) {
+        measured.depth = measured.depth.max(other.depth);
+        measured.aliases = measured.aliases.saturating_add(other.aliases);
+        measured.root_fields = measured.root_fields.saturating_add(other.root_fields);
+        for path in &other.fields {
+            self.record_field(&mut measured.fields, path.clone());
+        }
+    }
+
+    fn record_field(&self, fields: &mut HashSet<Vec<&'a str>>, path: Vec<&'a str>) {
+        if matches!(self.max_fields, Some(max_fields) if fields.len() < max_fields) {
+            fields.insert(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spec::Schema;
+    use crate::Configuration;
+
+    fn measure_with_limits(query: &str, limits: &OperationLimits) -> OperationMeasurements {
+        let configuration = Configuration::default();
+        let schema =
+            Schema::parse_test(include_str!("../../testing_schema.graphql"), &configuration)
+                .unwrap();
+        let query = Query::parse(query, &schema, &configuration).unwrap();
+        OperationMeasurements::measure(&query, None, limits).unwrap()
+    }
+
+    fn measure(query: &str) -> OperationMeasurements {
+        measure_with_limits(
+            query,
+            &OperationLimits {
+                max_height: Some(100),
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn it_measures_operations() {
+        assert_eq!(
+            measure("{ me { name } }"),
+            OperationMeasurements {
+                depth: 2,
+                height: 2,
+                root_fields: 1,
+                aliases: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn it_expands_fragments_and_counts_aliases() {
+        assert_eq!(
+            measure(
+                "query { me { ...F first: name } second: me { id } }
+                fragment F on User { name id }"
+            ),
+            OperationMeasurements {
+                depth: 2,
+                height: 3,
+                root_fields: 2,
+                aliases: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn it_measures_each_fragment_once() {
+        // each fragment spreads the previous one twice: expanding them would select 2^40 fields
+        let mut query = String::from("query { me { ...F40 } }\nfragment F0 on User { a: name }\n");
+        for i in 1..=40 {
+            query.push_str(&format!(
+                "fragment F{i} on User {{ ...F{previous} ...F{previous} }}\n",
+                previous = i - 1
+            ));
+        }
+
+        let measurements = measure_with_limits(
+            &query,
+            &OperationLimits {
+                max_height: Some(1),
+                ..Default::default()
+            },
+        );
+        assert_eq!(measurements.depth, 2);
+        assert_eq!(measurements.root_fields, 1);
+        assert_eq!(measurements.aliases, u32::MAX);
+        // the height stops being counted above the limit
+        assert_eq!(measurements.height, 2);
+    }
+
+    #[test]
+    fn it_reports_exceeded_limits() {
+        let measurements = OperationMeasurements {
+            depth: 5,
+            height: 10,
+            root_fields: 1,
+            aliases: 0,
+        };
+        let limits = OperationLimits {
+            max_depth: Some(4),
+            max_height: Some(10),
+            max_root_fields: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            measurements.exceeded(&limits),
+            vec![
+                ExceededLimit {
+                    kind: LimitKind::Depth,
+                    limit: 4,
+                    measured: 5,
+                },
+                ExceededLimit {
+                    kind: LimitKind::RootFields,
+                    limit: 0,
+                    measured: 1,
+                },
+            ]
+        );
+    }
+}
@@ -28,6 +28,7 @@ use crate::json_ext::Object;
 use crate::json_ext::Path;
 use crate::json_ext::PathElement;
 use crate::json_ext::Value;
+use crate::plugins::subscription::SubscriptionConfig;
 use crate::query_planner::fetch::OperationKind;
 use crate::spec::FieldType;
 use crate::spec::Fragments;
@@ -48,7 +49,7 @@ pub(crate) struct Query {
     #[serde(skip)]
     compiler: OnceCell<Mutex<ApolloCompiler>>,
     #[derivative(PartialEq = "ignore", Hash = "ignore")]
-    fragments: Fragments,
+    pub(crate) fragments: Fragments,
     #[derivative(PartialEq = "ignore", Hash = "ignore")]
     pub(crate) operations: Vec<Operation>,
     #[derivative(PartialEq = "ignore", Hash = "ignore")]
@@ -288,12 +289,13 @@ impl Query {
         }
 
         let fragments = Fragments::from_hir(&compiler, schema)?;
+        let subscriptions_enabled = SubscriptionConfig::is_enabled(configuration);
 
         let operations = compiler
             .db
             .all_operations()
             .iter()
-            .map(|operation| Operation::from_hir(operation, schema))
+            .map(|operation| Operation::from_hir(operation, schema, subscriptions_enabled))
             .collect::<Result<Vec<_>, SpecError>>()?;
 
         Ok(Query {
@@ -567,6 +569,7 @@ impl Query {
                     selection_set,
                     field_type,
                     include_skip,
+                    ..
                 } => {
                     let field_name = alias.as_ref().unwrap_or(name);
                     if include_skip.should_skip(parameters.variables) {
@@ -737,6 +740,7 @@ impl Query {
                     selection_set,
                     field_type,
                     include_skip,
+                    ..
                 } => {
                     if include_skip.should_skip(parameters.variables) {
                         continue;
@@ -960,7 +964,7 @@ impl Query {
         })
     }
 
-    fn operation(&self, operation_name: Option<&str>) -> Option<&Operation> {
+    pub(crate) fn operation(&self, operation_name: Option<&str>) -> Option<&Operation> {
         match operation_name {
             Some(name) => self
                 .operations
@@ -1030,10 +1034,14 @@ pub(crate) struct Variable {
 }
 
 impl Operation {
-    fn from_hir(operation: &hir::OperationDefinition, schema: &Schema) -> Result<Self, SpecError> {
+    fn from_hir(
+        operation: &hir::OperationDefinition,
+        schema: &Schema,
+        subscriptions_enabled: bool,
+    ) -> Result<Self, SpecError> {
         let name = operation.name().map(|s| s.to_owned());
         let kind = operation.operation_ty().into();
-        if kind == OperationKind::Subscription {
+        if kind == OperationKind::Subscription && !subscriptions_enabled {
             return Err(SpecError::SubscriptionNotSupported);
         }
         let current_field_type = FieldType::Named(schema.root_operation_name(kind).to_owned());
@@ -1105,6 +1113,10 @@ impl Operation {
     pub(crate) fn kind(&self) -> &OperationKind {
         &self.kind
     }
+
+    pub(crate) fn selection_set(&self) -> &[Selection] {
+        &self.selection_set
+    }
 }
 
 impl From<hir::OperationType> for OperationKind {
@@ -2,7 +2,6 @@
 
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::str::FromStr;
 use std::sync::Arc;
 
 use apollo_compiler::hir;
@@ -18,6 +17,7 @@ use crate::error::SchemaError;
 use crate::json_ext::Object;
 use crate::json_ext::Value;
 use crate::query_planner::OperationKind;
+use crate::services::unix_socket::parse_subgraph_url;
 use crate::spec::query::parse_hir_value;
 use crate::spec::FieldType;
 use crate::Configuration;
@@ -36,6 +36,7 @@ pub(crate) struct Schema {
     api_schema: Option<Box<Schema>>,
     pub(crate) schema_id: Option<String>,
     root_operations: HashMap<OperationKind, String>,
+    pub(crate) authorization: AuthorizationDirectives,
 }
 
 #[cfg(test)]
@@ -113,8 +114,8 @@ impl Schema {
                 if url.is_empty() {
                     return Err(SchemaError::MissingSubgraphUrl(name.clone()));
                 }
-                let url =
-                    Uri::from_str(url).map_err(|err| SchemaError::UrlParse(name.clone(), err))?;
+                let url = parse_subgraph_url(url)
+                    .map_err(|err| SchemaError::UrlParse(name.clone(), err))?;
                 if subgraphs.insert(name.clone(), url).is_some() {
                     return Err(SchemaError::Api(format!(
                         "must not have several subgraphs with same name '{name}'"
@@ -185,6 +186,27 @@ impl Schema {
             .map(|(name, _def)| name.clone())
             .collect();
 
+        let mut authorization = AuthorizationDirectives::default();
+        for (name, def) in compiler.db.types_definitions_by_name().iter() {
+            authorization.add(name.clone(), def.directives());
+        }
+        for (name, def) in compiler.db.object_types().iter() {
+            for field in def.fields() {
+                authorization.add(
+                    format!("{name}.{}", field.name()),
+                    field.directives().iter(),
+                );
+            }
+        }
+        for (name, def) in compiler.db.interfaces().iter() {
+            for field in def.fields() {
+                authorization.add(
+                    format!("{name}.{}", field.name()),
+                    field.directives().iter(),
+                );
+            }
+        }
+
         let mut hasher = Sha256::new();
         hasher.update(schema.as_bytes());
         let schema_id = Some(format!("{:x}", hasher.finalize()));
@@ -201,6 +223,7 @@ impl Schema {
             api_schema: None,
             schema_id,
             root_operations,
+            authorization,
         })
     }
 }
@@ -239,6 +262,74 @@ impl Schema {
     }
 }
 
+/// Access control directives of the schema, by type name or field coordinate (`Type.field`)
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AuthorizationDirectives {
+    /// Types and fields marked with `@authenticated`
+    pub(crate) authenticated: HashSet<String>,
+    /// Scope sets of the types and fields marked with `@requiresScopes`. Access is granted
+    /// if the request has all the scopes of one of the sets
+    pub(crate) requires_scopes: HashMap<String, Vec<HashSet<String>>>,
+}
+
+impl AuthorizationDirectives {
+    fn add<'a>(
+        &mut self,
+        coordinate: String,
+        directives: impl Iterator<Item = &'a hir::Directive>,
+    ) {
+        for directive in directives {
+            match directive.name() {
+                "authenticated" => {
+                    self.authenticated.insert(coordinate.clone());
+                }
+                "requiresScopes" => {
+                    let scope_sets = match directive.argument_by_name("scopes") {
+                        Some(hir::Value::List(scope_sets)) => scope_sets
+                            .iter()
+                            .filter_map(|scopes| match scopes {
+                                hir::Value::List(scopes) => Some(
+                                    scopes
+                                        .iter()
+                                        .filter_map(|scope| match scope {
+                                            hir::Value::String(scope) => Some(scope.clone()),
+                                            _ => None,
+                                        })
+                                        .collect(),
+                                ),
+                                _ => None,
+                            })
+                            .collect(),
+                        _ => Vec::new(),
+                    };
+                    self.requires_scopes.insert(coordinate.clone(), scope_sets);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.authenticated.is_empty() && self.requires_scopes.is_empty()
+    }
+
+    /// Returns true if a request can access the type or field
+    pub(crate) fn is_authorized(
+        &self,
+        coordinate: &str,
+        authenticated: bool,
+        scopes: &HashSet<String>,
+    ) -> bool {
+        if !authenticated && self.authenticated.contains(coordinate) {
+            return false;
+        }
+        match self.requires_scopes.get(coordinate) {
+            Some(scope_sets) => scope_sets.iter().any(|set| set.is_subset(scopes)),
+            None => true,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct InvalidObject;
 
@@ -554,6 +645,31 @@ GraphQL request:42:1
         };
     }
 
+    #[test]
+    fn authorization_directives() {
+        let schema = r#"
+        schema { query: Query }
+        directive @authenticated on OBJECT | FIELD_DEFINITION | INTERFACE | SCALAR | ENUM
+        directive @requiresScopes(scopes: [[String!]!]!) on OBJECT | FIELD_DEFINITION | INTERFACE | SCALAR | ENUM
+        type Query {
+          me: User @authenticated
+          public: String
+        }
+        type User @requiresScopes(scopes: [["read:user"], ["admin"]]) {
+          id: ID!
+        }"#;
+        let schema = Schema::parse(schema, &Default::default(), None).unwrap();
+        let directives = &schema.authorization;
+        let no_scopes = HashSet::new();
+        let scopes = HashSet::from(["read:user".to_string()]);
+
+        assert!(!directives.is_authorized("Query.me", false, &scopes));
+        assert!(directives.is_authorized("Query.me", true, &no_scopes));
+        assert!(directives.is_authorized("Query.public", false, &no_scopes));
+        assert!(directives.is_authorized("User", false, &scopes));
+        assert!(!directives.is_authorized("User", true, &no_scopes));
+    }
+
     // https://github.com/apollographql/router/issues/2269
     #[test]
     fn unclosed_brace_error_does_not_panic() {
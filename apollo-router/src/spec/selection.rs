@@ -19,6 +19,9 @@ pub(crate) enum Selection {
         selection_set: Option<Vec<Selection>>,
         field_type: FieldType,
         include_skip: IncludeSkip,
+        /// Integer arguments, used to estimate the size of returned lists
+        #[serde(default)]
+        int_arguments: Vec<(String, IntArgument)>,
     },
     InlineFragment {
         // Optional in specs but we fill it with the current type if not specified
@@ -108,12 +111,22 @@ impl Selection {
                     }
                 };
 
+                let int_arguments = field
+                    .arguments()
+                    .iter()
+                    .filter_map(|argument| {
+                        IntArgument::parse(argument.value())
+                            .map(|value| (argument.name().to_owned(), value))
+                    })
+                    .collect();
+
                 Some(Self::Field {
                     alias,
                     name: field.name().into(),
                     selection_set,
                     field_type,
                     include_skip,
+                    int_arguments,
                 })
             }
             // Spec: https://spec.graphql.org/draft/#InlineFragment
@@ -316,3 +329,30 @@ impl Condition {
         }
     }
 }
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) enum IntArgument {
+    Value(i64),
+    Variable(String),
+}
+
+impl IntArgument {
+    fn parse(value: &hir::Value) -> Option<Self> {
+        match value {
+            hir::Value::Int(value) => Some(IntArgument::Value(value.get() as i64)),
+            hir::Value::Variable(variable) => {
+                Some(IntArgument::Variable(variable.name().to_owned()))
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn eval(&self, variables: &Object) -> Option<i64> {
+        match self {
+            IntArgument::Value(value) => Some(*value),
+            IntArgument::Variable(variable_name) => variables
+                .get(variable_name.as_str())
+                .and_then(|v| v.as_i64()),
+        }
+    }
+}
@@ -145,6 +145,11 @@ impl EntitlementReport {
                 .path("$.apq.router.cache.redis")
                 .name("APQ caching")
                 .build(),
+            ConfigurationRestriction::builder()
+                .path("$.preview_persisted_queries.enabled")
+                .value(true)
+                .name("Persisted queries")
+                .build(),
             ConfigurationRestriction::builder()
                 .path("$.traffic_shaping.experimental_cache")
                 .name("Subgraph caching")
@@ -153,6 +158,23 @@ impl EntitlementReport {
                 .path("$.traffic_shaping..experimental_entity_caching")
                 .name("Subgraph entity caching")
                 .build(),
+            ConfigurationRestriction::builder()
+                .path("$.traffic_shaping.experimental_entity_cache")
+                .name("Entity cache")
+                .build(),
+            ConfigurationRestriction::builder()
+                .path("$.traffic_shaping..global_rate_limit.experimental_distributed")
+                .value(true)
+                .name("Distributed rate limiting")
+                .build(),
+            ConfigurationRestriction::builder()
+                .path("$.preview_demand_control")
+                .name("Demand control")
+                .build(),
+            ConfigurationRestriction::builder()
+                .path("$.authorization")
+                .name("Authorization directives")
+                .build(),
         ]
     }
 }